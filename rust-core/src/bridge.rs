@@ -1,16 +1,26 @@
-use std::net::{TcpListener, TcpStream};
+use std::net::TcpListener;
 use std::io::{Read, Write};
 use std::thread;
+use std::time::Duration;
+use std::fmt;
 use serde::{Serialize, Deserialize};
 use serde_json::json;
-use std::sync::{Arc, Mutex};
-use chrono::Utc;
-use ed25519_dalek::{SigningKey, Signature, Signer};
+use std::collections::VecDeque;
+use std::sync::{Arc, Condvar, Mutex};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use chrono::{DateTime, Utc};
+use primitive_types::U256;
+use ed25519_dalek::{SigningKey, Signature, Signer, Verifier, VerifyingKey};
 use rand::rngs::OsRng;
 use std::fs;
 use std::path::Path;
+use crate::codec::Encode;
+use crate::pow::{self, PowConfig};
+use crate::pqc::{HybridKeyPair, HybridPublicKey};
+use crate::secure_channel::{ChannelError, SecureChannel};
+use crate::unified_runtime::NeoNetUnifiedFabric;
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
 pub struct Block {
     pub index: u64,
     pub timestamp: String,
@@ -18,47 +28,379 @@ pub struct Block {
     pub prev_hash: String,
     pub hash: String,
     pub nonce: u64,
+    /// Compact proof-of-work target the hash must meet
+    #[serde(default)]
+    pub bits: u32,
+    /// Total work of the chain up to and including this block, as hex
+    #[serde(default)]
+    pub chain_work: String,
     pub pub_key: String,
     pub signature: String
 }
 
+/// Version of the framing and message format, agreed in the `hello` handshake
+pub const PROTOCOL_VERSION: u32 = 1;
+/// Largest frame body accepted; a bigger length prefix closes the connection
+pub const MAX_FRAME_LEN: usize = 16 * 1024 * 1024;
+/// Most blocks `get_blocks` returns per page
+pub const MAX_PAGE_SIZE: u64 = 100;
+/// Reported by `node_status`: this node mines the blocks for transactions it is sent
+pub const NODE_ROLE: &str = "miner";
+/// Most transactions waiting for the miner; `submit_tx` is refused beyond this
+pub const MAX_QUEUED_TXS: usize = 1024;
+
+/// Connection limits for the bridge listener
+#[derive(Debug, Clone, Copy)]
+pub struct ServeLimits {
+    /// Connections served at once; further ones are closed as soon as they are accepted
+    pub max_connections: usize,
+    /// Read and write timeout on every connection, handshake included; an idle or stalled
+    /// peer is disconnected after this long
+    pub io_timeout: Duration,
+}
+
+impl Default for ServeLimits {
+    fn default() -> Self {
+        ServeLimits { max_connections: 64, io_timeout: Duration::from_secs(30) }
+    }
+}
+
 #[derive(Serialize, Deserialize)]
 struct Request {
+    /// Echoed in the response so a client can pipeline requests on one connection
+    id: u64,
     cmd: String,
     data: Option<serde_json::Value>
 }
 
+/// Error codes carried in `{"ok": false, "error": {"code", "message"}}` responses
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCode {
+    /// Frame body is not a well-formed request
+    ParseError = 1,
+    /// A command was sent before a successful `hello`
+    HandshakeRequired = 2,
+    UnsupportedVersion = 3,
+    UnknownCommand = 4,
+    InvalidParams = 5,
+    FrameTooLarge = 6,
+    /// `put_chain` candidate failed verification
+    InvalidChain = 7,
+    /// No block or transaction with the requested hash
+    NotFound = 8,
+    /// `commit_block` names a block other than ours at that height
+    Conflict = 9,
+    /// The chain file could not be written; the request had no effect
+    StorageError = 10,
+    /// `MAX_QUEUED_TXS` transactions are already waiting to be mined
+    QueueFull = 11,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BridgeError {
+    pub code: ErrorCode,
+    pub message: String,
+}
+
+impl BridgeError {
+    fn new(code: ErrorCode, message: impl Into<String>) -> Self {
+        BridgeError { code, message: message.into() }
+    }
+}
+
+impl fmt::Display for BridgeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?} ({}): {}", self.code, self.code as u16, self.message)
+    }
+}
+
+impl std::error::Error for BridgeError {}
+
+#[derive(Debug)]
+pub enum FrameError {
+    Io(std::io::Error),
+    TooLarge(usize),
+}
+
+impl From<std::io::Error> for FrameError {
+    fn from(e: std::io::Error) -> Self {
+        FrameError::Io(e)
+    }
+}
+
+/// Read one frame: a big-endian u32 body length followed by the body. Returns `None` on a
+/// clean EOF between frames.
+pub fn read_frame<R: Read>(r: &mut R) -> Result<Option<Vec<u8>>, FrameError> {
+    let mut len = [0u8; 4];
+    if r.read(&mut len[..1])? == 0 {
+        return Ok(None);
+    }
+    r.read_exact(&mut len[1..])?;
+    let len = u32::from_be_bytes(len) as usize;
+    if len > MAX_FRAME_LEN {
+        return Err(FrameError::TooLarge(len));
+    }
+    let mut body = vec![0u8; len];
+    r.read_exact(&mut body)?;
+    Ok(Some(body))
+}
+
+pub fn write_frame<W: Write>(w: &mut W, body: &[u8]) -> std::io::Result<()> {
+    w.write_all(&(body.len() as u32).to_be_bytes())?;
+    w.write_all(body)?;
+    w.flush()
+}
+
+/// Why `put_chain` refused a candidate chain
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChainRejection {
+    Empty,
+    /// Candidate starts from a different genesis block
+    GenesisMismatch { ours: String, theirs: String },
+    WrongIndex { position: usize, index: u64 },
+    /// `prev_hash` does not point at the preceding block
+    BrokenLink { index: u64 },
+    /// `hash` is not the hash of the block's contents
+    HashMismatch { index: u64 },
+    /// `bits` is not the difficulty the retargeting rules give at this height
+    WrongDifficulty { index: u64, expected: u32, got: u32 },
+    /// Hash is above the block's target
+    InsufficientWork { index: u64 },
+    WrongChainWork { index: u64 },
+    BadTimestamp { index: u64 },
+    /// Timestamp is not after the median of the previous `MEDIAN_TIME_SPAN` blocks
+    TimestampTooEarly { index: u64, median_time_past: i64 },
+    /// Timestamp is further ahead of our clock than the configured drift allows
+    TimestampInFuture { index: u64, latest: i64 },
+    InvalidSignature { index: u64 },
+    /// Candidate forks off below the finalized height
+    ConflictsWithFinalized { finalized: u64, common_ancestor: u64 },
+}
+
+impl fmt::Display for ChainRejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ChainRejection::Empty => write!(f, "chain is empty"),
+            ChainRejection::GenesisMismatch { ours, theirs } => write!(f, "genesis {} does not match ours {}", theirs, ours),
+            ChainRejection::WrongIndex { position, index } => write!(f, "block at position {} has index {}", position, index),
+            ChainRejection::BrokenLink { index } => write!(f, "block {} does not link to its parent", index),
+            ChainRejection::HashMismatch { index } => write!(f, "block {} hash does not match its contents", index),
+            ChainRejection::WrongDifficulty { index, expected, got } => write!(f, "block {} has bits {:#010x}, expected {:#010x}", index, got, expected),
+            ChainRejection::InsufficientWork { index } => write!(f, "block {} hash does not meet its target", index),
+            ChainRejection::WrongChainWork { index } => write!(f, "block {} chain work is wrong", index),
+            ChainRejection::BadTimestamp { index } => write!(f, "block {} timestamp is not RFC 3339", index),
+            ChainRejection::TimestampTooEarly { index, median_time_past } => {
+                write!(f, "block {} timestamp is not after the median time past {}", index, median_time_past)
+            }
+            ChainRejection::TimestampInFuture { index, latest } => write!(f, "block {} timestamp is after {}", index, latest),
+            ChainRejection::InvalidSignature { index } => write!(f, "block {} signature is invalid", index),
+            ChainRejection::ConflictsWithFinalized { finalized, common_ancestor } => {
+                write!(f, "chain forks at height {}, below finalized height {}", common_ancestor, finalized)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ChainRejection {}
+
+/// Hybrid identity the bridge proves in the channel handshake, and the clients it accepts
+pub struct BridgeIdentity {
+    pub keypair: HybridKeyPair,
+    pub trusted: Vec<HybridPublicKey>,
+}
+
 pub struct ChainState {
     pub chain: Vec<Block>,
     pub keypair: SigningKey,
     pub path: String,
+    pub pow: PowConfig,
+    /// Bumped whenever the head changes, so miners building on the old head give up
+    pub head_epoch: Arc<AtomicU64>,
+    /// Height up to which blocks are committed and can no longer be reorganized away
+    pub finalized: Option<u64>,
+    pub fabric: Arc<NeoNetUnifiedFabric>,
+    /// Transaction data waiting for the miner thread, oldest first
+    pub queue: VecDeque<String>,
+    /// Signalled whenever a transaction is queued
+    pub tx_queued: Arc<Condvar>,
 }
 
-fn calculate_hash(b: &Block) -> String {
+/// Finalized block, recorded in the chain file
+#[derive(Serialize, Deserialize)]
+struct FinalizedMarker {
+    height: u64,
+    hash: String,
+}
+
+/// Contents of the chain file. Files written before finality was tracked hold just the
+/// array of blocks.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StoredChain {
+    Current { blocks: Vec<Block>, finalized: Option<FinalizedMarker> },
+    Blocks(Vec<Block>),
+}
+
+impl StoredChain {
+    /// The blocks and the finalized height, if the recorded block is still among them
+    fn into_parts(self) -> (Vec<Block>, Option<u64>) {
+        match self {
+            StoredChain::Current { blocks, finalized } => {
+                let finalized = finalized
+                    .filter(|m| blocks.get(m.height as usize).is_some_and(|b| b.hash == m.hash))
+                    .map(|m| m.height);
+                (blocks, finalized)
+            }
+            StoredChain::Blocks(blocks) => (blocks, None),
+        }
+    }
+}
+
+fn storage_error(e: std::io::Error) -> BridgeError {
+    BridgeError::new(ErrorCode::StorageError, format!("could not save the chain: {}", e))
+}
+
+impl ChainState {
+    pub fn new(chain: Vec<Block>, finalized: Option<u64>, keypair: SigningKey, path: String, pow: PowConfig, fabric: Arc<NeoNetUnifiedFabric>) -> Self {
+        ChainState {
+            chain,
+            keypair,
+            path,
+            pow,
+            head_epoch: Arc::new(AtomicU64::new(0)),
+            finalized,
+            fabric,
+            queue: VecDeque::new(),
+            tx_queued: Arc::new(Condvar::new()),
+        }
+    }
+
+    /// Finalize the block `hash`, and with it every ancestor, then commit the unified runtime
+    /// state at its height. Committing an already finalized block is a no-op; a hash we don't
+    /// hold, or one that isn't our block at `expected_height`, is refused.
+    fn commit(&mut self, hash: &str, expected_height: Option<u64>) -> Result<serde_json::Value, BridgeError> {
+        let height = self.chain.iter()
+            .position(|b| b.hash == hash)
+            .ok_or_else(|| not_found("block", hash))? as u64;
+        if let Some(expected) = expected_height.filter(|&expected| expected != height) {
+            return Err(BridgeError::new(
+                ErrorCode::Conflict,
+                format!("block {} is at height {}, not {}", hash, height, expected),
+            ));
+        }
+        if self.finalized.is_some_and(|f| height <= f) {
+            return Ok(json!({"committed": hash, "height": height, "finalized_height": self.finalized}));
+        }
+
+        save_chain(&self.path, &self.chain, Some(height)).map_err(storage_error)?;
+        self.finalized = Some(height);
+        let root = self.fabric.commit_block(height);
+        Ok(json!({
+            "committed": hash,
+            "height": height,
+            "finalized_height": height,
+            "state_root": hex::encode(root.hash),
+            "evm_state_root": hex::encode(root.evm_state_root),
+            "wasm_state_root": hex::encode(root.wasm_state_root),
+            "shared_state_root": hex::encode(root.shared_state_root),
+        }))
+    }
+
+    fn set_chain(&mut self, chain: Vec<Block>) -> Result<(), BridgeError> {
+        save_chain(&self.path, &chain, self.finalized).map_err(storage_error)?;
+        self.chain = chain;
+        self.head_epoch.fetch_add(1, Ordering::SeqCst);
+        Ok(())
+    }
+
+    fn push_block(&mut self, b: Block) -> Result<(), BridgeError> {
+        self.chain.push(b);
+        if let Err(e) = save_chain(&self.path, &self.chain, self.finalized) {
+            self.chain.pop();
+            return Err(storage_error(e));
+        }
+        self.head_epoch.fetch_add(1, Ordering::SeqCst);
+        Ok(())
+    }
+}
+
+fn hash_bytes(b: &Block) -> [u8; 32] {
     use sha2::{Sha256, Digest};
-    let record = format!("{}{}{}{}{}", b.index, b.timestamp, b.data, b.prev_hash, b.nonce);
-    let mut hasher = Sha256::new();
-    hasher.update(record.as_bytes());
-    let res = hasher.finalize();
-    hex::encode(res)
+    let record = (b.index, &b.timestamp, &b.data, &b.prev_hash, b.nonce, b.bits).to_bytes();
+    Sha256::digest(record).into()
+}
+
+fn calculate_hash(b: &Block) -> String {
+    hex::encode(hash_bytes(b))
+}
+
+fn chain_work(b: Option<&Block>) -> U256 {
+    b.and_then(|b| U256::from_str_radix(&b.chain_work, 16).ok()).unwrap_or_default()
+}
+
+fn timestamp_ms(b: &Block) -> Result<i64, ChainRejection> {
+    DateTime::parse_from_rfc3339(&b.timestamp)
+        .map(|t| t.timestamp_millis())
+        .map_err(|_| ChainRejection::BadTimestamp { index: b.index })
+}
+
+/// Median timestamp of the last `MEDIAN_TIME_SPAN` blocks of `chain`, which must not be empty
+fn median_time_past(chain: &[Block]) -> Result<i64, ChainRejection> {
+    let start = chain.len().saturating_sub(pow::MEDIAN_TIME_SPAN);
+    let mut times = chain[start..].iter().map(timestamp_ms).collect::<Result<Vec<_>, _>>()?;
+    times.sort_unstable();
+    Ok(times[times.len() / 2])
+}
+
+/// Timestamp for a block on top of `chain`: the current time, or just after the median time
+/// past if the clock is behind it
+fn next_timestamp(chain: &[Block]) -> String {
+    let now = Utc::now();
+    match median_time_past(chain) {
+        Ok(median) if now.timestamp_millis() <= median => {
+            DateTime::from_timestamp_millis(median + 1).unwrap_or(now).to_rfc3339()
+        }
+        _ => now.to_rfc3339(),
+    }
+}
+
+/// Difficulty of the block after the last one in `chain`, which must hold at least the last
+/// `retarget_interval + 1` blocks. A retarget scales the target by how long the last
+/// interval actually took against `target_block_secs` per block.
+fn next_bits(chain: &[Block], pow: &PowConfig) -> Result<u32, ChainRejection> {
+    let parent = match chain.last() {
+        Some(parent) => parent,
+        None => return Ok(pow.initial_bits),
+    };
+    if !pow.is_retarget_height(parent.index + 1) {
+        return Ok(parent.bits);
+    }
+    let first = &chain[chain.len().saturating_sub(pow.retarget_interval as usize + 1)];
+    Ok(pow.retarget(parent.bits, timestamp_ms(parent)? - timestamp_ms(first)?))
 }
 
-fn mine_block(mut b: Block, difficulty: usize) -> Block {
-    let target = "0".repeat(difficulty);
+/// Search nonces until the hash meets the block's `bits`. Gives up with `None` once
+/// `cancelled` returns true, checked every 1024 attempts.
+fn mine_block(mut b: Block, cancelled: impl Fn() -> bool) -> Option<Block> {
     loop {
+        if b.nonce.is_multiple_of(1024) && cancelled() {
+            return None;
+        }
         b.nonce += 1;
-        b.hash = calculate_hash(&b);
-        if &b.hash[..difficulty] == target {
-            return b;
+        let hash = hash_bytes(&b);
+        if pow::meets_target(&hash, b.bits) {
+            b.hash = hex::encode(hash);
+            return Some(b);
         }
     }
 }
 
-fn load_or_create_chain(path: &str, keypair: &SigningKey) -> Vec<Block> {
+/// The stored chain and finalized height, or a freshly mined genesis block
+fn load_or_create_chain(path: &str, keypair: &SigningKey, pow: &PowConfig) -> (Vec<Block>, Option<u64>) {
     if Path::new(path).exists() {
         if let Ok(s) = fs::read_to_string(path) {
-            if let Ok(arr) = serde_json::from_str::<Vec<Block>>(&s) {
-                return arr;
+            if let Ok(stored) = serde_json::from_str::<StoredChain>(&s) {
+                return stored.into_parts();
             }
         }
     }
@@ -70,177 +412,971 @@ fn load_or_create_chain(path: &str, keypair: &SigningKey) -> Vec<Block> {
         prev_hash: "".to_string(),
         hash: "".to_string(),
         nonce: 0,
+        bits: pow.initial_bits,
+        chain_work: format!("{:x}", pow::work(pow.initial_bits)),
         pub_key: hex::encode(keypair.verifying_key().to_bytes()),
         signature: "".to_string(),
     };
-    g = mine_block(g, 1);
+    g = mine_block(g, || false).unwrap();
     // sign genesis
     let sig: Signature = keypair.sign(g.hash.as_bytes());
     g.signature = hex::encode(sig.to_bytes());
     let arr = vec![g];
     // persist
-    let _ = fs::write(path, serde_json::to_string_pretty(&arr).unwrap());
-    arr
+    let _ = save_chain(path, &arr, None);
+    (arr, None)
+}
+
+/// Mine and sign a block on top of the last block of `chain`; see `next_bits` for how much
+/// of the chain is needed
+fn next_block(
+    chain: &[Block],
+    data: String,
+    timestamp: String,
+    keypair: &SigningKey,
+    pow: &PowConfig,
+    cancelled: impl Fn() -> bool,
+) -> Option<Block> {
+    let parent = chain.last().expect("chain has a genesis block");
+    let bits = next_bits(chain, pow).unwrap_or(pow.initial_bits);
+    let b = Block {
+        index: parent.index + 1,
+        timestamp,
+        data,
+        prev_hash: parent.hash.clone(),
+        hash: "".to_string(),
+        nonce: 0,
+        bits,
+        chain_work: format!("{:x}", chain_work(Some(parent)) + pow::work(bits)),
+        pub_key: hex::encode(keypair.verifying_key().to_bytes()),
+        signature: "".to_string(),
+    };
+    let mut b = mine_block(b, cancelled)?;
+    let sig: Signature = keypair.sign(b.hash.as_bytes());
+    b.signature = hex::encode(sig.to_bytes());
+    Some(b)
 }
 
-fn save_chain(path: &str, chain: &Vec<Block>) {
-    let _ = fs::write(path, serde_json::to_string_pretty(chain).unwrap());
+/// Mine `data` into a block on the current head without holding the lock, starting over on
+/// the new head whenever another block or chain replaces it first
+fn mine_on_head(shared: &Mutex<ChainState>, data: String) -> Result<Block, BridgeError> {
+    loop {
+        let (tail, keypair, pow, head_epoch, epoch) = {
+            let state = shared.lock().unwrap();
+            let needed = (state.pow.retarget_interval as usize + 1).max(pow::MEDIAN_TIME_SPAN);
+            let start = state.chain.len().saturating_sub(needed);
+            let epoch = state.head_epoch.load(Ordering::SeqCst);
+            (state.chain[start..].to_vec(), state.keypair.clone(), state.pow, state.head_epoch.clone(), epoch)
+        };
+        let cancelled = || head_epoch.load(Ordering::SeqCst) != epoch;
+        if let Some(b) = next_block(&tail, data.clone(), next_timestamp(&tail), &keypair, &pow, cancelled) {
+            let mut state = shared.lock().unwrap();
+            if !cancelled() {
+                state.push_block(b.clone())?;
+                return Ok(b);
+            }
+        }
+    }
 }
 
-fn handle_request(req: Request, state: &mut ChainState) -> serde_json::Value {
+fn verify_block_signature(b: &Block) -> bool {
+    let key = hex::decode(&b.pub_key).ok().and_then(|k| <[u8; 32]>::try_from(k).ok());
+    let sig = hex::decode(&b.signature).ok().and_then(|s| <[u8; 64]>::try_from(s).ok());
+    match (key.and_then(|k| VerifyingKey::from_bytes(&k).ok()), sig) {
+        (Some(key), Some(sig)) => key.verify(b.hash.as_bytes(), &Signature::from_bytes(&sig)).is_ok(),
+        _ => false,
+    }
+}
+
+/// Mine the oldest queued transaction into a block, or return `None` if the queue is empty.
+/// The transaction stays queued until its block is on the chain, so it is never unaccounted
+/// for, and is retried if storing the block fails.
+fn mine_next(shared: &Mutex<ChainState>) -> Option<Result<Block, BridgeError>> {
+    let data = shared.lock().unwrap().queue.front()?.clone();
+    let result = mine_on_head(shared, data);
+    if result.is_ok() {
+        shared.lock().unwrap().queue.pop_front();
+    }
+    Some(result)
+}
+
+/// Body of the miner thread: mine queued transactions one block at a time, sleeping while
+/// there are none
+fn run_miner(shared: Arc<Mutex<ChainState>>) {
+    let tx_queued = shared.lock().unwrap().tx_queued.clone();
+    loop {
+        {
+            let mut state = shared.lock().unwrap();
+            while state.queue.is_empty() {
+                state = tx_queued.wait(state).unwrap();
+            }
+        }
+        if let Some(Err(e)) = mine_next(&shared) {
+            eprintln!("mining failed: {}", e);
+            thread::sleep(std::time::Duration::from_secs(1));
+        }
+    }
+}
+
+/// Check every block of `candidate`, including its difficulty, chain work and timestamp, and
+/// that it starts from our genesis. Returns the height of the last block it shares with `ours`.
+fn verify_chain(candidate: &[Block], ours: &[Block], pow: &PowConfig, now_ms: i64) -> Result<u64, ChainRejection> {
+    let genesis = candidate.first().ok_or(ChainRejection::Empty)?;
+    if let Some(our_genesis) = ours.first() {
+        if genesis.hash != our_genesis.hash {
+            return Err(ChainRejection::GenesisMismatch { ours: our_genesis.hash.clone(), theirs: genesis.hash.clone() });
+        }
+    }
+    for (position, b) in candidate.iter().enumerate() {
+        if b.index != position as u64 {
+            return Err(ChainRejection::WrongIndex { position, index: b.index });
+        }
+        let parent_hash = if position == 0 { "" } else { candidate[position - 1].hash.as_str() };
+        if b.prev_hash != parent_hash {
+            return Err(ChainRejection::BrokenLink { index: b.index });
+        }
+        if calculate_hash(b) != b.hash {
+            return Err(ChainRejection::HashMismatch { index: b.index });
+        }
+        let expected = next_bits(&candidate[..position], pow)?;
+        if b.bits != expected {
+            return Err(ChainRejection::WrongDifficulty { index: b.index, expected, got: b.bits });
+        }
+        if !pow::meets_target(&hash_bytes(b), b.bits) {
+            return Err(ChainRejection::InsufficientWork { index: b.index });
+        }
+        let timestamp = timestamp_ms(b)?;
+        let latest = now_ms.saturating_add(pow.max_future_drift_secs.saturating_mul(1000) as i64);
+        if timestamp > latest {
+            return Err(ChainRejection::TimestampInFuture { index: b.index, latest });
+        }
+        if position > 0 {
+            let median_time_past = median_time_past(&candidate[..position])?;
+            if timestamp <= median_time_past {
+                return Err(ChainRejection::TimestampTooEarly { index: b.index, median_time_past });
+            }
+        }
+        let parent = position.checked_sub(1).map(|p| &candidate[p]);
+        if chain_work(Some(b)) != chain_work(parent) + pow::work(b.bits) {
+            return Err(ChainRejection::WrongChainWork { index: b.index });
+        }
+        if !verify_block_signature(b) {
+            return Err(ChainRejection::InvalidSignature { index: b.index });
+        }
+    }
+    let shared = candidate.iter().zip(ours).take_while(|(a, b)| a.hash == b.hash).count();
+    Ok(shared.saturating_sub(1) as u64)
+}
+
+/// Write the chain and the block finalized at `finalized` to a temporary file and move it
+/// over the chain file, so a crash leaves either the old or the new contents
+fn save_chain(path: &str, chain: &[Block], finalized: Option<u64>) -> std::io::Result<()> {
+    let finalized = finalized
+        .and_then(|height| chain.get(height as usize))
+        .map(|b| FinalizedMarker { height: b.index, hash: b.hash.clone() });
+    let contents = serde_json::to_string_pretty(&json!({"blocks": chain, "finalized": finalized}))?;
+    let tmp = format!("{}.tmp", path);
+    fs::write(&tmp, contents)?;
+    fs::rename(&tmp, path)
+}
+
+/// Hash identifying a transaction: the hash of its data
+pub fn tx_hash(data: &str) -> String {
+    use sha2::{Sha256, Digest};
+    hex::encode(Sha256::digest(data.to_bytes()))
+}
+
+/// First 16 hex digits of the SHA-256 of the node's block signing key
+fn key_fingerprint(keypair: &SigningKey) -> String {
+    use sha2::{Sha256, Digest};
+    hex::encode(&Sha256::digest(keypair.verifying_key().to_bytes())[..8])
+}
+
+fn str_param<'a>(data: &'a Option<serde_json::Value>, name: &str) -> Result<&'a str, BridgeError> {
+    data.as_ref()
+        .and_then(|d| d.get(name))
+        .and_then(|v| v.as_str())
+        .ok_or_else(|| BridgeError::new(ErrorCode::InvalidParams, format!("missing {}", name)))
+}
+
+/// An optional unsigned integer parameter; present but not a u64 is an error
+fn u64_param(data: &Option<serde_json::Value>, name: &str) -> Result<Option<u64>, BridgeError> {
+    match data.as_ref().and_then(|d| d.get(name)) {
+        None | Some(serde_json::Value::Null) => Ok(None),
+        Some(v) => v.as_u64()
+            .map(Some)
+            .ok_or_else(|| BridgeError::new(ErrorCode::InvalidParams, format!("{} must be a non-negative integer", name))),
+    }
+}
+
+fn not_found(what: &str, hash: &str) -> BridgeError {
+    BridgeError::new(ErrorCode::NotFound, format!("no {} with hash {}", what, hash))
+}
+
+fn handle_request(req: Request, shared: &Mutex<ChainState>) -> Result<serde_json::Value, BridgeError> {
     match req.cmd.as_str() {
         "commit_block" => {
-            if let Some(d) = req.data {
-                if let Some(hv) = d.get("hash").and_then(|v| v.as_str()) {
-                    let _ = fs::write(format!("committed_{}.txt", hv), "committed");
-                    return json!({"ok":true, "committed": hv});
-                }
-            }
-            json!({"ok":false})
+            let hash = str_param(&req.data, "hash")?;
+            let height = u64_param(&req.data, "height")?;
+            shared.lock().unwrap().commit(hash, height)
         },
         "get_chain" => {
-            json!({"ok": true, "chain": state.chain})
+            Ok(json!({"chain": shared.lock().unwrap().chain}))
+        },
+        "get_blocks" => {
+            let from = u64_param(&req.data, "from")?.unwrap_or(0);
+            let limit = u64_param(&req.data, "limit")?.unwrap_or(MAX_PAGE_SIZE).min(MAX_PAGE_SIZE);
+            let state = shared.lock().unwrap();
+            let start = (from as usize).min(state.chain.len());
+            let end = start.saturating_add(limit as usize).min(state.chain.len());
+            let next = if end < state.chain.len() { Some(end) } else { None };
+            Ok(json!({"blocks": state.chain[start..end], "next": next, "height": state.chain.len() - 1}))
+        },
+        "get_block_by_hash" => {
+            let hash = str_param(&req.data, "hash")?;
+            let state = shared.lock().unwrap();
+            let block = state.chain.iter().find(|b| b.hash == hash).ok_or_else(|| not_found("block", hash))?;
+            Ok(json!({"block": block}))
+        },
+        "get_tx" => {
+            let hash = str_param(&req.data, "hash")?;
+            let state = shared.lock().unwrap();
+            if let Some(block) = state.chain.iter().skip(1).find(|b| tx_hash(&b.data) == hash) {
+                return Ok(json!({"tx": {"hash": hash, "data": block.data, "block_hash": block.hash, "height": block.index, "pending": false}}));
+            }
+            let data = state.queue.iter().find(|d| tx_hash(d) == hash).ok_or_else(|| not_found("transaction", hash))?;
+            Ok(json!({"tx": {"hash": hash, "data": data, "block_hash": null, "height": null, "pending": true}}))
+        },
+        "get_head" => {
+            let state = shared.lock().unwrap();
+            Ok(json!({"block": state.chain.last()}))
+        },
+        "node_status" => {
+            let state = shared.lock().unwrap();
+            let head = state.chain.last().expect("chain has a genesis block");
+            Ok(json!({
+                "height": head.index,
+                "head": head.hash,
+                "chain_work": head.chain_work,
+                "role": NODE_ROLE,
+                "protocol_version": PROTOCOL_VERSION,
+                "node_version": env!("CARGO_PKG_VERSION"),
+                "key_fingerprint": key_fingerprint(&state.keypair),
+            }))
         },
         "submit_tx" => {
-            if let Some(d) = req.data {
-                let data_str = d.get("data").and_then(|v| v.as_str()).unwrap_or("").to_string();
-                let latest = state.chain.last().unwrap();
-                let mut newb = Block {
-                    index: latest.index + 1,
-                    timestamp: Utc::now().to_rfc3339(),
-                    data: data_str,
-                    prev_hash: latest.hash.clone(),
-                    hash: "".to_string(),
-                    nonce: 0,
-                    pub_key: hex::encode(state.keypair.verifying_key().to_bytes()),
-                    signature: "".to_string(),
-                };
-                newb = mine_block(newb, 1);
-                let sig: Signature = state.keypair.sign(newb.hash.as_bytes());
-                newb.signature = hex::encode(sig.to_bytes());
-                state.chain.push(newb.clone());
-                save_chain(&state.path, &state.chain);
-                json!({"ok":true, "block": newb})
-            } else {
-                json!({"ok":false, "error":"no data"})
+            let d = req.data.ok_or_else(|| BridgeError::new(ErrorCode::InvalidParams, "no data"))?;
+            let data_str = d.get("data").and_then(|v| v.as_str()).unwrap_or("").to_string();
+            let hash = tx_hash(&data_str);
+            let mut state = shared.lock().unwrap();
+            if state.queue.len() >= MAX_QUEUED_TXS {
+                return Err(BridgeError::new(ErrorCode::QueueFull, format!("{} transactions already queued", MAX_QUEUED_TXS)));
             }
+            state.queue.push_back(data_str);
+            state.tx_queued.notify_one();
+            Ok(json!({"tx_hash": hash, "queued": state.queue.len()}))
         },
         "put_chain" => {
-            if let Some(d) = req.data {
-                if let Ok(arr) = serde_json::from_value::<Vec<Block>>(d) {
-                    if arr.len() > state.chain.len() {
-                        state.chain = arr;
-                        save_chain(&state.path, &state.chain);
-                        return json!({"ok":true, "replaced": true});
-                    } else {
-                        return json!({"ok":true, "replaced": false});
+            let arr = req.data
+                .and_then(|d| serde_json::from_value::<Vec<Block>>(d).ok())
+                .ok_or_else(|| BridgeError::new(ErrorCode::InvalidParams, "expected an array of blocks"))?;
+            let mut state = shared.lock().unwrap();
+            let common = verify_chain(&arr, &state.chain, &state.pow, Utc::now().timestamp_millis())
+                .and_then(|common| match state.finalized {
+                    // Only the canonical chain is kept, so refusing these is what prunes
+                    // forks below the finalized height
+                    Some(finalized) if common < finalized => {
+                        Err(ChainRejection::ConflictsWithFinalized { finalized, common_ancestor: common })
                     }
-                }
+                    _ => Ok(common),
+                })
+                .map_err(|e| BridgeError::new(ErrorCode::InvalidChain, e.to_string()))?;
+            // Fork choice by total work, not length
+            let replaced = chain_work(arr.last()) > chain_work(state.chain.last());
+            if replaced {
+                state.set_chain(arr)?;
             }
-            json!({"ok":false})
+            Ok(json!({"replaced": replaced, "common_ancestor": common}))
         },
-        _ => json!({"ok":false, "error":"unknown command"})
+        other => Err(BridgeError::new(ErrorCode::UnknownCommand, format!("unknown command {:?}", other)))
     }
 }
 
-fn handle_stream(mut s: TcpStream, shared: Arc<Mutex<ChainState>>) {
-    let mut buf = Vec::new();
-    match s.read_to_end(&mut buf) {
-        Ok(_) => {
-            if buf.is_empty() { return; }
-            if let Ok(txt) = String::from_utf8(buf) {
-                if let Ok(req) = serde_json::from_str::<Request>(&txt) {
-                    let mut st = shared.lock().unwrap();
-                    let resp = handle_request(req, &mut *st);
-                    let _ = s.write_all(resp.to_string().as_bytes());
-                }
+/// Accept `hello` only for the version this node speaks
+fn handshake(req: &Request) -> Result<serde_json::Value, BridgeError> {
+    let version = req.data.as_ref()
+        .and_then(|d| d.get("version"))
+        .and_then(|v| v.as_u64())
+        .ok_or_else(|| BridgeError::new(ErrorCode::InvalidParams, "missing version"))?;
+    if version != PROTOCOL_VERSION as u64 {
+        return Err(BridgeError::new(
+            ErrorCode::UnsupportedVersion,
+            format!("protocol version {} is not supported, expected {}", version, PROTOCOL_VERSION),
+        ));
+    }
+    Ok(json!({"version": PROTOCOL_VERSION}))
+}
+
+fn send_response<S: Read + Write>(s: &mut SecureChannel<S>, id: Option<u64>, result: Result<serde_json::Value, BridgeError>) -> Result<(), ChannelError> {
+    let resp = match result {
+        Ok(result) => json!({"id": id, "ok": true, "result": result}),
+        Err(e) => json!({"id": id, "ok": false, "error": {"code": e.code as u16, "message": e.message}}),
+    };
+    s.send(resp.to_string().as_bytes())
+}
+
+/// Serve requests on one connection until the peer closes it. The connection must first
+/// complete the secure channel handshake with a trusted key, then send a `hello` with a
+/// supported version; responses are written in request order and carry the request's id.
+fn handle_stream<S: Read + Write>(stream: S, shared: Arc<Mutex<ChainState>>, identity: &BridgeIdentity) {
+    let mut s = match SecureChannel::accept(stream, &identity.keypair, &identity.trusted) {
+        Ok(channel) => channel,
+        Err(e) => {
+            eprintln!("bridge handshake failed: {}", e);
+            return;
+        }
+    };
+    let mut handshaken = false;
+    loop {
+        let body = match s.recv() {
+            Ok(Some(body)) => body,
+            Ok(None) => return,
+            Err(ChannelError::FrameTooLarge(len)) => {
+                let message = format!("frame of {} bytes exceeds the {} byte limit", len, MAX_FRAME_LEN);
+                let _ = send_response(&mut s, None, Err(BridgeError::new(ErrorCode::FrameTooLarge, message)));
+                return;
+            }
+            Err(e) => {
+                eprintln!("read err from {}: {}", s.peer().address(), e);
+                return;
+            }
+        };
+        let value: Option<serde_json::Value> = serde_json::from_slice(&body).ok();
+        let id = value.as_ref().and_then(|v| v.get("id")).and_then(|v| v.as_u64());
+        let req = match value.map(serde_json::from_value::<Request>) {
+            Some(Ok(req)) => req,
+            Some(Err(e)) => {
+                if send_response(&mut s, id, Err(BridgeError::new(ErrorCode::ParseError, e.to_string()))).is_err() { return; }
+                continue;
+            }
+            None => {
+                if send_response(&mut s, id, Err(BridgeError::new(ErrorCode::ParseError, "frame is not valid JSON"))).is_err() { return; }
+                continue;
             }
+        };
+        let id = req.id;
+        let result = if req.cmd == "hello" {
+            let result = handshake(&req);
+            handshaken = result.is_ok();
+            result
+        } else if !handshaken {
+            Err(BridgeError::new(ErrorCode::HandshakeRequired, "send hello before other commands"))
+        } else {
+            handle_request(req, &shared)
+        };
+        let rejected_version = matches!(&result, Err(e) if e.code == ErrorCode::UnsupportedVersion);
+        if send_response(&mut s, Some(id), result).is_err() || rejected_version {
+            return;
         }
-        Err(e) => {
-            eprintln!("read err: {:?}", e);
+    }
+}
+
+/// Held by a connection's thread for as long as it is served
+struct ConnectionSlot(Arc<AtomicUsize>);
+
+impl ConnectionSlot {
+    fn acquire(open: &Arc<AtomicUsize>, max: usize) -> Option<Self> {
+        open.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| (n < max).then_some(n + 1)).ok()?;
+        Some(ConnectionSlot(open.clone()))
+    }
+}
+
+impl Drop for ConnectionSlot {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+fn serve(listener: TcpListener, shared: Arc<Mutex<ChainState>>, identity: Arc<BridgeIdentity>, limits: ServeLimits) {
+    let open = Arc::new(AtomicUsize::new(0));
+    for stream in listener.incoming() {
+        match stream {
+            Ok(s) => {
+                let Some(slot) = ConnectionSlot::acquire(&open, limits.max_connections) else {
+                    eprintln!("refusing {:?}: {} connections already open", s.peer_addr(), limits.max_connections);
+                    continue;
+                };
+                if let Err(e) = s.set_read_timeout(Some(limits.io_timeout)).and_then(|_| s.set_write_timeout(Some(limits.io_timeout))) {
+                    eprintln!("could not set timeouts on {:?}: {}", s.peer_addr(), e);
+                    continue;
+                }
+                let shared2 = shared.clone();
+                let identity = identity.clone();
+                thread::spawn(move || {
+                    let _slot = slot;
+                    handle_stream(s, shared2, &identity);
+                });
+            }
+            Err(e) => {
+                eprintln!("incoming err {:?}", e);
+            }
         }
     }
 }
 
-pub fn start_bridge() {
-    thread::spawn(|| {
-        let keypath = "rust_keys/node_priv.hex";
+/// Serve the bridge on a background thread; committed blocks commit the state of `fabric`,
+/// the unified runtime the node executes against
+pub fn start_bridge(fabric: Arc<NeoNetUnifiedFabric>) {
+    thread::spawn(move || {
         let path = "rust_chain_store.json";
-        let kp = if Path::new(keypath).exists() {
-            if let Ok(s) = fs::read_to_string(keypath) {
-                if let Ok(bytes) = hex::decode(s.trim()) {
-                    if bytes.len() == 32 {
-                        let arr: [u8; 32] = bytes.try_into().unwrap();
-                        SigningKey::from_bytes(&arr)
-                    } else {
-                        let mut rng = OsRng;
-                        let mut secret = [0u8; 32];
-                        rand::RngCore::fill_bytes(&mut rng, &mut secret);
-                        SigningKey::from_bytes(&secret)
-                    }
-                } else {
-                    let mut rng = OsRng;
-                    let mut secret = [0u8; 32];
-                    rand::RngCore::fill_bytes(&mut rng, &mut secret);
-                    SigningKey::from_bytes(&secret)
-                }
-            } else {
-                let mut rng = OsRng;
-                let mut secret = [0u8; 32];
-                rand::RngCore::fill_bytes(&mut rng, &mut secret);
-                SigningKey::from_bytes(&secret)
+        let kp = match load_or_create_node_key("rust_keys/node_priv.hex") {
+            Ok(kp) => kp,
+            Err(e) => {
+                eprintln!("could not load node key: {}", e);
+                return;
             }
-        } else {
-            let mut rng = OsRng;
-            let mut secret = [0u8; 32];
-            rand::RngCore::fill_bytes(&mut rng, &mut secret);
-            SigningKey::from_bytes(&secret)
         };
         
-        if !Path::new("rust_keys").exists() {
-            let _ = fs::create_dir("rust_keys");
-        }
-        let _ = fs::write("rust_keys/node_priv.hex", hex::encode(kp.to_bytes()));
-        
-        let chain = load_or_create_chain(path, &kp);
-        let state = ChainState { chain, keypair: kp, path: path.to_string() };
+        let pow = match load_pow_config() {
+            Ok(pow) => pow,
+            Err(e) => {
+                eprintln!("could not load proof-of-work config: {}", e);
+                return;
+            }
+        };
+        let (chain, finalized) = load_or_create_chain(path, &kp, &pow);
+        let state = ChainState::new(chain, finalized, kp, path.to_string(), pow, fabric);
         let shared = Arc::new(Mutex::new(state));
+        let miner = shared.clone();
+        thread::spawn(move || run_miner(miner));
         
-        if let Ok(listener) = TcpListener::bind("127.0.0.1:6000") {
-            println!("rust bridge listening on 127.0.0.1:6000");
-            for stream in listener.incoming() {
-                match stream {
-                    Ok(s) => {
-                        let shared2 = shared.clone();
-                        thread::spawn(move || {
-                            handle_stream(s, shared2);
-                        });
-                    }
-                    Err(e) => {
-                        eprintln!("incoming err {:?}", e);
-                    }
-                }
+        let identity = match load_bridge_identity("rust_keys") {
+            Ok(identity) => Arc::new(identity),
+            Err(e) => {
+                eprintln!("could not load bridge identity: {}", e);
+                return;
             }
+        };
+        if identity.trusted.is_empty() {
+            eprintln!("no trusted bridge peers in rust_keys/bridge_peers.json; all connections will be refused");
+        }
+
+        let addr = std::env::var("NEONET_BRIDGE_ADDR").unwrap_or_else(|_| "127.0.0.1:6000".to_string());
+        if let Ok(listener) = TcpListener::bind(&addr) {
+            println!("rust bridge listening on {} (protocol v{}, identity {})", addr, PROTOCOL_VERSION, identity.keypair.public_key().address());
+            serve(listener, shared, identity, ServeLimits::default());
         } else {
-            eprintln!("could not bind 127.0.0.1:6000");
+            eprintln!("could not bind {}", addr);
         }
     });
 }
 
-fn sled_save(path: &str, chain: &Vec<Block>) {
-    if let Ok(db) = sled::open(path) {
-        let _ = db.insert("chain", serde_json::to_vec(chain).unwrap());
-        let _ = db.flush();
+/// Load the Ed25519 key the bridge signs blocks with, or generate and save one that only its
+/// owner can read, the same way as the node's hybrid keys
+fn load_or_create_node_key(path: &str) -> anyhow::Result<SigningKey> {
+    let secret = crate::load_or_create_secret(path, || {
+        let mut secret = [0u8; 32];
+        rand::RngCore::fill_bytes(&mut OsRng, &mut secret);
+        secret.to_vec()
+    })?;
+    let secret: [u8; 32] = secret.try_into()
+        .map_err(|_| anyhow::anyhow!("Key file {} does not hold a 32-byte Ed25519 key", path))?;
+    Ok(SigningKey::from_bytes(&secret))
+}
+
+/// Proof-of-work parameters from the JSON file named by `NEONET_BRIDGE_POW`, or the defaults
+fn load_pow_config() -> anyhow::Result<PowConfig> {
+    match std::env::var("NEONET_BRIDGE_POW") {
+        Ok(path) => Ok(serde_json::from_str(&fs::read_to_string(path)?)?),
+        Err(_) => Ok(PowConfig::default()),
+    }
+}
+
+/// Load or create the bridge's hybrid keypair in `dir`, publish its public key for clients to
+/// pin in `bridge_identity.pub.json`, and read the trusted client keys from `bridge_peers.json`
+fn load_bridge_identity(dir: &str) -> anyhow::Result<BridgeIdentity> {
+    let keypair = crate::load_or_create_key(&format!("{}/bridge_identity.key", dir))?;
+    fs::write(format!("{}/bridge_identity.pub.json", dir), serde_json::to_string_pretty(&keypair.public_key())?)?;
+    let peers_path = format!("{}/bridge_peers.json", dir);
+    if !Path::new(&peers_path).exists() {
+        fs::write(&peers_path, "[]")?;
     }
+    let trusted = serde_json::from_str(&fs::read_to_string(&peers_path)?)?;
+    Ok(BridgeIdentity { keypair, trusted })
 }
 
-fn sled_load(path: &str) -> Option<Vec<Block>> {
-    if let Ok(db) = sled::open(path) {
-        if let Ok(Some(v)) = db.get("chain") {
-            if let Ok(arr) = serde_json::from_slice::<Vec<Block>>(&v) {
-                return Some(arr);
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::TEST_CHAIN_ID;
+    use std::net::TcpStream;
+
+    type Client = SecureChannel<TcpStream>;
+
+    struct TestBridge {
+        addr: std::net::SocketAddr,
+        server_key: HybridPublicKey,
+        client_key: HybridKeyPair,
+    }
+
+    impl TestBridge {
+        fn connect(&self) -> Client {
+            SecureChannel::connect(TcpStream::connect(self.addr).unwrap(), &self.client_key, &self.server_key).unwrap()
+        }
+    }
+
+    fn test_state(name: &str) -> ChainState {
+        let path = std::env::temp_dir().join(format!("neonet_bridge_{}_{}.json", name, std::process::id()));
+        let _ = fs::remove_file(&path);
+        reopen_state(path.to_str().unwrap(), Arc::new(NeoNetUnifiedFabric::new(TEST_CHAIN_ID)))
+    }
+
+    fn reopen_state(path: &str, fabric: Arc<NeoNetUnifiedFabric>) -> ChainState {
+        let keypair = SigningKey::from_bytes(&[7u8; 32]);
+        let (chain, finalized) = load_or_create_chain(path, &keypair, &PowConfig::default());
+        ChainState::new(chain, finalized, keypair, path.to_string(), PowConfig::default(), fabric)
+    }
+
+    fn start_test_bridge(name: &str) -> TestBridge {
+        start_limited_bridge(name, ServeLimits::default())
+    }
+
+    fn start_limited_bridge(name: &str, limits: ServeLimits) -> TestBridge {
+        let shared = Arc::new(Mutex::new(test_state(name)));
+        let client_key = HybridKeyPair::generate();
+        let identity = BridgeIdentity { keypair: HybridKeyPair::generate(), trusted: vec![client_key.public_key()] };
+        let server_key = identity.keypair.public_key();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let miner = shared.clone();
+        thread::spawn(move || run_miner(miner));
+        thread::spawn(move || serve(listener, shared, Arc::new(identity), limits));
+        TestBridge { addr, server_key, client_key }
+    }
+
+    fn send(s: &mut Client, id: u64, cmd: &str, data: serde_json::Value) {
+        s.send(json!({"id": id, "cmd": cmd, "data": data}).to_string().as_bytes()).unwrap();
+    }
+
+    fn recv(s: &mut Client) -> serde_json::Value {
+        serde_json::from_slice(&s.recv().unwrap().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_frames_round_trip_and_limits() {
+        let mut buf = Vec::new();
+        write_frame(&mut buf, b"abc").unwrap();
+        write_frame(&mut buf, b"").unwrap();
+        assert_eq!(buf[..4], [0, 0, 0, 3]);
+
+        let mut r = buf.as_slice();
+        assert_eq!(read_frame(&mut r).unwrap(), Some(b"abc".to_vec()));
+        assert_eq!(read_frame(&mut r).unwrap(), Some(vec![]));
+        assert_eq!(read_frame(&mut r).unwrap(), None);
+
+        let oversized = ((MAX_FRAME_LEN + 1) as u32).to_be_bytes();
+        assert!(matches!(read_frame(&mut oversized.as_slice()), Err(FrameError::TooLarge(_))));
+        assert!(matches!(read_frame(&mut [0u8, 0, 0, 5, 1].as_slice()), Err(FrameError::Io(_))));
+    }
+
+    #[test]
+    fn test_handshake_and_pipelined_requests_on_one_connection() {
+        let bridge = start_test_bridge("pipeline");
+
+        let mut s = bridge.connect();
+        send(&mut s, 1, "get_chain", json!(null));
+        let resp = recv(&mut s);
+        assert_eq!((resp["id"].as_u64(), resp["ok"].as_bool()), (Some(1), Some(false)));
+        assert_eq!(resp["error"]["code"], ErrorCode::HandshakeRequired as u16);
+
+        send(&mut s, 2, "hello", json!({"version": PROTOCOL_VERSION + 1}));
+        assert_eq!(recv(&mut s)["error"]["code"], ErrorCode::UnsupportedVersion as u16);
+        assert_eq!(s.recv().unwrap(), None);
+
+        let mut s = bridge.connect();
+        send(&mut s, 3, "hello", json!({"version": PROTOCOL_VERSION}));
+        assert_eq!(recv(&mut s)["result"]["version"], PROTOCOL_VERSION);
+
+        // Several requests in flight before any response is read
+        send(&mut s, 10, "submit_tx", json!({"data": "hello"}));
+        s.send(b"{not json").unwrap();
+        send(&mut s, 11, "no_such_command", json!(null));
+        send(&mut s, 12, "get_chain", json!(null));
+
+        let submitted = recv(&mut s);
+        assert_eq!((submitted["id"].as_u64(), submitted["ok"].as_bool()), (Some(10), Some(true)));
+        assert_eq!(submitted["result"]["tx_hash"], tx_hash("hello"));
+        let malformed = recv(&mut s);
+        assert_eq!((malformed["id"].as_u64(), malformed["error"]["code"].as_u64()), (None, Some(ErrorCode::ParseError as u64)));
+        let unknown = recv(&mut s);
+        assert_eq!((unknown["id"].as_u64(), unknown["error"]["code"].as_u64()), (Some(11), Some(ErrorCode::UnknownCommand as u64)));
+        let chain = recv(&mut s);
+        assert_eq!(chain["id"], 12);
+        assert!(chain["result"]["chain"].is_array());
+
+        // The miner thread picks the transaction up without another request
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(10);
+        let mined = loop {
+            send(&mut s, 13, "get_tx", json!({"hash": tx_hash("hello")}));
+            let tx = recv(&mut s)["result"]["tx"].clone();
+            if tx["pending"] == false || std::time::Instant::now() > deadline {
+                break tx;
             }
+            thread::sleep(std::time::Duration::from_millis(20));
+        };
+        assert_eq!(mined["height"], 1);
+    }
+
+    #[test]
+    fn test_oversized_frame_is_rejected_with_error() {
+        let bridge = start_test_bridge("oversized");
+        let mut s = bridge.connect();
+        s.get_mut().write_all(&((MAX_FRAME_LEN + 1) as u32).to_be_bytes()).unwrap();
+        let resp = recv(&mut s);
+        assert_eq!(resp["error"]["code"], ErrorCode::FrameTooLarge as u16);
+        assert_eq!(s.recv().unwrap(), None);
+    }
+
+    #[test]
+    fn test_untrusted_and_plaintext_clients_are_refused() {
+        let bridge = start_test_bridge("untrusted");
+        let stranger = HybridKeyPair::generate();
+        assert!(SecureChannel::connect(TcpStream::connect(bridge.addr).unwrap(), &stranger, &bridge.server_key).is_err());
+
+        // A client speaking the plaintext protocol never gets a response
+        let mut plain = TcpStream::connect(bridge.addr).unwrap();
+        write_frame(&mut plain, json!({"id": 1, "cmd": "get_chain"}).to_string().as_bytes()).unwrap();
+        assert!(!matches!(read_frame(&mut plain), Ok(Some(_))));
+
+        // Frames that don't decrypt under the session key end the connection
+        let mut s = bridge.connect();
+        write_frame(s.get_mut(), b"forged").unwrap();
+        assert!(!matches!(s.recv(), Ok(Some(_))));
+    }
+
+    #[test]
+    fn test_connection_cap_and_idle_timeout() {
+        let limits = ServeLimits { max_connections: 1, io_timeout: Duration::from_millis(200) };
+        let bridge = start_limited_bridge("limits", limits);
+        let mut idle = bridge.connect();
+
+        // The only slot is taken, so the next connection is closed without a handshake
+        let mut extra = TcpStream::connect(bridge.addr).unwrap();
+        extra.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        assert!(matches!(read_frame(&mut extra), Ok(None)));
+
+        // The idle client is dropped after the timeout, which frees its slot
+        assert!(matches!(idle.recv(), Ok(None)));
+        let mut s = (0..50).find_map(|_| {
+            thread::sleep(Duration::from_millis(50));
+            SecureChannel::connect(TcpStream::connect(bridge.addr).unwrap(), &bridge.client_key, &bridge.server_key).ok()
+        }).expect("slot is released");
+        send(&mut s, 1, "hello", json!({"version": PROTOCOL_VERSION}));
+        assert_eq!(recv(&mut s)["ok"], true);
+    }
+
+    fn call(state: &Mutex<ChainState>, cmd: &str, data: serde_json::Value) -> Result<serde_json::Value, BridgeError> {
+        handle_request(Request { id: 1, cmd: cmd.to_string(), data: Some(data) }, state)
+    }
+
+    fn put_chain(state: &Mutex<ChainState>, chain: &[Block]) -> Result<serde_json::Value, BridgeError> {
+        call(state, "put_chain", json!(chain))
+    }
+
+    fn extend_at(chain: &[Block], data: &str, timestamp: DateTime<Utc>, keypair: &SigningKey, pow: &PowConfig) -> Vec<Block> {
+        let mut chain = chain.to_vec();
+        chain.push(next_block(&chain, data.to_string(), timestamp.to_rfc3339(), keypair, pow, || false).unwrap());
+        chain
+    }
+
+    fn extend(chain: &[Block], data: &str, keypair: &SigningKey) -> Vec<Block> {
+        let mut chain = chain.to_vec();
+        let timestamp = next_timestamp(&chain);
+        chain.push(next_block(&chain, data.to_string(), timestamp, keypair, &PowConfig::default(), || false).unwrap());
+        chain
+    }
+
+    /// Mine every queued transaction on the calling thread
+    fn mine_queued(state: &Mutex<ChainState>) {
+        while let Some(result) = mine_next(state) {
+            result.unwrap();
+        }
+    }
+
+    fn rejection(result: Result<serde_json::Value, BridgeError>) -> String {
+        let err = result.unwrap_err();
+        assert_eq!(err.code, ErrorCode::InvalidChain);
+        err.message
+    }
+
+    #[test]
+    fn test_put_chain_replaces_with_longer_valid_fork() {
+        let state = Mutex::new(test_state("put_fork"));
+        let other = SigningKey::from_bytes(&[8u8; 32]);
+        let keypair = state.lock().unwrap().keypair.clone();
+        let ours = extend(&state.lock().unwrap().chain, "ours", &keypair);
+        state.lock().unwrap().chain = ours.clone();
+
+        let fork = extend(&extend(&ours[..1], "theirs 1", &other), "theirs 2", &other);
+        assert_eq!(put_chain(&state, &fork).unwrap(), json!({"replaced": true, "common_ancestor": 0}));
+        assert_eq!(state.lock().unwrap().chain, fork);
+
+        assert_eq!(put_chain(&state, &ours).unwrap(), json!({"replaced": false, "common_ancestor": 0}));
+        assert_eq!(put_chain(&state, &fork[..2]).unwrap()["common_ancestor"], 1);
+    }
+
+    #[test]
+    fn test_put_chain_rejects_invalid_chains_with_reason() {
+        let state = Mutex::new(test_state("put_invalid"));
+        let (original, keypair) = {
+            let state = state.lock().unwrap();
+            (state.chain.clone(), state.keypair.clone())
+        };
+        let valid = extend(&extend(&original, "a", &keypair), "b", &keypair);
+
+        assert!(rejection(put_chain(&state, &[])).contains("empty"));
+
+        let mut tampered = valid.clone();
+        tampered[1].data = "forged".to_string();
+        assert_eq!(rejection(put_chain(&state, &tampered)), ChainRejection::HashMismatch { index: 1 }.to_string());
+
+        let mut unlinked = valid.clone();
+        unlinked[2].prev_hash = unlinked[0].hash.clone();
+        assert_eq!(rejection(put_chain(&state, &unlinked)), ChainRejection::BrokenLink { index: 2 }.to_string());
+
+        let mut forged = valid.clone();
+        let other = SigningKey::from_bytes(&[9u8; 32]);
+        forged[2].signature = hex::encode(other.sign(forged[2].hash.as_bytes()).to_bytes());
+        assert_eq!(rejection(put_chain(&state, &forged)), ChainRejection::InvalidSignature { index: 2 }.to_string());
+
+        let resign = |b: &mut Block| {
+            b.hash = calculate_hash(b);
+            b.signature = hex::encode(keypair.sign(b.hash.as_bytes()).to_bytes());
+        };
+        let mut lazy = valid.clone();
+        while pow::meets_target(&hash_bytes(&lazy[2]), lazy[2].bits) {
+            lazy[2].nonce += 1;
+        }
+        resign(&mut lazy[2]);
+        assert_eq!(rejection(put_chain(&state, &lazy)), ChainRejection::InsufficientWork { index: 2 }.to_string());
+
+        let mut easier = valid.clone();
+        easier[2].bits = 0x2100_ffff;
+        resign(&mut easier[2]);
+        let expected = ChainRejection::WrongDifficulty { index: 2, expected: PowConfig::default().initial_bits, got: 0x2100_ffff };
+        assert_eq!(rejection(put_chain(&state, &easier)), expected.to_string());
+
+        let mut inflated = valid.clone();
+        inflated[2].chain_work = format!("{:x}", U256::from(1_000_000));
+        assert_eq!(rejection(put_chain(&state, &inflated)), ChainRejection::WrongChainWork { index: 2 }.to_string());
+
+        // Timestamps must pass the median of the recent blocks and stay near our clock
+        let pow = PowConfig::default();
+        let genesis_time = DateTime::parse_from_rfc3339(&original[0].timestamp).unwrap().with_timezone(&Utc);
+        let stalled = extend_at(&valid[..2], "stalled", genesis_time, &keypair, &pow);
+        let expected = ChainRejection::TimestampTooEarly { index: 2, median_time_past: timestamp_ms(&valid[1]).unwrap() };
+        assert_eq!(rejection(put_chain(&state, &stalled)), expected.to_string());
+        let ahead = extend_at(&valid[..2], "ahead", Utc::now() + chrono::Duration::hours(3), &keypair, &pow);
+        assert!(rejection(put_chain(&state, &ahead)).starts_with("block 2 timestamp is after"));
+        let now = timestamp_ms(&ahead[2]).unwrap() - 3 * 60 * 60 * 1000;
+        assert_eq!(verify_chain(&ahead, &original, &pow, now + 60 * 60 * 1000), Ok(0));
+
+        let foreign = test_state("put_foreign").chain;
+        let foreign = extend(&extend(&foreign, "x", &keypair), "y", &keypair);
+        assert!(rejection(put_chain(&state, &foreign)).contains("does not match"));
+
+        assert_eq!(state.lock().unwrap().chain, original);
+    }
+
+    #[test]
+    fn test_retargeting_and_fork_choice_by_total_work() {
+        let pow = PowConfig { initial_bits: 0x200f_ffff, target_block_secs: 3600, retarget_interval: 2, max_future_drift_secs: 24 * 60 * 60 };
+        let mut state = test_state("retarget");
+        state.pow = pow;
+        let genesis = state.chain[..1].to_vec();
+        let start = DateTime::parse_from_rfc3339(&genesis[0].timestamp).unwrap().with_timezone(&Utc);
+
+        // Blocks far faster than the one hour target: difficulty rises 4x at height 4
+        let mut fast = genesis.clone();
+        for i in 1..5 {
+            fast = extend_at(&fast, &format!("fast {}", i), start + chrono::Duration::seconds(i), &state.keypair, &pow);
+        }
+        assert_eq!(fast[3].bits, pow.initial_bits);
+        assert_eq!(pow::work(fast[4].bits), pow::work(pow.initial_bits) * 4);
+        assert_eq!(verify_chain(&fast, &genesis, &pow, Utc::now().timestamp_millis()), Ok(0));
+
+        // Blocks slower than the target stay at the easiest difficulty
+        let mut slow = genesis.clone();
+        for i in 1..6 {
+            slow = extend_at(&slow, &format!("slow {}", i), start + chrono::Duration::hours(2 * i), &state.keypair, &pow);
+        }
+        assert!(slow.iter().all(|b| b.bits == pow.initial_bits));
+        assert!(chain_work(slow.last()) < chain_work(fast.last()));
+
+        state.chain = slow.clone();
+        let state = Mutex::new(state);
+        // The shorter chain carries more work and wins
+        assert_eq!(put_chain(&state, &fast).unwrap()["replaced"], true);
+        assert_eq!(put_chain(&state, &slow).unwrap()["replaced"], false);
+        assert_eq!(state.lock().unwrap().chain, fast);
+    }
+
+    #[test]
+    fn test_miner_stops_when_cancelled() {
+        let stop = Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let flag = stop.clone();
+        let mut block = test_state("cancel").chain.remove(0);
+        // About 2^32 hashes: never found before the cancellation
+        block.bits = 0x1d00_ffff;
+        let miner = thread::spawn(move || mine_block(block, || flag.load(Ordering::SeqCst)));
+        thread::sleep(std::time::Duration::from_millis(50));
+        stop.store(true, Ordering::SeqCst);
+        assert!(miner.join().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_mining_on_head_extends_chain_and_bumps_epoch() {
+        let state = Arc::new(Mutex::new(test_state("restart")));
+        let epoch = state.lock().unwrap().head_epoch.load(Ordering::SeqCst);
+        let block = mine_on_head(&state, "tx".to_string()).unwrap();
+        let state = state.lock().unwrap();
+        assert_eq!(state.chain.last(), Some(&block));
+        assert_eq!(state.head_epoch.load(Ordering::SeqCst), epoch + 1);
+        assert_eq!(verify_chain(&state.chain, &state.chain, &state.pow, Utc::now().timestamp_millis()), Ok(1));
+    }
+
+    #[test]
+    fn test_query_commands_page_and_look_up_blocks() {
+        let state = Mutex::new(test_state("queries"));
+        let tx_hashes: Vec<String> = (0..5)
+            .map(|i| call(&state, "submit_tx", json!({"data": format!("tx {}", i)})).unwrap()["tx_hash"].as_str().unwrap().to_string())
+            .collect();
+        // Queued transactions are known but not yet in a block
+        let pending = call(&state, "get_tx", json!({"hash": tx_hashes[4]})).unwrap()["tx"].clone();
+        assert_eq!((pending["pending"].as_bool(), pending["height"].is_null()), (Some(true), true));
+        assert_eq!(state.lock().unwrap().chain.len(), 1);
+        mine_queued(&state);
+        let chain = state.lock().unwrap().chain.clone();
+
+        let page = call(&state, "get_blocks", json!({"from": 1, "limit": 2})).unwrap();
+        assert_eq!(page["blocks"], json!(chain[1..3]));
+        assert_eq!((page["next"].as_u64(), page["height"].as_u64()), (Some(3), Some(5)));
+        let last = call(&state, "get_blocks", json!({"from": 4})).unwrap();
+        assert_eq!((last["blocks"].as_array().unwrap().len(), last["next"].is_null()), (2, true));
+        assert_eq!(call(&state, "get_blocks", json!({"from": 99})).unwrap()["blocks"], json!([]));
+        assert_eq!(call(&state, "get_blocks", json!({"limit": -1})).unwrap_err().code, ErrorCode::InvalidParams);
+
+        assert_eq!(call(&state, "get_block_by_hash", json!({"hash": chain[2].hash})).unwrap()["block"], json!(chain[2]));
+        assert_eq!(call(&state, "get_block_by_hash", json!({"hash": "00"})).unwrap_err().code, ErrorCode::NotFound);
+
+        let tx = call(&state, "get_tx", json!({"hash": tx_hashes[3]})).unwrap()["tx"].clone();
+        assert_eq!((tx["data"].as_str(), tx["height"].as_u64()), (Some("tx 3"), Some(4)));
+        assert_eq!(tx["block_hash"], chain[4].hash);
+        assert_eq!(call(&state, "get_tx", json!({"hash": tx_hash("genesis")})).unwrap_err().code, ErrorCode::NotFound);
+        assert_eq!(call(&state, "get_tx", json!({})).unwrap_err().code, ErrorCode::InvalidParams);
+
+        assert_eq!(call(&state, "get_head", json!(null)).unwrap()["block"], json!(chain[5]));
+        let status = call(&state, "node_status", json!(null)).unwrap();
+        assert_eq!((status["height"].as_u64(), status["role"].as_str()), (Some(5), Some(NODE_ROLE)));
+        assert_eq!(status["head"], chain[5].hash);
+        assert_eq!(status["protocol_version"], PROTOCOL_VERSION);
+        assert_eq!(status["key_fingerprint"].as_str().unwrap().len(), 16);
+
+        state.lock().unwrap().queue = vec!["filler".to_string(); MAX_QUEUED_TXS].into();
+        assert_eq!(call(&state, "submit_tx", json!({"data": "one too many"})).unwrap_err().code, ErrorCode::QueueFull);
+    }
+
+    #[test]
+    fn test_commit_block_finalizes_and_refuses_reorgs_below_it() {
+        let fabric = Arc::new(NeoNetUnifiedFabric::new(TEST_CHAIN_ID));
+        let mut state = test_state("commit");
+        state.fabric = fabric.clone();
+        let state = Mutex::new(state);
+        for i in 0..3 {
+            call(&state, "submit_tx", json!({"data": format!("tx {}", i)})).unwrap();
+        }
+        mine_queued(&state);
+        let (chain, keypair) = {
+            let state = state.lock().unwrap();
+            (state.chain.clone(), state.keypair.clone())
+        };
+
+        assert_eq!(call(&state, "commit_block", json!({"hash": "ff"})).unwrap_err().code, ErrorCode::NotFound);
+        let conflicting = call(&state, "commit_block", json!({"hash": chain[1].hash, "height": 2}));
+        assert_eq!(conflicting.unwrap_err().code, ErrorCode::Conflict);
+        // A height past our head doesn't match either
+        let ahead = call(&state, "commit_block", json!({"hash": chain[2].hash, "height": 7}));
+        assert_eq!(ahead.unwrap_err().code, ErrorCode::Conflict);
+        assert_eq!(state.lock().unwrap().finalized, None);
+
+        let committed = call(&state, "commit_block", json!({"hash": chain[2].hash, "height": 2})).unwrap();
+        assert_eq!((committed["height"].as_u64(), committed["finalized_height"].as_u64()), (Some(2), Some(2)));
+        // The state committed is the runtime's the node was started with
+        let root = fabric.state_engine.last_commit();
+        assert_eq!((root.height, hex::encode(root.hash)), (2, committed["state_root"].as_str().unwrap().to_string()));
+        assert_eq!(committed["evm_state_root"].as_str().unwrap(), hex::encode(root.evm_state_root));
+
+        // Ancestors are final already; committing one changes nothing
+        let again = call(&state, "commit_block", json!({"hash": chain[1].hash})).unwrap();
+        assert_eq!(again["finalized_height"], 2);
+        assert!(again.get("state_root").is_none());
+
+        // A heavier fork from below the finalized block is refused, one above it is not
+        let other = SigningKey::from_bytes(&[8u8; 32]);
+        let mut deep = chain[..2].to_vec();
+        for i in 0..4 {
+            deep = extend(&deep, &format!("deep {}", i), &other);
         }
+        let expected = ChainRejection::ConflictsWithFinalized { finalized: 2, common_ancestor: 1 };
+        assert_eq!(rejection(put_chain(&state, &deep)), expected.to_string());
+        let shallow = extend(&extend(&chain[..3], "a", &keypair), "b", &keypair);
+        assert_eq!(put_chain(&state, &shallow).unwrap()["replaced"], true);
+
+        // Finality is stored with the chain, not beside it
+        let path = state.lock().unwrap().path.clone();
+        assert!(!Path::new(&format!("{}.finalized", path)).exists());
+        assert_eq!(reopen_state(&path, fabric).finalized, Some(2));
+    }
+
+    #[test]
+    fn test_commit_reports_storage_errors_and_reads_old_chain_files() {
+        let mut state = test_state("storage");
+        let path = state.path.clone();
+        let blocks = state.chain.clone();
+        state.path = std::env::temp_dir().join("neonet_missing_dir").join("chain.json").to_str().unwrap().to_string();
+        let state = Mutex::new(state);
+        let err = call(&state, "commit_block", json!({"hash": blocks[0].hash})).unwrap_err();
+        assert_eq!(err.code, ErrorCode::StorageError);
+        assert_eq!(state.lock().unwrap().finalized, None);
+
+        // A bare array of blocks, as written before finality was stored, still loads
+        fs::write(&path, serde_json::to_string(&blocks).unwrap()).unwrap();
+        let reopened = reopen_state(&path, Arc::new(NeoNetUnifiedFabric::new(TEST_CHAIN_ID)));
+        assert_eq!((reopened.chain, reopened.finalized), (blocks, None));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_node_key_file_is_private() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = std::env::temp_dir().join(format!("neonet_node_key_test_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let path = dir.join("node_priv.hex");
+        let path = path.to_str().unwrap();
+        let key = load_or_create_node_key(path).unwrap();
+        let mode = fs::metadata(path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        assert_eq!(load_or_create_node_key(path).unwrap().to_bytes(), key.to_bytes());
+
+        fs::set_permissions(path, fs::Permissions::from_mode(0o644)).unwrap();
+        let err = load_or_create_node_key(path).expect_err("a world-readable key loaded");
+        assert!(err.to_string().contains("mode 644"), "{}", err);
+
+        fs::set_permissions(path, fs::Permissions::from_mode(0o600)).unwrap();
+        fs::write(path, hex::encode([7u8; 16])).unwrap();
+        let err = load_or_create_node_key(path).expect_err("a short key loaded");
+        assert!(err.to_string().contains("32-byte"), "{}", err);
+        fs::remove_dir_all(&dir).unwrap();
     }
-    None
 }
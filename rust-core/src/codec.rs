@@ -0,0 +1,344 @@
+// Codec for NeoNet - canonical binary encoding for hashing, signing, storage and networking
+//
+// Every value has exactly one encoding:
+// - integers are fixed width, big-endian; bools are one byte, 0 or 1
+// - strings, byte strings and sequences are a u32 length (bytes or items) followed by the contents
+// - options are a 0 tag, or a 1 tag followed by the value
+// - enums are a u8 variant tag followed by the variant's fields
+// - structs are their fields in declaration order, with nothing in between
+//
+// Values persisted or sent over the wire are prefixed with `CODEC_VERSION` so a future
+// format change can be detected instead of misread.
+use std::fmt;
+
+/// Version byte written by `to_versioned_bytes`
+pub const CODEC_VERSION: u8 = 1;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CodecError {
+    UnexpectedEof { needed: usize, remaining: usize },
+    TrailingBytes(usize),
+    InvalidTag { type_name: &'static str, tag: u8 },
+    InvalidUtf8,
+    UnsupportedVersion(u8),
+}
+
+impl fmt::Display for CodecError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CodecError::UnexpectedEof { needed, remaining } => write!(f, "unexpected end of input: needed {} bytes, {} left", needed, remaining),
+            CodecError::TrailingBytes(n) => write!(f, "{} trailing bytes after value", n),
+            CodecError::InvalidTag { type_name, tag } => write!(f, "invalid tag {} for {}", tag, type_name),
+            CodecError::InvalidUtf8 => write!(f, "string is not valid UTF-8"),
+            CodecError::UnsupportedVersion(v) => write!(f, "unsupported codec version {}", v),
+        }
+    }
+}
+
+impl std::error::Error for CodecError {}
+
+pub trait Encode {
+    fn encode(&self, out: &mut Vec<u8>);
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        self.encode(&mut out);
+        out
+    }
+
+    /// Encoding prefixed with `CODEC_VERSION`, for storage and the network
+    fn to_versioned_bytes(&self) -> Vec<u8> {
+        let mut out = vec![CODEC_VERSION];
+        self.encode(&mut out);
+        out
+    }
+}
+
+pub trait Decode: Sized {
+    fn decode(input: &mut Decoder<'_>) -> Result<Self, CodecError>;
+
+    /// Decode a value that must span all of `bytes`
+    fn from_bytes(bytes: &[u8]) -> Result<Self, CodecError> {
+        let mut input = Decoder::new(bytes);
+        let value = Self::decode(&mut input)?;
+        input.finish()?;
+        Ok(value)
+    }
+
+    fn from_versioned_bytes(bytes: &[u8]) -> Result<Self, CodecError> {
+        match bytes.split_first() {
+            Some((&CODEC_VERSION, rest)) => Self::from_bytes(rest),
+            Some((&version, _)) => Err(CodecError::UnsupportedVersion(version)),
+            None => Err(CodecError::UnexpectedEof { needed: 1, remaining: 0 }),
+        }
+    }
+}
+
+/// Cursor over an encoded value
+pub struct Decoder<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Decoder<'a> {
+    pub fn new(bytes: &'a [u8]) -> Self {
+        Decoder { bytes }
+    }
+
+    pub fn take(&mut self, n: usize) -> Result<&'a [u8], CodecError> {
+        if n > self.bytes.len() {
+            return Err(CodecError::UnexpectedEof { needed: n, remaining: self.bytes.len() });
+        }
+        let (head, tail) = self.bytes.split_at(n);
+        self.bytes = tail;
+        Ok(head)
+    }
+
+    pub fn tag(&mut self) -> Result<u8, CodecError> {
+        Ok(self.take(1)?[0])
+    }
+
+    /// A length prefix; each counted item takes at least one byte, so a length beyond the
+    /// remaining input is rejected before anything is allocated
+    fn len(&mut self) -> Result<usize, CodecError> {
+        let len = u32::decode(self)? as usize;
+        if len > self.bytes.len() {
+            return Err(CodecError::UnexpectedEof { needed: len, remaining: self.bytes.len() });
+        }
+        Ok(len)
+    }
+
+    pub fn finish(&self) -> Result<(), CodecError> {
+        match self.bytes.len() {
+            0 => Ok(()),
+            n => Err(CodecError::TrailingBytes(n)),
+        }
+    }
+}
+
+/// Implement `Encode` and `Decode` for a struct as the listed fields in order. Every field
+/// must be listed, or the generated `decode` won't compile.
+macro_rules! impl_codec_struct {
+    ($t:ident { $($field:ident),* $(,)? }) => {
+        impl $crate::codec::Encode for $t {
+            fn encode(&self, out: &mut Vec<u8>) {
+                $( $crate::codec::Encode::encode(&self.$field, out); )*
+            }
+        }
+
+        impl $crate::codec::Decode for $t {
+            fn decode(input: &mut $crate::codec::Decoder<'_>) -> Result<Self, $crate::codec::CodecError> {
+                Ok($t { $( $field: $crate::codec::Decode::decode(input)?, )* })
+            }
+        }
+    };
+}
+
+pub(crate) use impl_codec_struct;
+
+/// Implement `Encode` and `Decode` for a fieldless enum as a one-byte tag per variant
+macro_rules! impl_codec_enum {
+    ($t:ident { $($variant:ident = $tag:literal),* $(,)? }) => {
+        impl $crate::codec::Encode for $t {
+            fn encode(&self, out: &mut Vec<u8>) {
+                out.push(match self { $( $t::$variant => $tag, )* });
+            }
+        }
+
+        impl $crate::codec::Decode for $t {
+            fn decode(input: &mut $crate::codec::Decoder<'_>) -> Result<Self, $crate::codec::CodecError> {
+                match input.tag()? {
+                    $( $tag => Ok($t::$variant), )*
+                    tag => Err($crate::codec::CodecError::InvalidTag { type_name: stringify!($t), tag }),
+                }
+            }
+        }
+    };
+}
+
+pub(crate) use impl_codec_enum;
+
+macro_rules! impl_int {
+    ($($t:ty),*) => {$(
+        impl Encode for $t {
+            fn encode(&self, out: &mut Vec<u8>) {
+                out.extend_from_slice(&self.to_be_bytes());
+            }
+        }
+
+        impl Decode for $t {
+            fn decode(input: &mut Decoder<'_>) -> Result<Self, CodecError> {
+                let bytes = input.take(std::mem::size_of::<$t>())?;
+                Ok(<$t>::from_be_bytes(bytes.try_into().unwrap()))
+            }
+        }
+    )*};
+}
+
+impl_int!(u8, u32, u64, u128);
+
+impl Encode for bool {
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.push(*self as u8);
+    }
+}
+
+impl Decode for bool {
+    fn decode(input: &mut Decoder<'_>) -> Result<Self, CodecError> {
+        match input.tag()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            tag => Err(CodecError::InvalidTag { type_name: "bool", tag }),
+        }
+    }
+}
+
+impl Encode for str {
+    fn encode(&self, out: &mut Vec<u8>) {
+        (self.len() as u32).encode(out);
+        out.extend_from_slice(self.as_bytes());
+    }
+}
+
+impl Encode for String {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.as_str().encode(out);
+    }
+}
+
+impl Decode for String {
+    fn decode(input: &mut Decoder<'_>) -> Result<Self, CodecError> {
+        let len = input.len()?;
+        String::from_utf8(input.take(len)?.to_vec()).map_err(|_| CodecError::InvalidUtf8)
+    }
+}
+
+/// Byte vectors use the same layout as any other sequence: a count, then one byte per item
+impl<T: Encode> Encode for [T] {
+    fn encode(&self, out: &mut Vec<u8>) {
+        (self.len() as u32).encode(out);
+        for item in self {
+            item.encode(out);
+        }
+    }
+}
+
+impl<T: Encode> Encode for Vec<T> {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.as_slice().encode(out);
+    }
+}
+
+impl<T: Decode> Decode for Vec<T> {
+    fn decode(input: &mut Decoder<'_>) -> Result<Self, CodecError> {
+        let len = input.len()?;
+        (0..len).map(|_| T::decode(input)).collect()
+    }
+}
+
+/// Fixed-size arrays have no length prefix
+impl<const N: usize> Encode for [u8; N] {
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(self);
+    }
+}
+
+impl<const N: usize> Decode for [u8; N] {
+    fn decode(input: &mut Decoder<'_>) -> Result<Self, CodecError> {
+        Ok(input.take(N)?.try_into().unwrap())
+    }
+}
+
+impl<T: Encode> Encode for Option<T> {
+    fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            None => out.push(0),
+            Some(value) => {
+                out.push(1);
+                value.encode(out);
+            }
+        }
+    }
+}
+
+impl<T: Decode> Decode for Option<T> {
+    fn decode(input: &mut Decoder<'_>) -> Result<Self, CodecError> {
+        match input.tag()? {
+            0 => Ok(None),
+            1 => Ok(Some(T::decode(input)?)),
+            tag => Err(CodecError::InvalidTag { type_name: "Option", tag }),
+        }
+    }
+}
+
+impl<T: Encode + ?Sized> Encode for Box<T> {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.as_ref().encode(out);
+    }
+}
+
+impl<T: Decode> Decode for Box<T> {
+    fn decode(input: &mut Decoder<'_>) -> Result<Self, CodecError> {
+        T::decode(input).map(Box::new)
+    }
+}
+
+impl<T: Encode + ?Sized> Encode for &T {
+    fn encode(&self, out: &mut Vec<u8>) {
+        (*self).encode(out);
+    }
+}
+
+macro_rules! impl_tuple {
+    ($($name:ident),+) => {
+        impl<$($name: Encode),+> Encode for ($($name,)+) {
+            #[allow(non_snake_case)]
+            fn encode(&self, out: &mut Vec<u8>) {
+                let ($($name,)+) = self;
+                $($name.encode(out);)+
+            }
+        }
+    };
+}
+
+impl_tuple!(A, B);
+impl_tuple!(A, B, C);
+impl_tuple!(A, B, C, D);
+impl_tuple!(A, B, C, D, E);
+impl_tuple!(A, B, C, D, E, F);
+impl_tuple!(A, B, C, D, E, F, G);
+impl_tuple!(A, B, C, D, E, F, G, H);
+impl_tuple!(A, B, C, D, E, F, G, H, I);
+impl_tuple!(A, B, C, D, E, F, G, H, I, J);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_primitive_golden_vectors() {
+        assert_eq!(hex::encode(7u64.to_bytes()), "0000000000000007");
+        assert_eq!(hex::encode(1u128.to_bytes()), "00000000000000000000000000000001");
+        assert_eq!(hex::encode("neo".to_bytes()), "000000036e656f");
+        assert_eq!(hex::encode(vec![0xabu8, 0xcd].to_bytes()), "00000002abcd");
+        assert_eq!(hex::encode(Some(true).to_bytes()), "0101");
+        assert_eq!(hex::encode(None::<u8>.to_bytes()), "00");
+        assert_eq!(hex::encode(vec!["a".to_string(), "bc".to_string()].to_bytes()), "000000020000000161000000026263");
+        // Separators make adjacent fields unambiguous, unlike plain concatenation
+        assert_ne!(("ab", "c").to_bytes(), ("a", "bc").to_bytes());
+    }
+
+    #[test]
+    fn test_decode_round_trip_and_errors() {
+        let value = vec![Some("x".to_string()), None];
+        assert_eq!(Vec::<Option<String>>::from_bytes(&value.to_bytes()).unwrap(), value);
+        assert_eq!(u64::from_versioned_bytes(&9u64.to_versioned_bytes()).unwrap(), 9);
+
+        assert_eq!(u64::from_bytes(&[0; 9]), Err(CodecError::TrailingBytes(1)));
+        assert_eq!(u64::from_bytes(&[0; 3]), Err(CodecError::UnexpectedEof { needed: 8, remaining: 3 }));
+        assert_eq!(bool::from_bytes(&[2]), Err(CodecError::InvalidTag { type_name: "bool", tag: 2 }));
+        assert_eq!(String::from_bytes(&[0, 0, 0, 1, 0xff]), Err(CodecError::InvalidUtf8));
+        // A huge length prefix fails up front rather than allocating
+        assert!(matches!(Vec::<u8>::from_bytes(&[0xff, 0xff, 0xff, 0xff]), Err(CodecError::UnexpectedEof { .. })));
+        assert_eq!(u64::from_versioned_bytes(&[2, 0]), Err(CodecError::UnsupportedVersion(2)));
+    }
+}
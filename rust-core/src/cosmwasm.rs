@@ -0,0 +1,448 @@
+// CosmWasm-compatible contract interface for the NeoNet WASM VM
+// Implements the cosmwasm-std v1 message types and the VM <-> contract ABI
+// (Region-based memory passing, `allocate`/`deallocate` exports, `env` imports).
+use serde::{Deserialize, Serialize};
+use anyhow::{Result, anyhow};
+use base64::{Engine as _, engine::general_purpose::STANDARD as BASE64};
+use ed25519_dalek::{Signature, VerifyingKey, Verifier};
+use wasmi::{AsContext, AsContextMut, Caller, Extern, Func, Instance, Linker, Memory, Value};
+use wasmi::core::Trap;
+
+use crate::wasm_vm::{consume, HostState, GAS_HOST_CALL, GAS_PER_DATA_BYTE, GAS_STORAGE_READ, GAS_STORAGE_WRITE};
+
+/// Denomination of the native token when sent as contract funds
+pub const NATIVE_DENOM: &str = "uneo";
+
+/// Marker export required of every CosmWasm 1.x contract
+pub const INTERFACE_VERSION_EXPORT: &str = "interface_version_8";
+pub const REQUIRED_EXPORTS: [&str; 4] = [INTERFACE_VERSION_EXPORT, "allocate", "deallocate", "instantiate"];
+
+pub const IMPORTS: [&str; 13] = [
+    "db_read", "db_write", "db_remove",
+    "addr_validate", "addr_canonicalize", "addr_humanize",
+    "debug", "abort", "query_chain",
+    "ed25519_verify", "ed25519_batch_verify", "secp256k1_verify", "secp256k1_recover_pubkey",
+];
+
+const GAS_VERIFY: u64 = 3000;
+/// Largest entry point result the VM will copy out of guest memory
+const MAX_RESULT_LENGTH: usize = 64 * 1024 * 1024;
+const MAX_ADDRESS_LENGTH: usize = 90;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Coin {
+    pub denom: String,
+    pub amount: String,
+}
+
+impl Coin {
+    pub fn new(amount: u128, denom: &str) -> Self {
+        Coin { denom: denom.to_string(), amount: amount.to_string() }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BlockInfo {
+    pub height: u64,
+    /// Nanoseconds since the UNIX epoch, serialized as a string like `cosmwasm_std::Timestamp`
+    pub time: String,
+    pub chain_id: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TransactionInfo {
+    pub index: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ContractInfo {
+    pub address: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Env {
+    pub block: BlockInfo,
+    pub transaction: Option<TransactionInfo>,
+    pub contract: ContractInfo,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MessageInfo {
+    pub sender: String,
+    pub funds: Vec<Coin>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Attribute {
+    pub key: String,
+    pub value: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Event {
+    #[serde(rename = "type")]
+    pub ty: String,
+    #[serde(default)]
+    pub attributes: Vec<Attribute>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ReplyOn {
+    Always,
+    Error,
+    Success,
+    Never,
+}
+
+/// A message emitted by a contract for the host chain to dispatch after the call
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SubMsg {
+    pub id: u64,
+    /// `CosmosMsg` as JSON (e.g. `{"bank":{"send":{..}}}` or `{"wasm":{"execute":{..}}}`)
+    pub msg: serde_json::Value,
+    pub gas_limit: Option<u64>,
+    pub reply_on: ReplyOn,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct Response {
+    #[serde(default)]
+    pub messages: Vec<SubMsg>,
+    #[serde(default)]
+    pub attributes: Vec<Attribute>,
+    #[serde(default)]
+    pub events: Vec<Event>,
+    /// Base64-encoded binary data
+    pub data: Option<String>,
+}
+
+impl Response {
+    pub fn attribute(&self, key: &str) -> Option<&str> {
+        self.attributes.iter()
+            .find(|attr| attr.key == key)
+            .map(|attr| attr.value.as_str())
+    }
+}
+
+/// `cosmwasm_std::ContractResult`, serialized as `{"ok": ..}` or `{"error": ".."}`
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum ContractResult<T> {
+    #[serde(rename = "ok")]
+    Ok(T),
+    #[serde(rename = "error")]
+    Err(String),
+}
+
+impl<T> ContractResult<T> {
+    pub fn into_result(self) -> Result<T> {
+        match self {
+            ContractResult::Ok(value) => Ok(value),
+            ContractResult::Err(msg) => Err(anyhow!("Contract error: {}", msg)),
+        }
+    }
+}
+
+/// Decode the base64 `Binary` returned by a query
+pub fn decode_binary(data: &str) -> Result<Vec<u8>> {
+    BASE64.decode(data).map_err(|e| anyhow!("Invalid base64 binary: {}", e))
+}
+
+/// Contract exports used to move data across the VM boundary
+#[derive(Clone, Copy)]
+struct GuestExports {
+    memory: Memory,
+    allocate: Func,
+}
+
+impl GuestExports {
+    fn from_instance(ctx: impl AsContext, instance: &Instance) -> Result<Self, Trap> {
+        let memory = instance.get_memory(&ctx, "memory")
+            .ok_or_else(|| Trap::new("contract does not export memory"))?;
+        let allocate = instance.get_func(&ctx, "allocate")
+            .ok_or_else(|| Trap::new("contract does not export allocate"))?;
+        Ok(GuestExports { memory, allocate })
+    }
+
+    fn from_caller(caller: &Caller<'_, HostState>) -> Result<Self, Trap> {
+        let memory = caller.get_export("memory")
+            .and_then(Extern::into_memory)
+            .ok_or_else(|| Trap::new("contract does not export memory"))?;
+        let allocate = caller.get_export("allocate")
+            .and_then(Extern::into_func)
+            .ok_or_else(|| Trap::new("contract does not export allocate"))?;
+        Ok(GuestExports { memory, allocate })
+    }
+}
+
+/// `Region { offset: u32, capacity: u32, length: u32 }` in guest memory
+struct Region {
+    offset: u32,
+    capacity: u32,
+    length: u32,
+}
+
+fn read_u32(ctx: impl AsContext, memory: Memory, ptr: u32) -> Result<u32, Trap> {
+    let mut buf = [0u8; 4];
+    memory.read(ctx, ptr as usize, &mut buf)
+        .map_err(|_| Trap::new("region pointer out of bounds"))?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn read_region_header(ctx: impl AsContext, memory: Memory, ptr: u32) -> Result<Region, Trap> {
+    if ptr == 0 {
+        return Err(Trap::new("null region pointer"));
+    }
+    let region = Region {
+        offset: read_u32(&ctx, memory, ptr)?,
+        capacity: read_u32(&ctx, memory, ptr + 4)?,
+        length: read_u32(&ctx, memory, ptr + 8)?,
+    };
+    if region.length > region.capacity {
+        return Err(Trap::new("region length exceeds capacity"));
+    }
+    Ok(region)
+}
+
+fn read_region(ctx: impl AsContext, memory: Memory, ptr: u32, max_length: usize) -> Result<Vec<u8>, Trap> {
+    let region = read_region_header(&ctx, memory, ptr)?;
+    if region.length as usize > max_length {
+        return Err(Trap::new("region data too large"));
+    }
+    // Bounds-check against linear memory before copying, so a forged length cannot make
+    // the host allocate more than the guest actually has
+    let data = memory.data(ctx.as_context());
+    let start = region.offset as usize;
+    let end = start.checked_add(region.length as usize)
+        .filter(|&end| end <= data.len())
+        .ok_or_else(|| Trap::new("region data out of bounds"))?;
+    Ok(data[start..end].to_vec())
+}
+
+/// Write into a region the contract allocated, respecting its capacity
+fn write_region(mut ctx: impl AsContextMut, memory: Memory, ptr: u32, data: &[u8]) -> Result<(), Trap> {
+    let region = read_region_header(&ctx, memory, ptr)?;
+    if data.len() > region.capacity as usize {
+        return Err(Trap::new("region too small"));
+    }
+    memory.write(&mut ctx, region.offset as usize, data)
+        .map_err(|_| Trap::new("region data out of bounds"))?;
+    memory.write(&mut ctx, ptr as usize + 8, &(data.len() as u32).to_le_bytes())
+        .map_err(|_| Trap::new("region pointer out of bounds"))
+}
+
+/// Ask the contract to allocate a region and copy `data` into it
+fn allocate_region(mut ctx: impl AsContextMut, exports: GuestExports, data: &[u8]) -> Result<u32, Trap> {
+    let mut result = [Value::I32(0)];
+    exports.allocate.call(&mut ctx, &[Value::I32(data.len() as i32)], &mut result)
+        .map_err(|e| Trap::new(format!("allocate failed: {}", e)))?;
+    let ptr = match result[0] {
+        Value::I32(ptr) => ptr as u32,
+        _ => return Err(Trap::new("allocate returned a non-i32 value")),
+    };
+    write_region(&mut ctx, exports.memory, ptr, data)?;
+    Ok(ptr)
+}
+
+/// Call a CosmWasm entry point (`instantiate`, `execute`, `query`, `migrate`)
+/// with the given JSON arguments and return the raw JSON result.
+pub(crate) fn call_entry_point(
+    store: &mut impl AsContextMut<UserState = HostState>,
+    instance: &Instance,
+    entry_point: &str,
+    args: &[Vec<u8>],
+) -> Result<Vec<u8>, wasmi::Error> {
+    let exports = GuestExports::from_instance(&*store, instance)?;
+    let func = instance.get_func(&*store, entry_point)
+        .ok_or_else(|| Trap::new(format!("contract does not export {}", entry_point)))?;
+
+    let mut params = Vec::with_capacity(args.len());
+    for arg in args {
+        params.push(Value::I32(allocate_region(&mut *store, exports, arg)? as i32));
+    }
+    let mut result = [Value::I32(0)];
+    func.call(&mut *store, &params, &mut result)?;
+
+    let ptr = match result[0] {
+        Value::I32(ptr) => ptr as u32,
+        _ => return Err(Trap::new("entry point returned a non-i32 value").into()),
+    };
+    Ok(read_region(&*store, exports.memory, ptr, MAX_RESULT_LENGTH)?)
+}
+
+fn validate_address(address: &str) -> Result<(), String> {
+    if address.is_empty() || address.len() > MAX_ADDRESS_LENGTH {
+        return Err(format!("Invalid address length: {}", address.len()));
+    }
+    if !address.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit()) {
+        return Err(format!("Address must be lowercase alphanumeric: {}", address));
+    }
+    Ok(())
+}
+
+/// Return 0 on success or a freshly allocated region holding the error message
+fn error_region(caller: &mut Caller<'_, HostState>, result: Result<(), String>) -> Result<u32, Trap> {
+    match result {
+        Ok(()) => Ok(0),
+        Err(msg) => {
+            let exports = GuestExports::from_caller(caller)?;
+            allocate_region(caller, exports, msg.as_bytes())
+        }
+    }
+}
+
+fn read_arg(caller: &Caller<'_, HostState>, ptr: u32, max_length: usize) -> Result<Vec<u8>, Trap> {
+    let exports = GuestExports::from_caller(caller)?;
+    read_region(caller, exports.memory, ptr, max_length)
+}
+
+fn storage_key(key: &[u8]) -> String {
+    hex::encode(key)
+}
+
+/// Register the CosmWasm `env` imports. Raw storage keys and values are kept
+/// hex-encoded in `WasmContract::storage`.
+pub(crate) fn register_imports(linker: &mut Linker<HostState>) -> Result<()> {
+    linker.func_wrap("env", "db_read",
+        |mut caller: Caller<'_, HostState>, key_ptr: u32| -> Result<u32, Trap> {
+            let key = read_arg(&caller, key_ptr, 64 * 1024)?;
+            consume(&mut caller, GAS_STORAGE_READ + GAS_PER_DATA_BYTE * key.len() as u64)?;
+            let value = match caller.data().storage.get(&storage_key(&key)) {
+                Some(value) => hex::decode(value).map_err(|_| Trap::new("corrupt storage value"))?,
+                None => return Ok(0),
+            };
+            consume(&mut caller, GAS_PER_DATA_BYTE * value.len() as u64)?;
+            let exports = GuestExports::from_caller(&caller)?;
+            allocate_region(&mut caller, exports, &value)
+        })
+        .map_err(|e| anyhow!("{}", e))?;
+
+    linker.func_wrap("env", "db_write",
+        |mut caller: Caller<'_, HostState>, key_ptr: u32, value_ptr: u32| -> Result<(), Trap> {
+            if caller.data().read_only {
+                return Err(Trap::new("db_write is not allowed in a read-only call"));
+            }
+            let key = read_arg(&caller, key_ptr, 64 * 1024)?;
+            let value = read_arg(&caller, value_ptr, 128 * 1024)?;
+            consume(&mut caller, GAS_STORAGE_WRITE + GAS_PER_DATA_BYTE * (key.len() + value.len()) as u64)?;
+            caller.data_mut().storage.insert(storage_key(&key), hex::encode(value));
+            Ok(())
+        })
+        .map_err(|e| anyhow!("{}", e))?;
+
+    linker.func_wrap("env", "db_remove",
+        |mut caller: Caller<'_, HostState>, key_ptr: u32| -> Result<(), Trap> {
+            if caller.data().read_only {
+                return Err(Trap::new("db_remove is not allowed in a read-only call"));
+            }
+            let key = read_arg(&caller, key_ptr, 64 * 1024)?;
+            consume(&mut caller, GAS_STORAGE_WRITE)?;
+            caller.data_mut().storage.remove(&storage_key(&key));
+            Ok(())
+        })
+        .map_err(|e| anyhow!("{}", e))?;
+
+    linker.func_wrap("env", "addr_validate",
+        |mut caller: Caller<'_, HostState>, source_ptr: u32| -> Result<u32, Trap> {
+            consume(&mut caller, GAS_HOST_CALL)?;
+            let source = read_arg(&caller, source_ptr, MAX_ADDRESS_LENGTH + 1)?;
+            let result = String::from_utf8(source)
+                .map_err(|_| "Address is not valid UTF-8".to_string())
+                .and_then(|address| validate_address(&address));
+            error_region(&mut caller, result)
+        })
+        .map_err(|e| anyhow!("{}", e))?;
+
+    // NeoNet addresses are their own canonical form: canonicalize/humanize copy the bytes
+    linker.func_wrap("env", "addr_canonicalize",
+        |mut caller: Caller<'_, HostState>, source_ptr: u32, dest_ptr: u32| -> Result<u32, Trap> {
+            consume(&mut caller, GAS_HOST_CALL)?;
+            let source = read_arg(&caller, source_ptr, MAX_ADDRESS_LENGTH + 1)?;
+            let result = String::from_utf8(source.clone())
+                .map_err(|_| "Address is not valid UTF-8".to_string())
+                .and_then(|address| validate_address(&address));
+            if result.is_ok() {
+                let exports = GuestExports::from_caller(&caller)?;
+                write_region(&mut caller, exports.memory, dest_ptr, &source)?;
+            }
+            error_region(&mut caller, result)
+        })
+        .map_err(|e| anyhow!("{}", e))?;
+
+    linker.func_wrap("env", "addr_humanize",
+        |mut caller: Caller<'_, HostState>, source_ptr: u32, dest_ptr: u32| -> Result<u32, Trap> {
+            consume(&mut caller, GAS_HOST_CALL)?;
+            let source = read_arg(&caller, source_ptr, MAX_ADDRESS_LENGTH + 1)?;
+            let exports = GuestExports::from_caller(&caller)?;
+            write_region(&mut caller, exports.memory, dest_ptr, &source)?;
+            Ok(0)
+        })
+        .map_err(|e| anyhow!("{}", e))?;
+
+    linker.func_wrap("env", "debug",
+        |mut caller: Caller<'_, HostState>, source_ptr: u32| -> Result<(), Trap> {
+            consume(&mut caller, GAS_HOST_CALL)?;
+            let message = read_arg(&caller, source_ptr, 64 * 1024)?;
+            caller.data_mut().debug_log.push(String::from_utf8_lossy(&message).into_owned());
+            Ok(())
+        })
+        .map_err(|e| anyhow!("{}", e))?;
+
+    linker.func_wrap("env", "abort",
+        |caller: Caller<'_, HostState>, source_ptr: u32| -> Result<(), Trap> {
+            let message = read_arg(&caller, source_ptr, 64 * 1024)?;
+            Err(Trap::new(format!("contract aborted: {}", String::from_utf8_lossy(&message))))
+        })
+        .map_err(|e| anyhow!("{}", e))?;
+
+    // Cross-contract and bank queries are not routed through the VM yet
+    linker.func_wrap("env", "query_chain",
+        |mut caller: Caller<'_, HostState>, _request_ptr: u32| -> Result<u32, Trap> {
+            consume(&mut caller, GAS_HOST_CALL)?;
+            let response = br#"{"error":{"unsupported_request":{"kind":"query_chain"}}}"#;
+            let exports = GuestExports::from_caller(&caller)?;
+            allocate_region(&mut caller, exports, response)
+        })
+        .map_err(|e| anyhow!("{}", e))?;
+
+    linker.func_wrap("env", "ed25519_verify",
+        |mut caller: Caller<'_, HostState>, msg_ptr: u32, sig_ptr: u32, pubkey_ptr: u32| -> Result<u32, Trap> {
+            consume(&mut caller, GAS_VERIFY)?;
+            let message = read_arg(&caller, msg_ptr, 128 * 1024)?;
+            let signature = read_arg(&caller, sig_ptr, 64)?;
+            let public_key = read_arg(&caller, pubkey_ptr, 32)?;
+
+            let (Ok(sig_bytes), Ok(key_bytes)) = (
+                <[u8; 64]>::try_from(signature.as_slice()),
+                <[u8; 32]>::try_from(public_key.as_slice()),
+            ) else {
+                return Ok(1);
+            };
+            let Ok(key) = VerifyingKey::from_bytes(&key_bytes) else {
+                return Ok(1);
+            };
+            let valid = key.verify(&message, &Signature::from_bytes(&sig_bytes)).is_ok();
+            Ok(if valid { 0 } else { 1 })
+        })
+        .map_err(|e| anyhow!("{}", e))?;
+
+    linker.func_wrap("env", "ed25519_batch_verify",
+        |_caller: Caller<'_, HostState>, _msgs_ptr: u32, _sigs_ptr: u32, _pubkeys_ptr: u32| -> Result<u32, Trap> {
+            Err(Trap::new("ed25519_batch_verify is not supported"))
+        })
+        .map_err(|e| anyhow!("{}", e))?;
+
+    linker.func_wrap("env", "secp256k1_verify",
+        |_caller: Caller<'_, HostState>, _hash_ptr: u32, _sig_ptr: u32, _pubkey_ptr: u32| -> Result<u32, Trap> {
+            Err(Trap::new("secp256k1_verify is not supported"))
+        })
+        .map_err(|e| anyhow!("{}", e))?;
+
+    linker.func_wrap("env", "secp256k1_recover_pubkey",
+        |_caller: Caller<'_, HostState>, _hash_ptr: u32, _sig_ptr: u32, _param: u32| -> Result<u64, Trap> {
+            Err(Trap::new("secp256k1_recover_pubkey is not supported"))
+        })
+        .map_err(|e| anyhow!("{}", e))?;
+
+    Ok(())
+}
@@ -0,0 +1,837 @@
+// Ethereum JSON-RPC for NeoNet - eth_* endpoints for wallets and tooling over the EVM adapter
+//
+// Every accepted transaction is sealed in an EVM block of its own straight away, so its
+// receipt exists as soon as eth_sendRawTransaction returns. Receipts, their logs and the
+// EVM state after the latest block are kept in the block store. State queries accept any
+// block tag but always answer from the latest state.
+use std::fmt;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sha3::{Digest, Keccak256};
+use warp::{Filter, Reply};
+
+use crate::codec::{impl_codec_struct, Encode};
+use crate::eth_tx::{self, SignedEthTx};
+use crate::evm_adapter::{EVMAdapter, BLOCK_GAS_LIMIT};
+use crate::evm_interpreter::{self, EVMLog, ExecutionResult, ExitReason};
+use crate::store::BlockStore;
+use crate::TX_BASE_GAS;
+
+/// Widest block range a single eth_getLogs query may scan
+pub const MAX_LOG_RANGE: u64 = 10_000;
+pub const MAX_BODY_LEN: u64 = 1024 * 1024;
+pub const DEFAULT_RPC_ADDR: &str = "127.0.0.1:8545";
+/// Sender of eth_call and eth_estimateGas requests that leave `from` out
+const ZERO_ADDRESS: &str = "0x0000000000000000000000000000000000000000";
+
+/// JSON-RPC 2.0 and EIP-1474 error codes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RpcErrorCode {
+    ParseError = -32700,
+    InvalidRequest = -32600,
+    MethodNotFound = -32601,
+    InvalidParams = -32602,
+    Internal = -32603,
+    /// Execution failed for a reason other than REVERT
+    ExecutionFailed = -32000,
+    /// Transaction refused before execution: bad nonce, insufficient funds, too little gas
+    TransactionRejected = -32003,
+    LimitExceeded = -32005,
+    /// eth_call or eth_estimateGas hit REVERT; the revert data is attached
+    ExecutionReverted = 3,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RpcError {
+    pub code: RpcErrorCode,
+    pub message: String,
+    pub data: Option<Value>,
+}
+
+impl RpcError {
+    fn new(code: RpcErrorCode, message: impl Into<String>) -> Self {
+        RpcError { code, message: message.into(), data: None }
+    }
+
+    fn invalid_params(message: impl Into<String>) -> Self {
+        Self::new(RpcErrorCode::InvalidParams, message)
+    }
+
+    fn rejected(message: impl fmt::Display) -> Self {
+        Self::new(RpcErrorCode::TransactionRejected, message.to_string())
+    }
+
+    fn internal(e: impl fmt::Display) -> Self {
+        Self::new(RpcErrorCode::Internal, e.to_string())
+    }
+
+    fn to_json(&self) -> Value {
+        let mut error = json!({"code": self.code as i64, "message": self.message});
+        if let Some(data) = &self.data {
+            error["data"] = data.clone();
+        }
+        error
+    }
+}
+
+impl fmt::Display for RpcError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?} ({}): {}", self.code, self.code as i64, self.message)
+    }
+}
+
+impl std::error::Error for RpcError {}
+
+/// A transaction whose sender is already established, ready to run against the EVM
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EthTransaction {
+    pub from: String,
+    /// `None` deploys `data` as init code
+    pub to: Option<String>,
+    pub value: u128,
+    pub data: Vec<u8>,
+    pub gas_limit: u64,
+    /// Highest price per gas the sender accepts: the gas price, or an EIP-1559 max fee
+    pub gas_price: u64,
+    pub nonce: u64,
+}
+
+impl From<&SignedEthTx> for EthTransaction {
+    fn from(tx: &SignedEthTx) -> Self {
+        EthTransaction {
+            from: hex_data(&tx.from),
+            to: tx.to.map(|to| hex_data(&to)),
+            value: tx.value,
+            data: tx.data.clone(),
+            gas_limit: tx.gas_limit,
+            gas_price: tx.gas_price,
+            nonce: tx.nonce,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct EthReceipt {
+    pub tx_hash: [u8; 32],
+    pub block_number: u64,
+    pub from: String,
+    pub to: Option<String>,
+    pub contract_address: Option<String>,
+    /// Including `TX_BASE_GAS`
+    pub gas_used: u64,
+    pub success: bool,
+    pub logs: Vec<EVMLog>,
+}
+
+impl_codec_struct!(EthReceipt { tx_hash, block_number, from, to, contract_address, gas_used, success, logs });
+
+impl EthReceipt {
+    /// EVM blocks hold a single transaction, so the block hash commits to its number and that
+    pub fn block_hash(&self) -> [u8; 32] {
+        keccak256(&(self.block_number, self.tx_hash).to_bytes())
+    }
+}
+
+pub struct EthRpc {
+    evm: EVMAdapter,
+    store: BlockStore,
+}
+
+impl EthRpc {
+    /// Serve `evm`, or the state `store` holds if earlier EVM blocks were sealed there, numbering
+    /// new EVM blocks after the last one stored
+    pub fn new(mut evm: EVMAdapter, store: BlockStore) -> anyhow::Result<Self> {
+        if let Some(last) = store.last_evm_block()? {
+            let state = store.evm_state()?
+                .ok_or_else(|| anyhow::anyhow!("store has EVM blocks up to {} but no EVM state", last))?;
+            evm.restore_state(&state)?;
+            evm.set_block_number(evm.get_block_number().max(last));
+        }
+        Ok(EthRpc { evm, store })
+    }
+
+    /// Execute `tx` in a new EVM block and store its receipt. Transactions failing the checks
+    /// made before execution are refused without using a block; ones that revert or run out
+    /// of gas still get a failed receipt, use up their nonce and pay for the gas they used.
+    pub fn apply(&mut self, tx: &EthTransaction, hash: [u8; 32]) -> Result<EthReceipt, RpcError> {
+        if self.store.get_receipt(&hash).map_err(RpcError::internal)?.is_some() {
+            return Err(RpcError::rejected("already known"));
+        }
+        let nonce = self.evm.get_nonce(&tx.from).unwrap_or(0);
+        if tx.nonce != nonce {
+            return Err(RpcError::rejected(format!("nonce {} does not match account nonce {}", tx.nonce, nonce)));
+        }
+        if tx.gas_limit < TX_BASE_GAS {
+            return Err(RpcError::rejected(format!("intrinsic gas too low: {} < {}", tx.gas_limit, TX_BASE_GAS)));
+        }
+        if tx.gas_limit > BLOCK_GAS_LIMIT {
+            return Err(RpcError::rejected(format!("gas limit {} exceeds block gas limit {}", tx.gas_limit, BLOCK_GAS_LIMIT)));
+        }
+        let gas_price = self.evm.gas_price();
+        if tx.gas_price < gas_price {
+            return Err(RpcError::rejected(format!("gas price {} below the minimum {}", tx.gas_price, gas_price)));
+        }
+        let cost = u128::from(tx.gas_limit).checked_mul(u128::from(gas_price))
+            .and_then(|fee| fee.checked_add(tx.value));
+        if cost.is_none_or(|cost| self.evm.get_balance(&tx.from).unwrap_or(0) < cost) {
+            return Err(RpcError::rejected("insufficient funds for gas * price + value"));
+        }
+
+        let _ = self.evm.create_account(tx.from.clone(), 0);
+        self.evm.increment_block();
+        let (contract_address, result) = run(&mut self.evm, tx)?;
+        let gas_used = TX_BASE_GAS + result.gas_used;
+        self.evm.debit(&tx.from, u128::from(gas_used) * u128::from(gas_price)).map_err(RpcError::internal)?;
+        let receipt = EthReceipt {
+            tx_hash: hash,
+            block_number: self.evm.get_block_number(),
+            from: tx.from.clone(),
+            to: tx.to.clone(),
+            contract_address,
+            gas_used,
+            success: result.is_success(),
+            logs: result.logs,
+        };
+        self.store.put_receipt(&receipt, &self.evm.encode_state()).map_err(RpcError::internal)?;
+        Ok(receipt)
+    }
+
+    /// Run `tx` against a copy of the current state, for eth_call and eth_estimateGas
+    fn simulate(&self, tx: &EthTransaction) -> Result<ExecutionResult, RpcError> {
+        let mut evm = self.evm.clone();
+        let _ = evm.create_account(tx.from.clone(), 0);
+        let (_, result) = run(&mut evm, tx)?;
+        match &result.exit_reason {
+            ExitReason::Reverted => Err(RpcError {
+                data: Some(json!(hex_data(&result.return_data))),
+                ..RpcError::new(RpcErrorCode::ExecutionReverted, "execution reverted")
+            }),
+            ExitReason::Failed(e) => Err(RpcError::new(RpcErrorCode::ExecutionFailed, format!("execution failed: {}", e))),
+            _ => Ok(result),
+        }
+    }
+
+    /// Answer one method call with positional `params`
+    pub fn call(&mut self, method: &str, params: &[Value]) -> Result<Value, RpcError> {
+        match method {
+            "eth_chainId" => Ok(quantity(self.evm.chain_id())),
+            "net_version" => Ok(json!(self.evm.chain_id().to_string())),
+            "eth_blockNumber" => Ok(quantity(self.evm.get_block_number())),
+            "eth_getBalance" => {
+                let address = parse_address(param(params, 0, "address")?, "address")?;
+                self.block_param(params.get(1))?;
+                Ok(quantity(self.evm.get_balance(&address).unwrap_or(0)))
+            },
+            "eth_getTransactionCount" => {
+                let address = parse_address(param(params, 0, "address")?, "address")?;
+                self.block_param(params.get(1))?;
+                Ok(quantity(self.evm.get_nonce(&address).unwrap_or(0)))
+            },
+            "eth_getStorageAt" => {
+                let address = parse_address(param(params, 0, "address")?, "address")?;
+                let slot = parse_slot(param(params, 1, "storage slot")?, "storage slot")?;
+                self.block_param(params.get(2))?;
+                let value = self.evm.get_storage(&address, &slot).unwrap_or_else(|| "00".repeat(32));
+                Ok(json!(format!("0x{}", value)))
+            },
+            "eth_call" => {
+                let tx = self.call_param(params)?;
+                let result = self.simulate(&tx)?;
+                Ok(json!(hex_data(&result.return_data)))
+            },
+            "eth_estimateGas" => {
+                let tx = self.call_param(params)?;
+                let result = self.simulate(&tx)?;
+                Ok(quantity(TX_BASE_GAS + result.gas_used))
+            },
+            "eth_sendRawTransaction" => {
+                let raw = parse_bytes(param(params, 0, "transaction")?, "transaction")?;
+                let signed = eth_tx::decode_signed_tx(&raw).map_err(|e| RpcError::invalid_params(e.to_string()))?;
+                signed.check_chain_id(self.evm.chain_id()).map_err(RpcError::rejected)?;
+                self.apply(&EthTransaction::from(&signed), signed.hash)?;
+                Ok(json!(hex_data(&signed.hash)))
+            },
+            "eth_getTransactionReceipt" => {
+                let hash = parse_hash(param(params, 0, "transaction hash")?, "transaction hash")?;
+                match self.store.get_receipt(&hash).map_err(RpcError::internal)? {
+                    Some(receipt) => Ok(receipt_json(&receipt, self.evm.gas_price())),
+                    None => Ok(Value::Null),
+                }
+            },
+            "eth_getLogs" => self.get_logs(param(params, 0, "filter")?),
+            _ => Err(RpcError::new(RpcErrorCode::MethodNotFound, format!("method {} not found", method))),
+        }
+    }
+
+    /// A block number or tag; `None` means latest
+    fn block_param(&self, value: Option<&Value>) -> Result<u64, RpcError> {
+        let latest = self.evm.get_block_number();
+        match value {
+            None | Some(Value::Null) => Ok(latest),
+            Some(v) => match v.as_str() {
+                Some("latest" | "pending" | "safe" | "finalized") => Ok(latest),
+                Some("earliest") => Ok(0),
+                _ => parse_u64(v, "block"),
+            },
+        }
+    }
+
+    /// The call object of eth_call and eth_estimateGas, defaulting to a zero-value call from
+    /// the zero address with the whole block gas limit
+    fn call_param(&self, params: &[Value]) -> Result<EthTransaction, RpcError> {
+        let call = param(params, 0, "call object")?;
+        if !call.is_object() {
+            return Err(RpcError::invalid_params("call object must be an object"));
+        }
+        let field = |name: &str| call.get(name).filter(|v| !v.is_null());
+        let from = field("from").map(|v| parse_address(v, "from")).transpose()?
+            .unwrap_or_else(|| ZERO_ADDRESS.to_string());
+        let to = field("to").map(|v| parse_address(v, "to")).transpose()?;
+        let value = field("value").map(|v| parse_quantity(v, "value")).transpose()?.unwrap_or(0);
+        let data = field("input").or_else(|| field("data")).map(|v| parse_bytes(v, "input")).transpose()?
+            .unwrap_or_default();
+        let gas_limit = field("gas").map(|v| parse_u64(v, "gas")).transpose()?.unwrap_or(BLOCK_GAS_LIMIT);
+        let gas_price = field("gasPrice").or_else(|| field("maxFeePerGas")).map(|v| parse_u64(v, "gasPrice")).transpose()?
+            .unwrap_or(0);
+        self.block_param(params.get(1))?;
+        let nonce = self.evm.get_nonce(&from).unwrap_or(0);
+        Ok(EthTransaction { from, to, value, data, gas_limit, gas_price, nonce })
+    }
+
+    fn get_logs(&self, filter: &Value) -> Result<Value, RpcError> {
+        if filter.get("blockHash").is_some_and(|v| !v.is_null()) {
+            return Err(RpcError::invalid_params("blockHash filters are not supported"));
+        }
+        let from = self.block_param(filter.get("fromBlock"))?;
+        let to = self.block_param(filter.get("toBlock"))?;
+        if from > to {
+            return Ok(json!([]));
+        }
+        if to - from >= MAX_LOG_RANGE {
+            return Err(RpcError::new(RpcErrorCode::LimitExceeded, format!("block range exceeds {} blocks", MAX_LOG_RANGE)));
+        }
+
+        let addresses = match filter.get("address") {
+            None | Some(Value::Null) => vec![],
+            Some(Value::Array(list)) => list.iter().map(|v| parse_address(v, "address")).collect::<Result<_, _>>()?,
+            Some(v) => vec![parse_address(v, "address")?],
+        };
+        // One entry per topic position: `None` matches anything, otherwise any of the hashes
+        let topics: Vec<Option<Vec<[u8; 32]>>> = match filter.get("topics") {
+            None | Some(Value::Null) => vec![],
+            Some(Value::Array(positions)) => positions.iter()
+                .map(|position| match position {
+                    Value::Null => Ok(None),
+                    Value::Array(any) if any.is_empty() => Ok(None),
+                    Value::Array(any) => any.iter().map(|t| parse_hash(t, "topic")).collect::<Result<_, _>>().map(Some),
+                    t => parse_hash(t, "topic").map(|h| Some(vec![h])),
+                })
+                .collect::<Result<_, _>>()?,
+            Some(_) => return Err(RpcError::invalid_params("topics must be an array")),
+        };
+
+        let receipts = self.store.receipts_in_range(from, to).map_err(RpcError::internal)?;
+        let mut logs = vec![];
+        for receipt in &receipts {
+            for (index, log) in receipt.logs.iter().enumerate() {
+                let address = canonical_address(&log.address);
+                let topics_match = topics.iter().enumerate().all(|(i, wanted)| match wanted {
+                    None => true,
+                    Some(any) => log.topics.get(i).is_some_and(|t| any.contains(t)),
+                });
+                if topics_match && (addresses.is_empty() || addresses.contains(&address)) {
+                    logs.push(log_json(receipt, index, log));
+                }
+            }
+        }
+        Ok(Value::Array(logs))
+    }
+
+    /// Answer one request object; `None` for a notification, which gets no response
+    pub fn handle_request(&mut self, req: &Value) -> Option<Value> {
+        let (id, method, params) = match parse_request(req) {
+            Ok(parts) => parts,
+            Err(e) => return Some(error_response(req.get("id").cloned().unwrap_or(Value::Null), &e)),
+        };
+        let outcome = self.call(method, params);
+        let id = id?;
+        Some(match outcome {
+            Ok(result) => json!({"jsonrpc": "2.0", "id": id, "result": result}),
+            Err(e) => error_response(id, &e),
+        })
+    }
+}
+
+/// Execute `tx` without any pre-checks, returning the deployed address for creations
+fn run(evm: &mut EVMAdapter, tx: &EthTransaction) -> Result<(Option<String>, ExecutionResult), RpcError> {
+    let gas_limit = tx.gas_limit.saturating_sub(TX_BASE_GAS);
+    match &tx.to {
+        Some(to) => {
+            let _ = evm.create_account(to.clone(), 0);
+            let result = evm.execute(&tx.from, to, tx.data.clone(), tx.value, gas_limit)
+                .map_err(RpcError::rejected)?;
+            Ok((None, result))
+        },
+        None => {
+            let (address, result) = evm.create(&tx.from, tx.data.clone(), tx.value, gas_limit)
+                .map_err(RpcError::rejected)?;
+            Ok((Some(address), result))
+        },
+    }
+}
+
+fn parse_request(req: &Value) -> Result<(Option<Value>, &str, &[Value]), RpcError> {
+    if req.get("jsonrpc").and_then(|v| v.as_str()) != Some("2.0") {
+        return Err(RpcError::new(RpcErrorCode::InvalidRequest, "jsonrpc must be \"2.0\""));
+    }
+    let method = req.get("method").and_then(|v| v.as_str())
+        .ok_or_else(|| RpcError::new(RpcErrorCode::InvalidRequest, "missing method"))?;
+    let params = match req.get("params") {
+        None | Some(Value::Null) => &[][..],
+        Some(Value::Array(params)) => params.as_slice(),
+        Some(_) => return Err(RpcError::invalid_params("params must be an array")),
+    };
+    Ok((req.get("id").cloned(), method, params))
+}
+
+fn error_response(id: Value, e: &RpcError) -> Value {
+    json!({"jsonrpc": "2.0", "id": id, "error": e.to_json()})
+}
+
+/// Answer a request body holding a single request or a batch; `None` when nothing needs a
+/// response because every request was a notification
+pub fn handle_body(rpc: &Mutex<EthRpc>, body: &[u8]) -> Option<Value> {
+    let req: Value = match serde_json::from_slice(body) {
+        Ok(req) => req,
+        Err(e) => return Some(error_response(Value::Null, &RpcError::new(RpcErrorCode::ParseError, e.to_string()))),
+    };
+    let mut rpc = rpc.lock().unwrap();
+    match req {
+        Value::Array(batch) if batch.is_empty() => {
+            Some(error_response(Value::Null, &RpcError::new(RpcErrorCode::InvalidRequest, "empty batch")))
+        },
+        Value::Array(batch) => {
+            let responses: Vec<Value> = batch.iter().filter_map(|req| rpc.handle_request(req)).collect();
+            (!responses.is_empty()).then_some(Value::Array(responses))
+        },
+        req => rpc.handle_request(&req),
+    }
+}
+
+/// POST / with a JSON-RPC body, open to browser dApps on any origin
+pub fn routes(rpc: Arc<Mutex<EthRpc>>) -> impl Filter<Extract = (impl Reply,), Error = warp::Rejection> + Clone {
+    let cors = warp::cors()
+        .allow_any_origin()
+        .allow_method("POST")
+        .allow_header("content-type");
+    warp::post()
+        .and(warp::path::end())
+        .and(warp::body::content_length_limit(MAX_BODY_LEN))
+        .and(warp::body::bytes())
+        .map(move |body: warp::hyper::body::Bytes| match handle_body(&rpc, &body) {
+            Some(response) => warp::reply::json(&response).into_response(),
+            None => warp::http::StatusCode::NO_CONTENT.into_response(),
+        })
+        .with(cors)
+}
+
+/// Serve JSON-RPC on `NEONET_ETH_RPC_ADDR` (default 127.0.0.1:8545) from a background thread
+pub fn start_eth_rpc(rpc: EthRpc) {
+    thread::spawn(move || {
+        let addr = std::env::var("NEONET_ETH_RPC_ADDR").unwrap_or_else(|_| DEFAULT_RPC_ADDR.to_string());
+        let addr: SocketAddr = match addr.parse() {
+            Ok(addr) => addr,
+            Err(e) => {
+                eprintln!("invalid eth rpc address {}: {}", addr, e);
+                return;
+            }
+        };
+        let runtime = match tokio::runtime::Runtime::new() {
+            Ok(runtime) => runtime,
+            Err(e) => {
+                eprintln!("could not start eth rpc runtime: {}", e);
+                return;
+            }
+        };
+        let routes = routes(Arc::new(Mutex::new(rpc)));
+        runtime.block_on(async move {
+            match warp::serve(routes).try_bind_ephemeral(addr) {
+                Ok((addr, server)) => {
+                    println!("eth json-rpc listening on {}", addr);
+                    server.await;
+                },
+                Err(e) => eprintln!("could not bind {}: {}", addr, e),
+            }
+        });
+    });
+}
+
+fn param<'a>(params: &'a [Value], index: usize, name: &str) -> Result<&'a Value, RpcError> {
+    params.get(index)
+        .filter(|v| !v.is_null())
+        .ok_or_else(|| RpcError::invalid_params(format!("missing {}", name)))
+}
+
+fn parse_quantity(value: &Value, name: &str) -> Result<u128, RpcError> {
+    value.as_str()
+        .and_then(|s| s.strip_prefix("0x"))
+        .filter(|digits| !digits.is_empty())
+        .and_then(|digits| u128::from_str_radix(digits, 16).ok())
+        .ok_or_else(|| RpcError::invalid_params(format!("{} must be a hex quantity", name)))
+}
+
+fn parse_u64(value: &Value, name: &str) -> Result<u64, RpcError> {
+    u64::try_from(parse_quantity(value, name)?)
+        .map_err(|_| RpcError::invalid_params(format!("{} is out of range", name)))
+}
+
+fn parse_bytes(value: &Value, name: &str) -> Result<Vec<u8>, RpcError> {
+    value.as_str()
+        .and_then(|s| s.strip_prefix("0x"))
+        .and_then(|digits| hex::decode(digits).ok())
+        .ok_or_else(|| RpcError::invalid_params(format!("{} must be 0x-prefixed hex data", name)))
+}
+
+/// A storage slot quantity of up to 32 bytes, as the adapter keys storage: 64 lowercase hex digits
+fn parse_slot(value: &Value, name: &str) -> Result<String, RpcError> {
+    value.as_str()
+        .and_then(|s| s.strip_prefix("0x"))
+        .filter(|digits| !digits.is_empty() && digits.len() <= 64 && digits.bytes().all(|b| b.is_ascii_hexdigit()))
+        .map(|digits| format!("{:0>64}", digits.to_ascii_lowercase()))
+        .ok_or_else(|| RpcError::invalid_params(format!("{} must be a hex quantity of at most 32 bytes", name)))
+}
+
+/// A 20-byte address, lowercased to match the adapter's account keys
+fn parse_address(value: &Value, name: &str) -> Result<String, RpcError> {
+    match parse_bytes(value, name) {
+        Ok(bytes) if bytes.len() == 20 => Ok(hex_data(&bytes)),
+        _ => Err(RpcError::invalid_params(format!("{} must be a 20-byte address", name))),
+    }
+}
+
+fn parse_hash(value: &Value, name: &str) -> Result<[u8; 32], RpcError> {
+    parse_bytes(value, name)?
+        .try_into()
+        .map_err(|_| RpcError::invalid_params(format!("{} must be a 32-byte hash", name)))
+}
+
+fn quantity(n: impl Into<u128>) -> Value {
+    json!(format!("0x{:x}", n.into()))
+}
+
+fn hex_data(bytes: &[u8]) -> String {
+    format!("0x{}", hex::encode(bytes))
+}
+
+fn keccak256(bytes: &[u8]) -> [u8; 32] {
+    Keccak256::digest(bytes).into()
+}
+
+/// The 20-byte form of an adapter account key
+fn canonical_address(address: &str) -> String {
+    let mut word = [0u8; 32];
+    evm_interpreter::address_to_word(address).to_big_endian(&mut word);
+    hex_data(&word[12..])
+}
+
+/// 2048-bit bloom over log addresses and topics: three bits per item, each chosen by a pair
+/// of bytes of the item's keccak hash
+fn logs_bloom(logs: &[EVMLog]) -> [u8; 256] {
+    let mut bloom = [0u8; 256];
+    let mut add = |item: &[u8]| {
+        let hash = keccak256(item);
+        for i in [0, 2, 4] {
+            let bit = (usize::from(hash[i]) << 8 | usize::from(hash[i + 1])) & 2047;
+            bloom[255 - bit / 8] |= 1 << (bit % 8);
+        }
+    };
+    for log in logs {
+        let mut word = [0u8; 32];
+        evm_interpreter::address_to_word(&log.address).to_big_endian(&mut word);
+        add(&word[12..]);
+        for topic in &log.topics {
+            add(topic);
+        }
+    }
+    bloom
+}
+
+fn log_json(receipt: &EthReceipt, index: usize, log: &EVMLog) -> Value {
+    json!({
+        "address": canonical_address(&log.address),
+        "topics": log.topics.iter().map(|t| hex_data(t)).collect::<Vec<_>>(),
+        "data": hex_data(&log.data),
+        "blockNumber": quantity(receipt.block_number),
+        "blockHash": hex_data(&receipt.block_hash()),
+        "transactionHash": hex_data(&receipt.tx_hash),
+        "transactionIndex": "0x0",
+        "logIndex": quantity(index as u64),
+        "removed": false,
+    })
+}
+
+fn receipt_json(receipt: &EthReceipt, gas_price: u64) -> Value {
+    json!({
+        "transactionHash": hex_data(&receipt.tx_hash),
+        "transactionIndex": "0x0",
+        "blockHash": hex_data(&receipt.block_hash()),
+        "blockNumber": quantity(receipt.block_number),
+        "from": receipt.from,
+        "to": receipt.to,
+        "contractAddress": receipt.contract_address,
+        "cumulativeGasUsed": quantity(receipt.gas_used),
+        "gasUsed": quantity(receipt.gas_used),
+        "effectiveGasPrice": quantity(gas_price),
+        "logs": receipt.logs.iter().enumerate().map(|(i, log)| log_json(receipt, i, log)).collect::<Vec<_>>(),
+        "logsBloom": hex_data(&logs_bloom(&receipt.logs)),
+        "status": if receipt.success { "0x1" } else { "0x0" },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::TEST_CHAIN_ID;
+
+    const ALICE: &str = "0x00000000000000000000000000000000000a11ce";
+    const EMITTER: &str = "0x00000000000000000000000000000000000000e1";
+    const REVERTER: &str = "0x00000000000000000000000000000000000000e2";
+
+    /// Runtime code: LOG1(mem[0..32] = 42, topic 7), then return the same word
+    const EMITTER_CODE: &str = "602a600052600760206000a160206000f3";
+    /// Runtime code: revert with the word 42
+    const REVERTER_CODE: &str = "602a60005260206000fd";
+    const STORER: &str = "0x00000000000000000000000000000000000000e3";
+    /// Runtime code: store 42 in slot 7
+    const STORER_CODE: &str = "602a60075500";
+
+    fn test_rpc() -> EthRpc {
+        let mut evm = EVMAdapter::new();
+        evm.set_chain_id(TEST_CHAIN_ID);
+        evm.create_account(ALICE.to_string(), 10_000_000).unwrap();
+        evm.install_contract(EMITTER, hex::decode(EMITTER_CODE).unwrap()).unwrap();
+        evm.install_contract(REVERTER, hex::decode(REVERTER_CODE).unwrap()).unwrap();
+        evm.install_contract(STORER, hex::decode(STORER_CODE).unwrap()).unwrap();
+        EthRpc::new(evm, BlockStore::temporary().unwrap()).unwrap()
+    }
+
+    fn call(rpc: &mut EthRpc, method: &str, params: Value) -> Result<Value, RpcError> {
+        rpc.call(method, params.as_array().unwrap())
+    }
+
+    fn tx(to: Option<&str>, data: &str, nonce: u64) -> EthTransaction {
+        EthTransaction {
+            from: ALICE.to_string(),
+            to: to.map(str::to_string),
+            value: 0,
+            data: hex::decode(data).unwrap(),
+            gas_limit: 100_000,
+            gas_price: 20,
+            nonce,
+        }
+    }
+
+    fn word(n: u8) -> [u8; 32] {
+        let mut word = [0u8; 32];
+        word[31] = n;
+        word
+    }
+
+    #[test]
+    fn test_account_queries() {
+        let mut rpc = test_rpc();
+        assert_eq!(call(&mut rpc, "eth_chainId", json!([])).unwrap(), json!("0x1"));
+        assert_eq!(call(&mut rpc, "net_version", json!([])).unwrap(), json!("1"));
+        assert_eq!(call(&mut rpc, "eth_blockNumber", json!([])).unwrap(), json!("0x0"));
+        // Checksummed and unknown addresses both work
+        let checksummed = ALICE.replace("a11ce", "A11CE");
+        assert_eq!(call(&mut rpc, "eth_getBalance", json!([checksummed, "latest"])).unwrap(), json!("0x989680"));
+        assert_eq!(call(&mut rpc, "eth_getBalance", json!([ZERO_ADDRESS])).unwrap(), json!("0x0"));
+        assert_eq!(call(&mut rpc, "eth_getTransactionCount", json!([ALICE, "latest"])).unwrap(), json!("0x0"));
+
+        let e = call(&mut rpc, "eth_getBalance", json!(["0xalice"])).unwrap_err();
+        assert_eq!(e.code, RpcErrorCode::InvalidParams);
+        let e = call(&mut rpc, "eth_getBalance", json!([ALICE, "soon"])).unwrap_err();
+        assert_eq!(e.code, RpcErrorCode::InvalidParams);
+        let e = call(&mut rpc, "eth_mine", json!([])).unwrap_err();
+        assert_eq!(e.code, RpcErrorCode::MethodNotFound);
+    }
+
+    #[test]
+    fn test_storage_queries() {
+        let mut rpc = test_rpc();
+        let slot = |n: u8| json!([STORER, quantity(n), "latest"]);
+        assert_eq!(call(&mut rpc, "eth_getStorageAt", slot(7)).unwrap(), json!(hex_data(&word(0))));
+        rpc.apply(&tx(Some(STORER), "", 0), [1u8; 32]).unwrap();
+        assert_eq!(call(&mut rpc, "eth_getStorageAt", slot(7)).unwrap(), json!(hex_data(&word(42))));
+        let padded = json!([STORER, format!("0x{}", "0".repeat(63) + "7")]);
+        assert_eq!(call(&mut rpc, "eth_getStorageAt", padded).unwrap(), json!(hex_data(&word(42))));
+        assert_eq!(call(&mut rpc, "eth_getStorageAt", slot(8)).unwrap(), json!(hex_data(&word(0))));
+
+        let e = call(&mut rpc, "eth_getStorageAt", json!([STORER, format!("0x1{}", "0".repeat(64))])).unwrap_err();
+        assert_eq!(e.code, RpcErrorCode::InvalidParams);
+    }
+
+    #[test]
+    fn test_call_and_estimate_leave_state_alone() {
+        let mut rpc = test_rpc();
+        let result = call(&mut rpc, "eth_call", json!([{"from": ALICE, "to": EMITTER}, "latest"])).unwrap();
+        assert_eq!(result, json!(hex_data(&word(42))));
+        let gas = call(&mut rpc, "eth_estimateGas", json!([{"from": ALICE, "to": EMITTER}])).unwrap();
+        let gas = parse_u64(&gas, "gas").unwrap();
+        assert!(gas > TX_BASE_GAS);
+        // A plain transfer costs exactly the base gas
+        let transfer = json!([{"from": ALICE, "to": ZERO_ADDRESS, "value": "0x10"}]);
+        assert_eq!(call(&mut rpc, "eth_estimateGas", transfer).unwrap(), quantity(TX_BASE_GAS));
+
+        assert_eq!(call(&mut rpc, "eth_getTransactionCount", json!([ALICE])).unwrap(), json!("0x0"));
+        assert_eq!(call(&mut rpc, "eth_getBalance", json!([ZERO_ADDRESS])).unwrap(), json!("0x0"));
+        assert_eq!(call(&mut rpc, "eth_blockNumber", json!([])).unwrap(), json!("0x0"));
+
+        let e = call(&mut rpc, "eth_call", json!([{"to": REVERTER}])).unwrap_err();
+        assert_eq!(e.code, RpcErrorCode::ExecutionReverted);
+        assert_eq!(e.data, Some(json!(hex_data(&word(42)))));
+        assert_eq!(e.to_json()["code"], json!(3));
+    }
+
+    #[test]
+    fn test_applied_transactions_get_receipts_and_logs() {
+        let mut rpc = test_rpc();
+        let receipt = rpc.apply(&tx(Some(EMITTER), "", 0), [1u8; 32]).unwrap();
+        assert!(receipt.success);
+        assert_eq!(receipt.block_number, 1);
+        assert_eq!(receipt.logs.len(), 1);
+        let failed = rpc.apply(&tx(Some(REVERTER), "", 1), [2u8; 32]).unwrap();
+        assert!(!failed.success);
+        assert!(failed.logs.is_empty());
+
+        // Init code copying EMITTER_CODE into place and returning it
+        let init = format!("6011600c60003960116000f3{}", EMITTER_CODE);
+        let deployed = rpc.apply(&tx(None, &init, 2), [3u8; 32]).unwrap();
+        let contract = deployed.contract_address.clone().unwrap();
+        assert!(deployed.success);
+        assert_eq!(call(&mut rpc, "eth_call", json!([{"to": contract}])).unwrap(), json!(hex_data(&word(42))));
+
+        assert_eq!(call(&mut rpc, "eth_blockNumber", json!([])).unwrap(), json!("0x3"));
+        assert_eq!(call(&mut rpc, "eth_getTransactionCount", json!([ALICE])).unwrap(), json!("0x3"));
+        let e = rpc.apply(&tx(Some(EMITTER), "", 0), [4u8; 32]).unwrap_err();
+        assert_eq!(e.code, RpcErrorCode::TransactionRejected);
+        let e = rpc.apply(&tx(Some(EMITTER), "", 3), [1u8; 32]).unwrap_err();
+        assert_eq!(e.message, "already known");
+
+        // Every receipt, failed or not, was paid for at the adapter's gas price
+        let gas_used = receipt.gas_used + failed.gas_used + deployed.gas_used;
+        let balance = call(&mut rpc, "eth_getBalance", json!([ALICE])).unwrap();
+        assert_eq!(balance, quantity(10_000_000 - u128::from(gas_used) * 20));
+        // The up-front check covers the whole gas limit, not just what would be used
+        let broke = EthTransaction { gas_limit: 1_000_000, ..tx(Some(EMITTER), "", 3) };
+        let e = rpc.apply(&broke, [5u8; 32]).unwrap_err();
+        assert!(e.message.contains("insufficient funds"), "{}", e);
+        let cheap = EthTransaction { gas_price: 19, ..tx(Some(EMITTER), "", 3) };
+        assert!(rpc.apply(&cheap, [5u8; 32]).unwrap_err().message.contains("gas price"));
+
+        let json = call(&mut rpc, "eth_getTransactionReceipt", json!([hex_data(&[1u8; 32])])).unwrap();
+        assert_eq!(json["status"], json!("0x1"));
+        assert_eq!(json["blockNumber"], json!("0x1"));
+        assert_eq!(json["logs"][0]["address"], json!(EMITTER));
+        assert_eq!(json["logs"][0]["data"], json!(hex_data(&word(42))));
+        assert_ne!(json["logsBloom"], json!(hex_data(&[0u8; 256])));
+        let json = call(&mut rpc, "eth_getTransactionReceipt", json!([hex_data(&[3u8; 32])])).unwrap();
+        assert_eq!(json["contractAddress"], json!(contract));
+        assert_eq!(call(&mut rpc, "eth_getTransactionReceipt", json!([hex_data(&[9u8; 32])])).unwrap(), Value::Null);
+
+        let logs = call(&mut rpc, "eth_getLogs", json!([{"fromBlock": "earliest", "topics": [hex_data(&word(7))]}])).unwrap();
+        assert_eq!(logs.as_array().unwrap().len(), 1);
+        let logs = call(&mut rpc, "eth_getLogs", json!([{"fromBlock": "0x1", "address": [EMITTER, REVERTER]}])).unwrap();
+        assert_eq!(logs[0]["transactionHash"], json!(hex_data(&[1u8; 32])));
+        let logs = call(&mut rpc, "eth_getLogs", json!([{"fromBlock": "0x2", "topics": [null, hex_data(&word(7))]}])).unwrap();
+        assert_eq!(logs, json!([]));
+    }
+
+    #[test]
+    fn test_receipts_and_state_survive_restart() {
+        let store = BlockStore::temporary().unwrap();
+        let mut evm = EVMAdapter::new();
+        evm.create_account(ALICE.to_string(), 10_000_000).unwrap();
+        let mut rpc = EthRpc::new(evm.clone(), store.clone()).unwrap();
+        rpc.apply(&EthTransaction { value: 5, ..tx(Some(ZERO_ADDRESS), "", 0) }, [1u8; 32]).unwrap();
+        let balance = call(&mut rpc, "eth_getBalance", json!([ALICE])).unwrap();
+
+        // The genesis accounts handed in again are superseded by the stored state
+        let mut rpc = EthRpc::new(evm.clone(), store.clone()).unwrap();
+        assert_eq!(call(&mut rpc, "eth_blockNumber", json!([])).unwrap(), json!("0x1"));
+        assert!(call(&mut rpc, "eth_getTransactionReceipt", json!([hex_data(&[1u8; 32])])).unwrap().is_object());
+        assert_eq!(call(&mut rpc, "eth_getBalance", json!([ALICE])).unwrap(), balance);
+        assert_eq!(call(&mut rpc, "eth_getBalance", json!([ZERO_ADDRESS])).unwrap(), json!("0x5"));
+        assert_eq!(call(&mut rpc, "eth_getTransactionCount", json!([ALICE])).unwrap(), json!("0x1"));
+        let e = rpc.apply(&tx(Some(ZERO_ADDRESS), "", 0), [2u8; 32]).unwrap_err();
+        assert_eq!(e.code, RpcErrorCode::TransactionRejected);
+
+        // Receipts whose state is missing or unreadable cannot be resumed from
+        let orphaned = BlockStore::temporary().unwrap();
+        let receipt = store.get_receipt(&[1u8; 32]).unwrap().unwrap();
+        orphaned.put_receipt(&receipt, &[]).unwrap();
+        assert!(EthRpc::new(evm, orphaned).is_err());
+    }
+
+    #[test]
+    fn test_send_raw_transaction() {
+        use crate::eth_tx::tests::dynamic_fee_fields;
+        use crate::eth_tx::{evm_address, sign_tx, EthTxType};
+        use k256::ecdsa::SigningKey;
+
+        let key = SigningKey::from_slice(&[3; 32]).unwrap();
+        let sender = hex_data(&evm_address(key.verifying_key()));
+        let store = BlockStore::temporary().unwrap();
+        let mut evm = EVMAdapter::new();
+        evm.set_chain_id(TEST_CHAIN_ID);
+        evm.create_account(sender.clone(), 10_000_000).unwrap();
+        let mut rpc = EthRpc::new(evm, store).unwrap();
+
+        let to = [0xaa; 20];
+        let raw = sign_tx(&key, EthTxType::DynamicFee, TEST_CHAIN_ID, dynamic_fee_fields(TEST_CHAIN_ID, 0, Some(to), 1000, &[]));
+        let hash = call(&mut rpc, "eth_sendRawTransaction", json!([hex_data(&raw)])).unwrap();
+        assert_eq!(hash, json!(hex_data(&keccak256(&raw))));
+        let receipt = call(&mut rpc, "eth_getTransactionReceipt", json!([hash])).unwrap();
+        assert_eq!(receipt["status"], json!("0x1"));
+        assert_eq!(receipt["from"], json!(sender));
+        assert_eq!(call(&mut rpc, "eth_getBalance", json!([hex_data(&to)])).unwrap(), json!("0x3e8"));
+        assert_eq!(call(&mut rpc, "eth_getTransactionCount", json!([sender])).unwrap(), json!("0x1"));
+
+        // Replays, other chains and garbage are all refused
+        let e = call(&mut rpc, "eth_sendRawTransaction", json!([hex_data(&raw)])).unwrap_err();
+        assert_eq!(e.code, RpcErrorCode::TransactionRejected);
+        let other_chain = sign_tx(&key, EthTxType::DynamicFee, 9, dynamic_fee_fields(9, 1, Some(to), 1, &[]));
+        let e = call(&mut rpc, "eth_sendRawTransaction", json!([hex_data(&other_chain)])).unwrap_err();
+        assert_eq!(e.code, RpcErrorCode::TransactionRejected);
+        let e = call(&mut rpc, "eth_sendRawTransaction", json!(["0xc0"])).unwrap_err();
+        assert_eq!(e.code, RpcErrorCode::InvalidParams);
+    }
+
+    #[tokio::test]
+    async fn test_http_requests_and_batches() {
+        let routes = routes(Arc::new(Mutex::new(test_rpc())));
+        let post = |body: &str| warp::test::request().method("POST").path("/").body(body);
+
+        let res = post(r#"{"jsonrpc":"2.0","id":7,"method":"eth_chainId"}"#).reply(&routes).await;
+        assert_eq!(res.status(), 200);
+        let body: Value = serde_json::from_slice(res.body()).unwrap();
+        assert_eq!(body, json!({"jsonrpc": "2.0", "id": 7, "result": "0x1"}));
+
+        // Notifications get no entry in the batch response
+        let batch = r#"[{"jsonrpc":"2.0","id":1,"method":"net_version"},{"jsonrpc":"2.0","method":"eth_chainId"},{"id":2}]"#;
+        let body: Value = serde_json::from_slice(post(batch).reply(&routes).await.body()).unwrap();
+        assert_eq!(body.as_array().unwrap().len(), 2);
+        assert_eq!(body[0]["result"], json!("1"));
+        assert_eq!(body[1]["error"]["code"], json!(-32600));
+
+        let body: Value = serde_json::from_slice(post("{not json").reply(&routes).await.body()).unwrap();
+        assert_eq!(body["error"]["code"], json!(-32700));
+        let res = post(r#"{"jsonrpc":"2.0","method":"eth_chainId"}"#).reply(&routes).await;
+        assert_eq!(res.status(), 204);
+    }
+}
@@ -0,0 +1,572 @@
+// Ethereum transactions for NeoNet - RLP decoding, secp256k1 sender recovery, chain id checks
+// and conversion into unified runtime transactions
+//
+// Three wire formats are accepted:
+//   legacy    rlp([nonce, gasPrice, gas, to, value, data, v, r, s])
+//   EIP-2930  0x01 || rlp([chainId, nonce, gasPrice, gas, to, value, data, accessList, yParity, r, s])
+//   EIP-1559  0x02 || rlp([chainId, nonce, maxPriorityFee, maxFee, gas, to, value, data, accessList, yParity, r, s])
+// The signature covers the keccak hash of the same encoding without its last three fields; for
+// EIP-155 legacy transactions those are replaced by [chainId, 0, 0]. Decoding is strict: only
+// canonical RLP is accepted and signatures must use the low-s form of EIP-2, so every
+// transaction has exactly one valid encoding and hash.
+use std::fmt;
+
+use k256::ecdsa::{RecoveryId, Signature, SigningKey, VerifyingKey};
+use sha3::{Digest, Keccak256};
+
+use crate::unified_runtime::{eip155_v, DualAddress, DualSignature, RuntimeType, SignatureMode, UnifiedTransaction};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EthTxError {
+    InvalidRlp(&'static str),
+    UnsupportedType(u8),
+    WrongFieldCount { expected: usize, got: usize },
+    /// Named field has the wrong shape, is not minimally encoded or does not fit its type
+    InvalidField(&'static str),
+    InvalidV(u64),
+    InvalidSignature,
+    WrongChainId { expected: u64, got: u64 },
+    /// Legacy transaction signed without EIP-155, valid on every chain
+    NotReplayProtected,
+}
+
+impl fmt::Display for EthTxError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EthTxError::InvalidRlp(reason) => write!(f, "invalid RLP: {}", reason),
+            EthTxError::UnsupportedType(t) => write!(f, "unsupported transaction type {}", t),
+            EthTxError::WrongFieldCount { expected, got } => {
+                write!(f, "expected {} transaction fields, got {}", expected, got)
+            }
+            EthTxError::InvalidField(name) => write!(f, "invalid {}", name),
+            EthTxError::InvalidV(v) => write!(f, "invalid signature v {}", v),
+            EthTxError::InvalidSignature => write!(f, "invalid signature"),
+            EthTxError::WrongChainId { expected, got } => {
+                write!(f, "transaction is for chain {}, expected {}", got, expected)
+            }
+            EthTxError::NotReplayProtected => write!(f, "transaction is not replay protected"),
+        }
+    }
+}
+
+impl std::error::Error for EthTxError {}
+
+/// EIP-2930 storage slots a transaction declares it will touch, per address
+pub type AccessList = Vec<([u8; 20], Vec<[u8; 32]>)>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EthTxType {
+    Legacy = 0,
+    /// EIP-2930
+    AccessList = 1,
+    /// EIP-1559
+    DynamicFee = 2,
+}
+
+/// A decoded transaction whose signature has been checked and sender recovered
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SignedEthTx {
+    pub tx_type: EthTxType,
+    /// `None` only for legacy transactions signed without EIP-155 replay protection
+    pub chain_id: Option<u64>,
+    pub nonce: u64,
+    /// Gas price, or the max fee per gas of an EIP-1559 transaction
+    pub gas_price: u64,
+    pub max_priority_fee_per_gas: Option<u64>,
+    pub gas_limit: u64,
+    pub to: Option<[u8; 20]>,
+    pub value: u128,
+    pub data: Vec<u8>,
+    pub access_list: AccessList,
+    pub recovery_id: u8,
+    pub r: [u8; 32],
+    pub s: [u8; 32],
+    pub from: [u8; 20],
+    /// Keccak hash of the raw encoding, the hash wallets and explorers know it by
+    pub hash: [u8; 32],
+}
+
+impl SignedEthTx {
+    /// Reject transactions for another network. Typed transactions sign their chain id
+    /// directly and legacy ones through an EIP-155 `v`; unprotected legacy signatures could be
+    /// replayed anywhere.
+    pub fn check_chain_id(&self, chain_id: u64) -> Result<(), EthTxError> {
+        match self.chain_id {
+            Some(id) if id == chain_id => Ok(()),
+            Some(id) => Err(EthTxError::WrongChainId { expected: chain_id, got: id }),
+            None => Err(EthTxError::NotReplayProtected),
+        }
+    }
+}
+
+/// Deepest list nesting a transaction needs: the transaction, its access list, an entry and
+/// the entry's storage keys. Anything deeper is refused before it can exhaust the stack.
+const MAX_RLP_DEPTH: usize = 4;
+
+/// One RLP item, borrowing its byte strings from the input
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Rlp<'a> {
+    Bytes(&'a [u8]),
+    List(Vec<Rlp<'a>>),
+}
+
+/// Decode the item at the start of `input`, returning it and the bytes after it
+fn decode_item(input: &[u8]) -> Result<(Rlp<'_>, &[u8]), EthTxError> {
+    decode_nested(input, 0)
+}
+
+/// `decode_item` for an item inside `depth` enclosing lists
+fn decode_nested(input: &[u8], depth: usize) -> Result<(Rlp<'_>, &[u8]), EthTxError> {
+    let prefix = *input.first().ok_or(EthTxError::InvalidRlp("unexpected end of input"))?;
+    let (is_list, header_len, payload_len) = match prefix {
+        0x00..=0x7f => return Ok((Rlp::Bytes(&input[..1]), &input[1..])),
+        0x80..=0xb7 => (false, 1, (prefix - 0x80) as usize),
+        0xb8..=0xbf => {
+            let n = (prefix - 0xb7) as usize;
+            (false, 1 + n, long_length(&input[1..], n)?)
+        },
+        0xc0..=0xf7 => (true, 1, (prefix - 0xc0) as usize),
+        0xf8..=0xff => {
+            let n = (prefix - 0xf7) as usize;
+            (true, 1 + n, long_length(&input[1..], n)?)
+        },
+    };
+    let end = header_len.checked_add(payload_len)
+        .filter(|&end| end <= input.len())
+        .ok_or(EthTxError::InvalidRlp("item runs past the end of input"))?;
+    let payload = &input[header_len..end];
+    if !is_list && payload_len == 1 && payload[0] < 0x80 {
+        return Err(EthTxError::InvalidRlp("single byte below 0x80 must encode as itself"));
+    }
+
+    let item = if is_list {
+        if depth == MAX_RLP_DEPTH {
+            return Err(EthTxError::InvalidRlp("lists nested too deeply"));
+        }
+        let mut items = vec![];
+        let mut rest = payload;
+        while !rest.is_empty() {
+            let (item, after) = decode_nested(rest, depth + 1)?;
+            items.push(item);
+            rest = after;
+        }
+        Rlp::List(items)
+    } else {
+        Rlp::Bytes(payload)
+    };
+    Ok((item, &input[end..]))
+}
+
+/// Length in the `n` big-endian bytes of a long-form header, which must need the long form
+fn long_length(input: &[u8], n: usize) -> Result<usize, EthTxError> {
+    let bytes = input.get(..n).ok_or(EthTxError::InvalidRlp("unexpected end of input"))?;
+    if bytes[0] == 0 {
+        return Err(EthTxError::InvalidRlp("length has leading zeros"));
+    }
+    if n > std::mem::size_of::<usize>() {
+        return Err(EthTxError::InvalidRlp("length too large"));
+    }
+    let len = bytes.iter().fold(0usize, |acc, &b| acc << 8 | b as usize);
+    if len < 56 {
+        return Err(EthTxError::InvalidRlp("short length in long form"));
+    }
+    Ok(len)
+}
+
+fn encode_header(offset: u8, len: usize) -> Vec<u8> {
+    if len < 56 {
+        return vec![offset + len as u8];
+    }
+    let be = len.to_be_bytes();
+    let digits = &be[be.iter().position(|&b| b != 0).unwrap_or(be.len() - 1)..];
+    let mut out = vec![offset + 55 + digits.len() as u8];
+    out.extend_from_slice(digits);
+    out
+}
+
+pub(crate) fn encode_bytes(bytes: &[u8]) -> Vec<u8> {
+    if bytes.len() == 1 && bytes[0] < 0x80 {
+        return bytes.to_vec();
+    }
+    let mut out = encode_header(0x80, bytes.len());
+    out.extend_from_slice(bytes);
+    out
+}
+
+/// An unsigned integer as its minimal big-endian byte string
+pub(crate) fn encode_uint(n: u128) -> Vec<u8> {
+    let be = n.to_be_bytes();
+    let start = be.iter().position(|&b| b != 0).unwrap_or(be.len());
+    encode_bytes(&be[start..])
+}
+
+/// A list of already encoded items
+pub(crate) fn encode_list(items: &[Vec<u8>]) -> Vec<u8> {
+    let payload = items.concat();
+    let mut out = encode_header(0xc0, payload.len());
+    out.extend_from_slice(&payload);
+    out
+}
+
+fn encode_item(item: &Rlp<'_>) -> Vec<u8> {
+    match item {
+        Rlp::Bytes(bytes) => encode_bytes(bytes),
+        Rlp::List(items) => encode_list(&items.iter().map(encode_item).collect::<Vec<_>>()),
+    }
+}
+
+fn bytes_field<'a>(item: &Rlp<'a>, name: &'static str) -> Result<&'a [u8], EthTxError> {
+    match item {
+        Rlp::Bytes(bytes) => Ok(bytes),
+        Rlp::List(_) => Err(EthTxError::InvalidField(name)),
+    }
+}
+
+/// A minimally encoded unsigned integer of at most `max_len` bytes, right-aligned in a u128
+fn uint_field(item: &Rlp<'_>, name: &'static str, max_len: usize) -> Result<u128, EthTxError> {
+    let bytes = bytes_field(item, name)?;
+    if bytes.len() > max_len || bytes.first() == Some(&0) {
+        return Err(EthTxError::InvalidField(name));
+    }
+    Ok(bytes.iter().fold(0u128, |acc, &b| acc << 8 | b as u128))
+}
+
+fn u64_field(item: &Rlp<'_>, name: &'static str) -> Result<u64, EthTxError> {
+    uint_field(item, name, 8).map(|n| n as u64)
+}
+
+/// A 256-bit scalar such as `r` or `s`, left-padded to 32 bytes
+fn word_field(item: &Rlp<'_>, name: &'static str) -> Result<[u8; 32], EthTxError> {
+    let bytes = bytes_field(item, name)?;
+    if bytes.len() > 32 || bytes.first() == Some(&0) {
+        return Err(EthTxError::InvalidField(name));
+    }
+    let mut word = [0u8; 32];
+    word[32 - bytes.len()..].copy_from_slice(bytes);
+    Ok(word)
+}
+
+/// An address, or `None` for the empty string of a contract creation
+fn to_field(item: &Rlp<'_>) -> Result<Option<[u8; 20]>, EthTxError> {
+    match bytes_field(item, "to")? {
+        [] => Ok(None),
+        bytes => bytes.try_into().map(Some).map_err(|_| EthTxError::InvalidField("to")),
+    }
+}
+
+fn access_list_field(item: &Rlp<'_>) -> Result<AccessList, EthTxError> {
+    let invalid = || EthTxError::InvalidField("access list");
+    let Rlp::List(entries) = item else { return Err(invalid()) };
+    entries.iter()
+        .map(|entry| match entry {
+            Rlp::List(pair) if pair.len() == 2 => {
+                let address = bytes_field(&pair[0], "access list")?.try_into().map_err(|_| invalid())?;
+                let Rlp::List(keys) = &pair[1] else { return Err(invalid()) };
+                let keys = keys.iter()
+                    .map(|key| bytes_field(key, "access list")?.try_into().map_err(|_| invalid()))
+                    .collect::<Result<_, _>>()?;
+                Ok((address, keys))
+            },
+            _ => Err(invalid()),
+        })
+        .collect()
+}
+
+fn keccak256(bytes: &[u8]) -> [u8; 32] {
+    Keccak256::digest(bytes).into()
+}
+
+/// Last 20 bytes of the keccak hash of the uncompressed public key, without its 0x04 tag
+pub fn evm_address(key: &VerifyingKey) -> [u8; 20] {
+    let point = key.to_encoded_point(false);
+    let hash = keccak256(&point.as_bytes()[1..]);
+    hash[12..].try_into().unwrap()
+}
+
+/// Recover the address that signed `hash`, accepting only low-s signatures
+pub(crate) fn recover_sender(hash: &[u8; 32], r: &[u8; 32], s: &[u8; 32], recovery_id: u8) -> Result<[u8; 20], EthTxError> {
+    let signature = Signature::from_scalars(*r, *s).map_err(|_| EthTxError::InvalidSignature)?;
+    if signature.normalize_s().is_some() {
+        return Err(EthTxError::InvalidSignature);
+    }
+    let recovery_id = RecoveryId::from_byte(recovery_id).ok_or(EthTxError::InvalidSignature)?;
+    let key = VerifyingKey::recover_from_prehash(hash, &signature, recovery_id)
+        .map_err(|_| EthTxError::InvalidSignature)?;
+    Ok(evm_address(&key))
+}
+
+/// Decode a signed raw transaction, as passed to eth_sendRawTransaction, and recover its sender
+pub fn decode_signed_tx(raw: &[u8]) -> Result<SignedEthTx, EthTxError> {
+    let (tx_type, body) = match raw.first() {
+        Some(0x01) => (EthTxType::AccessList, &raw[1..]),
+        Some(0x02) => (EthTxType::DynamicFee, &raw[1..]),
+        Some(&t) if t < 0xc0 => return Err(EthTxError::UnsupportedType(t)),
+        _ => (EthTxType::Legacy, raw),
+    };
+    let (item, rest) = decode_item(body)?;
+    if !rest.is_empty() {
+        return Err(EthTxError::InvalidRlp("trailing bytes after transaction"));
+    }
+    let Rlp::List(fields) = item else {
+        return Err(EthTxError::InvalidRlp("transaction is not a list"));
+    };
+    let expected = match tx_type {
+        EthTxType::Legacy => 9,
+        EthTxType::AccessList => 11,
+        EthTxType::DynamicFee => 12,
+    };
+    if fields.len() != expected {
+        return Err(EthTxError::WrongFieldCount { expected, got: fields.len() });
+    }
+
+    let chain_id = match tx_type {
+        EthTxType::Legacy => None,
+        _ => Some(u64_field(&fields[0], "chain id")?),
+    };
+    let f = if chain_id.is_some() { &fields[1..] } else { &fields[..] };
+    let nonce = u64_field(&f[0], "nonce")?;
+    // Past the fee fields every type continues gas, to, value, data[, access list]
+    let (gas_price, max_priority_fee_per_gas, f) = match tx_type {
+        EthTxType::DynamicFee => {
+            (u64_field(&f[2], "max fee per gas")?, Some(u64_field(&f[1], "max priority fee per gas")?), &f[3..])
+        },
+        _ => (u64_field(&f[1], "gas price")?, None, &f[2..]),
+    };
+    let gas_limit = u64_field(&f[0], "gas limit")?;
+    let to = to_field(&f[1])?;
+    let value = uint_field(&f[2], "value", 16)?;
+    let data = bytes_field(&f[3], "data")?.to_vec();
+    let access_list = match tx_type {
+        EthTxType::Legacy => vec![],
+        _ => access_list_field(&f[4])?,
+    };
+
+    let [v, r, s] = &fields[expected - 3..] else { unreachable!() };
+    let v = u64_field(v, "v")?;
+    let r = word_field(r, "r")?;
+    let s = word_field(s, "s")?;
+    let unsigned: Vec<Vec<u8>> = fields[..expected - 3].iter().map(encode_item).collect();
+    let (chain_id, recovery_id, signing_payload) = match tx_type {
+        EthTxType::Legacy => match v {
+            27 | 28 => (None, (v - 27) as u8, encode_list(&unsigned)),
+            v if v >= 35 => {
+                let chain_id = (v - 35) / 2;
+                let mut fields = unsigned;
+                fields.extend([encode_uint(chain_id as u128), encode_uint(0), encode_uint(0)]);
+                (Some(chain_id), ((v - 35) % 2) as u8, encode_list(&fields))
+            },
+            v => return Err(EthTxError::InvalidV(v)),
+        },
+        _ => {
+            if v > 1 {
+                return Err(EthTxError::InvalidV(v));
+            }
+            let payload = [&[tx_type as u8][..], &encode_list(&unsigned)].concat();
+            (chain_id, v as u8, payload)
+        },
+    };
+    let from = recover_sender(&keccak256(&signing_payload), &r, &s, recovery_id)?;
+
+    Ok(SignedEthTx {
+        tx_type,
+        chain_id,
+        nonce,
+        gas_price,
+        max_priority_fee_per_gas,
+        gas_limit,
+        to,
+        value,
+        data,
+        access_list,
+        recovery_id,
+        r,
+        s,
+        from,
+        hash: keccak256(raw),
+    })
+}
+
+/// Decode a signed raw transaction into the unified runtime's form, keeping what its signature
+/// is checked against there: the EIP-155 `v` of a legacy transaction, or the raw envelope of a
+/// typed one. Unprotected legacy transactions are refused, as they carry no chain id.
+pub fn decode_unified_tx(raw: &[u8], timestamp: u64) -> Result<UnifiedTransaction, EthTxError> {
+    let signed = decode_signed_tx(raw)?;
+    let chain_id = signed.chain_id.ok_or(EthTxError::NotReplayProtected)?;
+    let (ecdsa_v, eth_envelope) = match signed.tx_type {
+        EthTxType::Legacy => (Some(eip155_v(chain_id, signed.recovery_id)), None),
+        _ => (None, Some(raw.to_vec())),
+    };
+    Ok(UnifiedTransaction {
+        tx_hash: signed.hash,
+        chain_id,
+        from: DualAddress::from_evm(signed.from),
+        to: signed.to.map(DualAddress::from_evm),
+        value: signed.value,
+        gas_limit: signed.gas_limit,
+        gas_price: signed.gas_price,
+        nonce: signed.nonce,
+        data: signed.data,
+        signature: DualSignature {
+            ecdsa_sig: Some([signed.r, signed.s].concat()),
+            ecdsa_v,
+            dilithium_sig: None,
+            signature_mode: SignatureMode::EVMOnly,
+            eth_envelope,
+        },
+        runtime_hint: Some(RuntimeType::EVM),
+        cross_runtime_calls: vec![],
+        timestamp,
+    })
+}
+
+/// Sign an unsigned field list (already RLP-encoded items) the way a wallet would: EIP-155
+/// for legacy transactions, `type || rlp(fields)` for typed ones
+pub fn sign_tx(key: &SigningKey, tx_type: EthTxType, chain_id: u64, fields: Vec<Vec<u8>>) -> Vec<u8> {
+    let payload = match tx_type {
+        EthTxType::Legacy => {
+            let mut with_chain = fields.clone();
+            with_chain.extend([encode_uint(chain_id as u128), encode_uint(0), encode_uint(0)]);
+            encode_list(&with_chain)
+        },
+        _ => [&[tx_type as u8][..], &encode_list(&fields)].concat(),
+    };
+    let (signature, recovery_id) = key.sign_prehash_recoverable(&keccak256(&payload)).expect("signing a prehash cannot fail");
+    let v = match tx_type {
+        EthTxType::Legacy => eip155_v(chain_id, recovery_id.to_byte()),
+        _ => recovery_id.to_byte() as u64,
+    };
+    let (r, s) = signature.split_bytes();
+    let mut signed = fields;
+    signed.extend([encode_uint(v as u128), encode_bytes(trim(&r)), encode_bytes(trim(&s))]);
+    match tx_type {
+        EthTxType::Legacy => encode_list(&signed),
+        _ => [&[tx_type as u8][..], &encode_list(&signed)].concat(),
+    }
+}
+
+/// A big-endian word without its leading zero bytes, as RLP integers are encoded
+fn trim(word: &[u8]) -> &[u8] {
+    &word[word.iter().position(|&b| b != 0).unwrap_or(word.len())..]
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use k256::elliptic_curve::PrimeField;
+
+    /// Unsigned EIP-1559 fields: chain id, nonce, tip, max fee, gas, to, value, data, access list
+    pub(crate) fn dynamic_fee_fields(chain_id: u64, nonce: u64, to: Option<[u8; 20]>, value: u128, data: &[u8]) -> Vec<Vec<u8>> {
+        vec![
+            encode_uint(chain_id as u128),
+            encode_uint(nonce as u128),
+            encode_uint(1_000_000_000),
+            encode_uint(30_000_000_000),
+            encode_uint(100_000),
+            encode_bytes(to.as_ref().map_or(&[][..], |a| &a[..])),
+            encode_uint(value),
+            encode_bytes(data),
+            encode_list(&[]),
+        ]
+    }
+
+    #[test]
+    fn test_decode_eip155_example() {
+        // The worked example from EIP-155
+        let raw = hex::decode("f86c098504a817c800825208943535353535353535353535353535353535353535880de0b6b3a76400008025a028ef61340bd939bc2195fe537567866003e1a15d3c71ff63e1590620aa636276a067cbe9d8997f761aecb703304b3800ccf555c9f3dc64214b297fb1966a3b6d83").unwrap();
+        let tx = decode_signed_tx(&raw).unwrap();
+        assert_eq!(tx.tx_type, EthTxType::Legacy);
+        assert_eq!(tx.chain_id, Some(1));
+        assert_eq!(tx.nonce, 9);
+        assert_eq!(tx.gas_price, 20_000_000_000);
+        assert_eq!(tx.gas_limit, 21_000);
+        assert_eq!(tx.to, Some([0x35; 20]));
+        assert_eq!(tx.value, 1_000_000_000_000_000_000);
+        assert_eq!(hex::encode(tx.from), "9d8a62f656a8d1615c1294fd71e9cfb3e4855a4f");
+        let key = SigningKey::from_slice(&[0x46; 32]).unwrap();
+        assert_eq!(tx.from, evm_address(key.verifying_key()));
+
+        assert_eq!(tx.hash, keccak256(&raw));
+        assert_eq!(tx.recovery_id, 0);
+        assert_eq!(tx.check_chain_id(1), Ok(()));
+        assert_eq!(tx.check_chain_id(2), Err(EthTxError::WrongChainId { expected: 2, got: 1 }));
+    }
+
+    #[test]
+    fn test_typed_transactions_round_trip() {
+        let key = SigningKey::from_slice(&[7; 32]).unwrap();
+        let sender = evm_address(key.verifying_key());
+
+        let raw = sign_tx(&key, EthTxType::DynamicFee, 5, dynamic_fee_fields(5, 3, Some([0xaa; 20]), 1000, &[1, 2]));
+        let tx = decode_signed_tx(&raw).unwrap();
+        assert_eq!(tx.tx_type, EthTxType::DynamicFee);
+        assert_eq!((tx.chain_id, tx.nonce, tx.gas_limit, tx.value), (Some(5), 3, 100_000, 1000));
+        assert_eq!((tx.gas_price, tx.max_priority_fee_per_gas), (30_000_000_000, Some(1_000_000_000)));
+        assert_eq!(tx.data, vec![1, 2]);
+        assert_eq!(tx.from, sender);
+        assert_eq!(tx.to, Some([0xaa; 20]));
+        assert_eq!(tx.check_chain_id(5), Ok(()));
+        assert_eq!(tx.check_chain_id(1), Err(EthTxError::WrongChainId { expected: 1, got: 5 }));
+
+        let access_list = encode_list(&[encode_list(&[encode_bytes(&[0xbb; 20]), encode_list(&[encode_bytes(&[1; 32])])])]);
+        let fields = vec![
+            encode_uint(5), encode_uint(0), encode_uint(20), encode_uint(50_000),
+            encode_bytes(&[]), encode_uint(0), encode_bytes(&[0x60, 0x00]), access_list,
+        ];
+        let tx = decode_signed_tx(&sign_tx(&key, EthTxType::AccessList, 5, fields)).unwrap();
+        assert_eq!(tx.tx_type, EthTxType::AccessList);
+        assert_eq!((tx.to, tx.gas_price, tx.gas_limit), (None, 20, 50_000));
+        assert_eq!(tx.access_list, vec![([0xbb; 20], vec![[1; 32]])]);
+        assert_eq!(tx.from, sender);
+
+        // A legacy transaction without EIP-155 still decodes, but is refused on every chain
+        let fields = vec![encode_uint(0), encode_uint(1), encode_uint(21_000), encode_bytes(&[0xaa; 20]), encode_uint(1), encode_bytes(&[])];
+        let (signature, recovery_id) = key.sign_prehash_recoverable(&keccak256(&encode_list(&fields))).unwrap();
+        let (r, s) = signature.split_bytes();
+        let mut signed = fields;
+        signed.extend([encode_uint(27 + recovery_id.to_byte() as u128), encode_bytes(trim(&r)), encode_bytes(trim(&s))]);
+        let tx = decode_signed_tx(&encode_list(&signed)).unwrap();
+        assert_eq!((tx.chain_id, tx.from), (None, sender));
+        assert_eq!(tx.check_chain_id(0), Err(EthTxError::NotReplayProtected));
+        assert!(matches!(decode_unified_tx(&encode_list(&signed), 0), Err(EthTxError::NotReplayProtected)));
+    }
+
+    #[test]
+    fn test_rejects_malformed_transactions() {
+        let key = SigningKey::from_slice(&[7; 32]).unwrap();
+        let fields = dynamic_fee_fields(1, 0, Some([0xaa; 20]), 1, &[]);
+        let raw = sign_tx(&key, EthTxType::DynamicFee, 1, fields.clone());
+
+        let mut trailing = raw.clone();
+        trailing.push(0);
+        assert_eq!(decode_signed_tx(&trailing), Err(EthTxError::InvalidRlp("trailing bytes after transaction")));
+        assert_eq!(decode_signed_tx(&raw[..raw.len() - 1]), Err(EthTxError::InvalidRlp("item runs past the end of input")));
+        assert_eq!(decode_signed_tx(&[&[0x03][..], &raw[1..]].concat()), Err(EthTxError::UnsupportedType(3)));
+        assert!(matches!(decode_signed_tx(&[0xc1, 0x80]), Err(EthTxError::WrongFieldCount { expected: 9, got: 1 })));
+        // Non-canonical RLP: a small byte wrapped in a string header, and a nonce with a leading zero
+        assert!(matches!(decode_item(&[0x81, 0x05]), Err(EthTxError::InvalidRlp(_))));
+        // Deep nesting is refused rather than recursed into
+        let mut headers = vec![];
+        let mut len = 1;
+        for _ in 0..100_000 {
+            let header = encode_header(0xc0, len);
+            len += header.len();
+            headers.push(header);
+        }
+        let nested: Vec<u8> = headers.into_iter().rev().flatten().chain([0xc0]).collect();
+        assert_eq!(decode_signed_tx(&nested), Err(EthTxError::InvalidRlp("lists nested too deeply")));
+        assert!(decode_item(&[0xc3, 0xc2, 0xc1, 0xc0]).is_ok());
+        assert!(decode_item(&[0xc4, 0xc3, 0xc2, 0xc1, 0xc0]).is_err());
+        let mut padded = fields.clone();
+        padded[1] = encode_bytes(&[0, 1]);
+        let raw_padded = sign_tx(&key, EthTxType::DynamicFee, 1, padded);
+        assert_eq!(decode_signed_tx(&raw_padded), Err(EthTxError::InvalidField("nonce")));
+
+        // The high-s twin of a valid signature is refused
+        let Ok((Rlp::List(mut items), _)) = decode_item(&raw[1..]) else { panic!("not a list") };
+        let s = k256::Scalar::from_repr(word_field(&items[11], "s").unwrap().into()).unwrap();
+        let high_s: [u8; 32] = (-s).to_bytes().into();
+        items[11] = Rlp::Bytes(&high_s);
+        let encoded = [&[0x02][..], &encode_item(&Rlp::List(items))].concat();
+        assert_eq!(decode_signed_tx(&encoded), Err(EthTxError::InvalidSignature));
+    }
+}
@@ -2,7 +2,13 @@
 use serde::{Deserialize, Serialize};
 use anyhow::{Result, anyhow};
 use std::collections::HashMap;
-use sha2::{Sha256, Digest};
+use sha3::{Digest, Keccak256};
+use crate::codec::{impl_codec_struct, Decode, Encode};
+use crate::eth_tx::{encode_bytes, encode_list, encode_uint};
+use crate::evm_interpreter::{self, AddressIndex, BlockEnv, CallFrame, CallKind, ExitReason, ExecutionResult};
+
+/// Default gas limit for a block, exposed to contracts through the GASLIMIT opcode
+pub const BLOCK_GAS_LIMIT: u64 = 30_000_000;
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct EVMAccount {
@@ -13,70 +19,189 @@ pub struct EVMAccount {
     pub storage: HashMap<String, String>,
 }
 
+/// An account as persisted, with its storage sorted so equal states encode identically
+struct StoredAccount {
+    address: String,
+    balance: u128,
+    nonce: u64,
+    code: Vec<u8>,
+    storage: Vec<StoredSlot>,
+}
+
+struct StoredSlot {
+    key: String,
+    value: String,
+}
+
+impl_codec_struct!(StoredAccount { address, balance, nonce, code, storage });
+impl_codec_struct!(StoredSlot { key, value });
+
+/// Address of the contract `deployer` creates with `nonce`: the last 20 bytes of
+/// keccak(rlp([deployer, nonce])), as Ethereum's CREATE derives it
+pub fn create_address(deployer: &str, nonce: u64) -> String {
+    let mut word = [0u8; 32];
+    evm_interpreter::address_to_word(deployer).to_big_endian(&mut word);
+    let hash = Keccak256::digest(encode_list(&[encode_bytes(&word[12..]), encode_uint(nonce as u128)]));
+    format!("0x{}", hex::encode(&hash[12..]))
+}
+
+#[derive(Clone)]
 pub struct EVMAdapter {
     accounts: HashMap<String, EVMAccount>,
+    /// Resolves EVM words to the keys of `accounts` that aren't canonical hex addresses
+    index: AddressIndex,
     gas_price: u64,
     block_number: u64,
+    block_timestamp: u64,
+    chain_id: u64,
 }
 
 impl EVMAdapter {
     pub fn new() -> Self {
         EVMAdapter {
             accounts: HashMap::new(),
+            index: AddressIndex::default(),
             gas_price: 20,
             block_number: 0,
+            block_timestamp: 0,
+            chain_id: 0,
         }
     }
 
+    /// Chain id reported by CHAINID and expected in EIP-155 signatures
+    pub fn set_chain_id(&mut self, chain_id: u64) {
+        self.chain_id = chain_id;
+    }
+
+    pub fn chain_id(&self) -> u64 {
+        self.chain_id
+    }
+
+    pub fn gas_price(&self) -> u64 {
+        self.gas_price
+    }
+
     pub fn create_account(&mut self, address: String, initial_balance: u128) -> Result<()> {
         if self.accounts.contains_key(&address) {
             return Err(anyhow!("Account already exists"));
         }
 
-        let account = EVMAccount {
-            address: address.clone(),
+        self.insert_account(EVMAccount {
+            address,
             balance: initial_balance,
             nonce: 0,
             code: vec![],
             storage: HashMap::new(),
-        };
-
-        self.accounts.insert(address, account);
+        });
         Ok(())
     }
 
+    fn insert_account(&mut self, account: EVMAccount) {
+        self.index.insert(&account.address);
+        self.accounts.insert(account.address.clone(), account);
+    }
+
     pub fn deploy_contract(&mut self, deployer: &str, code: Vec<u8>) -> Result<String> {
         let deployer_account = self.accounts.get_mut(deployer)
             .ok_or_else(|| anyhow!("Deployer account not found"))?;
 
         let nonce = deployer_account.nonce;
         deployer_account.nonce += 1;
+        let contract_address = create_address(deployer, nonce);
 
-        let mut hasher = Sha256::new();
-        hasher.update(format!("{}{}", deployer, nonce).as_bytes());
-        let hash = hasher.finalize();
-        let contract_address = format!("0x{}", hex::encode(&hash[..20]));
-
-        let contract = EVMAccount {
+        self.insert_account(EVMAccount {
             address: contract_address.clone(),
             balance: 0,
             nonce: 1,
             code,
             storage: HashMap::new(),
+        });
+        Ok(contract_address)
+    }
+
+    /// Place runtime code at a fixed address without running init code, as genesis does
+    /// for pre-deployed contracts.
+    pub fn install_contract(&mut self, address: &str, code: Vec<u8>) -> Result<()> {
+        if self.accounts.contains_key(address) {
+            return Err(anyhow!("Account already exists"));
+        }
+        self.insert_account(EVMAccount {
+            address: address.to_string(),
+            balance: 0,
+            nonce: 1,
+            code,
+            storage: HashMap::new(),
+        });
+        Ok(())
+    }
+
+    /// Deploy a contract by running its init code and storing the returned runtime code.
+    /// A failed deployment is reported through the execution result; the address is returned
+    /// either way, and after a failure no account exists there and only the deployer nonce
+    /// has changed.
+    pub fn create(
+        &mut self,
+        deployer: &str,
+        init_code: Vec<u8>,
+        value: u128,
+        gas_limit: u64
+    ) -> Result<(String, ExecutionResult)> {
+        let deployer_account = self.accounts.get_mut(deployer)
+            .ok_or_else(|| anyhow!("Deployer account not found"))?;
+
+        let nonce = deployer_account.nonce;
+        deployer_account.nonce += 1;
+        let contract_address = create_address(deployer, nonce);
+
+        if self.accounts.contains_key(&contract_address) {
+            return Err(anyhow!("Contract address collision"));
+        }
+        self.insert_account(EVMAccount {
+            address: contract_address.clone(),
+            balance: 0,
+            nonce: 1,
+            code: vec![],
+            storage: HashMap::new(),
+        });
+
+        let frame = CallFrame {
+            kind: CallKind::Call,
+            address: contract_address.clone(),
+            code_address: contract_address.clone(),
+            caller: deployer.to_string(),
+            origin: deployer.to_string(),
+            value,
+            data: vec![],
+            gas_limit,
+            is_static: false,
+            depth: 0,
         };
+        let env = self.block_env();
+        let result = evm_interpreter::execute_code(&mut self.accounts, &self.index, &env, frame, init_code);
 
-        self.accounts.insert(contract_address.clone(), contract);
-        Ok(contract_address)
+        if !result.is_success() {
+            self.accounts.remove(&contract_address);
+        } else if let Some(contract) = self.accounts.get_mut(&contract_address) {
+            contract.code = result.return_data.clone();
+        }
+        Ok((contract_address, result))
     }
 
-    pub fn call_contract(
+    /// Execute the code stored at `to`, returning the full execution result.
+    /// State changes are discarded if execution reverts or fails; the sender nonce is bumped
+    /// whenever the code runs.
+    pub fn execute(
         &mut self,
         from: &str,
         to: &str,
         data: Vec<u8>,
         value: u128,
-        _gas_limit: u64
-    ) -> Result<Vec<u8>> {
+        gas_limit: u64
+    ) -> Result<ExecutionResult> {
+        if !self.accounts.contains_key(to) {
+            return Err(anyhow!("Contract not found"));
+        }
+
         let from_account = self.accounts.get_mut(from)
             .ok_or_else(|| anyhow!("From account not found"))?;
 
@@ -84,35 +209,53 @@ impl EVMAdapter {
             return Err(anyhow!("Insufficient balance"));
         }
 
-        from_account.balance -= value;
         from_account.nonce += 1;
-        let from_balance = from_account.balance;
-
-        let to_account = self.accounts.get_mut(to)
-            .ok_or_else(|| anyhow!("Contract not found"))?;
 
-        to_account.balance += value;
+        let frame = CallFrame {
+            kind: CallKind::Call,
+            address: to.to_string(),
+            code_address: to.to_string(),
+            caller: from.to_string(),
+            origin: from.to_string(),
+            value,
+            data,
+            gas_limit,
+            is_static: false,
+            depth: 0,
+        };
+        let env = self.block_env();
+        Ok(evm_interpreter::execute_frame(&mut self.accounts, &self.index, &env, frame))
+    }
 
-        let method_sig = if data.len() >= 4 {
-            hex::encode(&data[0..4])
+    pub fn call_contract(
+        &mut self,
+        from: &str,
+        to: &str,
+        data: Vec<u8>,
+        value: u128,
+        gas_limit: u64
+    ) -> Result<Vec<u8>> {
+        let result = self.execute(from, to, data, value, gas_limit)?;
+        if result.is_success() {
+            Ok(result.return_data)
         } else {
-            String::new()
-        };
+            Err(execution_error(&result))
+        }
+    }
 
-        match method_sig.as_str() {
-            "70a08231" => {
-                let balance_bytes = to_account.balance.to_be_bytes();
-                Ok(balance_bytes.to_vec())
-            },
-            "a9059cbb" => {
-                let mut success = vec![0u8; 31];
-                success.push(1u8);
-                Ok(success)
-            },
-            _ => {
-                Ok(format!("EVM call: from balance {}, to {}, data {} bytes", 
-                    from_balance, to, data.len()).into_bytes())
-            }
+    pub fn get_storage(&self, address: &str, key: &str) -> Option<String> {
+        self.accounts.get(address)
+            .and_then(|acc| acc.storage.get(key).cloned())
+    }
+
+    fn block_env(&self) -> BlockEnv {
+        BlockEnv {
+            number: self.block_number,
+            timestamp: self.block_timestamp,
+            gas_price: self.gas_price,
+            gas_limit: BLOCK_GAS_LIMIT,
+            chain_id: self.chain_id,
+            coinbase: String::new(),
         }
     }
 
@@ -142,6 +285,17 @@ impl EVMAdapter {
             .ok_or_else(|| anyhow!("Account not found"))
     }
 
+    /// Take `amount` from `address` without bumping its nonce, e.g. to charge a transaction fee
+    pub fn debit(&mut self, address: &str, amount: u128) -> Result<()> {
+        let account = self.accounts.get_mut(address)
+            .ok_or_else(|| anyhow!("Account not found"))?;
+        if account.balance < amount {
+            return Err(anyhow!("Insufficient balance"));
+        }
+        account.balance -= amount;
+        Ok(())
+    }
+
     pub fn get_nonce(&self, address: &str) -> Result<u64> {
         self.accounts.get(address)
             .map(|acc| acc.nonce)
@@ -150,11 +304,68 @@ impl EVMAdapter {
 
     pub fn increment_block(&mut self) {
         self.block_number += 1;
+        self.block_timestamp = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
     }
 
     pub fn get_block_number(&self) -> u64 {
         self.block_number
     }
+
+    /// Resume block numbering where a previous run left off
+    pub fn set_block_number(&mut self, number: u64) {
+        self.block_number = number;
+    }
+
+    /// Every account with its balance, nonce, code and storage, in a canonical encoding
+    pub fn encode_state(&self) -> Vec<u8> {
+        let mut accounts: Vec<StoredAccount> = self.accounts.values()
+            .map(|acc| {
+                let mut storage: Vec<StoredSlot> = acc.storage.iter()
+                    .map(|(key, value)| StoredSlot { key: key.clone(), value: value.clone() })
+                    .collect();
+                storage.sort_by(|a, b| a.key.cmp(&b.key));
+                StoredAccount {
+                    address: acc.address.clone(),
+                    balance: acc.balance,
+                    nonce: acc.nonce,
+                    code: acc.code.clone(),
+                    storage,
+                }
+            })
+            .collect();
+        accounts.sort_by(|a, b| a.address.cmp(&b.address));
+        accounts.to_versioned_bytes()
+    }
+
+    /// Replace all accounts with a state written by `encode_state`
+    pub fn restore_state(&mut self, bytes: &[u8]) -> Result<()> {
+        let accounts = Vec::<StoredAccount>::from_versioned_bytes(bytes)?;
+        self.accounts = accounts.into_iter()
+            .map(|acc| (acc.address.clone(), EVMAccount {
+                address: acc.address,
+                balance: acc.balance,
+                nonce: acc.nonce,
+                code: acc.code,
+                storage: acc.storage.into_iter().map(|slot| (slot.key, slot.value)).collect(),
+            }))
+            .collect();
+        self.index = AddressIndex::default();
+        for address in self.accounts.keys() {
+            self.index.insert(address);
+        }
+        Ok(())
+    }
+}
+
+fn execution_error(result: &ExecutionResult) -> anyhow::Error {
+    match &result.exit_reason {
+        ExitReason::Reverted => anyhow!("Execution reverted: 0x{}", hex::encode(&result.return_data)),
+        ExitReason::Failed(e) => anyhow!("Execution failed: {}", e),
+        _ => anyhow!("Execution did not fail"),
+    }
 }
 
 impl Default for EVMAdapter {
@@ -196,4 +407,151 @@ mod tests {
         assert!(contract_addr.starts_with("0x"));
         assert_eq!(evm.get_nonce("0xdeployer").unwrap(), 1);
     }
+
+    // Runtime code: increment storage slot 0 and return the new value
+    const COUNTER: [u8; 18] = [
+        0x60, 0x00, 0x54, 0x60, 0x01, 0x01, 0x80, 0x60, 0x00, 0x55,
+        0x60, 0x00, 0x52, 0x60, 0x20, 0x60, 0x00, 0xf3,
+    ];
+
+    fn as_u128(data: &[u8]) -> u128 {
+        u128::from_be_bytes(data[16..32].try_into().unwrap())
+    }
+
+    #[test]
+    fn test_call_contract_executes_code() {
+        let mut evm = EVMAdapter::new();
+        evm.create_account("0xalice".to_string(), 1000).unwrap();
+        let counter = evm.deploy_contract("0xalice", COUNTER.to_vec()).unwrap();
+
+        let first = evm.call_contract("0xalice", &counter, vec![], 0, 100_000).unwrap();
+        let second = evm.call_contract("0xalice", &counter, vec![], 0, 100_000).unwrap();
+
+        assert_eq!(as_u128(&first), 1);
+        assert_eq!(as_u128(&second), 2);
+        assert_eq!(
+            evm.get_storage(&counter, &"00".repeat(32)),
+            Some(format!("{}02", "00".repeat(31)))
+        );
+    }
+
+    #[test]
+    fn test_revert_rolls_back_state() {
+        let mut evm = EVMAdapter::new();
+        evm.create_account("0xalice".to_string(), 1000).unwrap();
+        // SSTORE(0, 1) then REVERT(0, 0)
+        let code = vec![0x60, 0x01, 0x60, 0x00, 0x55, 0x60, 0x00, 0x60, 0x00, 0xfd];
+        let contract = evm.deploy_contract("0xalice", code).unwrap();
+
+        let result = evm.call_contract("0xalice", &contract, vec![], 100, 100_000);
+
+        assert!(result.unwrap_err().to_string().contains("reverted"));
+        assert_eq!(evm.get_storage(&contract, &"00".repeat(32)), None);
+        assert_eq!(evm.get_balance("0xalice").unwrap(), 1000);
+        assert_eq!(evm.get_balance(&contract).unwrap(), 0);
+        assert_eq!(evm.get_nonce("0xalice").unwrap(), 2);
+    }
+
+    #[test]
+    fn test_failed_sub_call_keeps_caller_changes() {
+        let mut evm = EVMAdapter::new();
+        evm.create_account("0xalice".to_string(), 1000).unwrap();
+        // SSTORE(0, 1) then REVERT(0, 0)
+        let reverter = vec![0x60, 0x01, 0x60, 0x00, 0x55, 0x60, 0x00, 0x60, 0x00, 0xfd];
+        let reverter = evm.deploy_contract("0xalice", reverter).unwrap();
+        // SSTORE(0, 7), then CALL(gas, reverter, 5, 0, 0, 0, 0) and STOP
+        let mut caller = vec![0x60, 0x07, 0x60, 0x00, 0x55, 0x60, 0x00, 0x60, 0x00, 0x60, 0x00, 0x60, 0x00, 0x60, 0x05, 0x73];
+        caller.extend(hex::decode(&reverter[2..]).unwrap());
+        caller.extend([0x5a, 0xf1, 0x50, 0x00]);
+        let caller = evm.deploy_contract("0xalice", caller).unwrap();
+
+        let result = evm.execute("0xalice", &caller, vec![], 100, 200_000).unwrap();
+
+        assert!(result.is_success());
+        assert_eq!(evm.get_storage(&caller, &"00".repeat(32)), Some(format!("{}07", "00".repeat(31))));
+        assert_eq!(evm.get_balance(&caller).unwrap(), 100);
+        assert_eq!(evm.get_storage(&reverter, &"00".repeat(32)), None);
+        assert_eq!(evm.get_balance(&reverter).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_missing_contract_leaves_nonce() {
+        let mut evm = EVMAdapter::new();
+        evm.create_account("0xalice".to_string(), 1000).unwrap();
+
+        assert!(evm.execute("0xalice", "0xnowhere", vec![], 0, 100_000).is_err());
+        assert_eq!(evm.get_nonce("0xalice").unwrap(), 0);
+    }
+
+    #[test]
+    fn test_gas_limit_enforced() {
+        let mut evm = EVMAdapter::new();
+        evm.create_account("0xalice".to_string(), 1000).unwrap();
+        let counter = evm.deploy_contract("0xalice", COUNTER.to_vec()).unwrap();
+
+        let result = evm.execute("0xalice", &counter, vec![], 0, 10_000).unwrap();
+
+        assert_eq!(result.exit_reason, ExitReason::Failed(crate::evm_interpreter::EVMError::OutOfGas));
+        assert_eq!(result.gas_used, 10_000);
+        assert_eq!(evm.get_storage(&counter, &"00".repeat(32)), None);
+    }
+
+    #[test]
+    fn test_contract_to_contract_call() {
+        let mut evm = EVMAdapter::new();
+        evm.create_account("0xalice".to_string(), 1000).unwrap();
+        let counter = evm.deploy_contract("0xalice", COUNTER.to_vec()).unwrap();
+
+        // CALL(gas, counter, 0, 0, 0, 0, 32) and return the 32-byte output
+        let mut proxy = vec![0x60, 0x20, 0x60, 0x00, 0x60, 0x00, 0x60, 0x00, 0x60, 0x00, 0x73];
+        proxy.extend(hex::decode(&counter[2..]).unwrap());
+        proxy.extend([0x5a, 0xf1, 0x50, 0x60, 0x20, 0x60, 0x00, 0xf3]);
+        let proxy = evm.deploy_contract("0xalice", proxy).unwrap();
+
+        let output = evm.call_contract("0xalice", &proxy, vec![], 0, 200_000).unwrap();
+
+        assert_eq!(as_u128(&output), 1);
+        assert!(evm.get_storage(&counter, &"00".repeat(32)).is_some());
+    }
+
+    #[test]
+    fn test_create_address_matches_ethereum() {
+        let sender = "0x6ac7ea33f8831ea9dcc53393aaa88b25a785dbf0";
+        assert_eq!(create_address(sender, 0), "0xcd234a471b72ba2f1ccf0a70fcaba648a5eecd8d");
+        assert_eq!(create_address(sender, 1), "0x343c43a37d37dff08ae8c4a11544c718abb4fcf8");
+
+        let mut evm = EVMAdapter::new();
+        evm.create_account(sender.to_string(), 0).unwrap();
+        assert_eq!(evm.deploy_contract(sender, COUNTER.to_vec()).unwrap(), create_address(sender, 0));
+    }
+
+    #[test]
+    fn test_state_round_trips() {
+        let mut evm = EVMAdapter::new();
+        evm.create_account("0xalice".to_string(), 1000).unwrap();
+        let counter = evm.deploy_contract("0xalice", COUNTER.to_vec()).unwrap();
+        evm.call_contract("0xalice", &counter, vec![], 0, 100_000).unwrap();
+
+        let mut restored = EVMAdapter::new();
+        restored.restore_state(&evm.encode_state()).unwrap();
+        assert_eq!(restored.encode_state(), evm.encode_state());
+        assert_eq!(restored.get_nonce("0xalice").unwrap(), 2);
+        let output = restored.call_contract("0xalice", &counter, vec![], 0, 100_000).unwrap();
+        assert_eq!(as_u128(&output), 2);
+    }
+
+    #[test]
+    fn test_create_runs_init_code() {
+        let mut evm = EVMAdapter::new();
+        evm.create_account("0xalice".to_string(), 1000).unwrap();
+
+        // CODECOPY the runtime that follows this 12-byte prefix and RETURN it
+        let mut init = vec![0x60, COUNTER.len() as u8, 0x60, 0x0c, 0x60, 0x00, 0x39, 0x60, COUNTER.len() as u8, 0x60, 0x00, 0xf3];
+        init.extend_from_slice(&COUNTER);
+        let (counter, result) = evm.create("0xalice", init, 0, 100_000).unwrap();
+        assert!(result.is_success());
+
+        let output = evm.call_contract("0xalice", &counter, vec![], 0, 100_000).unwrap();
+        assert_eq!(as_u128(&output), 1);
+    }
 }
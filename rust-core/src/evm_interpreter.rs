@@ -0,0 +1,1151 @@
+// EVM bytecode interpreter for NeoNet - stack machine executing deployed contract code
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use primitive_types::{U256, U512};
+use sha3::{Digest, Keccak256};
+
+use crate::codec::impl_codec_struct;
+use crate::evm_adapter::EVMAccount;
+
+pub const MAX_CALL_DEPTH: usize = 1024;
+const MAX_STACK_SIZE: usize = 1024;
+const MAX_MEMORY_SIZE: usize = 32 * 1024 * 1024;
+/// Native stack for one execution: every nested call recurses through `run_code`, so it must
+/// hold MAX_CALL_DEPTH interpreter frames whatever thread the caller happens to be on. A full
+/// depth needs under 8 MiB optimized and under 64 MiB in debug builds.
+const EXECUTION_STACK_SIZE: usize = 128 * 1024 * 1024;
+
+// Gas schedule (Istanbul-style, without access lists or refunds)
+const GAS_SLOAD: u64 = 800;
+const GAS_SSTORE_SET: u64 = 20000;
+const GAS_SSTORE_RESET: u64 = 5000;
+const GAS_SSTORE_SENTRY: u64 = 2300;
+const GAS_CALL: u64 = 700;
+const GAS_CALL_VALUE: u64 = 9000;
+const GAS_NEW_ACCOUNT: u64 = 25000;
+const GAS_CALL_STIPEND: u64 = 2300;
+const GAS_EXTERNAL: u64 = 700;
+const GAS_LOG: u64 = 375;
+const GAS_LOG_DATA: u64 = 8;
+const GAS_SHA3_WORD: u64 = 6;
+const GAS_COPY_WORD: u64 = 3;
+const GAS_EXP_BYTE: u64 = 50;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum EVMError {
+    OutOfGas,
+    StackUnderflow,
+    StackOverflow,
+    InvalidJump(usize),
+    InvalidOpcode(u8),
+    StaticStateChange,
+    ReturnDataOutOfBounds,
+    InsufficientBalance,
+}
+
+impl fmt::Display for EVMError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EVMError::OutOfGas => write!(f, "out of gas"),
+            EVMError::StackUnderflow => write!(f, "stack underflow"),
+            EVMError::StackOverflow => write!(f, "stack overflow"),
+            EVMError::InvalidJump(dest) => write!(f, "invalid jump destination {}", dest),
+            EVMError::InvalidOpcode(op) => write!(f, "invalid opcode 0x{:02x}", op),
+            EVMError::StaticStateChange => write!(f, "state change in static call"),
+            EVMError::ReturnDataOutOfBounds => write!(f, "return data out of bounds"),
+            EVMError::InsufficientBalance => write!(f, "insufficient balance for transfer"),
+        }
+    }
+}
+
+impl std::error::Error for EVMError {}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExitReason {
+    Stopped,
+    Returned,
+    Reverted,
+    Failed(EVMError),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct EVMLog {
+    pub address: String,
+    pub topics: Vec<[u8; 32]>,
+    pub data: Vec<u8>,
+}
+
+impl_codec_struct!(EVMLog { address, topics, data });
+
+#[derive(Debug, Clone)]
+pub struct ExecutionResult {
+    pub exit_reason: ExitReason,
+    pub return_data: Vec<u8>,
+    pub gas_used: u64,
+    pub logs: Vec<EVMLog>,
+}
+
+impl ExecutionResult {
+    pub fn is_success(&self) -> bool {
+        matches!(self.exit_reason, ExitReason::Stopped | ExitReason::Returned)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct BlockEnv {
+    pub number: u64,
+    pub timestamp: u64,
+    pub gas_price: u64,
+    pub gas_limit: u64,
+    pub chain_id: u64,
+    pub coinbase: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CallKind {
+    Call,
+    DelegateCall,
+    StaticCall,
+}
+
+#[derive(Debug, Clone)]
+pub struct CallFrame {
+    pub kind: CallKind,
+    /// Account whose storage and balance the code operates on
+    pub address: String,
+    /// Account whose code is executed (differs from `address` for DELEGATECALL)
+    pub code_address: String,
+    pub caller: String,
+    pub origin: String,
+    pub value: u128,
+    pub data: Vec<u8>,
+    pub gas_limit: u64,
+    pub is_static: bool,
+    pub depth: usize,
+}
+
+/// Keys of accounts that are not named by their canonical hex address (e.g. "0xalice" or a
+/// checksummed address), by EVM word, so resolving a word to an account is a lookup
+#[derive(Debug, Clone, Default)]
+pub struct AddressIndex(HashMap<U256, String>);
+
+impl AddressIndex {
+    /// Record the account key `address`; canonical addresses resolve without the index
+    pub fn insert(&mut self, address: &str) {
+        let word = address_to_word(address);
+        if canonical_address(word) != address {
+            self.0.insert(word, address.to_string());
+        }
+    }
+}
+
+/// Undo record for one change to the accounts
+enum Change {
+    Created(String),
+    Balance { address: String, balance: u128 },
+    Storage { address: String, key: String, value: Option<String> },
+}
+
+/// Changes made by the frames of one execution, oldest first. A frame that fails reverts
+/// the journal to where it started, undoing its changes and those of its sub-calls.
+#[derive(Default)]
+struct Journal(Vec<Change>);
+
+impl Journal {
+    fn checkpoint(&self) -> usize {
+        self.0.len()
+    }
+
+    fn revert(&mut self, accounts: &mut HashMap<String, EVMAccount>, checkpoint: usize) {
+        for change in self.0.drain(checkpoint..).rev() {
+            match change {
+                Change::Created(address) => {
+                    accounts.remove(&address);
+                }
+                Change::Balance { address, balance } => {
+                    if let Some(account) = accounts.get_mut(&address) {
+                        account.balance = balance;
+                    }
+                }
+                Change::Storage { address, key, value } => {
+                    if let Some(account) = accounts.get_mut(&address) {
+                        match value {
+                            Some(value) => account.storage.insert(key, value),
+                            None => account.storage.remove(&key),
+                        };
+                    }
+                }
+            }
+        }
+    }
+
+    /// The account at `address`, created empty (and journaled) if it doesn't exist
+    fn account<'a>(&mut self, accounts: &'a mut HashMap<String, EVMAccount>, address: &str) -> &'a mut EVMAccount {
+        if !accounts.contains_key(address) {
+            self.0.push(Change::Created(address.to_string()));
+        }
+        accounts.entry(address.to_string()).or_insert_with(|| empty_account(address))
+    }
+}
+
+/// Execute a message call against the code stored at `frame.code_address`.
+/// All state changes (including the value transfer) are reverted unless the call succeeds.
+pub fn execute_frame(
+    accounts: &mut HashMap<String, EVMAccount>,
+    index: &AddressIndex,
+    env: &BlockEnv,
+    frame: CallFrame,
+) -> ExecutionResult {
+    on_execution_stack(|| call_frame(accounts, index, &mut Journal::default(), env, frame))
+}
+
+/// Execute arbitrary bytecode (e.g. contract init code) in the context of `frame`.
+pub fn execute_code(
+    accounts: &mut HashMap<String, EVMAccount>,
+    index: &AddressIndex,
+    env: &BlockEnv,
+    frame: CallFrame,
+    code: Vec<u8>,
+) -> ExecutionResult {
+    on_execution_stack(|| run_code(accounts, index, &mut Journal::default(), env, frame, code))
+}
+
+/// Run `execute` on a thread with EXECUTION_STACK_SIZE of stack. The memory is reserved, not
+/// committed, so only as much as the calls actually nest gets touched.
+fn on_execution_stack<T: Send>(execute: impl FnOnce() -> T + Send) -> T {
+    std::thread::scope(|scope| {
+        std::thread::Builder::new()
+            .name("evm".to_string())
+            .stack_size(EXECUTION_STACK_SIZE)
+            .spawn_scoped(scope, execute)
+            .expect("failed to spawn EVM execution thread")
+            .join()
+            .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+    })
+}
+
+fn call_frame(
+    accounts: &mut HashMap<String, EVMAccount>,
+    index: &AddressIndex,
+    journal: &mut Journal,
+    env: &BlockEnv,
+    frame: CallFrame,
+) -> ExecutionResult {
+    let code = accounts.get(&frame.code_address)
+        .map(|acc| acc.code.clone())
+        .unwrap_or_default();
+    run_code(accounts, index, journal, env, frame, code)
+}
+
+fn run_code(
+    accounts: &mut HashMap<String, EVMAccount>,
+    index: &AddressIndex,
+    journal: &mut Journal,
+    env: &BlockEnv,
+    frame: CallFrame,
+    code: Vec<u8>,
+) -> ExecutionResult {
+    let checkpoint = journal.checkpoint();
+
+    if frame.kind == CallKind::Call && frame.value > 0 {
+        if let Err(e) = transfer_value(accounts, journal, &frame.caller, &frame.address, frame.value) {
+            journal.revert(accounts, checkpoint);
+            return ExecutionResult {
+                exit_reason: ExitReason::Failed(e),
+                return_data: vec![],
+                gas_used: 0,
+                logs: vec![],
+            };
+        }
+    }
+
+    if code.is_empty() {
+        return ExecutionResult {
+            exit_reason: ExitReason::Stopped,
+            return_data: vec![],
+            gas_used: 0,
+            logs: vec![],
+        };
+    }
+
+    let gas_limit = frame.gas_limit;
+    let mut machine = Machine::new(accounts, index, journal, env, &frame, code);
+    let outcome = machine.run();
+    let gas_remaining = machine.gas_remaining;
+    let logs = std::mem::take(&mut machine.logs);
+
+    match outcome {
+        Ok((ExitReason::Reverted, data)) => {
+            journal.revert(accounts, checkpoint);
+            ExecutionResult {
+                exit_reason: ExitReason::Reverted,
+                return_data: data,
+                gas_used: gas_limit - gas_remaining,
+                logs: vec![],
+            }
+        }
+        Ok((reason, data)) => ExecutionResult {
+            exit_reason: reason,
+            return_data: data,
+            gas_used: gas_limit - gas_remaining,
+            logs,
+        },
+        Err(e) => {
+            journal.revert(accounts, checkpoint);
+            ExecutionResult {
+                exit_reason: ExitReason::Failed(e),
+                return_data: vec![],
+                gas_used: gas_limit,
+                logs: vec![],
+            }
+        }
+    }
+}
+
+fn transfer_value(
+    accounts: &mut HashMap<String, EVMAccount>,
+    journal: &mut Journal,
+    from: &str,
+    to: &str,
+    value: u128,
+) -> Result<(), EVMError> {
+    let sender = accounts.get_mut(from).ok_or(EVMError::InsufficientBalance)?;
+    if sender.balance < value {
+        return Err(EVMError::InsufficientBalance);
+    }
+    journal.0.push(Change::Balance { address: from.to_string(), balance: sender.balance });
+    sender.balance -= value;
+
+    let recipient = journal.account(accounts, to);
+    let balance = recipient.balance;
+    recipient.balance += value;
+    journal.0.push(Change::Balance { address: to.to_string(), balance });
+    Ok(())
+}
+
+fn empty_account(address: &str) -> EVMAccount {
+    EVMAccount {
+        address: address.to_string(),
+        balance: 0,
+        nonce: 0,
+        code: vec![],
+        storage: HashMap::new(),
+    }
+}
+
+/// Map an account address to its 160-bit EVM word.
+/// Accounts that are not 20-byte hex addresses (e.g. "0xalice") get a stable keccak-derived alias.
+pub fn address_to_word(address: &str) -> U256 {
+    let hex_part = address.strip_prefix("0x").unwrap_or(address);
+    if hex_part.len() == 40 {
+        if let Ok(bytes) = hex::decode(hex_part) {
+            return U256::from_big_endian(&bytes);
+        }
+    }
+    let hash = Keccak256::digest(address.as_bytes());
+    U256::from_big_endian(&hash[12..])
+}
+
+/// Lowercase 0x-prefixed hex form of the low 160 bits of `word`
+fn canonical_address(word: U256) -> String {
+    let mut buf = [0u8; 32];
+    word.to_big_endian(&mut buf);
+    format!("0x{}", hex::encode(&buf[12..]))
+}
+
+/// Resolve an EVM word back to the key of a known account, falling back to the canonical hex form.
+pub fn resolve_address(accounts: &HashMap<String, EVMAccount>, index: &AddressIndex, word: U256) -> String {
+    let canonical = canonical_address(word);
+    if accounts.contains_key(&canonical) {
+        return canonical;
+    }
+    index.0.get(&(address_mask() & word))
+        .filter(|name| accounts.contains_key(*name))
+        .cloned()
+        .unwrap_or(canonical)
+}
+
+pub fn word_to_storage_key(word: U256) -> String {
+    let mut buf = [0u8; 32];
+    word.to_big_endian(&mut buf);
+    hex::encode(buf)
+}
+
+pub fn storage_value_to_word(value: &str) -> U256 {
+    hex::decode(value)
+        .map(|bytes| U256::from_big_endian(&bytes))
+        .unwrap_or_default()
+}
+
+fn address_mask() -> U256 {
+    (U256::one() << 160) - U256::one()
+}
+
+fn is_negative(v: U256) -> bool {
+    v.bit(255)
+}
+
+fn negate(v: U256) -> U256 {
+    (!v).overflowing_add(U256::one()).0
+}
+
+fn abs(v: U256) -> U256 {
+    if is_negative(v) { negate(v) } else { v }
+}
+
+fn u256_from_bool(b: bool) -> U256 {
+    if b { U256::one() } else { U256::zero() }
+}
+
+fn u512_to_u256(v: U512) -> U256 {
+    U256::try_from(v).expect("value reduced modulo a 256-bit number")
+}
+
+fn memory_cost(words: u64) -> u64 {
+    3 * words + words * words / 512
+}
+
+fn words(len: usize) -> u64 {
+    (len as u64).div_ceil(32)
+}
+
+/// Valid JUMPDEST positions, skipping over PUSH immediates.
+fn analyze_jumpdests(code: &[u8]) -> Vec<bool> {
+    let mut dests = vec![false; code.len()];
+    let mut i = 0;
+    while i < code.len() {
+        let op = code[i];
+        if op == 0x5b {
+            dests[i] = true;
+        } else if (0x60..=0x7f).contains(&op) {
+            i += (op - 0x5f) as usize;
+        }
+        i += 1;
+    }
+    dests
+}
+
+fn base_gas(op: u8) -> Option<u64> {
+    let gas = match op {
+        0x00 => 0,
+        0x01 | 0x03 => 3,
+        0x02 | 0x04..=0x07 | 0x0b => 5,
+        0x08 | 0x09 => 8,
+        0x0a => 10,
+        0x10..=0x1d => 3,
+        0x20 => 30,
+        0x30 | 0x32..=0x34 | 0x36 | 0x38 | 0x3a | 0x3d => 2,
+        0x35 | 0x37 | 0x39 | 0x3e => 3,
+        0x31 | 0x3b | 0x3c | 0x3f => GAS_EXTERNAL,
+        0x40 => 20,
+        0x41..=0x46 | 0x48 => 2,
+        0x47 => 5,
+        0x50 | 0x58..=0x5a | 0x5f => 2,
+        0x51..=0x53 => 3,
+        0x54 => GAS_SLOAD,
+        0x55 => 0,
+        0x56 => 8,
+        0x57 => 10,
+        0x5b => 1,
+        0x60..=0x9f => 3,
+        0xa0..=0xa4 => GAS_LOG,
+        0xf1 | 0xf4 | 0xfa => GAS_CALL,
+        0xf3 | 0xfd => 0,
+        _ => return None,
+    };
+    Some(gas)
+}
+
+struct Machine<'a> {
+    accounts: &'a mut HashMap<String, EVMAccount>,
+    index: &'a AddressIndex,
+    journal: &'a mut Journal,
+    env: &'a BlockEnv,
+    frame: &'a CallFrame,
+    code: Vec<u8>,
+    jumpdests: Vec<bool>,
+    pc: usize,
+    stack: Vec<U256>,
+    memory: Vec<u8>,
+    gas_remaining: u64,
+    return_data: Vec<u8>,
+    logs: Vec<EVMLog>,
+}
+
+impl<'a> Machine<'a> {
+    fn new(
+        accounts: &'a mut HashMap<String, EVMAccount>,
+        index: &'a AddressIndex,
+        journal: &'a mut Journal,
+        env: &'a BlockEnv,
+        frame: &'a CallFrame,
+        code: Vec<u8>,
+    ) -> Self {
+        let jumpdests = analyze_jumpdests(&code);
+        Machine {
+            accounts,
+            index,
+            journal,
+            env,
+            frame,
+            code,
+            jumpdests,
+            pc: 0,
+            stack: Vec::with_capacity(64),
+            memory: Vec::new(),
+            gas_remaining: frame.gas_limit,
+            return_data: Vec::new(),
+            logs: Vec::new(),
+        }
+    }
+
+    fn charge(&mut self, amount: u64) -> Result<(), EVMError> {
+        if amount > self.gas_remaining {
+            self.gas_remaining = 0;
+            return Err(EVMError::OutOfGas);
+        }
+        self.gas_remaining -= amount;
+        Ok(())
+    }
+
+    fn push(&mut self, value: U256) -> Result<(), EVMError> {
+        if self.stack.len() >= MAX_STACK_SIZE {
+            return Err(EVMError::StackOverflow);
+        }
+        self.stack.push(value);
+        Ok(())
+    }
+
+    fn pop(&mut self) -> Result<U256, EVMError> {
+        self.stack.pop().ok_or(EVMError::StackUnderflow)
+    }
+
+    /// Charge for and perform memory expansion covering `[offset, offset + len)`.
+    fn expand_memory(&mut self, offset: U256, len: U256) -> Result<(usize, usize), EVMError> {
+        if len.is_zero() {
+            return Ok((0, 0));
+        }
+        let limit = U256::from(MAX_MEMORY_SIZE);
+        if offset > limit || len > limit || offset + len > limit {
+            return Err(EVMError::OutOfGas);
+        }
+        let (offset, len) = (offset.as_usize(), len.as_usize());
+        let end = offset + len;
+        if end > self.memory.len() {
+            let old_words = words(self.memory.len());
+            let new_words = words(end);
+            self.charge(memory_cost(new_words) - memory_cost(old_words))?;
+            self.memory.resize(new_words as usize * 32, 0);
+        }
+        Ok((offset, len))
+    }
+
+    fn copy_to_memory(&mut self, mem_offset: U256, src: &[u8], src_offset: U256, len: U256) -> Result<(), EVMError> {
+        let (mem_offset, len) = self.expand_memory(mem_offset, len)?;
+        self.charge(GAS_COPY_WORD * words(len))?;
+        for i in 0..len {
+            let byte = if src_offset <= U256::from(src.len()) {
+                src.get(src_offset.as_usize() + i).copied().unwrap_or(0)
+            } else {
+                0
+            };
+            self.memory[mem_offset + i] = byte;
+        }
+        Ok(())
+    }
+
+    fn read_memory(&self, offset: usize, len: usize) -> Vec<u8> {
+        if len == 0 {
+            return vec![];
+        }
+        self.memory[offset..offset + len].to_vec()
+    }
+
+    fn storage_load(&self, key: U256) -> U256 {
+        self.accounts.get(&self.frame.address)
+            .and_then(|acc| acc.storage.get(&word_to_storage_key(key)))
+            .map(|v| storage_value_to_word(v))
+            .unwrap_or_default()
+    }
+
+    fn storage_store(&mut self, key: U256, value: U256) {
+        let account = self.journal.account(self.accounts, &self.frame.address);
+        let key = word_to_storage_key(key);
+        let previous = if value.is_zero() {
+            account.storage.remove(&key)
+        } else {
+            account.storage.insert(key.clone(), word_to_storage_key(value))
+        };
+        self.journal.0.push(Change::Storage { address: self.frame.address.clone(), key, value: previous });
+    }
+
+    fn balance_of(&self, address: &str) -> U256 {
+        self.accounts.get(address)
+            .map(|acc| U256::from(acc.balance))
+            .unwrap_or_default()
+    }
+
+    fn run(&mut self) -> Result<(ExitReason, Vec<u8>), EVMError> {
+        loop {
+            let op = self.code.get(self.pc).copied().unwrap_or(0x00);
+            self.pc += 1;
+
+            let gas = base_gas(op).ok_or(EVMError::InvalidOpcode(op))?;
+            self.charge(gas)?;
+
+            match op {
+                // STOP
+                0x00 => return Ok((ExitReason::Stopped, vec![])),
+
+                // Arithmetic
+                0x01 => { let (a, b) = (self.pop()?, self.pop()?); self.push(a.overflowing_add(b).0)?; }
+                0x02 => { let (a, b) = (self.pop()?, self.pop()?); self.push(a.overflowing_mul(b).0)?; }
+                0x03 => { let (a, b) = (self.pop()?, self.pop()?); self.push(a.overflowing_sub(b).0)?; }
+                0x04 => {
+                    let (a, b) = (self.pop()?, self.pop()?);
+                    self.push(if b.is_zero() { U256::zero() } else { a / b })?;
+                }
+                0x05 => {
+                    let (a, b) = (self.pop()?, self.pop()?);
+                    let result = if b.is_zero() {
+                        U256::zero()
+                    } else {
+                        let q = abs(a) / abs(b);
+                        if is_negative(a) != is_negative(b) { negate(q) } else { q }
+                    };
+                    self.push(result)?;
+                }
+                0x06 => {
+                    let (a, b) = (self.pop()?, self.pop()?);
+                    self.push(if b.is_zero() { U256::zero() } else { a % b })?;
+                }
+                0x07 => {
+                    let (a, b) = (self.pop()?, self.pop()?);
+                    let result = if b.is_zero() {
+                        U256::zero()
+                    } else {
+                        let r = abs(a) % abs(b);
+                        if is_negative(a) { negate(r) } else { r }
+                    };
+                    self.push(result)?;
+                }
+                0x08 => {
+                    let (a, b, n) = (self.pop()?, self.pop()?, self.pop()?);
+                    let result = if n.is_zero() {
+                        U256::zero()
+                    } else {
+                        u512_to_u256((U512::from(a) + U512::from(b)) % U512::from(n))
+                    };
+                    self.push(result)?;
+                }
+                0x09 => {
+                    let (a, b, n) = (self.pop()?, self.pop()?, self.pop()?);
+                    let result = if n.is_zero() {
+                        U256::zero()
+                    } else {
+                        u512_to_u256(a.full_mul(b) % U512::from(n))
+                    };
+                    self.push(result)?;
+                }
+                0x0a => {
+                    let (base, exponent) = (self.pop()?, self.pop()?);
+                    let exponent_bytes = (exponent.bits() as u64).div_ceil(8);
+                    self.charge(GAS_EXP_BYTE * exponent_bytes)?;
+                    self.push(base.overflowing_pow(exponent).0)?;
+                }
+                0x0b => {
+                    let (b, x) = (self.pop()?, self.pop()?);
+                    let result = if b < U256::from(31) {
+                        let bit = b.as_usize() * 8 + 7;
+                        let mask = (U256::one() << (bit + 1)) - U256::one();
+                        if x.bit(bit) { x | !mask } else { x & mask }
+                    } else {
+                        x
+                    };
+                    self.push(result)?;
+                }
+
+                // Comparison & bitwise logic
+                0x10 => { let (a, b) = (self.pop()?, self.pop()?); self.push(u256_from_bool(a < b))?; }
+                0x11 => { let (a, b) = (self.pop()?, self.pop()?); self.push(u256_from_bool(a > b))?; }
+                0x12 | 0x13 => {
+                    let (a, b) = (self.pop()?, self.pop()?);
+                    let (lhs, rhs) = if op == 0x12 { (a, b) } else { (b, a) };
+                    let less = if is_negative(lhs) != is_negative(rhs) {
+                        is_negative(lhs)
+                    } else {
+                        lhs < rhs
+                    };
+                    self.push(u256_from_bool(less))?;
+                }
+                0x14 => { let (a, b) = (self.pop()?, self.pop()?); self.push(u256_from_bool(a == b))?; }
+                0x15 => { let a = self.pop()?; self.push(u256_from_bool(a.is_zero()))?; }
+                0x16 => { let (a, b) = (self.pop()?, self.pop()?); self.push(a & b)?; }
+                0x17 => { let (a, b) = (self.pop()?, self.pop()?); self.push(a | b)?; }
+                0x18 => { let (a, b) = (self.pop()?, self.pop()?); self.push(a ^ b)?; }
+                0x19 => { let a = self.pop()?; self.push(!a)?; }
+                0x1a => {
+                    let (i, x) = (self.pop()?, self.pop()?);
+                    let result = if i < U256::from(32) {
+                        (x >> (8 * (31 - i.as_usize()))) & U256::from(0xff)
+                    } else {
+                        U256::zero()
+                    };
+                    self.push(result)?;
+                }
+                0x1b => {
+                    let (shift, value) = (self.pop()?, self.pop()?);
+                    let result = if shift < U256::from(256) { value << shift.as_usize() } else { U256::zero() };
+                    self.push(result)?;
+                }
+                0x1c => {
+                    let (shift, value) = (self.pop()?, self.pop()?);
+                    let result = if shift < U256::from(256) { value >> shift.as_usize() } else { U256::zero() };
+                    self.push(result)?;
+                }
+                0x1d => {
+                    let (shift, value) = (self.pop()?, self.pop()?);
+                    let negative = is_negative(value);
+                    let result = if shift >= U256::from(256) {
+                        if negative { U256::MAX } else { U256::zero() }
+                    } else if negative {
+                        !((!value) >> shift.as_usize())
+                    } else {
+                        value >> shift.as_usize()
+                    };
+                    self.push(result)?;
+                }
+
+                // SHA3
+                0x20 => {
+                    let (offset, len) = (self.pop()?, self.pop()?);
+                    let (offset, len) = self.expand_memory(offset, len)?;
+                    self.charge(GAS_SHA3_WORD * words(len))?;
+                    let hash = Keccak256::digest(self.read_memory(offset, len));
+                    self.push(U256::from_big_endian(&hash))?;
+                }
+
+                // Environment
+                0x30 => { let w = address_to_word(&self.frame.address); self.push(w)?; }
+                0x31 => {
+                    let word = self.pop()? & address_mask();
+                    let address = resolve_address(self.accounts, self.index, word);
+                    let balance = self.balance_of(&address);
+                    self.push(balance)?;
+                }
+                0x32 => { let w = address_to_word(&self.frame.origin); self.push(w)?; }
+                0x33 => { let w = address_to_word(&self.frame.caller); self.push(w)?; }
+                0x34 => { let v = U256::from(self.frame.value); self.push(v)?; }
+                0x35 => {
+                    let offset = self.pop()?;
+                    let mut word = [0u8; 32];
+                    if offset < U256::from(self.frame.data.len()) {
+                        let start = offset.as_usize();
+                        let end = (start + 32).min(self.frame.data.len());
+                        word[..end - start].copy_from_slice(&self.frame.data[start..end]);
+                    }
+                    self.push(U256::from_big_endian(&word))?;
+                }
+                0x36 => { let len = U256::from(self.frame.data.len()); self.push(len)?; }
+                0x37 => {
+                    let (mem_offset, data_offset, len) = (self.pop()?, self.pop()?, self.pop()?);
+                    let data = self.frame.data.clone();
+                    self.copy_to_memory(mem_offset, &data, data_offset, len)?;
+                }
+                0x38 => { let len = U256::from(self.code.len()); self.push(len)?; }
+                0x39 => {
+                    let (mem_offset, code_offset, len) = (self.pop()?, self.pop()?, self.pop()?);
+                    let code = self.code.clone();
+                    self.copy_to_memory(mem_offset, &code, code_offset, len)?;
+                }
+                0x3a => { let price = U256::from(self.env.gas_price); self.push(price)?; }
+                0x3b => {
+                    let word = self.pop()? & address_mask();
+                    let address = resolve_address(self.accounts, self.index, word);
+                    let size = self.accounts.get(&address).map(|acc| acc.code.len()).unwrap_or(0);
+                    self.push(U256::from(size))?;
+                }
+                0x3c => {
+                    let word = self.pop()? & address_mask();
+                    let (mem_offset, code_offset, len) = (self.pop()?, self.pop()?, self.pop()?);
+                    let address = resolve_address(self.accounts, self.index, word);
+                    let code = self.accounts.get(&address).map(|acc| acc.code.clone()).unwrap_or_default();
+                    self.copy_to_memory(mem_offset, &code, code_offset, len)?;
+                }
+                0x3d => { let len = U256::from(self.return_data.len()); self.push(len)?; }
+                0x3e => {
+                    let (mem_offset, data_offset, len) = (self.pop()?, self.pop()?, self.pop()?);
+                    let end = data_offset.checked_add(len).ok_or(EVMError::ReturnDataOutOfBounds)?;
+                    if end > U256::from(self.return_data.len()) {
+                        return Err(EVMError::ReturnDataOutOfBounds);
+                    }
+                    let data = self.return_data.clone();
+                    self.copy_to_memory(mem_offset, &data, data_offset, len)?;
+                }
+                0x3f => {
+                    let word = self.pop()? & address_mask();
+                    let address = resolve_address(self.accounts, self.index, word);
+                    let hash = match self.accounts.get(&address) {
+                        Some(acc) => U256::from_big_endian(&Keccak256::digest(&acc.code)),
+                        None => U256::zero(),
+                    };
+                    self.push(hash)?;
+                }
+
+                // Block information
+                0x40 => { self.pop()?; self.push(U256::zero())?; }
+                0x41 => { let w = address_to_word(&self.env.coinbase); self.push(w)?; }
+                0x42 => { let t = U256::from(self.env.timestamp); self.push(t)?; }
+                0x43 => { let n = U256::from(self.env.number); self.push(n)?; }
+                0x44 => self.push(U256::zero())?,
+                0x45 => { let l = U256::from(self.env.gas_limit); self.push(l)?; }
+                0x46 => { let id = U256::from(self.env.chain_id); self.push(id)?; }
+                0x47 => { let b = self.balance_of(&self.frame.address); self.push(b)?; }
+                0x48 => self.push(U256::zero())?,
+
+                // Stack, memory, storage and flow
+                0x50 => { self.pop()?; }
+                0x51 => {
+                    let offset = self.pop()?;
+                    let (offset, _) = self.expand_memory(offset, U256::from(32))?;
+                    let word = U256::from_big_endian(&self.memory[offset..offset + 32]);
+                    self.push(word)?;
+                }
+                0x52 => {
+                    let (offset, value) = (self.pop()?, self.pop()?);
+                    let (offset, _) = self.expand_memory(offset, U256::from(32))?;
+                    value.to_big_endian(&mut self.memory[offset..offset + 32]);
+                }
+                0x53 => {
+                    let (offset, value) = (self.pop()?, self.pop()?);
+                    let (offset, _) = self.expand_memory(offset, U256::one())?;
+                    self.memory[offset] = value.byte(0);
+                }
+                0x54 => {
+                    let key = self.pop()?;
+                    let value = self.storage_load(key);
+                    self.push(value)?;
+                }
+                0x55 => {
+                    if self.frame.is_static {
+                        return Err(EVMError::StaticStateChange);
+                    }
+                    if self.gas_remaining <= GAS_SSTORE_SENTRY {
+                        return Err(EVMError::OutOfGas);
+                    }
+                    let (key, value) = (self.pop()?, self.pop()?);
+                    let current = self.storage_load(key);
+                    let cost = if current.is_zero() && !value.is_zero() { GAS_SSTORE_SET } else { GAS_SSTORE_RESET };
+                    self.charge(cost)?;
+                    self.storage_store(key, value);
+                }
+                0x56 => {
+                    let dest = self.pop()?;
+                    self.jump(dest)?;
+                }
+                0x57 => {
+                    let (dest, condition) = (self.pop()?, self.pop()?);
+                    if !condition.is_zero() {
+                        self.jump(dest)?;
+                    }
+                }
+                0x58 => { let pc = U256::from(self.pc - 1); self.push(pc)?; }
+                0x59 => { let size = U256::from(self.memory.len()); self.push(size)?; }
+                0x5a => { let gas = U256::from(self.gas_remaining); self.push(gas)?; }
+                0x5b => {}
+                0x5f => self.push(U256::zero())?,
+
+                // PUSH1..PUSH32
+                0x60..=0x7f => {
+                    let n = (op - 0x5f) as usize;
+                    let mut bytes = [0u8; 32];
+                    for i in 0..n {
+                        bytes[32 - n + i] = self.code.get(self.pc + i).copied().unwrap_or(0);
+                    }
+                    self.pc += n;
+                    self.push(U256::from_big_endian(&bytes))?;
+                }
+
+                // DUP1..DUP16
+                0x80..=0x8f => {
+                    let n = (op - 0x7f) as usize;
+                    if self.stack.len() < n {
+                        return Err(EVMError::StackUnderflow);
+                    }
+                    let value = self.stack[self.stack.len() - n];
+                    self.push(value)?;
+                }
+
+                // SWAP1..SWAP16
+                0x90..=0x9f => {
+                    let n = (op - 0x8f) as usize;
+                    if self.stack.len() <= n {
+                        return Err(EVMError::StackUnderflow);
+                    }
+                    let top = self.stack.len() - 1;
+                    self.stack.swap(top, top - n);
+                }
+
+                // LOG0..LOG4
+                0xa0..=0xa4 => {
+                    if self.frame.is_static {
+                        return Err(EVMError::StaticStateChange);
+                    }
+                    let topic_count = (op - 0xa0) as usize;
+                    let (offset, len) = (self.pop()?, self.pop()?);
+                    let mut topics = Vec::with_capacity(topic_count);
+                    for _ in 0..topic_count {
+                        let mut topic = [0u8; 32];
+                        self.pop()?.to_big_endian(&mut topic);
+                        topics.push(topic);
+                    }
+                    let (offset, len) = self.expand_memory(offset, len)?;
+                    self.charge(GAS_LOG * topic_count as u64 + GAS_LOG_DATA * len as u64)?;
+                    let data = self.read_memory(offset, len);
+                    self.logs.push(EVMLog { address: self.frame.address.clone(), topics, data });
+                }
+
+                // CALL, DELEGATECALL, STATICCALL
+                0xf1 | 0xf4 | 0xfa => self.call(op)?,
+
+                // RETURN, REVERT
+                0xf3 | 0xfd => {
+                    let (offset, len) = (self.pop()?, self.pop()?);
+                    let (offset, len) = self.expand_memory(offset, len)?;
+                    let data = self.read_memory(offset, len);
+                    let reason = if op == 0xf3 { ExitReason::Returned } else { ExitReason::Reverted };
+                    return Ok((reason, data));
+                }
+
+                _ => return Err(EVMError::InvalidOpcode(op)),
+            }
+        }
+    }
+
+    fn jump(&mut self, dest: U256) -> Result<(), EVMError> {
+        if dest >= U256::from(self.code.len()) {
+            return Err(EVMError::InvalidJump(usize::MAX));
+        }
+        let dest = dest.as_usize();
+        if !self.jumpdests[dest] {
+            return Err(EVMError::InvalidJump(dest));
+        }
+        self.pc = dest;
+        Ok(())
+    }
+
+    fn call(&mut self, op: u8) -> Result<(), EVMError> {
+        let gas = self.pop()?;
+        let target = self.pop()? & address_mask();
+        let value = if op == 0xf1 { self.pop()? } else { U256::zero() };
+        let (in_offset, in_len) = (self.pop()?, self.pop()?);
+        let (out_offset, out_len) = (self.pop()?, self.pop()?);
+
+        if self.frame.is_static && !value.is_zero() {
+            return Err(EVMError::StaticStateChange);
+        }
+
+        let (in_offset, in_len) = self.expand_memory(in_offset, in_len)?;
+        let (out_offset, out_len) = self.expand_memory(out_offset, out_len)?;
+
+        let target = resolve_address(self.accounts, self.index, target);
+        if !value.is_zero() {
+            self.charge(GAS_CALL_VALUE)?;
+            if !self.accounts.contains_key(&target) {
+                self.charge(GAS_NEW_ACCOUNT)?;
+            }
+        }
+
+        // EIP-150: forward at most all but one 64th of the remaining gas
+        let available = self.gas_remaining - self.gas_remaining / 64;
+        let forwarded = if gas > U256::from(available) { available } else { gas.as_u64() };
+        self.charge(forwarded)?;
+        let stipend = if value.is_zero() { 0 } else { GAS_CALL_STIPEND };
+
+        let caller_balance = self.balance_of(&self.frame.address);
+        if self.frame.depth + 1 > MAX_CALL_DEPTH || value > caller_balance {
+            self.gas_remaining += forwarded;
+            self.return_data.clear();
+            return self.push(U256::zero());
+        }
+
+        let input = self.read_memory(in_offset, in_len);
+        let frame = match op {
+            0xf1 => CallFrame {
+                kind: CallKind::Call,
+                address: target.clone(),
+                code_address: target,
+                caller: self.frame.address.clone(),
+                origin: self.frame.origin.clone(),
+                value: value.as_u128(),
+                data: input,
+                gas_limit: forwarded + stipend,
+                is_static: self.frame.is_static,
+                depth: self.frame.depth + 1,
+            },
+            0xf4 => CallFrame {
+                kind: CallKind::DelegateCall,
+                address: self.frame.address.clone(),
+                code_address: target,
+                caller: self.frame.caller.clone(),
+                origin: self.frame.origin.clone(),
+                value: self.frame.value,
+                data: input,
+                gas_limit: forwarded,
+                is_static: self.frame.is_static,
+                depth: self.frame.depth + 1,
+            },
+            _ => CallFrame {
+                kind: CallKind::StaticCall,
+                address: target.clone(),
+                code_address: target,
+                caller: self.frame.address.clone(),
+                origin: self.frame.origin.clone(),
+                value: 0,
+                data: input,
+                gas_limit: forwarded,
+                is_static: true,
+                depth: self.frame.depth + 1,
+            },
+        };
+
+        let gas_given = frame.gas_limit;
+        let result = call_frame(self.accounts, self.index, self.journal, self.env, frame);
+        self.gas_remaining += gas_given.saturating_sub(result.gas_used);
+
+        let copy_len = out_len.min(result.return_data.len());
+        self.memory[out_offset..out_offset + copy_len].copy_from_slice(&result.return_data[..copy_len]);
+
+        let success = result.is_success();
+        if success {
+            self.logs.extend(result.logs);
+        }
+        self.return_data = result.return_data;
+        self.push(u256_from_bool(success))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(code: Vec<u8>, gas_limit: u64) -> ExecutionResult {
+        run_at("0xcontract", code, gas_limit)
+    }
+
+    fn run_at(address: &str, code: Vec<u8>, gas_limit: u64) -> ExecutionResult {
+        let mut accounts = HashMap::new();
+        accounts.insert(address.to_string(), EVMAccount {
+            address: address.to_string(),
+            balance: 0,
+            nonce: 1,
+            code,
+            storage: HashMap::new(),
+        });
+        let env = BlockEnv {
+            number: 7,
+            timestamp: 1_700_000_000,
+            gas_price: 20,
+            gas_limit: 30_000_000,
+            chain_id: 0,
+            coinbase: String::new(),
+        };
+        let frame = CallFrame {
+            kind: CallKind::Call,
+            address: address.to_string(),
+            code_address: address.to_string(),
+            caller: "0xcaller".to_string(),
+            origin: "0xcaller".to_string(),
+            value: 0,
+            data: vec![],
+            gas_limit,
+            is_static: false,
+            depth: 0,
+        };
+        execute_frame(&mut accounts, &AddressIndex::default(), &env, frame)
+    }
+
+    /// Wrap `body` so that the top stack item is stored at memory 0 and returned as one word.
+    fn returning_top(mut body: Vec<u8>) -> Vec<u8> {
+        body.extend_from_slice(&[0x60, 0x00, 0x52, 0x60, 0x20, 0x60, 0x00, 0xf3]);
+        body
+    }
+
+    fn word(result: &ExecutionResult) -> U256 {
+        U256::from_big_endian(&result.return_data)
+    }
+
+    #[test]
+    fn test_arithmetic() {
+        // (2 + 3) * 4
+        let code = returning_top(vec![0x60, 0x04, 0x60, 0x03, 0x60, 0x02, 0x01, 0x02]);
+        let result = run(code, 100_000);
+        assert!(result.is_success());
+        assert_eq!(word(&result), U256::from(20));
+    }
+
+    #[test]
+    fn test_signed_division() {
+        // -8 / 2 == -4 (SDIV with 0 - 8 as dividend)
+        let code = returning_top(vec![0x60, 0x02, 0x60, 0x08, 0x60, 0x00, 0x03, 0x05]);
+        let result = run(code, 100_000);
+        assert_eq!(word(&result), negate(U256::from(4)));
+    }
+
+    #[test]
+    fn test_invalid_jump_fails() {
+        // PUSH1 3, JUMP, STOP (no JUMPDEST at 3)
+        let result = run(vec![0x60, 0x03, 0x56, 0x00], 100_000);
+        assert_eq!(result.exit_reason, ExitReason::Failed(EVMError::InvalidJump(3)));
+        assert_eq!(result.gas_used, 100_000);
+    }
+
+    #[test]
+    fn test_loop_runs_out_of_gas() {
+        // JUMPDEST, PUSH1 0, JUMP
+        let result = run(vec![0x5b, 0x60, 0x00, 0x56], 1_000);
+        assert_eq!(result.exit_reason, ExitReason::Failed(EVMError::OutOfGas));
+    }
+
+    #[test]
+    fn test_revert_returns_data() {
+        // MSTORE 0xff at 0, REVERT(31, 1)
+        let code = vec![0x60, 0xff, 0x60, 0x00, 0x52, 0x60, 0x01, 0x60, 0x1f, 0xfd];
+        let result = run(code, 100_000);
+        assert_eq!(result.exit_reason, ExitReason::Reverted);
+        assert_eq!(result.return_data, vec![0xff]);
+        assert!(result.gas_used < 100_000);
+    }
+
+    #[test]
+    fn test_deep_self_call_does_not_overflow_the_stack() {
+        // CALL(GAS, ADDRESS, 0, 0, 0, 0, 0) and return its success flag: every frame calls
+        // itself with all the gas it may forward, nesting as deep as a block's gas allows
+        let code = returning_top(vec![0x60, 0x00, 0x60, 0x00, 0x60, 0x00, 0x60, 0x00, 0x60, 0x00, 0x30, 0x5a, 0xf1]);
+        let address = "0x00000000000000000000000000000000000000cc";
+        let result = run_at(address, code.clone(), 30_000_000);
+        assert_eq!(result.exit_reason, ExitReason::Returned);
+        assert_eq!(word(&result), U256::one());
+        // With gas to spare, nesting stops at the call depth limit instead
+        let result = run_at(address, code, 1 << 40);
+        assert_eq!(result.exit_reason, ExitReason::Returned);
+        assert_eq!(word(&result), U256::one());
+    }
+
+    #[test]
+    fn test_address_round_trip() {
+        let mut accounts = HashMap::new();
+        let mut index = AddressIndex::default();
+        let address = "0x00000000000000000000000000000000000000aa";
+        assert_eq!(resolve_address(&accounts, &index, address_to_word(address)), address);
+
+        // Accounts with non-canonical keys are found through the index
+        for name in ["0xalice", "0x00000000000000000000000000000000000000BB"] {
+            accounts.insert(name.to_string(), empty_account(name));
+            assert_ne!(resolve_address(&accounts, &index, address_to_word(name)), name);
+            index.insert(name);
+            assert_eq!(resolve_address(&accounts, &index, address_to_word(name)), name);
+        }
+    }
+}
@@ -0,0 +1,313 @@
+// BFT finality for NeoNet - signed prevotes/precommits and commit certificates
+//
+// Tendermint-style two-phase voting: validators prevote for a block, precommit once they
+// see a 2/3+ prevote quorum (a "polka"), and a block is final once 2/3+ of the validator
+// set's stake precommits it in the same round. The precommits form a `CommitCertificate`
+// that anyone holding the validator set can check.
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::codec::{impl_codec_enum, impl_codec_struct, Encode};
+use crate::genesis::NetworkId;
+use crate::pqc::{HybridKeyPair, HybridSignature, VOTE_DOMAIN};
+use crate::validator_set::{Validator, ValidatorSet};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum VoteType {
+    Prevote,
+    Precommit,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Vote {
+    pub vote_type: VoteType,
+    pub height: u64,
+    pub round: u32,
+    pub block_hash: String,
+    /// Address of the voting validator
+    pub validator: String,
+    pub signature: HybridSignature,
+}
+
+impl_codec_enum!(VoteType { Prevote = 0, Precommit = 1 });
+impl_codec_struct!(Vote { vote_type, height, round, block_hash, validator, signature });
+
+impl Vote {
+    /// Sign a vote, bound to the vote domain and `network`
+    pub fn new(keypair: &HybridKeyPair, network: &NetworkId, vote_type: VoteType, height: u64, round: u32, block_hash: &str) -> Self {
+        let mut vote = Vote {
+            vote_type,
+            height,
+            round,
+            block_hash: block_hash.to_string(),
+            validator: keypair.public_key().address(),
+            signature: HybridSignature::default(),
+        };
+        vote.signature = network.sign(keypair, VOTE_DOMAIN, &vote.signing_bytes());
+        vote
+    }
+
+    fn signing_bytes(&self) -> Vec<u8> {
+        (self.vote_type, self.height, self.round, &self.block_hash, &self.validator).to_bytes()
+    }
+
+    /// Check the vote against the validator set and chain, returning the signer
+    pub fn verify<'a>(&self, validators: &'a ValidatorSet, network: &NetworkId) -> Result<&'a Validator, FinalityError> {
+        let validator = validators.get(&self.validator)
+            .ok_or_else(|| FinalityError::UnknownValidator(self.validator.clone()))?;
+        match network.verify(&validator.public_key, VOTE_DOMAIN, &self.signing_bytes(), &self.signature) {
+            Ok(true) => Ok(validator),
+            _ => Err(FinalityError::InvalidSignature(self.validator.clone())),
+        }
+    }
+}
+
+/// Proof that a block was precommitted by validators holding 2/3+ of the stake
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct CommitCertificate {
+    pub height: u64,
+    pub round: u32,
+    pub block_hash: String,
+    pub precommits: Vec<Vote>,
+}
+
+impl_codec_struct!(CommitCertificate { height, round, block_hash, precommits });
+
+impl CommitCertificate {
+    pub fn verify(&self, validators: &ValidatorSet, network: &NetworkId) -> Result<(), FinalityError> {
+        let mut signers = Vec::with_capacity(self.precommits.len());
+        let mut stake = 0u128;
+        for vote in &self.precommits {
+            if vote.vote_type != VoteType::Precommit
+                || vote.height != self.height
+                || vote.round != self.round
+                || vote.block_hash != self.block_hash
+            {
+                return Err(FinalityError::MismatchedVote(vote.validator.clone()));
+            }
+            if signers.contains(&vote.validator) {
+                return Err(FinalityError::DuplicateVote(vote.validator.clone()));
+            }
+            stake += u128::from(vote.verify(validators, network)?.stake);
+            signers.push(vote.validator.clone());
+        }
+        let need = quorum(validators.total_stake());
+        if stake < need {
+            return Err(FinalityError::InsufficientQuorum { have: stake, need });
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FinalityError {
+    UnknownValidator(String),
+    InvalidSignature(String),
+    /// A certificate contains a vote for a different height, round, hash or type
+    MismatchedVote(String),
+    DuplicateVote(String),
+    /// The validator signed two different blocks for the same height, round and vote type
+    Equivocation { first: Box<Vote>, second: Box<Vote> },
+    /// Stake behind the votes falls short of the 2/3+ threshold
+    InsufficientQuorum { have: u128, need: u128 },
+    /// The block is at or below the finalized height but is not the finalized block
+    ConflictsWithFinalized { height: u64, finalized_height: u64 },
+    UnknownBlock(String),
+    /// No validator set is known yet for the epoch containing this height
+    UnknownValidatorSet { height: u64 },
+}
+
+impl fmt::Display for FinalityError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FinalityError::UnknownValidator(v) => write!(f, "vote from unknown validator {}", v),
+            FinalityError::InvalidSignature(v) => write!(f, "invalid vote signature from {}", v),
+            FinalityError::MismatchedVote(v) => write!(f, "vote from {} does not match the certificate", v),
+            FinalityError::DuplicateVote(v) => write!(f, "duplicate vote from {}", v),
+            FinalityError::Equivocation { first, .. } => write!(f, "validator {} voted for conflicting blocks at height {} round {}",
+                first.validator, first.height, first.round),
+            FinalityError::InsufficientQuorum { have, need } => write!(f, "insufficient quorum: {} of {} required stake", have, need),
+            FinalityError::ConflictsWithFinalized { height, finalized_height } => write!(f, "block at height {} conflicts with finalized height {}",
+                height, finalized_height),
+            FinalityError::UnknownBlock(hash) => write!(f, "unknown block {}", hash),
+            FinalityError::UnknownValidatorSet { height } => write!(f, "no validator set known for height {}", height),
+        }
+    }
+}
+
+impl std::error::Error for FinalityError {}
+
+/// Stake needed out of `total_stake` for a 2/3+ supermajority
+pub fn quorum(total_stake: u128) -> u128 {
+    total_stake * 2 / 3 + 1
+}
+
+/// Collects votes per (height, round) and reports quorums
+#[derive(Default)]
+pub struct VotePool {
+    votes: HashMap<(u64, u32, VoteType), HashMap<String, Vote>>,
+}
+
+impl VotePool {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Verify and record a vote. Returns a commit certificate once the vote completes a
+    /// precommit quorum for its block. Re-sending the same vote is a no-op.
+    pub fn add_vote(&mut self, vote: Vote, validators: &ValidatorSet, network: &NetworkId) -> Result<Option<CommitCertificate>, FinalityError> {
+        let stake = u128::from(vote.verify(validators, network)?.stake);
+        let votes = self.votes.entry((vote.height, vote.round, vote.vote_type)).or_default();
+        if let Some(existing) = votes.get(&vote.validator) {
+            if existing.block_hash != vote.block_hash {
+                return Err(FinalityError::Equivocation { first: Box::new(existing.clone()), second: Box::new(vote) });
+            }
+            return Ok(None);
+        }
+
+        let (height, round, vote_type, block_hash) = (vote.height, vote.round, vote.vote_type, vote.block_hash.clone());
+        votes.insert(vote.validator.clone(), vote);
+        let need = quorum(validators.total_stake());
+        let total = self.stake(height, round, vote_type, &block_hash, validators);
+        // Only the vote that crosses the threshold produces the certificate
+        if vote_type != VoteType::Precommit || total < need || total - stake >= need {
+            return Ok(None);
+        }
+
+        let precommits = self.votes[&(height, round, vote_type)].values()
+            .filter(|v| v.block_hash == block_hash)
+            .cloned()
+            .collect();
+        Ok(Some(CommitCertificate { height, round, block_hash, precommits }))
+    }
+
+    /// Stake behind the `vote_type` votes for `block_hash`
+    pub fn stake(&self, height: u64, round: u32, vote_type: VoteType, block_hash: &str, validators: &ValidatorSet) -> u128 {
+        self.votes.get(&(height, round, vote_type))
+            .map_or(0, |votes| votes.values()
+                .filter(|v| v.block_hash == block_hash)
+                .map(|v| u128::from(validators.stake_of(&v.validator)))
+                .sum())
+    }
+
+    /// Whether 2/3+ of the stake prevoted for `block_hash`, allowing validators to precommit it
+    pub fn has_polka(&self, height: u64, round: u32, block_hash: &str, validators: &ValidatorSet) -> bool {
+        self.stake(height, round, VoteType::Prevote, block_hash, validators) >= quorum(validators.total_stake())
+    }
+
+    /// Drop votes at or below a finalized height
+    pub fn prune(&mut self, finalized_height: u64) {
+        self.votes.retain(|(height, _, _), _| *height > finalized_height);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::TEST_CHAIN_ID;
+
+    fn network() -> NetworkId {
+        NetworkId { chain_id: TEST_CHAIN_ID, genesis_hash: "00".repeat(32) }
+    }
+
+    fn keys(n: usize) -> (Vec<HybridKeyPair>, ValidatorSet) {
+        let keys: Vec<HybridKeyPair> = (0..n).map(|_| HybridKeyPair::generate()).collect();
+        let validators = ValidatorSet::with_equal_stake(keys.iter().map(HybridKeyPair::public_key).collect(), 1);
+        (keys, validators)
+    }
+
+    #[test]
+    fn test_quorum_sizes() {
+        assert_eq!(quorum(1), 1);
+        assert_eq!(quorum(3), 3);
+        assert_eq!(quorum(4), 3);
+        assert_eq!(quorum(7), 5);
+        assert_eq!(quorum(3 * u128::from(u64::MAX)), 2 * u128::from(u64::MAX) + 1);
+    }
+
+    #[test]
+    fn test_precommit_quorum_produces_certificate() {
+        let (keys, validators) = keys(4);
+        let mut pool = VotePool::new();
+
+        for key in &keys[..3] {
+            assert_eq!(pool.add_vote(Vote::new(key, &network(), VoteType::Prevote, 1, 0, "aa"), &validators, &network()), Ok(None));
+        }
+        assert!(pool.has_polka(1, 0, "aa", &validators));
+
+        assert_eq!(pool.add_vote(Vote::new(&keys[0], &network(), VoteType::Precommit, 1, 0, "aa"), &validators, &network()), Ok(None));
+        assert_eq!(pool.add_vote(Vote::new(&keys[1], &network(), VoteType::Precommit, 1, 0, "aa"), &validators, &network()), Ok(None));
+        let cert = pool.add_vote(Vote::new(&keys[2], &network(), VoteType::Precommit, 1, 0, "aa"), &validators, &network()).unwrap().unwrap();
+
+        assert_eq!(cert.precommits.len(), 3);
+        assert_eq!(cert.verify(&validators, &network()), Ok(()));
+        // A late fourth precommit doesn't produce a second certificate
+        assert_eq!(pool.add_vote(Vote::new(&keys[3], &network(), VoteType::Precommit, 1, 0, "aa"), &validators, &network()), Ok(None));
+    }
+
+    #[test]
+    fn test_rejects_bad_votes() {
+        let (keys, validators) = keys(4);
+        let outsider = HybridKeyPair::generate();
+        let mut pool = VotePool::new();
+
+        let stranger = Vote::new(&outsider, &network(), VoteType::Prevote, 1, 0, "aa");
+        assert_eq!(pool.add_vote(stranger, &validators, &network()), Err(FinalityError::UnknownValidator(outsider.public_key().address())));
+
+        let mut forged = Vote::new(&keys[0], &network(), VoteType::Prevote, 1, 0, "aa");
+        forged.block_hash = "bb".into();
+        assert!(matches!(pool.add_vote(forged, &validators, &network()), Err(FinalityError::InvalidSignature(_))));
+
+        pool.add_vote(Vote::new(&keys[0], &network(), VoteType::Precommit, 1, 0, "aa"), &validators, &network()).unwrap();
+        let conflicting = pool.add_vote(Vote::new(&keys[0], &network(), VoteType::Precommit, 1, 0, "bb"), &validators, &network());
+        assert!(matches!(conflicting, Err(FinalityError::Equivocation { .. })));
+    }
+
+    #[test]
+    fn test_certificate_verification() {
+        let (keys, validators) = keys(4);
+        let precommits: Vec<Vote> = keys[..3].iter().map(|k| Vote::new(k, &network(), VoteType::Precommit, 5, 1, "cc")).collect();
+        let cert = CommitCertificate { height: 5, round: 1, block_hash: "cc".into(), precommits };
+        assert_eq!(cert.verify(&validators, &network()), Ok(()));
+
+        let mut short = cert.clone();
+        short.precommits.pop();
+        assert_eq!(short.verify(&validators, &network()), Err(FinalityError::InsufficientQuorum { have: 2, need: 3 }));
+
+        let mut padded = short.clone();
+        padded.precommits.push(padded.precommits[0].clone());
+        assert!(matches!(padded.verify(&validators, &network()), Err(FinalityError::DuplicateVote(_))));
+
+        let mut retargeted = cert.clone();
+        retargeted.block_hash = "dd".into();
+        assert!(matches!(retargeted.verify(&validators, &network()), Err(FinalityError::MismatchedVote(_))));
+        // Votes signed for another network don't count here
+        let other_chain = NetworkId { chain_id: TEST_CHAIN_ID + 1, ..network() };
+        assert!(matches!(cert.verify(&validators, &other_chain), Err(FinalityError::InvalidSignature(_))));
+        let other_genesis = NetworkId { genesis_hash: "11".repeat(32), ..network() };
+        assert!(matches!(cert.verify(&validators, &other_genesis), Err(FinalityError::InvalidSignature(_))));
+    }
+
+    #[test]
+    fn test_quorum_is_weighted_by_stake() {
+        let keys: Vec<HybridKeyPair> = (0..3).map(|_| HybridKeyPair::generate()).collect();
+        let validators = ValidatorSet::new(vec![
+            (keys[0].public_key(), 8),
+            (keys[1].public_key(), 1),
+            (keys[2].public_key(), 1),
+        ]);
+        // One validator with 80% of the stake finalizes alone; the other two together cannot
+        let mut pool = VotePool::new();
+        assert_eq!(pool.add_vote(Vote::new(&keys[1], &network(), VoteType::Precommit, 1, 0, "aa"), &validators, &network()), Ok(None));
+        assert_eq!(pool.add_vote(Vote::new(&keys[2], &network(), VoteType::Precommit, 1, 0, "aa"), &validators, &network()), Ok(None));
+        let cert = pool.add_vote(Vote::new(&keys[0], &network(), VoteType::Precommit, 1, 0, "aa"), &validators, &network()).unwrap().unwrap();
+        assert_eq!(cert.precommits.len(), 3);
+
+        let minority = CommitCertificate { height: 1, round: 0, block_hash: "aa".into(), precommits: cert.precommits.iter()
+            .filter(|v| v.validator != keys[0].public_key().address())
+            .cloned()
+            .collect() };
+        assert_eq!(minority.verify(&validators, &network()), Err(FinalityError::InsufficientQuorum { have: 2, need: 7 }));
+    }
+}
@@ -0,0 +1,234 @@
+// Fork choice for NeoNet - block tree with heaviest-chain selection
+use anyhow::{Result, anyhow};
+use std::collections::HashMap;
+
+use crate::Block;
+
+struct TreeNode {
+    block: Block,
+    /// Sum of proposer weights from genesis up to and including this block
+    total_weight: u128,
+}
+
+/// All known blocks linked by `prev_hash`. The head is the tip with the greatest total
+/// proposer weight; on a tie the current head is kept so nodes don't flap between forks.
+pub struct BlockTree {
+    nodes: HashMap<String, TreeNode>,
+    children: HashMap<String, Vec<String>>,
+    head: String,
+}
+
+impl BlockTree {
+    pub fn new(genesis: Block) -> Self {
+        let head = genesis.hash.clone();
+        let mut nodes = HashMap::new();
+        nodes.insert(head.clone(), TreeNode { block: genesis, total_weight: 0 });
+        BlockTree { nodes, children: HashMap::new(), head }
+    }
+
+    pub fn head(&self) -> &Block {
+        &self.nodes[&self.head].block
+    }
+
+    pub fn head_hash(&self) -> &str {
+        &self.head
+    }
+
+    pub fn contains(&self, hash: &str) -> bool {
+        self.nodes.contains_key(hash)
+    }
+
+    pub fn get(&self, hash: &str) -> Option<&Block> {
+        self.nodes.get(hash).map(|n| &n.block)
+    }
+
+    pub fn total_weight(&self, hash: &str) -> Option<u128> {
+        self.nodes.get(hash).map(|n| n.total_weight)
+    }
+
+    pub fn children(&self, hash: &str) -> &[String] {
+        self.children.get(hash).map(Vec::as_slice).unwrap_or(&[])
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Every known block at `height`
+    pub fn blocks_at(&self, height: u64) -> Vec<&Block> {
+        self.nodes.values().map(|n| &n.block).filter(|b| b.index == height).collect()
+    }
+
+    /// Add a block whose parent is already known, weighted by its proposer.
+    /// Returns true if the block became the new head.
+    pub fn insert(&mut self, block: Block, weight: u64) -> Result<bool> {
+        if self.nodes.contains_key(&block.hash) {
+            return Err(anyhow!("Block {} already known", block.hash));
+        }
+        let parent = self.nodes.get(&block.prev_hash)
+            .ok_or_else(|| anyhow!("Unknown parent {} for block {}", block.prev_hash, block.hash))?;
+        if block.index != parent.block.index + 1 {
+            return Err(anyhow!("Block {} has height {}, expected {}", block.hash, block.index, parent.block.index + 1));
+        }
+
+        let total_weight = parent.total_weight + u128::from(weight);
+        let hash = block.hash.clone();
+        self.children.entry(block.prev_hash.clone()).or_default().push(hash.clone());
+        self.nodes.insert(hash.clone(), TreeNode { block, total_weight });
+
+        if total_weight > self.nodes[&self.head].total_weight {
+            self.head = hash;
+            return Ok(true);
+        }
+        Ok(false)
+    }
+
+    /// Force the head back to a known block, e.g. when a reorg to a better tip fails
+    pub fn set_head(&mut self, hash: &str) -> Result<()> {
+        if !self.nodes.contains_key(hash) {
+            return Err(anyhow!("Unknown block {}", hash));
+        }
+        self.head = hash.to_string();
+        Ok(())
+    }
+
+    /// Remove a block and all of its descendants, returning the removed hashes
+    pub fn remove_subtree(&mut self, hash: &str) -> Vec<String> {
+        let mut removed = vec![];
+        let mut stack = vec![hash.to_string()];
+        while let Some(current) = stack.pop() {
+            if let Some(node) = self.nodes.remove(&current) {
+                if let Some(siblings) = self.children.get_mut(&node.block.prev_hash) {
+                    siblings.retain(|h| h != &current);
+                }
+                stack.extend(self.children.remove(&current).unwrap_or_default());
+                removed.push(current);
+            }
+        }
+        removed
+    }
+
+    /// Remove every block that is neither an ancestor nor a descendant of `hash`,
+    /// returning the removed hashes. Used once `hash` is finalized; the head must already
+    /// descend from it.
+    pub fn prune_conflicting(&mut self, hash: &str) -> Vec<String> {
+        let keep: Vec<(String, String)> = self.branch(hash).iter()
+            .skip(1)
+            .map(|b| (b.prev_hash.clone(), b.hash.clone()))
+            .collect();
+        let mut removed = vec![];
+        for (parent, child) in keep {
+            let siblings: Vec<String> = self.children(&parent).iter().filter(|h| **h != child).cloned().collect();
+            for sibling in siblings {
+                removed.extend(self.remove_subtree(&sibling));
+            }
+        }
+        removed
+    }
+
+    /// Blocks from genesis up to and including `hash`
+    pub fn branch(&self, hash: &str) -> Vec<&Block> {
+        let mut branch = vec![];
+        let mut current = self.nodes.get(hash);
+        while let Some(node) = current {
+            branch.push(&node.block);
+            // Genesis links to a placeholder parent hash, which may equal its own hash
+            if node.block.index == 0 {
+                break;
+            }
+            current = self.nodes.get(&node.block.prev_hash);
+        }
+        branch.reverse();
+        branch
+    }
+
+    /// Deepest block that is an ancestor of (or equal to) both `a` and `b`
+    pub fn common_ancestor(&self, a: &str, b: &str) -> Option<&Block> {
+        let mut a = self.nodes.get(a)?;
+        let mut b = self.nodes.get(b)?;
+        while a.block.index > b.block.index {
+            a = self.nodes.get(&a.block.prev_hash)?;
+        }
+        while b.block.index > a.block.index {
+            b = self.nodes.get(&b.block.prev_hash)?;
+        }
+        while a.block.hash != b.block.hash {
+            a = self.nodes.get(&a.block.prev_hash)?;
+            b = self.nodes.get(&b.block.prev_hash)?;
+        }
+        Some(&a.block)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block(parent: &Block, tag: &str) -> Block {
+        Block {
+            index: parent.index + 1,
+            prev_hash: parent.hash.clone(),
+            hash: format!("{}-{}", parent.index + 1, tag),
+            ..parent.clone()
+        }
+    }
+
+    fn genesis() -> Block {
+        crate::tests::TestNet::new(1).chain().blocks[0].clone()
+    }
+
+    #[test]
+    fn test_heaviest_branch_wins() {
+        let genesis = genesis();
+        let mut tree = BlockTree::new(genesis.clone());
+
+        let a1 = block(&genesis, "a");
+        let a2 = block(&a1, "a");
+        let b1 = block(&genesis, "b");
+        assert!(tree.insert(a1.clone(), 1).unwrap());
+        assert!(tree.insert(a2.clone(), 1).unwrap());
+        assert!(!tree.insert(b1.clone(), 1).unwrap());
+        assert_eq!(tree.head_hash(), a2.hash);
+
+        // A single heavily weighted block outweighs the longer branch
+        let b2 = block(&b1, "b");
+        assert!(tree.insert(b2.clone(), 5).unwrap());
+        assert_eq!(tree.head_hash(), b2.hash);
+        assert_eq!(tree.common_ancestor(&a2.hash, &b2.hash).unwrap().hash, genesis.hash);
+        assert_eq!(tree.branch(&b2.hash).len(), 3);
+
+        tree.set_head(&a2.hash).unwrap();
+        let mut removed = tree.prune_conflicting(&a1.hash);
+        removed.sort();
+        assert_eq!(removed, vec![b1.hash.clone(), b2.hash.clone()]);
+        assert!(tree.contains(&a2.hash));
+    }
+
+    #[test]
+    fn test_equal_weight_keeps_current_head() {
+        let genesis = genesis();
+        let mut tree = BlockTree::new(genesis.clone());
+        let a1 = block(&genesis, "a");
+        tree.insert(a1.clone(), 1).unwrap();
+        assert!(!tree.insert(block(&genesis, "b"), 1).unwrap());
+        assert_eq!(tree.head_hash(), a1.hash);
+    }
+
+    #[test]
+    fn test_rejects_unknown_parent_and_bad_height() {
+        let genesis = genesis();
+        let mut tree = BlockTree::new(genesis.clone());
+        let a1 = block(&genesis, "a");
+        let orphan = block(&a1, "a");
+        assert!(tree.insert(orphan, 1).is_err());
+
+        let mut wrong_height = a1.clone();
+        wrong_height.index = 5;
+        assert!(tree.insert(wrong_height, 1).is_err());
+
+        tree.insert(a1.clone(), 1).unwrap();
+        assert!(tree.insert(a1.clone(), 1).is_err());
+        assert_eq!(tree.remove_subtree(&a1.hash), vec![a1.hash.clone()]);
+        assert_eq!(tree.len(), 1);
+    }
+}
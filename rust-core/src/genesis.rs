@@ -0,0 +1,373 @@
+// Genesis for NeoNet - the network spec every node loads before block 1
+//
+// The spec fixes the chain id, genesis time, initial balances, validators and stakes,
+// pre-deployed contracts and gas and reward parameters. Nothing in it depends on when or
+// where a node starts, so every node derives the same genesis block hash and state root.
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+
+use crate::codec::{impl_codec_enum, impl_codec_struct, Encode};
+use crate::evm_adapter::BLOCK_GAS_LIMIT;
+use crate::pqc::{verify_in_domain, HybridKeyPair, HybridPublicKey, HybridSignature};
+use crate::rewards::{Ledger, RewardConfig, RewardError, NEO};
+use crate::slashing::SlashingConfig;
+use crate::validator_set::{StakingState, ValidatorSet, DEFAULT_EPOCH_LENGTH, MAX_STAKE};
+use crate::{ChainState, DEFAULT_GAS_PRICE};
+
+/// Chain id of locally generated development networks
+pub const DEV_CHAIN_ID: u64 = 7777;
+
+/// The network block and vote signatures are made for: its chain id and genesis block hash.
+/// Signatures by a validator key that is also used on another network, even one sharing
+/// the chain id, cannot pass for blocks, votes or evidence on this one.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct NetworkId {
+    pub chain_id: u64,
+    pub genesis_hash: String,
+}
+
+impl NetworkId {
+    pub fn sign(&self, keypair: &HybridKeyPair, domain: &str, message: &[u8]) -> HybridSignature {
+        keypair.sign_in_domain(domain, self.chain_id, &self.bind(message))
+    }
+
+    pub fn verify(
+        &self,
+        public_key: &HybridPublicKey,
+        domain: &str,
+        message: &[u8],
+        signature: &HybridSignature,
+    ) -> anyhow::Result<bool> {
+        verify_in_domain(public_key, domain, self.chain_id, &self.bind(message), signature)
+    }
+
+    fn bind(&self, message: &[u8]) -> Vec<u8> {
+        (&self.genesis_hash, message).to_bytes()
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ContractVm {
+    Evm,
+    Wasm,
+}
+
+impl_codec_enum!(ContractVm { Evm = 0, Wasm = 1 });
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct GenesisContract {
+    pub address: String,
+    pub vm: ContractVm,
+    /// Runtime code (EVM) or module (WASM), hex encoded
+    pub code: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct GenesisValidator {
+    pub public_key: HybridPublicKey,
+    pub stake: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GasConfig {
+    pub block_gas_limit: u64,
+    /// Lowest gas price a transaction may pay
+    pub min_gas_price: u64,
+}
+
+impl Default for GasConfig {
+    fn default() -> Self {
+        GasConfig { block_gas_limit: BLOCK_GAS_LIMIT, min_gas_price: DEFAULT_GAS_PRICE }
+    }
+}
+
+fn default_epoch_length() -> u64 {
+    DEFAULT_EPOCH_LENGTH
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct GenesisSpec {
+    pub chain_id: u64,
+    /// Timestamp of the genesis block, in milliseconds since the Unix epoch
+    pub genesis_time: u128,
+    /// Initial balances in base units, keyed by `neo1` or `0x` address
+    #[serde(default)]
+    pub allocations: BTreeMap<String, u128>,
+    pub validators: Vec<GenesisValidator>,
+    #[serde(default)]
+    pub contracts: Vec<GenesisContract>,
+    #[serde(default)]
+    pub gas: GasConfig,
+    #[serde(default)]
+    pub rewards: RewardConfig,
+    #[serde(default)]
+    pub slashing: SlashingConfig,
+    #[serde(default = "default_epoch_length")]
+    pub epoch_length: u64,
+}
+
+impl_codec_struct!(GenesisContract { address, vm, code });
+impl_codec_struct!(GenesisValidator { public_key, stake });
+impl_codec_struct!(GasConfig { block_gas_limit, min_gas_price });
+
+impl Encode for GenesisSpec {
+    fn encode(&self, out: &mut Vec<u8>) {
+        let GenesisSpec { chain_id, genesis_time, allocations, validators, contracts, gas, rewards, slashing, epoch_length } = self;
+        let RewardConfig { block_reward, burn_bps, max_supply } = rewards;
+        let SlashingConfig { double_sign_penalty_bps, downtime_penalty_bps, downtime_window, jail_blocks } = slashing;
+        // Allocations are encoded as a sequence of pairs in the map's (sorted) key order
+        let allocations: Vec<_> = allocations.iter().collect();
+        (chain_id, genesis_time, allocations, validators, contracts, gas).encode(out);
+        (block_reward, burn_bps, max_supply).encode(out);
+        (double_sign_penalty_bps, downtime_penalty_bps, *downtime_window as u64, jail_blocks).encode(out);
+        epoch_length.encode(out);
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GenesisError {
+    /// Not a lowercase `neo1` or `0x` address of 20 hex-encoded bytes
+    InvalidAddress(String),
+    NoValidators,
+    ZeroStake(String),
+    /// Stake above `MAX_STAKE`
+    StakeTooLarge(String),
+    DuplicateValidator(String),
+    DuplicateContract(String),
+    InvalidContractCode { address: String, reason: String },
+    ZeroEpochLength,
+    ZeroBlockGasLimit,
+    Allocations(RewardError),
+}
+
+impl fmt::Display for GenesisError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use GenesisError::*;
+        match self {
+            InvalidAddress(address) => write!(f, "invalid address {}", address),
+            NoValidators => write!(f, "genesis has no validators"),
+            ZeroStake(address) => write!(f, "validator {} has no stake", address),
+            StakeTooLarge(address) => write!(f, "validator {} stakes more than {}", address, MAX_STAKE),
+            DuplicateValidator(address) => write!(f, "validator {} listed twice", address),
+            DuplicateContract(address) => write!(f, "contract {} listed twice", address),
+            InvalidContractCode { address, reason } => write!(f, "contract {} has invalid code: {}", address, reason),
+            ZeroEpochLength => write!(f, "epoch length must be positive"),
+            ZeroBlockGasLimit => write!(f, "block gas limit must be positive"),
+            Allocations(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for GenesisError {}
+
+/// Accepts `neo1…` account addresses and `0x…` EVM addresses, both lowercase
+pub fn is_valid_address(address: &str) -> bool {
+    let body = match address.strip_prefix("neo1").or_else(|| address.strip_prefix("0x")) {
+        Some(body) => body,
+        None => return false,
+    };
+    body.len() == 40 && body.chars().all(|c| c.is_ascii_digit() || ('a'..='f').contains(&c))
+}
+
+impl GenesisContract {
+    pub fn code_bytes(&self) -> Result<Vec<u8>, GenesisError> {
+        let invalid = |reason: &str| GenesisError::InvalidContractCode { address: self.address.clone(), reason: reason.to_string() };
+        let code = hex::decode(self.code.trim_start_matches("0x")).map_err(|_| invalid("not hex"))?;
+        if code.is_empty() {
+            return Err(invalid("empty"));
+        }
+        if self.vm == ContractVm::Wasm && !code.starts_with(b"\0asm") {
+            return Err(invalid("missing WASM magic number"));
+        }
+        Ok(code)
+    }
+
+    /// State root leaf committing to the contract's address, VM and code
+    pub fn leaf(&self) -> [u8; 32] {
+        let code_hash = hex::encode(Sha256::digest(self.code_bytes().unwrap_or_default()));
+        Sha256::digest(("contract", &self.address, self.vm, code_hash).to_bytes()).into()
+    }
+}
+
+impl GenesisSpec {
+    /// Spec with the given validators and default parameters, no allocations and no contracts
+    pub fn new(chain_id: u64, genesis_time: u128, validators: Vec<GenesisValidator>) -> Self {
+        GenesisSpec {
+            chain_id,
+            genesis_time,
+            allocations: BTreeMap::new(),
+            validators,
+            contracts: vec![],
+            gas: GasConfig::default(),
+            rewards: RewardConfig::default(),
+            slashing: SlashingConfig::default(),
+            epoch_length: DEFAULT_EPOCH_LENGTH,
+        }
+    }
+
+    /// Single-machine network: every key validates with a stake of 1 and holds 1M NEO
+    pub fn dev(keys: &[HybridPublicKey], genesis_time: u128) -> Self {
+        let validators = keys.iter().map(|k| GenesisValidator { public_key: k.clone(), stake: 1 }).collect();
+        let mut spec = GenesisSpec::new(DEV_CHAIN_ID, genesis_time, validators);
+        spec.allocations = keys.iter().map(|k| (k.address(), 1_000_000 * NEO)).collect();
+        spec
+    }
+
+    pub fn from_json(json: &str) -> anyhow::Result<Self> {
+        let spec: GenesisSpec = serde_json::from_str(json)?;
+        spec.validate()?;
+        Ok(spec)
+    }
+
+    pub fn load(path: &str) -> anyhow::Result<Self> {
+        let json = std::fs::read_to_string(path)?;
+        Self::from_json(&json).map_err(|e| anyhow::anyhow!("Invalid genesis spec {}: {}", path, e))
+    }
+
+    pub fn save(&self, path: &str) -> anyhow::Result<()> {
+        if let Some(dir) = std::path::Path::new(path).parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    /// Hash of the whole spec, committed to by the genesis block
+    pub fn hash(&self) -> String {
+        hex::encode(Sha256::digest(self.to_bytes()))
+    }
+
+    pub fn validate(&self) -> Result<(), GenesisError> {
+        if self.validators.is_empty() {
+            return Err(GenesisError::NoValidators);
+        }
+        let mut seen = HashSet::new();
+        for validator in &self.validators {
+            let address = validator.public_key.address();
+            if validator.stake == 0 {
+                return Err(GenesisError::ZeroStake(address));
+            }
+            if validator.stake > MAX_STAKE {
+                return Err(GenesisError::StakeTooLarge(address));
+            }
+            if !seen.insert(address.clone()) {
+                return Err(GenesisError::DuplicateValidator(address));
+            }
+        }
+        if let Some(address) = self.allocations.keys().find(|a| !is_valid_address(a)) {
+            return Err(GenesisError::InvalidAddress(address.clone()));
+        }
+        let mut seen = HashSet::new();
+        for contract in &self.contracts {
+            if !is_valid_address(&contract.address) {
+                return Err(GenesisError::InvalidAddress(contract.address.clone()));
+            }
+            if !seen.insert(&contract.address) {
+                return Err(GenesisError::DuplicateContract(contract.address.clone()));
+            }
+            contract.code_bytes()?;
+        }
+        if self.epoch_length == 0 {
+            return Err(GenesisError::ZeroEpochLength);
+        }
+        if self.gas.block_gas_limit == 0 {
+            return Err(GenesisError::ZeroBlockGasLimit);
+        }
+        self.ledger().map(|_| ())
+    }
+
+    pub fn validator_set(&self) -> ValidatorSet {
+        ValidatorSet::new(self.validators.iter().map(|v| (v.public_key.clone(), v.stake)).collect())
+    }
+
+    /// Initial balances, with each validator's stake bonded on top of its allocation
+    pub fn ledger(&self) -> Result<Ledger, GenesisError> {
+        let bonds = self.validators.iter().map(|v| (v.public_key.address(), v.stake as u128)).collect();
+        Ledger::with_bonds(self.allocations.clone(), bonds, self.rewards).map_err(GenesisError::Allocations)
+    }
+
+    /// State before block 1
+    pub fn state(&self) -> Result<ChainState, GenesisError> {
+        self.validate()?;
+        let mut staking = StakingState::new(self.validator_set(), self.epoch_length);
+        staking.network.chain_id = self.chain_id;
+        staking.slashing = self.slashing;
+        Ok(ChainState {
+            nonces: HashMap::new(),
+            ledger: self.ledger()?,
+            staking,
+            contracts: self.contracts.iter().map(|c| (c.address.clone(), c.clone())).collect(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pqc::HybridKeyPair;
+    use crate::Chain;
+
+    const WASM_MODULE: &str = "0061736d01000000";
+
+    fn spec() -> GenesisSpec {
+        let keys: Vec<HybridPublicKey> = (0..2).map(|_| HybridKeyPair::generate().public_key()).collect();
+        let mut spec = GenesisSpec::dev(&keys, 1_700_000_000_000);
+        spec.allocations.insert(format!("0x{}", "ab".repeat(20)), 5 * NEO);
+        spec.contracts.push(GenesisContract { address: format!("neo1{}", "cd".repeat(20)), vm: ContractVm::Wasm, code: WASM_MODULE.into() });
+        spec
+    }
+
+    #[test]
+    fn test_genesis_is_deterministic() {
+        let spec = spec();
+        let json = serde_json::to_string_pretty(&spec).unwrap();
+        let reloaded = GenesisSpec::from_json(&json).unwrap();
+        assert_eq!(reloaded, spec);
+
+        let a = Chain::new(&spec).unwrap();
+        let b = Chain::new(&reloaded).unwrap();
+        assert_eq!(a.blocks[0].hash, b.blocks[0].hash);
+        assert_eq!(a.blocks[0].state_root, b.blocks[0].state_root);
+        assert_eq!(a.blocks[0].timestamp, 1_700_000_000_000);
+        assert_eq!(a.balance(&format!("0x{}", "ab".repeat(20))), 5 * NEO);
+
+        let mut other = spec.clone();
+        other.gas.min_gas_price = 2;
+        assert_ne!(Chain::new(&other).unwrap().blocks[0].hash, a.blocks[0].hash);
+        let mut other = spec.clone();
+        other.slashing.downtime_window += 1;
+        assert_ne!(other.hash(), spec.hash());
+        let mut other = spec.clone();
+        other.contracts.clear();
+        assert_ne!(Chain::new(&other).unwrap().blocks[0].state_root, a.blocks[0].state_root);
+    }
+
+    #[test]
+    fn test_invalid_specs_are_rejected() {
+        let mut bad = spec();
+        bad.allocations.insert("alice".into(), 1);
+        assert_eq!(bad.validate(), Err(GenesisError::InvalidAddress("alice".into())));
+
+        let mut bad = spec();
+        bad.validators[1].stake = 0;
+        assert!(matches!(bad.validate(), Err(GenesisError::ZeroStake(_))));
+
+        let mut bad = spec();
+        bad.validators.push(bad.validators[0].clone());
+        assert!(matches!(bad.validate(), Err(GenesisError::DuplicateValidator(_))));
+
+        let mut bad = spec();
+        bad.contracts[0].code = "00".into();
+        assert!(matches!(bad.validate(), Err(GenesisError::InvalidContractCode { .. })));
+
+        let mut bad = spec();
+        bad.rewards.max_supply = NEO;
+        assert!(matches!(bad.validate(), Err(GenesisError::Allocations(RewardError::AllocationsExceedCap { .. }))));
+
+        let minimal = r#"{"chain_id": 1, "genesis_time": 0, "validators": []}"#;
+        assert!(GenesisSpec::from_json(minimal).unwrap_err().to_string().contains("no validators"));
+    }
+}
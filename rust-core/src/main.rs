@@ -2,117 +2,912 @@ mod bridge;
 mod wasm_vm;
 mod pqc;
 mod evm_adapter;
+mod evm_interpreter;
+mod cosmwasm;
+mod mempool;
+mod merkle;
+mod store;
+mod fork_choice;
+mod validation;
+mod finality;
+mod validator_set;
+mod slashing;
+mod codec;
+mod secure_channel;
+mod pow;
+mod rewards;
+mod genesis;
+mod unified_runtime;
+mod eth_rpc;
+mod eth_tx;
 
 use serde::{Deserialize, Serialize};
 use sha2::{Sha256, Digest};
-use std::{fs, time::{SystemTime, UNIX_EPOCH}, collections::VecDeque, sync::{Arc, Mutex}};
+use std::{fmt, time::{SystemTime, UNIX_EPOCH}, collections::{HashMap, HashSet}};
 use wasm_vm::WasmVM;
-use pqc::{HybridKeyPair, verify_hybrid_signature};
+use mempool::{Mempool, MempoolConfig};
+use merkle::MerkleProof;
+use store::BlockStore;
+use fork_choice::BlockTree;
+use validation::ValidationError;
+use finality::{CommitCertificate, FinalityError, Vote, VotePool};
+use validator_set::{StakingOp, StakingState, Validator, ValidatorSet};
+use slashing::{Evidence, Offense, SlashingError};
+use rewards::Ledger;
+use genesis::{ContractVm, GenesisContract, GenesisError, GenesisSpec, NetworkId};
+use std::collections::BTreeMap;
+use std::borrow::Cow;
+use std::sync::Arc;
+use codec::{impl_codec_struct, Encode};
+use anyhow::anyhow;
+use pqc::{HybridKeyPair, HybridPublicKey, HybridSignature, verify_hybrid_signature, verify_in_domain, BLOCK_DOMAIN, TX_DOMAIN};
 use evm_adapter::EVMAdapter;
+use unified_runtime::{DualAddress, DualSignature, ExecutionResult, NeoNetUnifiedFabric, RuntimeType, SignatureMode, UnifiedTransaction};
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 pub struct Tx {
+    /// Network the transaction is valid on, bound into the signature
+    pub chain_id: u64,
     pub from: String,
     pub to: String,
     pub payload: String,
     pub nonce: u64,
+    pub gas_price: u64,
+    pub gas_limit: u64,
+    pub public_key: HybridPublicKey,
+    pub signature: HybridSignature,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+impl_codec_struct!(Tx { chain_id, from, to, payload, nonce, gas_price, gas_limit, public_key, signature });
+
+pub const DEFAULT_GAS_PRICE: u64 = 1;
+pub const TX_BASE_GAS: u64 = 21000;
+/// Gas per byte of payload, which every node stores and hashes
+pub const TX_PAYLOAD_BYTE_GAS: u64 = 16;
+
+impl Tx {
+    /// Build a transaction from the keypair's address and sign it for `chain_id`, paying the
+    /// default gas price with just enough gas for the payload
+    pub fn signed(keypair: &HybridKeyPair, chain_id: u64, to: &str, payload: &str, nonce: u64) -> Self {
+        Self::signed_with_gas(keypair, chain_id, to, payload, nonce, DEFAULT_GAS_PRICE, rewards::intrinsic_gas(payload))
+    }
+
+    pub fn signed_with_gas(
+        keypair: &HybridKeyPair,
+        chain_id: u64,
+        to: &str,
+        payload: &str,
+        nonce: u64,
+        gas_price: u64,
+        gas_limit: u64,
+    ) -> Self {
+        let public_key = keypair.public_key();
+        let mut tx = Tx {
+            chain_id,
+            from: public_key.address(),
+            to: to.to_string(),
+            payload: payload.to_string(),
+            nonce,
+            gas_price,
+            gas_limit,
+            public_key,
+            signature: HybridSignature::default(),
+        };
+        tx.signature = keypair.sign_in_domain(TX_DOMAIN, chain_id, &tx.signing_bytes());
+        tx
+    }
+
+    fn signing_bytes(&self) -> Vec<u8> {
+        (self.chain_id, &self.from, &self.to, &self.payload, self.nonce, self.gas_price, self.gas_limit).to_bytes()
+    }
+
+    /// Hash of the signed fields and the raw Ed25519 and Dilithium signatures, used to detect
+    /// duplicates and as the Merkle leaf. The signature's timestamp and algorithm label and the
+    /// key's Kyber half are not signed, so they are left out and can't be changed to mint a
+    /// new id for the same transaction.
+    pub fn id(&self) -> [u8; 32] {
+        let sig = &self.signature;
+        Sha256::digest((self.signing_bytes(), &sig.ed25519_sig, &sig.dilithium_sig).to_bytes()).into()
+    }
+
+    pub fn hash(&self) -> String {
+        hex::encode(self.id())
+    }
+
+    /// Serialized size, counted against the block size limit
+    pub fn encoded_len(&self) -> usize {
+        self.to_bytes().len()
+    }
+
+    /// Check that the transaction is for `chain_id`, `from` belongs to the attached key, the
+    /// gas limit covers the intrinsic cost and the hybrid signature is valid
+    pub fn verify(&self, chain_id: u64) -> Result<(), TxRejection> {
+        if self.chain_id != chain_id {
+            return Err(TxRejection::WrongChainId { expected: chain_id, got: self.chain_id });
+        }
+        if self.public_key.address() != self.from {
+            return Err(TxRejection::SenderMismatch);
+        }
+        let required = rewards::gas_used(self);
+        if self.gas_limit < required {
+            return Err(TxRejection::IntrinsicGasTooLow { required });
+        }
+        match verify_in_domain(&self.public_key, TX_DOMAIN, self.chain_id, &self.signing_bytes(), &self.signature) {
+            Ok(true) => Ok(()),
+            Ok(false) => Err(TxRejection::InvalidSignature),
+            Err(e) => Err(TxRejection::MalformedSignature(e.to_string())),
+        }
+    }
+}
+
+/// Why `Chain::add_tx` refused a transaction
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TxRejection {
+    /// Signed for another network
+    WrongChainId { expected: u64, got: u64 },
+    /// `from` is not the address of the attached public key
+    SenderMismatch,
+    InvalidSignature,
+    MalformedSignature(String),
+    /// Nonce already used by a committed or pending transaction
+    NonceTooLow { expected: u64, got: u64 },
+    /// Nonce skips ahead of the sender's next nonce
+    NonceGap { expected: u64, got: u64 },
+    Duplicate,
+    /// Same-nonce replacement without a sufficient gas price bump
+    Underpriced { required: u64 },
+    /// Pool is at capacity and the transaction does not outbid the cheapest entry
+    PoolFull,
+    /// Gas limit or size can never fit in a block
+    ExceedsBlockLimits,
+    /// Payload can't be decoded for its destination, e.g. a malformed staking operation
+    InvalidPayload(String),
+    /// Gas limit is below the base cost every transaction pays
+    IntrinsicGasTooLow { required: u64 },
+    /// Gas price is below the network minimum set at genesis
+    GasPriceTooLow { min: u64 },
+    /// Sender's balance can't cover the fee
+    InsufficientFunds { balance: u128, required: u128 },
+}
+
+impl fmt::Display for TxRejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TxRejection::WrongChainId { expected, got } => write!(f, "transaction is for chain {}, expected {}", got, expected),
+            TxRejection::SenderMismatch => write!(f, "sender does not match public key"),
+            TxRejection::InvalidSignature => write!(f, "invalid signature"),
+            TxRejection::MalformedSignature(e) => write!(f, "malformed signature: {}", e),
+            TxRejection::NonceTooLow { expected, got } => write!(f, "nonce too low: expected {}, got {}", expected, got),
+            TxRejection::NonceGap { expected, got } => write!(f, "nonce gap: expected {}, got {}", expected, got),
+            TxRejection::Duplicate => write!(f, "transaction already known"),
+            TxRejection::Underpriced { required } => write!(f, "replacement underpriced: gas price must be at least {}", required),
+            TxRejection::PoolFull => write!(f, "transaction pool is full"),
+            TxRejection::ExceedsBlockLimits => write!(f, "transaction exceeds block gas or size limit"),
+            TxRejection::InvalidPayload(e) => write!(f, "invalid payload: {}", e),
+            TxRejection::IntrinsicGasTooLow { required } => write!(f, "gas limit below intrinsic gas {}", required),
+            TxRejection::GasPriceTooLow { min } => write!(f, "gas price below minimum {}", min),
+            TxRejection::InsufficientFunds { balance, required } => write!(f, "insufficient funds: balance {}, fee {}", balance, required),
+        }
+    }
+}
+
+impl std::error::Error for TxRejection {}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 pub struct Block {
     pub index: u64,
     pub prev_hash: String,
     pub timestamp: u128,
+    /// Merkle root over the ids of `txs`
+    pub tx_root: String,
     pub txs: Vec<Tx>,
+    /// Merkle root over the ids of `evidence`
+    pub evidence_root: String,
+    /// Proof of validator misbehaviour to be punished
+    pub evidence: Vec<Evidence>,
     pub nonce: u64,
+    /// Address of the proposing validator's `HybridPublicKey`
     pub proposer: String,
+    /// Hash of the validator set for this block's epoch
+    pub validator_set_hash: String,
+    /// Root over account balances, nonces and token issuance after executing the block
+    pub state_root: String,
     pub hash: String,
+    /// Proposer's hybrid signature over `hash`
+    pub signature: HybridSignature,
 }
 
+impl_codec_struct!(Block {
+    index,
+    prev_hash,
+    timestamp,
+    tx_root,
+    txs,
+    evidence_root,
+    evidence,
+    nonce,
+    proposer,
+    validator_set_hash,
+    state_root,
+    hash,
+    signature,
+});
+
 impl Block {
-    pub fn compute_hash(&self) -> String {
-        let s = serde_json::to_string(&(
+    /// Canonical encoding of the header fields covered by `hash`
+    pub fn header_bytes(&self) -> Vec<u8> {
+        (
             self.index,
             &self.prev_hash,
             self.timestamp,
-            &self.txs,
+            &self.tx_root,
+            &self.evidence_root,
             self.nonce,
-            &self.proposer
-        )).unwrap();
-        let mut hasher = Sha256::new();
-        hasher.update(s);
-        hex::encode(hasher.finalize())
+            &self.proposer,
+            &self.validator_set_hash,
+            &self.state_root,
+        ).to_bytes()
+    }
+
+    /// Header hash; transactions are committed through `tx_root`
+    pub fn compute_hash(&self) -> String {
+        hex::encode(Sha256::digest(self.header_bytes()))
+    }
+
+    /// Copy of the block without its transactions and evidence; the hash still commits to both
+    pub fn header(&self) -> Block {
+        Block { txs: vec![], evidence: vec![], ..self.clone() }
+    }
+
+    /// Set the proposer, hash the header and sign it with the proposer's key, bound to the
+    /// block domain and `network`
+    pub fn seal(&mut self, keypair: &HybridKeyPair, network: &NetworkId) {
+        self.proposer = keypair.public_key().address();
+        self.hash = self.compute_hash();
+        self.signature = network.sign(keypair, BLOCK_DOMAIN, self.hash.as_bytes());
+    }
+
+    pub fn verify_signature(&self, proposer: &HybridPublicKey, network: &NetworkId) -> anyhow::Result<bool> {
+        network.verify(proposer, BLOCK_DOMAIN, self.hash.as_bytes(), &self.signature)
+    }
+
+    pub fn compute_tx_root(txs: &[Tx]) -> String {
+        let ids: Vec<[u8; 32]> = txs.iter().map(Tx::id).collect();
+        hex::encode(merkle::merkle_root(&ids))
+    }
+
+    pub fn compute_evidence_root(evidence: &[Evidence]) -> String {
+        let ids: Vec<[u8; 32]> = evidence.iter().map(Evidence::id).collect();
+        hex::encode(merkle::merkle_root(&ids))
+    }
+
+    /// Proof that the transaction at `index` is committed by this block's `tx_root`
+    pub fn inclusion_proof(&self, index: usize) -> Option<MerkleProof> {
+        let ids: Vec<[u8; 32]> = self.txs.iter().map(Tx::id).collect();
+        merkle::prove(&ids, index)
+    }
+
+    /// Verify a transaction against a block's `tx_root` without the rest of the block
+    pub fn verify_inclusion(tx_root: &str, tx: &Tx, proof: &MerkleProof) -> bool {
+        let root: [u8; 32] = match hex::decode(tx_root).ok().and_then(|b| b.try_into().ok()) {
+            Some(root) => root,
+            None => return false,
+        };
+        merkle::verify_proof(&root, &tx.id(), proof)
+    }
+}
+
+/// Result of importing a block received from a peer
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ImportOutcome {
+    /// The block extended the canonical chain
+    Extended,
+    /// The block was kept on a branch that is not heavier than the canonical chain
+    SideChain,
+    /// The canonical chain switched to the block's branch
+    Reorged { reverted: usize, applied: usize },
+}
+
+/// Everything block execution changes, as of some block
+#[derive(Debug, Clone)]
+pub struct ChainState {
+    /// Next committed nonce per sender
+    pub nonces: HashMap<String, u64>,
+    /// NEO balances and issuance
+    pub ledger: Ledger,
+    /// Validator sets per epoch and queued staking operations
+    pub staking: StakingState,
+    /// Contracts deployed at genesis, by address
+    pub contracts: BTreeMap<String, GenesisContract>,
+}
+
+impl ChainState {
+    /// State before any block has been applied, for replaying from genesis
+    pub fn reset(&self) -> Self {
+        ChainState {
+            nonces: HashMap::new(),
+            ledger: self.ledger.reset(),
+            staking: self.staking.reset(),
+            contracts: self.contracts.clone(),
+        }
+    }
+
+    /// Merkle root over accounts and issuance, then genesis contracts in address order
+    pub fn state_root(&self) -> String {
+        let mut leaves = rewards::state_leaves(&self.ledger, &self.nonces);
+        leaves.extend(self.contracts.values().map(GenesisContract::leaf));
+        hex::encode(merkle::merkle_root(&leaves))
     }
 }
 
 pub struct Chain {
+    /// Canonical chain from genesis to the fork-choice head
     pub blocks: Vec<Block>,
-    pub mempool: VecDeque<Tx>,
-    pub validators: Vec<String>,
-    pub next_proposer_idx: usize,
+    /// Every known valid block, including side branches
+    pub tree: BlockTree,
+    pub mempool: Mempool,
+    /// Nonces, balances and validator sets as of the head
+    pub state: ChainState,
+    /// State after each known block from the finalized one up, by hash, so imports and
+    /// reorgs start from their parent instead of replaying from genesis
+    snapshots: HashMap<String, ChainState>,
+    /// Verified misbehaviour waiting to be included in a block
+    pub evidence_pool: Vec<Evidence>,
+    /// Persistent block storage; `None` keeps the chain in memory only
+    pub store: Option<BlockStore>,
+    /// Prevotes and precommits for heights above `finalized_height`
+    pub votes: VotePool,
+    /// Highest block committed by a 2/3+ precommit quorum; never reverted by fork choice
+    pub finalized_height: u64,
+    /// Commit certificates by block height
+    pub certificates: HashMap<u64, CommitCertificate>,
+    /// Network spec the chain was started from
+    pub genesis: GenesisSpec,
 }
 
 impl Chain {
-    pub fn new(validators: Vec<String>) -> Self {
-        let genesis = Block {
+    pub fn new(spec: &GenesisSpec) -> Result<Self, GenesisError> {
+        let mut state = spec.state()?;
+        let genesis = Self::genesis_block(spec, &state);
+        state.staking.network.genesis_hash = genesis.hash.clone();
+        Ok(Chain {
+            tree: BlockTree::new(genesis.clone()),
+            snapshots: HashMap::from([(genesis.hash.clone(), state.clone())]),
+            blocks: vec![genesis],
+            mempool: Mempool::new(MempoolConfig { block_gas_limit: spec.gas.block_gas_limit, ..MempoolConfig::default() }),
+            state,
+            evidence_pool: vec![],
+            store: None,
+            votes: VotePool::new(),
+            finalized_height: 0,
+            certificates: HashMap::new(),
+            genesis: spec.clone(),
+        })
+    }
+
+    /// Open a chain persisted at `path`, reloading and re-validating stored blocks,
+    /// or create and persist a fresh genesis block if the store is empty.
+    pub fn open(path: &str, spec: &GenesisSpec) -> anyhow::Result<Self> {
+        let store = BlockStore::open(path)?;
+        let blocks = store.load_chain()?;
+        let mut chain = Chain::new(spec)?;
+
+        if blocks.is_empty() {
+            store.put_block(&chain.blocks[0])?;
+        } else {
+            if blocks[0] != chain.blocks[0] {
+                return Err(anyhow!("Stored chain at {} was created from a different genesis", path));
+            }
+            chain.blocks = blocks;
+            chain.validate().map_err(|e| anyhow!("Stored chain at {} failed validation: {}", path, e))?;
+            let certificates = store.load_certificates()?;
+            let finalized = certificates.iter().map(|c| c.height).max().unwrap_or(0);
+            chain.snapshots.clear();
+            for block in &chain.blocks {
+                validation::apply_state(block, &mut chain.state)?;
+                if block.index >= finalized {
+                    chain.snapshots.insert(block.hash.clone(), chain.state.clone());
+                }
+            }
+            chain.tree = BlockTree::new(chain.blocks[0].clone());
+            for block in &chain.blocks[1..] {
+                chain.tree.insert(block.clone(), chain.proposer_weight(block))?;
+            }
+            chain.tree.set_head(&chain.blocks[chain.blocks.len() - 1].hash)?;
+
+            for cert in certificates {
+                chain.check_certificate(&cert)
+                    .map_err(|e| anyhow!("Stored certificate for block {} is invalid: {}", cert.height, e))?;
+                chain.finalized_height = chain.finalized_height.max(cert.height);
+                chain.certificates.insert(cert.height, cert);
+            }
+        }
+        chain.store = Some(store);
+        Ok(chain)
+    }
+
+    /// Genesis has no parent, so its `prev_hash` commits to the whole spec instead; nodes
+    /// whose specs differ in any parameter end up with different genesis hashes
+    fn genesis_block(spec: &GenesisSpec, state: &ChainState) -> Block {
+        let mut block = Block {
             index: 0,
-            prev_hash: "0".repeat(64),
-            timestamp: now_millis(),
+            prev_hash: spec.hash(),
+            timestamp: spec.genesis_time,
+            tx_root: Block::compute_tx_root(&[]),
             txs: vec![],
+            evidence_root: Block::compute_evidence_root(&[]),
+            evidence: vec![],
             nonce: 0,
             proposer: String::from("genesis"),
-            hash: "0".repeat(64),
+            validator_set_hash: state.staking.current().hash().to_string(),
+            state_root: state.state_root(),
+            hash: String::new(),
+            signature: HybridSignature::default(),
+        };
+        block.hash = block.compute_hash();
+        block
+    }
+
+    /// Verify a signed transaction and add it to the pool
+    pub fn add_tx(&mut self, tx: Tx) -> Result<(), TxRejection> {
+        if self.mempool.contains(&tx.hash()) {
+            return Err(TxRejection::Duplicate);
+        }
+        tx.verify(self.genesis.chain_id)?;
+        if tx.gas_price < self.genesis.gas.min_gas_price {
+            return Err(TxRejection::GasPriceTooLow { min: self.genesis.gas.min_gas_price });
+        }
+        if let Some(Err(e)) = StakingOp::from_tx(&tx) {
+            return Err(TxRejection::InvalidPayload(e));
+        }
+        let (balance, required) = (self.state.ledger.balance(&tx.from), rewards::tx_fee(&tx));
+        if balance < required {
+            return Err(TxRejection::InsufficientFunds { balance, required });
+        }
+        let state_nonce = self.state_nonce(&tx.from);
+        self.mempool.insert(tx, state_nonce, now_millis())?;
+        Ok(())
+    }
+
+    pub fn state_nonce(&self, sender: &str) -> u64 {
+        self.state.nonces.get(sender).copied().unwrap_or(0)
+    }
+
+    /// Next nonce the sender should use, counting its ready pool transactions
+    pub fn next_nonce(&self, sender: &str) -> u64 {
+        self.mempool.next_nonce(sender, self.state_nonce(sender))
+    }
+
+    /// Validator set for the current epoch
+    pub fn validators(&self) -> &ValidatorSet {
+        self.state.staking.current()
+    }
+
+    pub fn balance(&self, address: &str) -> u128 {
+        self.state.ledger.balance(address)
+    }
+
+    /// Chain id and genesis hash that block and vote signatures are bound to
+    pub fn network(&self) -> &NetworkId {
+        &self.state.staking.network
+    }
+
+    /// Validator whose turn it is to propose the block at `height`
+    pub fn scheduled_proposer(&self, height: u64) -> Option<&Validator> {
+        self.state.staking.set_for_height(height)?.proposer(height)
+    }
+
+    /// Fork-choice weight of a canonical block: its proposer's stake; blocks from
+    /// non-validators carry no weight
+    pub fn proposer_weight(&self, block: &Block) -> u64 {
+        self.state.staking.set_for_height(block.index).map_or(0, |set| set.stake_of(&block.proposer))
+    }
+
+    /// State after the block `hash`: the head state, a cached snapshot, or a replay from
+    /// the nearest snapshotted ancestor (genesis if there is none)
+    fn state_at(&self, hash: &str) -> Result<Cow<'_, ChainState>, ValidationError> {
+        if hash == self.tree.head_hash() {
+            return Ok(Cow::Borrowed(&self.state));
+        }
+        if let Some(state) = self.snapshots.get(hash) {
+            return Ok(Cow::Borrowed(state));
+        }
+        let branch = self.tree.branch(hash);
+        let resume = branch.iter().rposition(|b| self.snapshots.contains_key(&b.hash));
+        let mut state = match resume {
+            Some(i) => self.snapshots[&branch[i].hash].clone(),
+            None => self.state.reset(),
         };
-        Chain { blocks: vec![genesis], mempool: VecDeque::new(), validators, next_proposer_idx: 0 }
+        for block in &branch[resume.map_or(0, |i| i + 1)..] {
+            validation::apply_state(block, &mut state)?;
+        }
+        Ok(Cow::Owned(state))
     }
 
-    pub fn add_tx(&mut self, tx: Tx) {
-        self.mempool.push_back(tx);
+    /// Drop snapshots of blocks below the finalized height or no longer in the tree
+    fn prune_snapshots(&mut self) {
+        let (tree, finalized_height) = (&self.tree, self.finalized_height);
+        self.snapshots.retain(|hash, _| tree.get(hash).is_some_and(|b| b.index >= finalized_height));
     }
 
-    pub fn rotate_proposer(&mut self) -> String {
-        if self.validators.is_empty() { return "".into(); }
-        let p = self.validators[self.next_proposer_idx % self.validators.len()].clone();
-        self.next_proposer_idx = (self.next_proposer_idx + 1) % self.validators.len();
-        p
+    /// Import a block from a peer into the block tree and switch to the heaviest branch
+    pub fn import_block(&mut self, block: Block) -> anyhow::Result<ImportOutcome> {
+        if self.tree.contains(&block.hash) {
+            return Err(anyhow!("Block {} already known", block.hash));
+        }
+        if block.index <= self.finalized_height {
+            return Err(FinalityError::ConflictsWithFinalized { height: block.index, finalized_height: self.finalized_height }.into());
+        }
+        let parent = self.tree.get(&block.prev_hash)
+            .ok_or_else(|| ValidationError::UnknownParent { prev_hash: block.prev_hash.clone() })?;
+        let mut state = self.state_at(&block.prev_hash)?.into_owned();
+        let validators = state.staking.set_for_height(block.index).ok_or(ValidationError::NoValidators)?;
+        validation::validate_block(&block, parent, validators, &self.genesis, self.network(), now_millis())?;
+        let weight = validators.stake_of(&block.proposer);
+        for evidence in &block.evidence {
+            evidence.links_into(&self.tree)
+                .map_err(|reason| ValidationError::InvalidEvidence { height: block.index, reason })?;
+        }
+        validation::apply_state(&block, &mut state)?;
+
+        let double_proposals: Vec<Evidence> = self.tree.blocks_at(block.index).into_iter()
+            .filter(|other| other.proposer == block.proposer)
+            .map(|other| Evidence::double_proposal(other, &block))
+            .collect();
+        // Evidence that is already queued or punished is refused; queued evidence ends up
+        // in `evidence_pool`
+        for evidence in double_proposals {
+            let _ = self.submit_evidence(evidence);
+        }
+        let hash = block.hash.clone();
+        let old_head = self.tree.head_hash().to_string();
+        self.snapshots.insert(hash.clone(), state);
+        if !self.tree.insert(block, weight)? {
+            return Ok(ImportOutcome::SideChain);
+        }
+        self.reorg_to(&hash, &old_head)
     }
 
-    pub fn mine_block(&mut self) -> Block {
-        // deterministic proposer rotation
-        let proposer = self.rotate_proposer();
-        let txs: Vec<Tx> = self.mempool.drain(..).collect();
+    /// Move the canonical chain to `new_head`, reverting blocks back to the common ancestor
+    /// and replaying the new branch from its deepest snapshot. Transactions only on the
+    /// reverted branch go back to the mempool. If the new branch turns out to be invalid it
+    /// is dropped from the tree.
+    fn reorg_to(&mut self, new_head: &str, old_head: &str) -> anyhow::Result<ImportOutcome> {
+        let ancestor = self.tree.common_ancestor(old_head, new_head)
+            .ok_or_else(|| anyhow!("Block {} does not share history with the chain", new_head))?
+            .index as usize;
+        if (ancestor as u64) < self.finalized_height {
+            let height = ancestor as u64 + 1;
+            if let Some(first) = self.tree.branch(new_head).get(ancestor + 1).map(|b| b.hash.clone()) {
+                self.tree.remove_subtree(&first);
+            }
+            self.tree.set_head(old_head)?;
+            self.prune_snapshots();
+            return Err(FinalityError::ConflictsWithFinalized { height, finalized_height: self.finalized_height }.into());
+        }
+        let applied: Vec<Block> = self.tree.branch(new_head)[ancestor + 1..].iter().map(|b| (*b).clone()).collect();
+
+        let resume = applied.iter().rposition(|b| self.snapshots.contains_key(&b.hash));
+        let mut state = match resume {
+            Some(i) => self.snapshots[&applied[i].hash].clone(),
+            None if self.blocks[ancestor].hash == old_head => self.state.clone(),
+            None => self.state_at(&self.blocks[ancestor].hash)?.into_owned(),
+        };
+        for block in &applied[resume.map_or(0, |i| i + 1)..] {
+            if let Err(e) = validation::apply_state(block, &mut state) {
+                self.tree.remove_subtree(&block.hash);
+                self.tree.set_head(old_head)?;
+                self.prune_snapshots();
+                return Err(e.into());
+            }
+            self.snapshots.insert(block.hash.clone(), state.clone());
+        }
+
+        let reverted = self.blocks[ancestor + 1..].to_vec();
+        if let Some(store) = &self.store {
+            if let Err(e) = store.put_branch(&reverted, &applied) {
+                self.tree.set_head(old_head)?;
+                return Err(e);
+            }
+        }
+        self.blocks.truncate(ancestor + 1);
+        self.blocks.extend(applied.iter().cloned());
+        self.state = state;
+
+        let included: HashSet<String> = applied.iter().flat_map(|b| b.txs.iter().map(Tx::hash)).collect();
+        for tx in reverted.iter().flat_map(|b| b.txs.iter()) {
+            if !included.contains(&tx.hash()) {
+                let state_nonce = self.state_nonce(&tx.from);
+                let _ = self.mempool.insert(tx.clone(), state_nonce, now_millis());
+            }
+        }
+        self.mempool.prune(&self.state.nonces, now_millis());
+        self.evidence_pool.extend(reverted.iter().flat_map(|b| b.evidence.iter().cloned()));
+        self.prune_evidence();
+
+        if reverted.is_empty() {
+            Ok(ImportOutcome::Extended)
+        } else {
+            Ok(ImportOutcome::Reorged { reverted: reverted.len(), applied: applied.len() })
+        }
+    }
+
+    /// Verify and record a prevote or precommit. When a precommit completes a 2/3+ quorum
+    /// the block is finalized and its certificate returned. Votes at or below the finalized
+    /// height are ignored.
+    pub fn add_vote(&mut self, vote: Vote) -> anyhow::Result<Option<CommitCertificate>> {
+        if vote.height <= self.finalized_height {
+            return Ok(None);
+        }
+        let validators = self.state.staking.set_for_height(vote.height)
+            .ok_or(FinalityError::UnknownValidatorSet { height: vote.height })?;
+        match self.votes.add_vote(vote, validators, &self.state.staking.network) {
+            Ok(Some(cert)) => {
+                self.finalize(cert.clone())?;
+                Ok(Some(cert))
+            }
+            Ok(None) => Ok(None),
+            Err(FinalityError::Equivocation { first, second }) => {
+                let _ = self.submit_evidence(Evidence::DoubleVote { first: first.clone(), second: second.clone() });
+                Err(FinalityError::Equivocation { first, second }.into())
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Verify evidence of misbehaviour and queue it for the next block, returning the offense
+    /// it proves
+    pub fn submit_evidence(&mut self, evidence: Evidence) -> Result<Offense, SlashingError> {
+        evidence.links_into(&self.tree)?;
+        let staking = &self.state.staking;
+        let offense = evidence.verify(staking)?;
+        let queued = self.evidence_pool.iter()
+            .any(|e| e.verify(staking).map(|o| o.id) == Ok(offense.id.clone()));
+        if queued || staking.is_punished(&offense.id) {
+            return Err(SlashingError::AlreadyPunished(offense.id));
+        }
+        self.evidence_pool.push(evidence);
+        Ok(offense)
+    }
+
+    /// Drop pooled evidence that no longer verifies or was punished by a committed block
+    fn prune_evidence(&mut self) {
+        let mut seen = HashSet::new();
+        let staking = &self.state.staking;
+        self.evidence_pool.retain(|e| match e.verify(staking) {
+            Ok(offense) => !staking.is_punished(&offense.id) && seen.insert(offense.id),
+            Err(_) => false,
+        });
+    }
+
+    /// Finalize the block named by a commit certificate, switching the canonical chain to it
+    /// if needed and dropping every branch that conflicts with it. Returns false if the block
+    /// was already final.
+    pub fn finalize(&mut self, cert: CommitCertificate) -> anyhow::Result<bool> {
+        if cert.height <= self.finalized_height {
+            if self.blocks.get(cert.height as usize).map(|b| &b.hash) != Some(&cert.block_hash) {
+                return Err(FinalityError::ConflictsWithFinalized { height: cert.height, finalized_height: self.finalized_height }.into());
+            }
+            return Ok(false);
+        }
+        self.check_certificate(&cert)?;
+
+        let head = self.tree.head_hash().to_string();
+        let on_canonical = self.blocks.get(cert.height as usize).map(|b| &b.hash) == Some(&cert.block_hash);
+        if !on_canonical {
+            self.tree.set_head(&cert.block_hash)?;
+            self.reorg_to(&cert.block_hash, &head)?;
+        }
+        if let Some(store) = &self.store {
+            store.put_certificate(&cert)?;
+        }
+        self.tree.prune_conflicting(&cert.block_hash);
+        self.finalized_height = cert.height;
+        self.prune_snapshots();
+        self.votes.prune(cert.height);
+        self.certificates.insert(cert.height, cert);
+        Ok(true)
+    }
+
+    /// A certificate must carry a valid quorum for a known block at the height it claims,
+    /// on a branch that contains the finalized block
+    fn check_certificate(&self, cert: &CommitCertificate) -> Result<(), FinalityError> {
+        let validators = self.state.staking.set_for_height(cert.height)
+            .ok_or(FinalityError::UnknownValidatorSet { height: cert.height })?;
+        cert.verify(validators, self.network())?;
+        let block = self.tree.get(&cert.block_hash)
+            .filter(|b| b.index == cert.height)
+            .ok_or_else(|| FinalityError::UnknownBlock(cert.block_hash.clone()))?;
+        let finalized = &self.blocks[self.finalized_height as usize].hash;
+        match self.tree.common_ancestor(&block.hash, finalized) {
+            Some(ancestor) if ancestor.index == self.finalized_height => Ok(()),
+            _ => Err(FinalityError::ConflictsWithFinalized { height: cert.height, finalized_height: self.finalized_height }),
+        }
+    }
+
+    /// Build, sign and append the next block. `keypair` must belong to the validator
+    /// scheduled for the new height.
+    pub fn mine_block(&mut self, keypair: &HybridKeyPair) -> anyhow::Result<Block> {
         let prev = self.blocks.last().unwrap();
+        // deterministic proposer rotation
+        let proposer = keypair.public_key().address();
+        let scheduled = self.scheduled_proposer(prev.index + 1).map(|v| v.address.as_str());
+        if scheduled != Some(proposer.as_str()) {
+            return Err(anyhow!("{} is not the scheduled proposer for block {}", proposer, prev.index + 1));
+        }
+        let validator_set_hash = self.state.staking.set_for_height(prev.index + 1).unwrap().hash().to_string();
+        // Senders that can no longer pay their fees keep their transactions in the pool
+        let mut ledger = self.state.ledger.clone();
+        let mut unfunded = HashSet::new();
+        let txs: Vec<Tx> = self.mempool.select(&self.state.nonces).into_iter()
+            .filter(|tx| {
+                if unfunded.contains(&tx.from) || ledger.charge_fee(tx).is_err() {
+                    unfunded.insert(tx.from.clone());
+                    return false;
+                }
+                true
+            })
+            .collect();
+        let evidence = self.evidence_pool.clone();
         let mut block = Block {
             index: prev.index + 1,
             prev_hash: prev.hash.clone(),
-            timestamp: now_millis(),
+            timestamp: now_millis().max(prev.timestamp),
+            tx_root: Block::compute_tx_root(&txs),
             txs,
+            evidence_root: Block::compute_evidence_root(&evidence),
+            evidence,
             nonce: 0,
-            proposer: proposer.clone(),
+            proposer,
+            validator_set_hash,
+            state_root: String::new(),
             hash: String::new(),
+            signature: HybridSignature::default(),
         };
-        block.hash = block.compute_hash();
+        let mut state = self.state.clone();
+        block.state_root = validation::execute_block(&block, &mut state)?;
+        block.seal(keypair, self.network());
+        if let Some(store) = &self.store {
+            store.put_block(&block)?;
+        }
+        self.tree.insert(block.clone(), self.proposer_weight(&block))?;
+        self.tree.set_head(&block.hash)?;
+        self.snapshots.insert(block.hash.clone(), state.clone());
+        self.state = state;
+        self.mempool.prune(&self.state.nonces, now_millis());
+        self.prune_evidence();
         self.blocks.push(block.clone());
-        println!("Mined block {} by {}", block.index, proposer);
-        block
+        println!("Mined block {} by {}", block.index, block.proposer);
+        Ok(block)
     }
 
-    pub fn validate(&self) -> bool {
+    /// Re-validate the canonical chain, returning the first failure
+    pub fn validate(&self) -> Result<(), ValidationError> {
+        let now = now_millis();
+        let mut state = self.state.reset();
+        validation::apply_state(&self.blocks[0], &mut state)?;
         for i in 1..self.blocks.len() {
             let cur = &self.blocks[i];
             let prev = &self.blocks[i-1];
-            if cur.prev_hash != prev.hash { return false; }
-            if cur.compute_hash() != cur.hash { return false; }
+            let validators = state.staking.set_for_height(cur.index).ok_or(ValidationError::NoValidators)?;
+            validation::validate_block(cur, prev, validators, &self.genesis, self.network(), now)?;
+            validation::apply_state(cur, &mut state)?;
         }
-        true
+        Ok(())
     }
 }
 
+/// Load the genesis spec at `path`. If there is none, start a development network whose
+/// validators are three keys generated into `key_dir`, and save its spec for later runs.
+fn load_or_create_genesis(path: &str, key_dir: &str) -> anyhow::Result<GenesisSpec> {
+    if std::path::Path::new(path).exists() {
+        return GenesisSpec::load(path);
+    }
+    let keys = (1..=3)
+        .map(|i| load_or_create_key(&format!("{}/validator{}.key", key_dir, i)).map(|k| k.public_key()))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let spec = GenesisSpec::dev(&keys, now_millis());
+    spec.save(path)?;
+    Ok(spec)
+}
+
+/// Every `.key` file in `dir`: the validator keys this node can propose with
+fn load_keys(dir: &str) -> anyhow::Result<Vec<HybridKeyPair>> {
+    let mut paths: Vec<_> = std::fs::read_dir(dir)?
+        .map(|entry| entry.map(|e| e.path()))
+        .collect::<Result<_, _>>()?;
+    paths.retain(|p| p.extension().is_some_and(|ext| ext == "key"));
+    paths.sort();
+    paths.iter().map(|p| load_or_create_key(&p.to_string_lossy())).collect()
+}
+
+/// Load a hybrid keypair saved by a previous run, or generate and save a new one that only
+/// its owner can read. A saved key that group or others can access is refused.
+fn load_or_create_key(path: &str) -> anyhow::Result<HybridKeyPair> {
+    HybridKeyPair::restore(&load_or_create_secret(path, || HybridKeyPair::generate().to_bytes())?)
+}
+
+/// Secret key bytes saved hex-encoded at `path` by a previous run, or `generate`d and saved
+/// so that only their owner can read them. A saved key that group or others can access is
+/// refused.
+fn load_or_create_secret(path: &str, generate: impl FnOnce() -> Vec<u8>) -> anyhow::Result<Vec<u8>> {
+    use std::io::{Read, Write};
+
+    match std::fs::File::open(path) {
+        Ok(mut file) => {
+            check_key_permissions(path, &file)?;
+            let mut hex_key = String::new();
+            file.read_to_string(&mut hex_key)?;
+            return Ok(hex::decode(hex_key.trim())?);
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => return Err(anyhow!("Cannot read key file {}: {}", path, e)),
+    }
+    let secret = generate();
+    if let Some(dir) = std::path::Path::new(path).parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options.open(path)?.write_all(hex::encode(&secret).as_bytes())?;
+    Ok(secret)
+}
+
+#[cfg(unix)]
+fn check_key_permissions(path: &str, file: &std::fs::File) -> anyhow::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let mode = file.metadata()?.permissions().mode() & 0o777;
+    if mode & 0o077 != 0 {
+        return Err(anyhow!("Key file {} has mode {:o}; restrict it to its owner with chmod 600", path, mode));
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn check_key_permissions(_path: &str, _file: &std::fs::File) -> anyhow::Result<()> {
+    Ok(())
+}
+
 fn now_millis() -> u128 {
     let d = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
     d.as_millis()
 }
 
+/// A CosmWasm contract whose `instantiate`, `execute` and `migrate` entry points respond with
+/// the attribute action=greet, and whose `query` returns "hello"
+const GREETER_CONTRACT: &str = concat!(
+    "0061736d01000000011a0560037f7f7f017f60000060017f017f60017f0060027f7f017f030908000102030000040405",
+    "030100010607017f014180080b076208066d656d6f7279020013696e746572666163655f76657273696f6e5f38000108",
+    "616c6c6f6361746500020a6465616c6c6f6361746500030b696e7374616e746961746500040765786563757465000505",
+    "71756572790006076d69677261746500070a76082601017f230021032003410c6a240020032000360200200320013602",
+    "042003200236020820030b02000b1a01017f2300410c6a2000410010002101230020006a240020010b02000b0a004100",
+    "4138413810000b0a0041004138413810000b0b0041c0004111411110000b0a0041004138413810000b0b55020041000b",
+    "387b226f6b223a7b2261747472696275746573223a5b7b226b6579223a22616374696f6e222c2276616c7565223a2267",
+    "72656574227d5d7d7d0041c0000b117b226f6b223a22614756736247383d227d004a046e616d65010901000672656769",
+    "6f6e022f02000400066f66667365740108636170616369747902066c656e67746803037074720202000473697a650103",
+    "707472070701000468656170",
+);
+
+/// Take GREETER_CONTRACT through the CosmWasm lifecycle: store the code, instantiate it with
+/// funds, then execute, query, migrate and hand off the admin role
+fn run_greeter(vm: &mut WasmVM) -> anyhow::Result<String> {
+    vm.fund_account("alice", 1000)?;
+    let code_id = vm.store_code("alice", hex::decode(GREETER_CONTRACT)?)?;
+    let checksum = vm.get_code(code_id).map_or(String::new(), |code| code.checksum[..8].to_string());
+    let funds = [cosmwasm::Coin::new(250, cosmwasm::NATIVE_DENOM)];
+    let (address, response) = vm.instantiate(code_id, "alice", &serde_json::json!({}), &funds, Some("alice".to_string()), "greeter")?;
+    vm.execute(&address, "alice", &serde_json::json!({ "greet": {} }), &[])?;
+    let greeting = vm.query(&address, &serde_json::json!({ "greeting": {} }))?;
+    vm.migrate(&address, "alice", code_id, &serde_json::json!({}))?;
+    vm.update_admin(&address, "alice", None)?;
+    Ok(format!("code {} ({}), instance {} holds {}, action={}, query returned {}",
+        code_id, checksum, address, vm.balance_of(&address),
+        response.attribute("action").unwrap_or("-"), String::from_utf8_lossy(&greeting)))
+}
+
 fn main() {
     println!("=== NeoNet Blockchain Core Starting ===");
     println!("Version: 0.1.0 - Web4 AI-Powered Blockchain");
@@ -127,9 +922,25 @@ fn main() {
     
     println!("\n2. Initializing WASM Virtual Machine...");
     let mut wasm_vm = WasmVM::new(1000000);
-    let contract_code = vec![0x00, 0x61, 0x73, 0x6d];
+    // (module (func (export "main") (result i32) i32.const 42))
+    let contract_code = vec![
+        0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00,
+        0x01, 0x05, 0x01, 0x60, 0x00, 0x01, 0x7f,
+        0x03, 0x02, 0x01, 0x00,
+        0x07, 0x08, 0x01, 0x04, 0x6d, 0x61, 0x69, 0x6e, 0x00, 0x00,
+        0x0a, 0x06, 0x01, 0x04, 0x00, 0x41, 0x2a, 0x0b,
+    ];
     wasm_vm.deploy_contract("wasm_contract_1".to_string(), contract_code).unwrap();
-    println!("   WASM VM: Contract deployed, Gas used: {}", wasm_vm.get_gas_used());
+    let size = wasm_vm.get_contract("wasm_contract_1").map_or(0, |c| c.code.len());
+    let output = wasm_vm.execute_wasm("wasm_contract_1", &[]).unwrap();
+    wasm_vm.deposit("wasm_contract_1", 100).unwrap();
+    let balance = wasm_vm.call_contract("wasm_contract_1", "get_balance", vec![]).unwrap();
+    println!("   WASM VM: Contract deployed ({} bytes), returned {}, balance {}, Gas used: {}",
+        size, String::from_utf8_lossy(&output), balance, wasm_vm.get_gas_used());
+    match run_greeter(&mut wasm_vm) {
+        Ok(summary) => println!("   CosmWasm: {}", summary),
+        Err(e) => println!("   CosmWasm: {}", e),
+    }
     
     println!("\n3. Initializing EVM Adapter...");
     let mut evm = EVMAdapter::new();
@@ -141,43 +952,143 @@ fn main() {
         evm.get_balance("0xalice").unwrap(),
         evm.get_balance("0xbob").unwrap()
     );
+    // Runtime code returning the word 42
+    let answer = evm.deploy_contract("0xalice", hex::decode("602a60005260206000f3").unwrap()).unwrap();
+    let output = evm.call_contract("0xalice", &answer, vec![], 0, 100_000).unwrap();
+    println!("   EVM: Contract {} returned 0x{}", answer, hex::encode(output));
     
     println!("\n4. Starting Blockchain...");
-    bridge::start_bridge();
+    let genesis_path = std::env::var("NEONET_GENESIS").unwrap_or_else(|_| "neonet_data/genesis.json".to_string());
+    let spec = load_or_create_genesis(&genesis_path, "neonet_data/validators").expect("failed to load genesis spec");
+    // Unified EVM/WASM runtime; blocks the bridge commits commit its state
+    let fabric = Arc::new(NeoNetUnifiedFabric::new(spec.chain_id));
+    bridge::start_bridge(fabric.clone());
+    let validator_keys = load_keys("neonet_data/validators").expect("failed to load validator keys");
+    let mut chain = Chain::open("neonet_data/chain", &spec).expect("failed to open chain store");
+    let proposer_key = |chain: &Chain| {
+        let proposer = chain.scheduled_proposer(chain.blocks.len() as u64).expect("no scheduled proposer");
+        validator_keys.iter().find(|k| k.public_key().address() == proposer.address).expect("proposer key not held locally")
+    };
     
-    let validators = vec!["validator1".into(), "validator2".into(), "validator3".into()];
-    let mut chain = Chain::new(validators);
+    println!("   Genesis {} (chain id {})", chain.blocks[0].hash, spec.chain_id);
+    println!("   Chain loaded at height {}", chain.blocks.len() - 1);
+    evm.set_chain_id(spec.chain_id);
+    wasm_vm.set_block_info(chain.blocks.len() as u64 - 1, now_millis() as u64 * 1_000_000, &spec.chain_id.to_string());
+    for (address, balance) in spec.allocations.iter().filter(|(a, _)| a.starts_with("0x")) {
+        let _ = evm.create_account(address.clone(), *balance);
+    }
+    for contract in &spec.contracts {
+        let code = contract.code_bytes().expect("genesis spec was validated");
+        let deployed = match contract.vm {
+            ContractVm::Evm => evm.install_contract(&contract.address, code),
+            ContractVm::Wasm => wasm_vm.deploy_contract(contract.address.clone(), code),
+        };
+        if let Err(e) = deployed {
+            println!("   Genesis contract {} not deployed: {}", contract.address, e);
+        }
+    }
+    let rpc_store = chain.store.clone().expect("chain store is open");
+    eth_rpc::start_eth_rpc(eth_rpc::EthRpc::new(evm, rpc_store).expect("failed to read EVM receipts"));
     
-    println!("   Genesis block created");
+    let alice = &validator_keys[0];
+    let bob = &validator_keys[1];
+    let bob_address = bob.public_key().address();
     
-    chain.add_tx(Tx{
-        from: "alice".into(), 
-        to: "bob".into(), 
-        payload: "transfer 10 NEO".into(), 
-        nonce: 0
-    });
+    let nonce = chain.next_nonce(&alice.public_key().address());
+    chain.add_tx(Tx::signed(alice, spec.chain_id, &bob_address, "transfer 10 NEO", nonce)).unwrap();
     
-    let block1 = chain.mine_block();
+    let block1 = chain.mine_block(proposer_key(&chain)).unwrap();
     println!("   Block {} mined by {}", block1.index, block1.proposer);
     
-    chain.add_tx(Tx{
-        from: "bob".into(),
-        to: "charlie".into(),
-        payload: "transfer 5 NEO".into(),
-        nonce: 1
-    });
+    let nonce = chain.next_nonce(&bob_address);
+    chain.add_tx(Tx::signed(bob, spec.chain_id, "charlie", "transfer 5 NEO", nonce)).unwrap();
     
-    let block2 = chain.mine_block();
+    let block2 = chain.mine_block(proposer_key(&chain)).unwrap();
     println!("   Block {} mined by {}", block2.index, block2.proposer);
     
-    println!("   Chain validation: {}", chain.validate());
+    println!("   Chain validation: {}", chain.validate().is_ok());
     println!("   Total blocks: {}", chain.blocks.len());
+    println!("   NEO supply: {} (minted {}, burned {})", chain.state.ledger.supply, chain.state.ledger.minted, chain.state.ledger.burned);
+    
+    println!("\n5. Executing on the Unified Runtime...");
+    let report = |label: &str, result: ExecutionResult| {
+        if result.success {
+            println!("   {} on {:?}: gas used {}", label, result.runtime_used, result.gas_used);
+        } else {
+            println!("   {} failed: {}", label, String::from_utf8_lossy(&result.return_data));
+        }
+    };
+    let wallet = k256::ecdsa::SigningKey::random(&mut rand::rngs::OsRng);
+    let sender = DualAddress::from_evm(eth_tx::evm_address(wallet.verifying_key()));
+    fabric.state_engine.create_account(sender.clone());
+    fabric.state_engine.update_balance(&sender.account_id, 1_000_000);
+    let mut unified_tx = UnifiedTransaction {
+        tx_hash: [0u8; 32],
+        chain_id: spec.chain_id,
+        from: sender,
+        to: Some(DualAddress::from_evm([0xb0; 20])),
+        value: 1000,
+        gas_limit: 100_000,
+        gas_price: 1,
+        nonce: 0,
+        data: vec![],
+        signature: DualSignature { ecdsa_sig: None, ecdsa_v: None, dilithium_sig: None, signature_mode: SignatureMode::EVMOnly, eth_envelope: None },
+        runtime_hint: None,
+        cross_runtime_calls: vec![],
+        timestamp: (now_millis() / 1000) as u64,
+    };
+    unified_tx.sign_ecdsa(&wallet);
+    unified_tx.tx_hash = unified_tx.compute_hash();
+    report("Unified tx", fabric.execute_transaction(unified_tx, &DualAddress::from_evm([0xc0; 20])));
+    // The same wallet sends an EIP-1559 transaction as raw bytes, as it would over JSON-RPC
+    let fields = vec![
+        eth_tx::encode_uint(spec.chain_id as u128),
+        eth_tx::encode_uint(1),
+        eth_tx::encode_uint(1),
+        eth_tx::encode_uint(1),
+        eth_tx::encode_uint(100_000),
+        eth_tx::encode_bytes(&[0xb0; 20]),
+        eth_tx::encode_uint(1000),
+        eth_tx::encode_bytes(&[]),
+        eth_tx::encode_list(&[]),
+    ];
+    let raw = eth_tx::sign_tx(&wallet, eth_tx::EthTxType::DynamicFee, spec.chain_id, fields);
+    match eth_tx::decode_unified_tx(&raw, (now_millis() / 1000) as u64) {
+        Ok(eth_unified) => report("Ethereum tx", fabric.execute_transaction(eth_unified, &DualAddress::from_evm([0xc0; 20]))),
+        Err(e) => println!("   Ethereum tx rejected: {}", e),
+    }
+    // An account signing with the Dilithium half of the keypair from step 1 registers its key first
+    let pq_sender = DualAddress::from_evm([0xd0; 20]);
+    fabric.state_engine.create_account(pq_sender.clone());
+    fabric.state_engine.update_balance(&pq_sender.account_id, 1_000_000);
+    fabric.state_engine.register_quantum_key(&pq_sender.account_id, public_key.dilithium_public.clone());
+    let mut pq_tx = UnifiedTransaction {
+        tx_hash: [0u8; 32],
+        chain_id: spec.chain_id,
+        from: pq_sender,
+        to: Some(DualAddress::from_evm([0xb0; 20])),
+        value: 1000,
+        gas_limit: 100_000,
+        gas_price: 1,
+        nonce: 0,
+        data: vec![],
+        signature: DualSignature { ecdsa_sig: None, ecdsa_v: None, dilithium_sig: None, signature_mode: SignatureMode::QuantumOnly, eth_envelope: None },
+        runtime_hint: Some(RuntimeType::WASM),
+        cross_runtime_calls: vec![],
+        timestamp: (now_millis() / 1000) as u64,
+    };
+    pq_tx.signature.dilithium_sig = Some(keypair.sign(&pq_tx.pq_signing_message()).dilithium_sig);
+    pq_tx.tx_hash = pq_tx.compute_hash();
+    report("Quantum-signed tx", fabric.execute_transaction(pq_tx, &DualAddress::from_evm([0xc0; 20])));
     
     println!("\n=== NeoNet Core Initialized Successfully ===");
     println!("Bridge running on port 6000");
     println!("Blockchain Core: {} blocks", chain.blocks.len());
     println!("WASM VM: Ready");
     println!("EVM Adapter: Ready");
+    let root = fabric.state_engine.last_commit();
+    println!("Unified Runtime: state root {} at height {}", hex::encode(root.hash), root.height);
+    println!("Ethereum JSON-RPC on {}", std::env::var("NEONET_ETH_RPC_ADDR").unwrap_or_else(|_| eth_rpc::DEFAULT_RPC_ADDR.to_string()));
     println!("PQC: Ready (Ed25519-Hybrid)");
     println!("\nPress Ctrl+C to shutdown");
     
@@ -185,3 +1096,573 @@ fn main() {
         std::thread::sleep(std::time::Duration::from_secs(60));
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use codec::Decode;
+    use finality::VoteType;
+    use genesis::GenesisValidator;
+
+    /// Chain id of every test network
+    pub(crate) const TEST_CHAIN_ID: u64 = 1;
+
+    /// Validator keys for building chains and signed blocks in tests
+    pub(crate) struct TestNet {
+        pub(crate) keys: Vec<HybridKeyPair>,
+    }
+
+    impl TestNet {
+        pub(crate) fn new(validators: usize) -> Self {
+            TestNet { keys: (0..validators).map(|_| HybridKeyPair::generate()).collect() }
+        }
+
+        /// Every validator with a stake of 1
+        pub(crate) fn validators(&self) -> ValidatorSet {
+            ValidatorSet::with_equal_stake(self.keys.iter().map(HybridKeyPair::public_key).collect(), 1)
+        }
+
+        fn key(&self, address: &str) -> &HybridKeyPair {
+            self.keys.iter().find(|k| k.public_key().address() == address).expect("not a test validator")
+        }
+
+        /// Spec with every validator at a stake of 1 that gives each of `accounts` 1,000 NEO
+        pub(crate) fn genesis(&self, accounts: &[&HybridKeyPair]) -> GenesisSpec {
+            let validators = self.keys.iter().map(|k| GenesisValidator { public_key: k.public_key(), stake: 1 }).collect();
+            let mut spec = GenesisSpec::new(TEST_CHAIN_ID, 1_700_000_000_000, validators);
+            spec.allocations = accounts.iter().map(|k| (k.public_key().address(), 1_000 * rewards::NEO)).collect();
+            spec
+        }
+
+        pub(crate) fn chain(&self) -> Chain {
+            self.funded_chain(&[])
+        }
+        /// Chain whose genesis funds `accounts` so they can pay fees
+        pub(crate) fn funded_chain(&self, accounts: &[&HybridKeyPair]) -> Chain {
+            Chain::new(&self.genesis(accounts)).unwrap()
+        }
+
+        /// Key of the validator the genesis set schedules for `height`
+        pub(crate) fn proposer(&self, height: u64) -> &HybridKeyPair {
+            self.key(&self.validators().proposer(height).unwrap().address)
+        }
+
+        /// Key of some validator that is not scheduled for `height`
+        pub(crate) fn non_proposer(&self, height: u64) -> &HybridKeyPair {
+            let scheduled = self.proposer(height).public_key().address();
+            self.keys.iter().find(|k| k.public_key().address() != scheduled).expect("needs two validators")
+        }
+
+        pub(crate) fn mine(&self, chain: &mut Chain) -> Block {
+            let height = chain.blocks.last().unwrap().index + 1;
+            let proposer = chain.scheduled_proposer(height).unwrap().address.clone();
+            chain.mine_block(self.key(&proposer)).unwrap()
+        }
+
+        /// Block on top of `parent` sealed by its scheduled proposer. The state root is
+        /// computed against `chain` and left empty if the block doesn't execute there.
+        pub(crate) fn child(&self, chain: &Chain, parent: &Block, txs: Vec<Tx>) -> Block {
+            let proposer = self.proposer(parent.index + 1);
+            let mut block = Block {
+                index: parent.index + 1,
+                prev_hash: parent.hash.clone(),
+                timestamp: parent.timestamp + 1,
+                tx_root: Block::compute_tx_root(&txs),
+                txs,
+                evidence_root: Block::compute_evidence_root(&[]),
+                evidence: vec![],
+                nonce: 0,
+                proposer: proposer.public_key().address(),
+                validator_set_hash: self.validators().hash().to_string(),
+                state_root: String::new(),
+                hash: String::new(),
+                signature: HybridSignature::default(),
+            };
+            if let Ok(state) = chain.state_at(&parent.hash) {
+                let mut state = state.into_owned();
+                block.state_root = validation::execute_block(&block, &mut state).unwrap_or_default();
+            }
+            block.seal(proposer, chain.network());
+            block
+        }
+
+        /// A `vote_type` vote for `block` in round 0 from every validator, signed for `chain`
+        pub(crate) fn votes(&self, chain: &Chain, vote_type: VoteType, block: &Block) -> Vec<Vote> {
+            self.keys.iter().map(|k| Vote::new(k, chain.network(), vote_type, block.index, 0, &block.hash)).collect()
+        }
+
+        /// Certificate for `block` signed by every validator for `chain`
+        pub(crate) fn certificate(&self, chain: &Chain, block: &Block) -> CommitCertificate {
+            CommitCertificate {
+                height: block.index,
+                round: 0,
+                block_hash: block.hash.clone(),
+                precommits: self.votes(chain, VoteType::Precommit, block),
+            }
+        }
+    }
+
+    #[test]
+    fn test_import_extends_and_keeps_side_chain() {
+        let net = TestNet::new(2);
+        let mut chain = net.chain();
+        let genesis = chain.blocks[0].clone();
+        let a1 = net.child(&chain, &genesis, vec![]);
+        assert_eq!(chain.import_block(a1.clone()).unwrap(), ImportOutcome::Extended);
+        assert_eq!(chain.blocks.len(), 2);
+
+        let mut b1 = net.child(&chain, &genesis, vec![]);
+        b1.nonce = 1;
+        b1.seal(net.proposer(1), chain.network());
+        assert_eq!(chain.import_block(b1).unwrap(), ImportOutcome::SideChain);
+        assert_eq!(chain.blocks[1].hash, a1.hash);
+
+        assert!(chain.import_block(a1.clone()).is_err());
+        let mallory = HybridKeyPair::generate();
+        let mut unscheduled = net.child(&chain, &a1, vec![]);
+        unscheduled.seal(&mallory, chain.network());
+        let err = chain.import_block(unscheduled).unwrap_err();
+        assert_eq!(err.downcast_ref::<ValidationError>(), Some(&ValidationError::UnexpectedProposer {
+            height: 2,
+            expected: net.proposer(2).public_key().address(),
+            got: mallory.public_key().address(),
+        }));
+    }
+
+    #[test]
+    fn test_import_rejects_forged_block_signature() {
+        let net = TestNet::new(2);
+        let mut chain = net.chain();
+        let genesis = chain.blocks[0].clone();
+
+        // Claims the scheduled proposer but is signed by someone else
+        let mut forged = net.child(&chain, &genesis, vec![]);
+        forged.signature = HybridKeyPair::generate().sign(forged.hash.as_bytes());
+        let err = chain.import_block(forged).unwrap_err();
+        assert_eq!(err.downcast_ref::<ValidationError>(), Some(&ValidationError::InvalidBlockSignature { height: 1 }));
+
+        assert!(chain.mine_block(net.non_proposer(1)).is_err());
+        assert_eq!(chain.blocks.len(), 1);
+    }
+
+    #[test]
+    fn test_reorg_returns_orphaned_txs_to_mempool() {
+        let net = TestNet::new(2);
+        let alice = HybridKeyPair::generate();
+        let mut chain = net.funded_chain(&[&alice]);
+        let genesis = chain.blocks[0].clone();
+        let tx = Tx::signed(&alice, TEST_CHAIN_ID, "bob", "transfer 1 NEO", 0);
+
+        chain.add_tx(tx.clone()).unwrap();
+        net.mine(&mut chain);
+        assert!(chain.mempool.is_empty());
+        assert_eq!(chain.state_nonce(&alice.public_key().address()), 1);
+
+        let b1 = net.child(&chain, &genesis, vec![]);
+        assert_eq!(chain.import_block(b1.clone()).unwrap(), ImportOutcome::SideChain);
+        let b2 = net.child(&chain, &b1, vec![]);
+        assert_eq!(chain.import_block(b2.clone()).unwrap(), ImportOutcome::Reorged { reverted: 1, applied: 2 });
+
+        assert_eq!(chain.blocks.last().unwrap().hash, b2.hash);
+        assert_eq!(chain.state_nonce(&alice.public_key().address()), 0);
+        assert!(chain.mempool.contains(&tx.hash()));
+        assert_eq!(chain.validate(), Ok(()));
+    }
+
+    #[test]
+    fn test_reorg_to_invalid_branch_is_rejected() {
+        let net = TestNet::new(2);
+        let alice = HybridKeyPair::generate();
+        let mut chain = net.funded_chain(&[&alice]);
+        let genesis = chain.blocks[0].clone();
+        let a1 = net.mine(&mut chain);
+
+        // Nonce 1 without nonce 0 makes the heavier branch invalid
+        let mut b1 = net.child(&chain, &genesis, vec![]);
+        b1.nonce = 1;
+        b1.seal(net.proposer(1), chain.network());
+        let b2 = net.child(&chain, &b1, vec![Tx::signed(&alice, TEST_CHAIN_ID, "bob", "transfer 1 NEO", 1)]);
+        chain.import_block(b1).unwrap();
+        let err = chain.import_block(b2.clone()).unwrap_err();
+        assert!(matches!(err.downcast_ref::<ValidationError>(), Some(ValidationError::BadNonce { .. })));
+        assert_eq!(chain.blocks.last().unwrap().hash, a1.hash);
+        assert_eq!(chain.tree.head_hash(), a1.hash);
+        assert!(!chain.tree.contains(&b2.hash));
+    }
+
+    #[test]
+    fn test_state_snapshots_cover_blocks_above_finality() {
+        let net = TestNet::new(3);
+        let mut chain = net.chain();
+        let genesis = chain.blocks[0].clone();
+        let a1 = net.mine(&mut chain);
+        let a2 = net.mine(&mut chain);
+        let mut b1 = net.child(&chain, &genesis, vec![]);
+        b1.nonce = 1;
+        b1.seal(net.proposer(1), chain.network());
+        chain.import_block(b1.clone()).unwrap();
+
+        // Side branches are served from their snapshot rather than replayed
+        assert!(matches!(chain.state_at(&b1.hash).unwrap(), Cow::Borrowed(_)));
+        assert_eq!(chain.state_at(&b1.hash).unwrap().state_root(), b1.state_root);
+        assert_eq!(chain.state_at(&a1.hash).unwrap().state_root(), a1.state_root);
+
+        let b2 = net.child(&chain, &b1, vec![]);
+        chain.import_block(b2.clone()).unwrap();
+        let b3 = net.child(&chain, &b2, vec![]);
+        assert!(matches!(chain.import_block(b3.clone()).unwrap(), ImportOutcome::Reorged { reverted: 2, applied: 3 }));
+        assert_eq!(chain.state.state_root(), b3.state_root);
+
+        chain.finalize(net.certificate(&chain, &b1)).unwrap();
+        assert!(!chain.snapshots.contains_key(&genesis.hash));
+        assert!(!chain.snapshots.contains_key(&a2.hash));
+        assert!(chain.snapshots.contains_key(&b1.hash) && chain.snapshots.contains_key(&b3.hash));
+    }
+
+    #[test]
+    fn test_reencoded_tx_is_still_a_duplicate() {
+        let net = TestNet::new(2);
+        let alice = HybridKeyPair::generate();
+        let mut chain = net.funded_chain(&[&alice]);
+        let tx = Tx::signed(&alice, TEST_CHAIN_ID, "bob", "transfer 1 NEO", 0);
+        chain.add_tx(tx.clone()).unwrap();
+
+        // Unsigned fields can be rewritten without breaking the signature, but not the id
+        let mut reencoded = tx.clone();
+        reencoded.signature.timestamp += 1;
+        reencoded.public_key.kyber_public.clear();
+        assert_eq!(reencoded.verify(TEST_CHAIN_ID), Ok(()));
+        assert_eq!(reencoded.hash(), tx.hash());
+        assert_eq!(chain.add_tx(reencoded), Err(TxRejection::Duplicate));
+    }
+
+    #[test]
+    fn test_add_signed_tx() {
+        let net = TestNet::new(2);
+        let alice = HybridKeyPair::generate();
+        let mut chain = net.funded_chain(&[&alice]);
+
+        chain.add_tx(Tx::signed(&alice, TEST_CHAIN_ID, "bob", "transfer 10 NEO", 0)).unwrap();
+        chain.add_tx(Tx::signed(&alice, TEST_CHAIN_ID, "bob", "transfer 5 NEO", 1)).unwrap();
+        assert_eq!(chain.mempool.len(), 2);
+        assert_eq!(chain.next_nonce(&alice.public_key().address()), 2);
+
+        let block = net.mine(&mut chain);
+        assert_eq!(block.txs.len(), 2);
+        assert_eq!(chain.validate(), Ok(()));
+    }
+
+    #[test]
+    fn test_mine_block_takes_ready_txs_only() {
+        let net = TestNet::new(2);
+        let alice = HybridKeyPair::generate();
+        let mut chain = net.funded_chain(&[&alice]);
+
+        chain.add_tx(Tx::signed(&alice, TEST_CHAIN_ID, "bob", "transfer 1 NEO", 0)).unwrap();
+        chain.add_tx(Tx::signed(&alice, TEST_CHAIN_ID, "bob", "transfer 3 NEO", 2)).unwrap();
+        assert_eq!(net.mine(&mut chain).txs.len(), 1);
+        assert_eq!(chain.mempool.len(), 1);
+
+        chain.add_tx(Tx::signed(&alice, TEST_CHAIN_ID, "bob", "transfer 2 NEO", 1)).unwrap();
+        assert_eq!(net.mine(&mut chain).txs.len(), 2);
+        assert!(chain.mempool.is_empty());
+        assert_eq!(chain.state_nonce(&alice.public_key().address()), 3);
+    }
+
+    #[test]
+    fn test_tx_inclusion_proof() {
+        let net = TestNet::new(2);
+        let alice = HybridKeyPair::generate();
+        let mut chain = net.funded_chain(&[&alice]);
+        for nonce in 0..3 {
+            chain.add_tx(Tx::signed(&alice, TEST_CHAIN_ID, "bob", "transfer 1 NEO", nonce)).unwrap();
+        }
+        let block = net.mine(&mut chain);
+
+        let proof = block.inclusion_proof(1).unwrap();
+        assert!(Block::verify_inclusion(&block.tx_root, &block.txs[1], &proof));
+        assert!(!Block::verify_inclusion(&block.tx_root, &block.txs[0], &proof));
+        assert!(block.inclusion_proof(3).is_none());
+
+        chain.blocks[1].txs.pop();
+        assert!(chain.validate().is_err());
+    }
+
+    fn fixed_tx() -> Tx {
+        Tx {
+            chain_id: 1,
+            from: "neo1alice".to_string(),
+            to: "bob".to_string(),
+            payload: "hi".to_string(),
+            nonce: 2,
+            gas_price: 1,
+            gas_limit: TX_BASE_GAS,
+            public_key: HybridPublicKey {
+                ed25519_public: vec![1],
+                dilithium_public: vec![2],
+                kyber_public: vec![],
+                algorithm: "hybrid".to_string(),
+            },
+            signature: HybridSignature {
+                ed25519_sig: vec![3],
+                dilithium_sig: vec![4],
+                algorithm: "hybrid".to_string(),
+                timestamp: 5,
+            },
+        }
+    }
+
+    #[test]
+    fn test_tx_canonical_encoding_golden_vector() {
+        let tx = fixed_tx();
+        // Pinned so any change to the encoding, and with it every tx id, is deliberate
+        assert_eq!(hex::encode(tx.signing_bytes()), "0000000000000001000000096e656f31616c69636500000003626f62000000026869000000000000000200000000000000010000000000005208");
+        assert_eq!(tx.hash(), "0380b9dd4da489558d16c46996604417a0a90e271c312214f444f5bfe5c169e6");
+        // The id covers the raw signatures but none of the unsigned fields around them
+        let mut resigned = tx.clone();
+        resigned.signature.dilithium_sig = vec![5];
+        assert_ne!(resigned.id(), tx.id());
+        let mut relabeled = tx.clone();
+        relabeled.signature.timestamp += 1;
+        relabeled.signature.algorithm = "other".to_string();
+        relabeled.public_key.kyber_public = vec![6];
+        relabeled.public_key.algorithm = "other".to_string();
+        assert_eq!(relabeled.id(), tx.id());
+
+        assert_eq!(Tx::from_bytes(&tx.to_bytes()).unwrap(), tx);
+        assert_eq!(Tx::from_versioned_bytes(&tx.to_versioned_bytes()).unwrap(), tx);
+        let mut truncated = tx.to_bytes();
+        truncated.pop();
+        assert!(Tx::from_bytes(&truncated).is_err());
+    }
+
+    #[test]
+    fn test_block_canonical_encoding_round_trip() {
+        let net = TestNet::new(2);
+        let alice = HybridKeyPair::generate();
+        let mut chain = net.funded_chain(&[&alice]);
+        chain.add_tx(Tx::signed(&alice, TEST_CHAIN_ID, "bob", "transfer", 0)).unwrap();
+        let block = net.mine(&mut chain);
+        let decoded = Block::from_versioned_bytes(&block.to_versioned_bytes()).unwrap();
+        assert_eq!(decoded, block);
+        assert_eq!(decoded.compute_hash(), block.hash);
+
+        let header = Block {
+            index: 1,
+            prev_hash: "00".repeat(32),
+            timestamp: 1_700_000_000_000,
+            tx_root: hex::encode(merkle::merkle_root(&[fixed_tx().id()])),
+            txs: vec![fixed_tx()],
+            evidence_root: String::new(),
+            evidence: vec![],
+            nonce: 0,
+            proposer: "neo1proposer".to_string(),
+            validator_set_hash: String::new(),
+            state_root: String::new(),
+            hash: String::new(),
+            signature: fixed_tx().signature,
+        };
+        assert_eq!(header.compute_hash(), "77bfc4854e7adf7289dd96f8611915cfb9f10077bd53079b452fe129da83d6db");
+    }
+
+    #[test]
+    fn test_fees_and_block_reward_credit_proposer() {
+        let net = TestNet::new(2);
+        let alice = HybridKeyPair::generate();
+        let bob = HybridKeyPair::generate();
+        let mut chain = net.funded_chain(&[&alice]);
+        let address = alice.public_key().address();
+        let supply = chain.state.ledger.supply;
+
+        // Gas beyond what the transfer uses is not charged
+        let gas = rewards::intrinsic_gas("transfer");
+        assert_eq!(gas, TX_BASE_GAS + 8 * TX_PAYLOAD_BYTE_GAS);
+        chain.add_tx(Tx::signed_with_gas(&alice, TEST_CHAIN_ID, "bob", "transfer", 0, 3, 2 * gas)).unwrap();
+        assert_eq!(
+            chain.add_tx(Tx::signed(&bob, TEST_CHAIN_ID, "alice", "transfer", 0)),
+            Err(TxRejection::InsufficientFunds { balance: 0, required: gas as u128 })
+        );
+        assert_eq!(
+            chain.add_tx(Tx::signed_with_gas(&alice, TEST_CHAIN_ID, "bob", "transfer", 1, 1, gas - 1)),
+            Err(TxRejection::IntrinsicGasTooLow { required: gas })
+        );
+        let block = net.mine(&mut chain);
+
+        let fee = 3 * gas as u128;
+        assert_eq!(chain.balance(&address), 1_000 * rewards::NEO - fee);
+        assert_eq!(chain.balance(&block.proposer), 10 * rewards::NEO + fee);
+        assert_eq!(chain.state.ledger.supply, supply + 10 * rewards::NEO);
+        assert_eq!(block.state_root, chain.state.state_root());
+        assert_eq!(chain.validate(), Ok(()));
+
+        let network = chain.network().clone();
+        chain.blocks[1].state_root = "00".repeat(32);
+        chain.blocks[1].seal(net.key(&block.proposer), &network);
+        assert!(matches!(chain.validate(), Err(ValidationError::StateRootMismatch { height: 1, .. })));
+    }
+
+    #[test]
+    fn test_reject_forged_tx() {
+        let net = TestNet::new(2);
+        let alice = HybridKeyPair::generate();
+        let mut chain = net.funded_chain(&[&alice]);
+        let mallory = HybridKeyPair::generate();
+
+        let mut tampered = Tx::signed(&alice, TEST_CHAIN_ID, "bob", "transfer 10 NEO", 0);
+        tampered.payload = "transfer 99 NEO".into();
+        assert_eq!(chain.add_tx(tampered), Err(TxRejection::InvalidSignature));
+
+        let mut impersonated = Tx::signed(&mallory, TEST_CHAIN_ID, "mallory", "transfer 10 NEO", 0);
+        impersonated.from = alice.public_key().address();
+        assert_eq!(chain.add_tx(impersonated), Err(TxRejection::SenderMismatch));
+
+        // A signature from another network doesn't carry over, even with the chain id rewritten
+        let foreign = Tx::signed(&alice, TEST_CHAIN_ID + 1, "bob", "transfer 10 NEO", 0);
+        assert_eq!(chain.add_tx(foreign.clone()), Err(TxRejection::WrongChainId { expected: TEST_CHAIN_ID, got: TEST_CHAIN_ID + 1 }));
+        let replayed = Tx { chain_id: TEST_CHAIN_ID, ..foreign };
+        assert_eq!(chain.add_tx(replayed), Err(TxRejection::InvalidSignature));
+
+        assert!(chain.mempool.is_empty());
+    }
+
+    #[test]
+    fn test_reject_bad_nonces() {
+        let net = TestNet::new(2);
+        let alice = HybridKeyPair::generate();
+        let mut chain = net.funded_chain(&[&alice]);
+        let tx = Tx::signed(&alice, TEST_CHAIN_ID, "bob", "transfer 10 NEO", 0);
+
+        assert_eq!(
+            chain.add_tx(Tx::signed(&alice, TEST_CHAIN_ID, "bob", "transfer 10 NEO", 500)),
+            Err(TxRejection::NonceGap { expected: 0, got: 500 })
+        );
+        chain.add_tx(tx.clone()).unwrap();
+        assert_eq!(chain.add_tx(tx), Err(TxRejection::Duplicate));
+
+        net.mine(&mut chain);
+        assert_eq!(
+            chain.add_tx(Tx::signed(&alice, TEST_CHAIN_ID, "carol", "transfer 1 NEO", 0)),
+            Err(TxRejection::NonceTooLow { expected: 1, got: 0 })
+        );
+    }
+
+    #[test]
+    fn test_precommit_quorum_finalizes_block() {
+        let net = TestNet::new(4);
+        let mut chain = net.chain();
+        let genesis = chain.blocks[0].clone();
+        let a1 = net.mine(&mut chain);
+        net.mine(&mut chain);
+
+        for vote in net.votes(&chain, VoteType::Prevote, &a1) {
+            assert_eq!(chain.add_vote(vote).unwrap(), None);
+        }
+        let precommits = net.votes(&chain, VoteType::Precommit, &a1);
+        assert_eq!(chain.add_vote(precommits[0].clone()).unwrap(), None);
+        assert_eq!(chain.add_vote(precommits[1].clone()).unwrap(), None);
+        assert_eq!(chain.finalized_height, 0);
+        let cert = chain.add_vote(precommits[2].clone()).unwrap().unwrap();
+        assert_eq!(cert.block_hash, a1.hash);
+        assert_eq!(chain.finalized_height, 1);
+        assert_eq!(chain.certificates[&1], cert);
+        assert_eq!(chain.add_vote(precommits[3].clone()).unwrap(), None);
+
+        // Fork choice can no longer move below the finalized block
+        let mut b1 = net.child(&chain, &genesis, vec![]);
+        b1.nonce = 1;
+        b1.seal(net.proposer(1), chain.network());
+        let err = chain.import_block(b1.clone()).unwrap_err();
+        assert_eq!(err.downcast_ref::<FinalityError>(), Some(&FinalityError::ConflictsWithFinalized { height: 1, finalized_height: 1 }));
+        assert!(chain.finalize(net.certificate(&chain, &b1)).is_err());
+    }
+
+    #[test]
+    fn test_finalizing_side_branch_switches_canonical_chain() {
+        let net = TestNet::new(3);
+        let mut chain = net.chain();
+        let genesis = chain.blocks[0].clone();
+        let a1 = net.mine(&mut chain);
+        let mut b1 = net.child(&chain, &genesis, vec![]);
+        b1.nonce = 1;
+        b1.seal(net.proposer(1), chain.network());
+        assert_eq!(chain.import_block(b1.clone()).unwrap(), ImportOutcome::SideChain);
+
+        let mut short = net.certificate(&chain, &b1);
+        short.precommits.truncate(2);
+        let err = chain.finalize(short).unwrap_err();
+        assert_eq!(err.downcast_ref::<FinalityError>(), Some(&FinalityError::InsufficientQuorum { have: 2, need: 3 }));
+
+        assert!(chain.finalize(net.certificate(&chain, &b1)).unwrap());
+        assert_eq!(chain.blocks[1].hash, b1.hash);
+        assert_eq!(chain.finalized_height, 1);
+        assert!(!chain.finalize(net.certificate(&chain, &b1)).unwrap());
+        assert!(!chain.tree.contains(&a1.hash));
+        assert_eq!(chain.validate(), Ok(()));
+    }
+
+    #[test]
+    fn test_double_proposal_is_slashed_and_jailed() {
+        let net = TestNet::new(3);
+        let mut spec = net.genesis(&[]);
+        spec.validators.iter_mut().for_each(|v| v.stake = 100);
+        spec.epoch_length = 2;
+        spec.slashing.jail_blocks = 2;
+        let mut chain = Chain::new(&spec).unwrap();
+
+        let a1 = net.mine(&mut chain);
+        let mut b1 = a1.clone();
+        b1.nonce = 1;
+        b1.seal(net.key(&a1.proposer), chain.network());
+        assert_eq!(chain.import_block(b1).unwrap(), ImportOutcome::SideChain);
+        assert_eq!(chain.evidence_pool.len(), 1);
+
+        let a2 = net.mine(&mut chain);
+        assert_eq!(a2.evidence.len(), 1);
+        assert!(chain.evidence_pool.is_empty());
+        assert!(!chain.state.staking.set_for_height(3).unwrap().contains(&a1.proposer));
+        let (jailed, until) = &chain.state.staking.jailed()[0];
+        assert_eq!((jailed.address.as_str(), jailed.stake, *until), (a1.proposer.as_str(), 95, 4));
+        assert!(matches!(chain.submit_evidence(a2.evidence[0].clone()), Err(SlashingError::AlreadyPunished(_))));
+        assert_eq!(chain.validate(), Ok(()));
+
+        net.mine(&mut chain);
+        net.mine(&mut chain);
+        assert_eq!(chain.state.staking.set_for_height(5).unwrap().stake_of(&a1.proposer), 95);
+        assert!(chain.state.staking.jailed().is_empty());
+    }
+
+    #[test]
+    fn test_equivocating_vote_becomes_evidence() {
+        let net = TestNet::new(4);
+        let mut chain = net.chain();
+        let a1 = net.mine(&mut chain);
+        chain.add_vote(Vote::new(&net.keys[0], chain.network(), VoteType::Precommit, 1, 0, &a1.hash)).unwrap();
+        let err = chain.add_vote(Vote::new(&net.keys[0], chain.network(), VoteType::Precommit, 1, 0, &"ff".repeat(32))).unwrap_err();
+        assert!(matches!(err.downcast_ref::<FinalityError>(), Some(FinalityError::Equivocation { .. })));
+        assert!(matches!(chain.evidence_pool[..], [Evidence::DoubleVote { .. }]));
+
+        let a2 = net.mine(&mut chain);
+        assert_eq!(a2.evidence.len(), 1);
+        assert_eq!(chain.validate(), Ok(()));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_key_files_are_private() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = std::env::temp_dir().join(format!("neonet_keys_test_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let path = dir.join("node.key");
+        let path = path.to_str().unwrap();
+
+        let created = load_or_create_key(path).unwrap();
+        assert_eq!(std::fs::metadata(path).unwrap().permissions().mode() & 0o777, 0o600);
+        assert_eq!(load_or_create_key(path).unwrap().public_key(), created.public_key());
+
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o644)).unwrap();
+        let err = load_or_create_key(path).err().expect("a world-readable key loaded");
+        assert!(err.to_string().contains("mode 644"), "{}", err);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
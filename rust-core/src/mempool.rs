@@ -0,0 +1,364 @@
+// Transaction pool for NeoNet - per-sender nonce queues with gas price priority
+use std::collections::{BTreeMap, BinaryHeap, HashMap};
+
+use crate::evm_adapter::BLOCK_GAS_LIMIT;
+use crate::validation::MAX_BLOCK_SIZE;
+use crate::{Tx, TxRejection};
+
+#[derive(Debug, Clone)]
+pub struct MempoolConfig {
+    /// Maximum number of transactions held across all senders
+    pub max_txs: usize,
+    /// Maximum number of transactions per sender; also bounds how far ahead a nonce may be
+    pub max_per_sender: usize,
+    /// Transactions older than this are dropped by `prune`
+    pub ttl_millis: u128,
+    pub block_gas_limit: u64,
+    pub block_size_limit: usize,
+    /// Minimum gas price increase, in percent, to replace a same-nonce transaction
+    pub price_bump_percent: u64,
+}
+
+impl Default for MempoolConfig {
+    fn default() -> Self {
+        MempoolConfig {
+            max_txs: 4096,
+            max_per_sender: 64,
+            ttl_millis: 3 * 60 * 60 * 1000,
+            block_gas_limit: BLOCK_GAS_LIMIT,
+            block_size_limit: MAX_BLOCK_SIZE,
+            price_bump_percent: 10,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InsertOutcome {
+    /// Executable now: the nonce continues the sender's ready queue
+    Ready,
+    /// Waiting for a lower nonce from the same sender
+    Future,
+    /// Replaced a same-nonce transaction with the given hash
+    Replaced(String),
+}
+
+#[derive(Debug, Clone)]
+struct PooledTx {
+    tx: Tx,
+    hash: String,
+    size: usize,
+    inserted_at: u128,
+}
+
+pub struct Mempool {
+    config: MempoolConfig,
+    senders: HashMap<String, BTreeMap<u64, PooledTx>>,
+    by_hash: HashMap<String, (String, u64)>,
+}
+
+impl Mempool {
+    pub fn new(config: MempoolConfig) -> Self {
+        Mempool {
+            config,
+            senders: HashMap::new(),
+            by_hash: HashMap::new(),
+        }
+    }
+
+    pub fn config(&self) -> &MempoolConfig {
+        &self.config
+    }
+
+    pub fn len(&self) -> usize {
+        self.by_hash.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.by_hash.is_empty()
+    }
+
+    pub fn contains(&self, hash: &str) -> bool {
+        self.by_hash.contains_key(hash)
+    }
+
+    pub fn get(&self, hash: &str) -> Option<&Tx> {
+        let (sender, nonce) = self.by_hash.get(hash)?;
+        self.senders.get(sender)?.get(nonce).map(|p| &p.tx)
+    }
+
+    /// Add an already verified transaction. `state_nonce` is the sender's next committed nonce.
+    pub fn insert(&mut self, tx: Tx, state_nonce: u64, now: u128) -> Result<InsertOutcome, TxRejection> {
+        let hash = tx.hash();
+        if self.by_hash.contains_key(&hash) {
+            return Err(TxRejection::Duplicate);
+        }
+        if tx.nonce < state_nonce {
+            return Err(TxRejection::NonceTooLow { expected: state_nonce, got: tx.nonce });
+        }
+        if tx.nonce >= state_nonce + self.config.max_per_sender as u64 {
+            return Err(TxRejection::NonceGap { expected: state_nonce, got: tx.nonce });
+        }
+        let size = tx.encoded_len();
+        if tx.gas_limit > self.config.block_gas_limit || size > self.config.block_size_limit {
+            return Err(TxRejection::ExceedsBlockLimits);
+        }
+
+        let sender = tx.from.clone();
+        let nonce = tx.nonce;
+        let replaced = match self.senders.get(&sender).and_then(|q| q.get(&nonce)) {
+            Some(existing) => {
+                // Worked out in u128 so no price is too high to outbid; the top price outbids itself
+                let price = existing.tx.gas_price as u128;
+                let bump = (price * self.config.price_bump_percent as u128).div_ceil(100).max(1);
+                let required = (price + bump).min(u64::MAX as u128) as u64;
+                if tx.gas_price < required {
+                    return Err(TxRejection::Underpriced { required });
+                }
+                Some(existing.hash.clone())
+            }
+            None => {
+                if self.senders.get(&sender).map_or(0, |q| q.len()) >= self.config.max_per_sender {
+                    return Err(TxRejection::PoolFull);
+                }
+                if self.len() >= self.config.max_txs {
+                    self.evict_for(tx.gas_price)?;
+                }
+                None
+            }
+        };
+
+        if let Some(old) = &replaced {
+            self.by_hash.remove(old);
+        }
+        self.by_hash.insert(hash.clone(), (sender.clone(), nonce));
+        let queue = self.senders.entry(sender.clone()).or_default();
+        queue.insert(nonce, PooledTx { tx, hash, size, inserted_at: now });
+
+        Ok(match replaced {
+            Some(old) => InsertOutcome::Replaced(old),
+            None if nonce < self.next_nonce(&sender, state_nonce) => InsertOutcome::Ready,
+            None => InsertOutcome::Future,
+        })
+    }
+
+    /// Make room for a transaction paying `gas_price` by dropping the cheapest queue tail.
+    /// Only the highest nonce of a sender is evicted so remaining queues stay contiguous.
+    fn evict_for(&mut self, gas_price: u64) -> Result<(), TxRejection> {
+        let victim = self.senders.values()
+            .filter_map(|queue| queue.values().next_back())
+            .min_by_key(|p| (p.tx.gas_price, std::cmp::Reverse(p.inserted_at)))
+            .map(|p| (p.tx.from.clone(), p.tx.nonce, p.tx.gas_price));
+
+        match victim {
+            Some((sender, nonce, price)) if price < gas_price => {
+                self.remove(&sender, nonce);
+                Ok(())
+            }
+            _ => Err(TxRejection::PoolFull),
+        }
+    }
+
+    fn remove(&mut self, sender: &str, nonce: u64) -> Option<Tx> {
+        let queue = self.senders.get_mut(sender)?;
+        let pooled = queue.remove(&nonce)?;
+        if queue.is_empty() {
+            self.senders.remove(sender);
+        }
+        self.by_hash.remove(&pooled.hash);
+        Some(pooled.tx)
+    }
+
+    /// Nonce after the sender's contiguous run of pooled transactions starting at `state_nonce`
+    pub fn next_nonce(&self, sender: &str, state_nonce: u64) -> u64 {
+        let mut next = state_nonce;
+        if let Some(queue) = self.senders.get(sender) {
+            while queue.contains_key(&next) {
+                next += 1;
+            }
+        }
+        next
+    }
+
+    /// Transactions executable against `nonces`, in nonce order per sender
+    pub fn ready(&self, nonces: &HashMap<String, u64>) -> Vec<&Tx> {
+        self.partition(nonces).0
+    }
+
+    /// Transactions waiting on a missing lower nonce
+    pub fn future(&self, nonces: &HashMap<String, u64>) -> Vec<&Tx> {
+        self.partition(nonces).1
+    }
+
+    fn partition(&self, nonces: &HashMap<String, u64>) -> (Vec<&Tx>, Vec<&Tx>) {
+        let mut ready = vec![];
+        let mut future = vec![];
+        for (sender, queue) in &self.senders {
+            let next = self.next_nonce(sender, nonces.get(sender).copied().unwrap_or(0));
+            for (nonce, pooled) in queue {
+                if *nonce < next { ready.push(&pooled.tx) } else { future.push(&pooled.tx) }
+            }
+        }
+        (ready, future)
+    }
+
+    /// Pick transactions for the next block: highest gas price first among each sender's
+    /// next executable nonce, stopping a sender once its next transaction no longer fits.
+    pub fn select(&self, nonces: &HashMap<String, u64>) -> Vec<Tx> {
+        let mut heads = BinaryHeap::new();
+        for (sender, queue) in &self.senders {
+            let nonce = nonces.get(sender).copied().unwrap_or(0);
+            if let Some(pooled) = queue.get(&nonce) {
+                heads.push((pooled.tx.gas_price, std::cmp::Reverse(pooled.inserted_at), sender, nonce));
+            }
+        }
+
+        let mut selected = vec![];
+        let mut gas = 0u64;
+        let mut size = 0usize;
+        while let Some((_, _, sender, nonce)) = heads.pop() {
+            let queue = &self.senders[sender];
+            let pooled = &queue[&nonce];
+            if gas + pooled.tx.gas_limit > self.config.block_gas_limit
+                || size + pooled.size > self.config.block_size_limit
+            {
+                continue;
+            }
+            gas += pooled.tx.gas_limit;
+            size += pooled.size;
+            selected.push(pooled.tx.clone());
+
+            if let Some(next) = queue.get(&(nonce + 1)) {
+                heads.push((next.tx.gas_price, std::cmp::Reverse(next.inserted_at), sender, nonce + 1));
+            }
+        }
+        selected
+    }
+
+    /// Drop transactions whose nonce is already committed and those older than the TTL.
+    /// Returns the number of transactions removed.
+    pub fn prune(&mut self, nonces: &HashMap<String, u64>, now: u128) -> usize {
+        let ttl = self.config.ttl_millis;
+        let stale: Vec<(String, u64)> = self.senders.iter()
+            .flat_map(|(sender, queue)| {
+                let state_nonce = nonces.get(sender).copied().unwrap_or(0);
+                queue.values()
+                    .filter(move |p| p.tx.nonce < state_nonce || now.saturating_sub(p.inserted_at) > ttl)
+                    .map(|p| (p.tx.from.clone(), p.tx.nonce))
+            })
+            .collect();
+
+        for (sender, nonce) in &stale {
+            self.remove(sender, *nonce);
+        }
+        stale.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pqc::HybridKeyPair;
+    use crate::tests::TEST_CHAIN_ID;
+
+    fn tx(keypair: &HybridKeyPair, nonce: u64, gas_price: u64) -> Tx {
+        Tx::signed_with_gas(keypair, TEST_CHAIN_ID, "bob", "transfer", nonce, gas_price, 21000)
+    }
+
+    fn nonces(entries: &[(&HybridKeyPair, u64)]) -> HashMap<String, u64> {
+        entries.iter().map(|(k, n)| (k.public_key().address(), *n)).collect()
+    }
+
+    #[test]
+    fn test_ready_and_future_queues() {
+        let alice = HybridKeyPair::generate();
+        let mut pool = Mempool::new(MempoolConfig::default());
+
+        assert_eq!(pool.insert(tx(&alice, 0, 1), 0, 0), Ok(InsertOutcome::Ready));
+        assert_eq!(pool.insert(tx(&alice, 2, 1), 0, 0), Ok(InsertOutcome::Future));
+        assert_eq!(pool.ready(&HashMap::new()).len(), 1);
+        assert_eq!(pool.future(&HashMap::new()).len(), 1);
+
+        // Filling the gap promotes nonce 2
+        assert_eq!(pool.insert(tx(&alice, 1, 1), 0, 0), Ok(InsertOutcome::Ready));
+        assert_eq!(pool.ready(&HashMap::new()).len(), 3);
+        assert_eq!(pool.next_nonce(&alice.public_key().address(), 0), 3);
+
+        assert_eq!(pool.insert(tx(&alice, 100, 1), 0, 0), Err(TxRejection::NonceGap { expected: 0, got: 100 }));
+    }
+
+    #[test]
+    fn test_replace_by_fee() {
+        let alice = HybridKeyPair::generate();
+        let mut pool = Mempool::new(MempoolConfig::default());
+        let original = tx(&alice, 0, 100);
+        pool.insert(original.clone(), 0, 0).unwrap();
+
+        assert_eq!(pool.insert(tx(&alice, 0, 105), 0, 0), Err(TxRejection::Underpriced { required: 110 }));
+        assert_eq!(pool.insert(tx(&alice, 0, 110), 0, 0), Ok(InsertOutcome::Replaced(original.hash())));
+        assert_eq!(pool.len(), 1);
+        assert!(!pool.contains(&original.hash()));
+
+        // Prices near the top of the range still get the full bump, saturating at the maximum
+        let bob = HybridKeyPair::generate();
+        pool.insert(tx(&bob, 0, u64::MAX / 2), 0, 0).unwrap();
+        let required = 10_145_709_240_540_253_388;
+        assert_eq!(pool.insert(tx(&bob, 0, required - 1), 0, 0), Err(TxRejection::Underpriced { required }));
+        let top = tx(&bob, 0, u64::MAX);
+        assert!(matches!(pool.insert(top.clone(), 0, 0), Ok(InsertOutcome::Replaced(_))));
+        assert_eq!(pool.insert(tx(&bob, 0, u64::MAX - 1), 0, 0), Err(TxRejection::Underpriced { required: u64::MAX }));
+        assert!(pool.contains(&top.hash()));
+    }
+
+    #[test]
+    fn test_evicts_lowest_fee_when_full() {
+        let alice = HybridKeyPair::generate();
+        let bob = HybridKeyPair::generate();
+        let carol = HybridKeyPair::generate();
+        let config = MempoolConfig { max_txs: 2, ..MempoolConfig::default() };
+        let mut pool = Mempool::new(config);
+
+        let cheap = tx(&alice, 0, 1);
+        pool.insert(cheap.clone(), 0, 0).unwrap();
+        pool.insert(tx(&bob, 0, 5), 0, 0).unwrap();
+
+        assert_eq!(pool.insert(tx(&carol, 0, 1), 0, 0), Err(TxRejection::PoolFull));
+        pool.insert(tx(&carol, 0, 3), 0, 0).unwrap();
+        assert_eq!(pool.len(), 2);
+        assert!(!pool.contains(&cheap.hash()));
+    }
+
+    #[test]
+    fn test_select_orders_by_price_within_gas_limit() {
+        let alice = HybridKeyPair::generate();
+        let bob = HybridKeyPair::generate();
+        let config = MempoolConfig { block_gas_limit: 63000, ..MempoolConfig::default() };
+        let mut pool = Mempool::new(config);
+
+        pool.insert(tx(&alice, 0, 1), 0, 0).unwrap();
+        pool.insert(tx(&alice, 1, 50), 0, 0).unwrap();
+        pool.insert(tx(&bob, 0, 10), 0, 0).unwrap();
+        pool.insert(tx(&bob, 2, 99), 0, 0).unwrap();
+
+        let selected = pool.select(&HashMap::new());
+        let picked: Vec<(u64, u64)> = selected.iter().map(|t| (t.gas_price, t.nonce)).collect();
+        // bob's nonce 2 is future; alice's nonce 1 must wait for her nonce 0
+        assert_eq!(picked, vec![(10, 0), (1, 0), (50, 1)]);
+    }
+
+    #[test]
+    fn test_prune_committed_and_expired() {
+        let alice = HybridKeyPair::generate();
+        let bob = HybridKeyPair::generate();
+        let config = MempoolConfig { ttl_millis: 1000, ..MempoolConfig::default() };
+        let mut pool = Mempool::new(config);
+
+        pool.insert(tx(&alice, 0, 1), 0, 0).unwrap();
+        pool.insert(tx(&alice, 1, 1), 0, 0).unwrap();
+        pool.insert(tx(&bob, 0, 1), 0, 500).unwrap();
+
+        assert_eq!(pool.prune(&nonces(&[(&alice, 1)]), 900), 1);
+        assert_eq!(pool.len(), 2);
+        assert_eq!(pool.prune(&HashMap::new(), 1200), 1);
+        assert_eq!(pool.ready(&nonces(&[(&alice, 1)])).len(), 1);
+    }
+}
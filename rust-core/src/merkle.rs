@@ -0,0 +1,184 @@
+// Binary Merkle tree for NeoNet - transaction roots and inclusion proofs
+//
+// Leaves are hashed as SHA-256(0x00 || leaf) and inner nodes as SHA-256(0x01 || left || right)
+// so a leaf can never be passed off as an inner node. A node without a sibling is promoted
+// to the next level unchanged instead of being paired with itself. The shape of a proof
+// follows from the leaf index and count alone, so a proof carries only the sibling hashes.
+use serde::{Deserialize, Serialize};
+use sha2::{Sha256, Digest};
+
+const LEAF_PREFIX: u8 = 0x00;
+const NODE_PREFIX: u8 = 0x01;
+
+/// Root of a tree with no leaves
+pub const EMPTY_ROOT: [u8; 32] = [0u8; 32];
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ProofStep {
+    /// Hex-encoded sibling hash
+    pub hash: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct MerkleProof {
+    pub index: usize,
+    pub leaf_count: usize,
+    pub steps: Vec<ProofStep>,
+}
+
+pub fn hash_leaf(leaf: &[u8]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update([LEAF_PREFIX]);
+    hasher.update(leaf);
+    hasher.finalize().into()
+}
+
+fn hash_node(left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update([NODE_PREFIX]);
+    hasher.update(left);
+    hasher.update(right);
+    hasher.finalize().into()
+}
+
+fn next_level(level: &[[u8; 32]]) -> Vec<[u8; 32]> {
+    level.chunks(2)
+        .map(|pair| match pair {
+            [left, right] => hash_node(left, right),
+            [single] => *single,
+            _ => unreachable!(),
+        })
+        .collect()
+}
+
+pub fn merkle_root<T: AsRef<[u8]>>(leaves: &[T]) -> [u8; 32] {
+    if leaves.is_empty() {
+        return EMPTY_ROOT;
+    }
+    let mut level: Vec<[u8; 32]> = leaves.iter().map(|l| hash_leaf(l.as_ref())).collect();
+    while level.len() > 1 {
+        level = next_level(&level);
+    }
+    level[0]
+}
+
+/// Build an inclusion proof for the leaf at `index`
+pub fn prove<T: AsRef<[u8]>>(leaves: &[T], index: usize) -> Option<MerkleProof> {
+    if index >= leaves.len() {
+        return None;
+    }
+    let mut level: Vec<[u8; 32]> = leaves.iter().map(|l| hash_leaf(l.as_ref())).collect();
+    let mut position = index;
+    let mut steps = vec![];
+
+    while level.len() > 1 {
+        let sibling = position ^ 1;
+        if sibling < level.len() {
+            steps.push(ProofStep { hash: hex::encode(level[sibling]) });
+        }
+        level = next_level(&level);
+        position /= 2;
+    }
+
+    Some(MerkleProof { index, leaf_count: leaves.len(), steps })
+}
+
+/// Check that `leaf` is included under `root` according to `proof`. Which side each sibling
+/// sits on, and how many siblings there are, follow from the index and the leaf count.
+pub fn verify_proof(root: &[u8; 32], leaf: &[u8], proof: &MerkleProof) -> bool {
+    if proof.index >= proof.leaf_count {
+        return false;
+    }
+    let mut current = hash_leaf(leaf);
+    let mut steps = proof.steps.iter();
+    let mut position = proof.index;
+    let mut level_len = proof.leaf_count;
+
+    while level_len > 1 {
+        let sibling = position ^ 1;
+        if sibling < level_len {
+            let hash: [u8; 32] = match steps.next().and_then(|step| hex::decode(&step.hash).ok()).and_then(|b| b.try_into().ok()) {
+                Some(hash) => hash,
+                None => return false,
+            };
+            current = if sibling < position {
+                hash_node(&hash, &current)
+            } else {
+                hash_node(&current, &hash)
+            };
+        }
+        level_len = level_len.div_ceil(2);
+        position /= 2;
+    }
+    steps.next().is_none() && current == *root
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn leaves(n: usize) -> Vec<Vec<u8>> {
+        (0..n).map(|i| format!("tx{}", i).into_bytes()).collect()
+    }
+
+    #[test]
+    fn test_root_of_small_trees() {
+        assert_eq!(merkle_root::<Vec<u8>>(&[]), EMPTY_ROOT);
+
+        let one = leaves(1);
+        assert_eq!(merkle_root(&one), hash_leaf(&one[0]));
+
+        let two = leaves(2);
+        assert_eq!(merkle_root(&two), hash_node(&hash_leaf(&two[0]), &hash_leaf(&two[1])));
+        assert_ne!(merkle_root(&leaves(3)), merkle_root(&leaves(4)));
+    }
+
+    #[test]
+    fn test_proofs_verify_for_every_index() {
+        for n in 1..=9 {
+            let leaves = leaves(n);
+            let root = merkle_root(&leaves);
+            for (i, leaf) in leaves.iter().enumerate() {
+                let proof = prove(&leaves, i).unwrap();
+                assert!(verify_proof(&root, leaf, &proof), "n={} i={}", n, i);
+            }
+            assert!(prove(&leaves, n).is_none());
+        }
+    }
+
+    #[test]
+    fn test_proof_rejects_wrong_leaf_or_root() {
+        let leaves = leaves(5);
+        let root = merkle_root(&leaves);
+        let proof = prove(&leaves, 2).unwrap();
+
+        assert!(!verify_proof(&root, &leaves[3], &proof));
+        assert!(!verify_proof(&merkle_root(&leaves[..4]), &leaves[2], &proof));
+
+        // The index fixes the path, so the proof can't be replayed for another position
+        let moved = MerkleProof { index: 3, ..proof.clone() };
+        assert!(!verify_proof(&root, &leaves[2], &moved));
+
+        let mut padded = proof.clone();
+        padded.steps.push(proof.steps[0].clone());
+        assert!(!verify_proof(&root, &leaves[2], &padded));
+        let mut truncated = proof.clone();
+        truncated.steps.pop();
+        assert!(!verify_proof(&root, &leaves[2], &truncated));
+    }
+
+    #[test]
+    fn test_proof_is_bound_to_leaf_count() {
+        // The last leaf of 5 is promoted twice, so its proof has a single step
+        let five = leaves(5);
+        let proof = prove(&five, 4).unwrap();
+        assert_eq!(proof.steps.len(), 1);
+        assert!(verify_proof(&merkle_root(&five), &five[4], &proof));
+
+        // Claiming a larger tree changes the expected path length
+        let stretched = MerkleProof { leaf_count: 8, ..proof.clone() };
+        assert!(!verify_proof(&merkle_root(&five), &five[4], &stretched));
+        let out_of_range = MerkleProof { leaf_count: 4, ..proof };
+        assert!(!verify_proof(&merkle_root(&five), &five[4], &out_of_range));
+    }
+}
@@ -0,0 +1,136 @@
+// Proof of work for NeoNet - compact difficulty targets, retargeting and cumulative chain work
+//
+// Targets use Bitcoin's compact "bits" encoding: the high byte is a size in bytes and the low
+// three bytes the most significant digits, so target = mantissa * 256^(size - 3). A block hash,
+// read as a big-endian 256-bit number, must not exceed its target.
+use primitive_types::{U256, U512};
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PowConfig {
+    /// Difficulty of the genesis block, and the easiest target retargeting may reach
+    pub initial_bits: u32,
+    pub target_block_secs: u64,
+    /// Blocks between difficulty adjustments
+    pub retarget_interval: u64,
+    /// How far past the local clock a block's timestamp may be
+    #[serde(default = "default_max_future_drift_secs")]
+    pub max_future_drift_secs: u64,
+}
+
+/// Blocks whose median timestamp a new block must be later than
+pub const MEDIAN_TIME_SPAN: usize = 11;
+
+fn default_max_future_drift_secs() -> u64 {
+    2 * 60 * 60
+}
+
+impl Default for PowConfig {
+    fn default() -> Self {
+        // One in sixteen hashes: the old single leading zero hex digit
+        PowConfig {
+            initial_bits: 0x200f_ffff,
+            target_block_secs: 10,
+            retarget_interval: 10,
+            max_future_drift_secs: default_max_future_drift_secs(),
+        }
+    }
+}
+
+impl PowConfig {
+    pub fn pow_limit(&self) -> U256 {
+        bits_to_target(self.initial_bits)
+    }
+
+    /// Whether the block at `height` adjusts difficulty rather than inheriting its parent's
+    pub fn is_retarget_height(&self, height: u64) -> bool {
+        self.retarget_interval > 0 && height > self.retarget_interval && height.is_multiple_of(self.retarget_interval)
+    }
+
+    /// Difficulty after a retarget window of `retarget_interval` blocks took `actual_ms`.
+    /// The adjustment is limited to a factor of four either way and never goes below the
+    /// genesis difficulty.
+    pub fn retarget(&self, bits: u32, actual_ms: i64) -> u32 {
+        let expected = (self.retarget_interval * self.target_block_secs * 1000).max(1);
+        let actual = (actual_ms.max(0) as u64).clamp(expected / 4, expected * 4);
+        let target = U512::from(bits_to_target(bits)) * U512::from(actual) / U512::from(expected);
+        let target = U256::try_from(target).unwrap_or(U256::MAX);
+        target_to_bits(target.min(self.pow_limit()))
+    }
+}
+
+pub fn bits_to_target(bits: u32) -> U256 {
+    let size = bits >> 24;
+    let mantissa = U256::from(bits & 0x007f_ffff);
+    match size {
+        0..=3 => mantissa >> (8 * (3 - size) as usize),
+        4..=32 => mantissa << (8 * (size - 3) as usize),
+        _ => U256::MAX,
+    }
+}
+
+/// Most compact bits for `target`, rounding it down to three significant bytes
+pub fn target_to_bits(target: U256) -> u32 {
+    let mut size = target.bits().div_ceil(8);
+    let mut mantissa = if size <= 3 {
+        target.low_u32() << (8 * (3 - size))
+    } else {
+        (target >> (8 * (size - 3))).low_u32()
+    };
+    // The top mantissa bit is a sign bit in the encoding, so keep it clear
+    if mantissa & 0x0080_0000 != 0 {
+        mantissa >>= 8;
+        size += 1;
+    }
+    mantissa | (size as u32) << 24
+}
+
+/// Expected number of hashes to find a block at `bits`: 2^256 / (target + 1)
+pub fn work(bits: u32) -> U256 {
+    let target = bits_to_target(bits);
+    if target == U256::MAX {
+        return U256::one();
+    }
+    (!target / (target + 1)) + 1
+}
+
+pub fn meets_target(hash: &[u8], bits: u32) -> bool {
+    hash.len() == 32 && U256::from_big_endian(hash) <= bits_to_target(bits)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compact_bits_round_trip() {
+        // Bitcoin's genesis difficulty
+        assert_eq!(bits_to_target(0x1d00_ffff), U256::from(0xffff) << 208);
+        assert_eq!(target_to_bits(U256::from(0xffff) << 208), 0x1d00_ffff);
+        assert_eq!(target_to_bits(bits_to_target(0x200f_ffff)), 0x200f_ffff);
+        assert_eq!(target_to_bits(U256::from(0x80)), 0x0200_8000);
+        assert_eq!(bits_to_target(0x0200_8000), U256::from(0x80));
+
+        let mut hash = [0xffu8; 32];
+        assert!(!meets_target(&hash, 0x200f_ffff));
+        hash[0] = 0x0e;
+        assert!(meets_target(&hash, 0x200f_ffff));
+        assert_eq!(work(0x200f_ffff), U256::from(16));
+    }
+
+    #[test]
+    fn test_retarget_tracks_block_times_within_limits() {
+        let config = PowConfig::default();
+        let expected_ms = 100_000;
+        let harder = config.retarget(config.initial_bits, expected_ms / 2);
+        assert_eq!(work(harder), work(config.initial_bits) * 2);
+        assert_eq!(config.retarget(harder, expected_ms), harder);
+        // Never easier than the limit, never more than 4x per window
+        assert_eq!(config.retarget(config.initial_bits, expected_ms * 10), config.initial_bits);
+        assert_eq!(config.retarget(config.initial_bits, 0), config.retarget(config.initial_bits, expected_ms / 4));
+
+        assert!(!config.is_retarget_height(10));
+        assert!(config.is_retarget_height(20));
+        assert!(!config.is_retarget_height(21));
+    }
+}
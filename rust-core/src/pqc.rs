@@ -4,6 +4,8 @@ use serde::{Deserialize, Serialize};
 use ed25519_dalek::{SigningKey, VerifyingKey, Signature as EdSignature, Signer, Verifier};
 use rand::rngs::OsRng;
 use anyhow::{Result, anyhow};
+use sha2::{Sha256, Digest};
+use crate::codec::impl_codec_struct;
 
 // PQC imports
 use pqcrypto_dilithium::dilithium3;
@@ -11,7 +13,14 @@ use pqcrypto_kyber::kyber1024;
 use pqcrypto_traits::sign::{PublicKey as PQPublicKey, SecretKey as PQSecretKey, DetachedSignature};
 use pqcrypto_traits::kem::{PublicKey as KemPublicKey, SecretKey as KemSecretKey, Ciphertext, SharedSecret};
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+/// Signing domain of NeoNet transactions
+pub const TX_DOMAIN: &str = "neonet/tx";
+/// Signing domain of block hashes sealed by their proposer
+pub const BLOCK_DOMAIN: &str = "neonet/block";
+/// Signing domain of finality prevotes and precommits
+pub const VOTE_DOMAIN: &str = "neonet/vote";
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 pub struct HybridPublicKey {
     pub ed25519_public: Vec<u8>,
     pub dilithium_public: Vec<u8>,
@@ -19,7 +28,7 @@ pub struct HybridPublicKey {
     pub algorithm: String,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
 pub struct HybridSignature {
     pub ed25519_sig: Vec<u8>,
     pub dilithium_sig: Vec<u8>,
@@ -27,6 +36,19 @@ pub struct HybridSignature {
     pub timestamp: u64,
 }
 
+impl_codec_struct!(HybridPublicKey { ed25519_public, dilithium_public, kyber_public, algorithm });
+impl_codec_struct!(HybridSignature { ed25519_sig, dilithium_sig, algorithm, timestamp });
+
+impl HybridPublicKey {
+    /// Account address derived from both signing keys: `neo1` + first 20 bytes of SHA-256
+    pub fn address(&self) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(&self.ed25519_public);
+        hasher.update(&self.dilithium_public);
+        format!("neo1{}", hex::encode(&hasher.finalize()[..20]))
+    }
+}
+
 pub struct HybridKeyPair {
     ed_signing_key: SigningKey,
     dilithium_public: dilithium3::PublicKey,
@@ -108,9 +130,60 @@ impl HybridKeyPair {
         }
     }
 
+    /// Sign `message` bound to a signing domain and chain id; see `domain_message`
+    pub fn sign_in_domain(&self, domain: &str, chain_id: u64, message: &[u8]) -> HybridSignature {
+        self.sign(&domain_message(domain, chain_id, message))
+    }
+
     pub fn secret_bytes(&self) -> Vec<u8> {
         self.ed_signing_key.to_bytes().to_vec()
     }
+
+    /// Serialize every key (Ed25519 secret, Dilithium3 and Kyber1024 keypairs) so the
+    /// full hybrid identity survives a restart, unlike `secret_bytes`
+    pub fn to_bytes(&self) -> Vec<u8> {
+        [
+            self.ed_signing_key.to_bytes().as_slice(),
+            self.dilithium_public.as_bytes(),
+            self.dilithium_secret.as_bytes(),
+            self.kyber_public.as_bytes(),
+            self.kyber_secret.as_bytes(),
+        ].concat()
+    }
+
+    /// Restore a keypair serialized with `to_bytes`
+    pub fn restore(bytes: &[u8]) -> Result<Self> {
+        let sizes = [
+            32,
+            dilithium3::public_key_bytes(),
+            dilithium3::secret_key_bytes(),
+            kyber1024::public_key_bytes(),
+            kyber1024::secret_key_bytes(),
+        ];
+        if bytes.len() != sizes.iter().sum::<usize>() {
+            return Err(anyhow!("Invalid hybrid keypair length"));
+        }
+        let mut parts = Vec::with_capacity(sizes.len());
+        let mut offset = 0;
+        for size in sizes {
+            parts.push(&bytes[offset..offset + size]);
+            offset += size;
+        }
+
+        let secret_bytes: [u8; 32] = parts[0].try_into()
+            .map_err(|_| anyhow!("Invalid secret key bytes"))?;
+        Ok(HybridKeyPair {
+            ed_signing_key: SigningKey::from_bytes(&secret_bytes),
+            dilithium_public: dilithium3::PublicKey::from_bytes(parts[1])
+                .map_err(|_| anyhow!("Failed to parse Dilithium public key"))?,
+            dilithium_secret: dilithium3::SecretKey::from_bytes(parts[2])
+                .map_err(|_| anyhow!("Failed to parse Dilithium secret key"))?,
+            kyber_public: kyber1024::PublicKey::from_bytes(parts[3])
+                .map_err(|_| anyhow!("Failed to parse Kyber public key"))?,
+            kyber_secret: kyber1024::SecretKey::from_bytes(parts[4])
+                .map_err(|_| anyhow!("Failed to parse Kyber secret key"))?,
+        })
+    }
     
     /// Kyber1024 key encapsulation
     pub fn kyber_encapsulate(&self) -> (Vec<u8>, Vec<u8>) {
@@ -132,6 +205,42 @@ impl HybridKeyPair {
     }
 }
 
+/// Kyber1024 encapsulation to a peer's public key, returning (shared secret, ciphertext);
+/// only the holder of the matching keypair can recover the secret with `kyber_decapsulate`
+pub fn kyber_encapsulate_to(public_key: &HybridPublicKey) -> Result<(Vec<u8>, Vec<u8>)> {
+    let kyber_public = kyber1024::PublicKey::from_bytes(&public_key.kyber_public)
+        .map_err(|_| anyhow!("Failed to parse Kyber public key"))?;
+    let (shared_secret, ciphertext) = kyber1024::encapsulate(&kyber_public);
+    Ok((shared_secret.as_bytes().to_vec(), ciphertext.as_bytes().to_vec()))
+}
+
+/// Bytes actually signed for `message` in `domain` on chain `chain_id`. The length-prefixed
+/// domain keeps a signature made for one purpose from verifying for another, and the chain
+/// id keeps a signature made on one network from being replayed on another.
+pub fn domain_message(domain: &str, chain_id: u64, message: &[u8]) -> Vec<u8> {
+    let domain = domain.as_bytes();
+    [&(domain.len() as u32).to_be_bytes(), domain, &chain_id.to_be_bytes(), message].concat()
+}
+
+/// Verify a signature made with `HybridKeyPair::sign_in_domain`
+pub fn verify_in_domain(
+    public_key: &HybridPublicKey,
+    domain: &str,
+    chain_id: u64,
+    message: &[u8],
+    signature: &HybridSignature
+) -> Result<bool> {
+    verify_hybrid_signature(public_key, &domain_message(domain, chain_id, message), signature)
+}
+
+/// Verify a detached Dilithium3 signature on its own, for accounts that sign without Ed25519
+pub fn verify_dilithium(public_key: &[u8], message: &[u8], signature: &[u8]) -> bool {
+    match (dilithium3::PublicKey::from_bytes(public_key), dilithium3::DetachedSignature::from_bytes(signature)) {
+        (Ok(key), Ok(sig)) => dilithium3::verify_detached_signature(&sig, message, &key).is_ok(),
+        _ => false,
+    }
+}
+
 /// Verify hybrid signature (both Ed25519 and Dilithium3 must be valid)
 pub fn verify_hybrid_signature(
     public_key: &HybridPublicKey,
@@ -251,4 +360,38 @@ mod tests {
         assert_eq!(signature.dilithium_sig.len(), dilithium3::signature_bytes());
         assert!(signature.timestamp > 0);
     }
+
+    #[test]
+    fn test_to_bytes_and_restore() {
+        let keypair = HybridKeyPair::generate();
+        let restored = HybridKeyPair::restore(&keypair.to_bytes()).unwrap();
+
+        assert_eq!(restored.public_key().address(), keypair.public_key().address());
+        let signature = restored.sign(b"restored");
+        assert!(verify_hybrid_signature(&keypair.public_key(), b"restored", &signature).unwrap());
+        assert!(HybridKeyPair::restore(&keypair.to_bytes()[1..]).is_err());
+    }
+
+    #[test]
+    fn test_domain_separated_signature() {
+        let keypair = HybridKeyPair::generate();
+        let public_key = keypair.public_key();
+        let signature = keypair.sign_in_domain(TX_DOMAIN, 1, b"payload");
+
+        assert!(verify_in_domain(&public_key, TX_DOMAIN, 1, b"payload", &signature).unwrap());
+        assert!(!verify_in_domain(&public_key, TX_DOMAIN, 2, b"payload", &signature).unwrap());
+        assert!(!verify_in_domain(&public_key, "neonet/block", 1, b"payload", &signature).unwrap());
+        assert!(!verify_hybrid_signature(&public_key, b"payload", &signature).unwrap());
+    }
+
+    #[test]
+    fn test_address_derivation() {
+        let keypair = HybridKeyPair::generate();
+        let address = keypair.public_key().address();
+
+        assert!(address.starts_with("neo1"));
+        assert_eq!(address.len(), 44);
+        assert_eq!(address, keypair.public_key().address());
+        assert_ne!(address, HybridKeyPair::generate().public_key().address());
+    }
 }
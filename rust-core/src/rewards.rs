@@ -0,0 +1,308 @@
+// Rewards for NeoNet - transaction fees, block rewards and capped NEO issuance
+//
+// Every transaction pays `gas_used * gas_price` from its sender. The proposer receives the
+// fees minus a burned share plus a freshly minted block reward, and minting stops once the
+// total supply reaches the 50M NEO cap. Validator stake is held out of the spendable balance
+// as a bond: staking operations bond and unbond it, and slashing burns it.
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt;
+
+use crate::codec::Encode;
+use crate::{Tx, TX_BASE_GAS, TX_PAYLOAD_BYTE_GAS};
+
+/// Base units per NEO
+pub const NEO: u128 = 1_000_000_000_000_000_000;
+/// Hard cap on NEO in existence
+pub const MAX_SUPPLY: u128 = 50_000_000 * NEO;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RewardConfig {
+    /// Minted to the proposer of every block until the cap is reached
+    pub block_reward: u128,
+    /// Share of fees burned instead of paid to the proposer, in basis points
+    pub burn_bps: u64,
+    pub max_supply: u128,
+}
+
+impl Default for RewardConfig {
+    fn default() -> Self {
+        RewardConfig { block_reward: 10 * NEO, burn_bps: 0, max_supply: MAX_SUPPLY }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RewardError {
+    AllocationsExceedCap { total: u128, max_supply: u128 },
+    InsufficientFunds { balance: u128, required: u128 },
+}
+
+impl fmt::Display for RewardError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RewardError::AllocationsExceedCap { total, max_supply } => write!(f, "genesis allocations of {} exceed the supply cap {}", total, max_supply),
+            RewardError::InsufficientFunds { balance, required } => write!(f, "insufficient funds: balance {}, required {}", balance, required),
+        }
+    }
+}
+
+impl std::error::Error for RewardError {}
+
+/// Token movements caused by one block
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Issuance {
+    pub fees: u128,
+    pub burned: u128,
+    pub minted: u128,
+}
+
+/// Gas a transaction with `payload` needs before it does anything: the base cost plus a
+/// charge per payload byte
+pub fn intrinsic_gas(payload: &str) -> u64 {
+    TX_BASE_GAS.saturating_add(TX_PAYLOAD_BYTE_GAS.saturating_mul(payload.len() as u64))
+}
+
+/// Gas charged for a transaction. Plain transfers and staking operations run no code, so
+/// they pay exactly their intrinsic gas whatever limit they set.
+pub fn gas_used(tx: &Tx) -> u64 {
+    intrinsic_gas(&tx.payload)
+}
+
+pub fn tx_fee(tx: &Tx) -> u128 {
+    gas_used(tx) as u128 * tx.gas_price as u128
+}
+
+/// NEO balances and bonded stake plus running totals of issuance
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Ledger {
+    pub config: RewardConfig,
+    allocations: BTreeMap<String, u128>,
+    /// Stake of the genesis validators, bonded from the start
+    genesis_bonds: BTreeMap<String, u128>,
+    balances: BTreeMap<String, u128>,
+    bonded: BTreeMap<String, u128>,
+    /// Tokens in existence: allocations and genesis bonds plus everything minted minus
+    /// everything burned
+    pub supply: u128,
+    pub minted: u128,
+    pub burned: u128,
+}
+
+impl Default for Ledger {
+    fn default() -> Self {
+        Ledger::new(BTreeMap::new(), RewardConfig::default()).unwrap()
+    }
+}
+
+impl Ledger {
+    pub fn new(allocations: BTreeMap<String, u128>, config: RewardConfig) -> Result<Self, RewardError> {
+        Self::with_bonds(allocations, BTreeMap::new(), config)
+    }
+
+    /// Genesis ledger whose validators start with `bonds` already staked; bonds count
+    /// towards the supply cap like allocations do
+    pub fn with_bonds(allocations: BTreeMap<String, u128>, bonds: BTreeMap<String, u128>, config: RewardConfig) -> Result<Self, RewardError> {
+        let total = allocations.values().chain(bonds.values())
+            .try_fold(0u128, |sum, v| sum.checked_add(*v))
+            .unwrap_or(u128::MAX);
+        if total > config.max_supply {
+            return Err(RewardError::AllocationsExceedCap { total, max_supply: config.max_supply });
+        }
+        Ok(Ledger {
+            config,
+            balances: allocations.clone(),
+            allocations,
+            bonded: bonds.clone(),
+            genesis_bonds: bonds,
+            supply: total,
+            minted: 0,
+            burned: 0,
+        })
+    }
+
+    /// The ledger as it was at genesis
+    pub fn reset(&self) -> Self {
+        Ledger::with_bonds(self.allocations.clone(), self.genesis_bonds.clone(), self.config).unwrap()
+    }
+
+    pub fn balance(&self, address: &str) -> u128 {
+        self.balances.get(address).copied().unwrap_or(0)
+    }
+
+    pub fn balances(&self) -> &BTreeMap<String, u128> {
+        &self.balances
+    }
+
+    /// Stake the address has bonded
+    pub fn bonded(&self, address: &str) -> u128 {
+        self.bonded.get(address).copied().unwrap_or(0)
+    }
+
+    /// Move `amount` from the address's balance into its bond
+    pub fn bond(&mut self, address: &str, amount: u128) -> Result<(), RewardError> {
+        let balance = self.balance(address);
+        if balance < amount {
+            return Err(RewardError::InsufficientFunds { balance, required: amount });
+        }
+        if amount > 0 {
+            self.balances.insert(address.to_string(), balance - amount);
+            *self.bonded.entry(address.to_string()).or_default() += amount;
+        }
+        Ok(())
+    }
+
+    /// Return up to `amount` of the address's bond to its balance
+    pub fn unbond(&mut self, address: &str, amount: u128) {
+        let amount = self.take_bond(address, amount);
+        self.credit(address, amount);
+    }
+
+    /// Burn up to `amount` of the address's bond as a slashing penalty
+    pub fn slash(&mut self, address: &str, amount: u128) {
+        let amount = self.take_bond(address, amount);
+        self.supply -= amount;
+        self.burned += amount;
+    }
+
+    fn take_bond(&mut self, address: &str, amount: u128) -> u128 {
+        let Some(bonded) = self.bonded.get_mut(address) else { return 0 };
+        let amount = amount.min(*bonded);
+        *bonded -= amount;
+        if *bonded == 0 {
+            self.bonded.remove(address);
+        }
+        amount
+    }
+
+    fn credit(&mut self, address: &str, amount: u128) {
+        if amount > 0 {
+            *self.balances.entry(address.to_string()).or_default() += amount;
+        }
+    }
+
+    /// Take the transaction's fee from its sender, returning the amount charged
+    pub fn charge_fee(&mut self, tx: &Tx) -> Result<u128, RewardError> {
+        let required = tx_fee(tx);
+        let balance = self.balance(&tx.from);
+        if balance < required {
+            return Err(RewardError::InsufficientFunds { balance, required });
+        }
+        if required > 0 {
+            self.balances.insert(tx.from.clone(), balance - required);
+        }
+        Ok(required)
+    }
+
+    /// Pay the proposer its share of `fees` plus the block reward, capped by the supply limit
+    pub fn reward_proposer(&mut self, proposer: &str, fees: u128) -> Issuance {
+        let burned = fees * self.config.burn_bps.min(10_000) as u128 / 10_000;
+        self.supply -= burned;
+        let minted = self.config.block_reward.min(self.config.max_supply.saturating_sub(self.supply));
+        self.supply += minted;
+        self.minted += minted;
+        self.burned += burned;
+        self.credit(proposer, fees - burned + minted);
+        Issuance { fees, burned, minted }
+    }
+}
+
+/// State root leaves for every account's balance, bond and nonce, in address order, followed
+/// by the supply totals, so headers commit to both account state and issuance
+pub fn state_leaves(ledger: &Ledger, nonces: &HashMap<String, u64>) -> Vec<[u8; 32]> {
+    let addresses: BTreeSet<&String> = ledger.balances.keys().chain(ledger.bonded.keys()).chain(nonces.keys()).collect();
+    let mut leaves: Vec<[u8; 32]> = addresses.into_iter()
+        .map(|address| {
+            let nonce = nonces.get(address).copied().unwrap_or(0);
+            Sha256::digest((address, ledger.balance(address), ledger.bonded(address), nonce).to_bytes()).into()
+        })
+        .collect();
+    leaves.push(Sha256::digest(("issuance", ledger.supply, ledger.minted, ledger.burned).to_bytes()).into());
+    leaves
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pqc::HybridKeyPair;
+    use crate::tests::TEST_CHAIN_ID;
+
+    fn funded(address: &str, amount: u128, config: RewardConfig) -> Ledger {
+        Ledger::new(BTreeMap::from([(address.to_string(), amount)]), config).unwrap()
+    }
+
+    #[test]
+    fn test_fee_charged_and_paid_to_proposer_with_burn() {
+        let alice = HybridKeyPair::generate();
+        let address = alice.public_key().address();
+        let config = RewardConfig { block_reward: 0, burn_bps: 2_500, ..RewardConfig::default() };
+        let mut ledger = funded(&address, 100_000, config);
+        // The fee follows the gas the payload uses, not the limit
+        let tx = Tx::signed_with_gas(&alice, TEST_CHAIN_ID, "bob", "", 0, 2, 3 * TX_BASE_GAS);
+        assert_eq!(gas_used(&tx), TX_BASE_GAS);
+        let transfer = Tx::signed_with_gas(&alice, TEST_CHAIN_ID, "bob", "transfer", 0, 2, 3 * TX_BASE_GAS);
+        assert_eq!(tx_fee(&transfer), 2 * (TX_BASE_GAS + 8 * TX_PAYLOAD_BYTE_GAS) as u128);
+
+        assert_eq!(ledger.charge_fee(&tx), Ok(42_000));
+        assert_eq!(ledger.balance(&address), 58_000);
+        let issuance = ledger.reward_proposer("proposer", 42_000);
+        assert_eq!(issuance, Issuance { fees: 42_000, burned: 10_500, minted: 0 });
+        assert_eq!(ledger.balance("proposer"), 31_500);
+        assert_eq!(ledger.supply, 100_000 - 10_500);
+
+        assert_eq!(ledger.charge_fee(&tx), Ok(42_000));
+        assert_eq!(ledger.charge_fee(&tx), Err(RewardError::InsufficientFunds { balance: 16_000, required: 42_000 }));
+    }
+
+    #[test]
+    fn test_minting_stops_at_supply_cap() {
+        let config = RewardConfig { block_reward: 10, burn_bps: 0, max_supply: 1_025 };
+        let mut ledger = funded("genesis", 1_000, config);
+        assert_eq!(ledger.reward_proposer("p", 0).minted, 10);
+        assert_eq!(ledger.reward_proposer("p", 0).minted, 10);
+        assert_eq!(ledger.reward_proposer("p", 0).minted, 5);
+        assert_eq!(ledger.reward_proposer("p", 0).minted, 0);
+        assert_eq!((ledger.supply, ledger.minted, ledger.balance("p")), (1_025, 25, 25));
+
+        let over = Ledger::new(BTreeMap::from([("a".to_string(), 2_000)]), config);
+        assert_eq!(over, Err(RewardError::AllocationsExceedCap { total: 2_000, max_supply: 1_025 }));
+    }
+
+    #[test]
+    fn test_bonds_move_out_of_balance_and_are_burned_by_slashing() {
+        let mut ledger = Ledger::with_bonds(
+            BTreeMap::from([("alice".to_string(), 1_000)]),
+            BTreeMap::from([("genesis".to_string(), 50)]),
+            RewardConfig::default(),
+        ).unwrap();
+        assert_eq!((ledger.supply, ledger.bonded("genesis")), (1_050, 50));
+
+        assert_eq!(ledger.bond("alice", 1_001), Err(RewardError::InsufficientFunds { balance: 1_000, required: 1_001 }));
+        ledger.bond("alice", 600).unwrap();
+        assert_eq!((ledger.balance("alice"), ledger.bonded("alice")), (400, 600));
+        ledger.unbond("alice", 100);
+        assert_eq!((ledger.balance("alice"), ledger.bonded("alice")), (500, 500));
+        ledger.slash("alice", 200);
+        assert_eq!((ledger.bonded("alice"), ledger.supply, ledger.burned), (300, 850, 200));
+        // Nothing beyond the bond can be returned or burned
+        ledger.unbond("alice", 1_000);
+        assert_eq!((ledger.balance("alice"), ledger.bonded("alice")), (800, 0));
+        ledger.slash("alice", 1);
+        assert_eq!(ledger.supply, 850);
+        assert_eq!(ledger.reset().bonded("genesis"), 50);
+
+        let over = Ledger::with_bonds(BTreeMap::new(), BTreeMap::from([("v".to_string(), MAX_SUPPLY + 1)]), RewardConfig::default());
+        assert!(matches!(over, Err(RewardError::AllocationsExceedCap { .. })));
+    }
+
+    #[test]
+    fn test_state_leaves_commit_to_issuance() {
+        let mut ledger = Ledger::default();
+        let nonces = HashMap::from([("alice".to_string(), 1)]);
+        let before = state_leaves(&ledger, &nonces);
+        assert_eq!(before, state_leaves(&ledger.clone(), &nonces.clone()));
+        ledger.reward_proposer("p", 0);
+        assert_ne!(state_leaves(&ledger, &nonces), before);
+        assert_ne!(state_leaves(&Ledger::default(), &HashMap::new()), before);
+    }
+}
@@ -0,0 +1,356 @@
+// Secure channel for NeoNet - mutually authenticated, encrypted bridge connections
+//
+// Handshake, one frame per message, bodies in the canonical codec:
+//   client -> server  ClientHello  { version, client key, client nonce }
+//   server -> client  ServerHello  { server key, server nonce, Kyber ciphertext to the client,
+//                                    server signature over the transcript so far }
+//   client -> server  ClientFinish { Kyber ciphertext to the server, client signature over the
+//                                    whole transcript }
+// Each side only proceeds if the peer's key is one it trusts and its hybrid signature verifies.
+// Both Kyber shared secrets feed HKDF-SHA256, salted with the transcript hash, to give one
+// ChaCha20-Poly1305 key per direction. Every later frame is sealed under that key with a
+// per-direction counter as nonce, so tampered, reordered or replayed frames fail to open.
+use chacha20poly1305::aead::{Aead, KeyInit};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use hkdf::Hkdf;
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::io::{Read, Write};
+
+use crate::bridge::{read_frame, write_frame, FrameError};
+use crate::codec::{impl_codec_struct, CodecError, Decode, Encode};
+use crate::pqc::{kyber_encapsulate_to, verify_hybrid_signature, HybridKeyPair, HybridPublicKey, HybridSignature};
+
+/// Version of the handshake and record format
+pub const CHANNEL_VERSION: u32 = 1;
+
+const SERVER_SIGNING_DOMAIN: &str = "neonet/bridge/server-hello";
+const CLIENT_SIGNING_DOMAIN: &str = "neonet/bridge/client-finish";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct ClientHello {
+    version: u32,
+    public_key: HybridPublicKey,
+    nonce: [u8; 32],
+}
+
+impl_codec_struct!(ClientHello { version, public_key, nonce });
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct ServerHello {
+    public_key: HybridPublicKey,
+    nonce: [u8; 32],
+    ciphertext: Vec<u8>,
+    signature: HybridSignature,
+}
+
+impl_codec_struct!(ServerHello { public_key, nonce, ciphertext, signature });
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct ClientFinish {
+    ciphertext: Vec<u8>,
+    signature: HybridSignature,
+}
+
+impl_codec_struct!(ClientFinish { ciphertext, signature });
+
+#[derive(Debug)]
+pub enum ChannelError {
+    Io(std::io::Error),
+    FrameTooLarge(usize),
+    Codec(CodecError),
+    /// Peer closed the connection during the handshake
+    Closed,
+    UnsupportedVersion(u32),
+    /// Peer's key is not on the trusted list; carries its address
+    UntrustedPeer(String),
+    InvalidSignature,
+    KeyExchange(String),
+    /// A frame failed to authenticate: tampered, reordered, replayed or under another key
+    Decrypt,
+}
+
+impl fmt::Display for ChannelError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ChannelError::Io(e) => write!(f, "io error: {}", e),
+            ChannelError::FrameTooLarge(len) => write!(f, "frame of {} bytes is too large", len),
+            ChannelError::Codec(e) => write!(f, "malformed handshake message: {}", e),
+            ChannelError::Closed => write!(f, "connection closed during handshake"),
+            ChannelError::UnsupportedVersion(v) => write!(f, "unsupported channel version {}", v),
+            ChannelError::UntrustedPeer(address) => write!(f, "peer {} is not trusted", address),
+            ChannelError::InvalidSignature => write!(f, "invalid handshake signature"),
+            ChannelError::KeyExchange(e) => write!(f, "key exchange failed: {}", e),
+            ChannelError::Decrypt => write!(f, "frame failed authentication"),
+        }
+    }
+}
+
+impl std::error::Error for ChannelError {}
+
+impl From<std::io::Error> for ChannelError {
+    fn from(e: std::io::Error) -> Self {
+        ChannelError::Io(e)
+    }
+}
+
+impl From<FrameError> for ChannelError {
+    fn from(e: FrameError) -> Self {
+        match e {
+            FrameError::Io(e) => ChannelError::Io(e),
+            FrameError::TooLarge(len) => ChannelError::FrameTooLarge(len),
+        }
+    }
+}
+
+impl From<CodecError> for ChannelError {
+    fn from(e: CodecError) -> Self {
+        ChannelError::Codec(e)
+    }
+}
+
+/// One direction of the channel: an AEAD key and the number of frames sealed or opened so far
+pub struct CipherState {
+    cipher: ChaCha20Poly1305,
+    counter: u64,
+}
+
+impl CipherState {
+    fn new(key: &[u8; 32]) -> Self {
+        CipherState { cipher: ChaCha20Poly1305::new(Key::from_slice(key)), counter: 0 }
+    }
+
+    fn nonce(&self) -> Nonce {
+        let mut nonce = [0u8; 12];
+        nonce[4..].copy_from_slice(&self.counter.to_be_bytes());
+        *Nonce::from_slice(&nonce)
+    }
+
+    pub fn seal(&mut self, plaintext: &[u8]) -> Vec<u8> {
+        let sealed = self.cipher.encrypt(&self.nonce(), plaintext).expect("ChaCha20-Poly1305 encryption is infallible");
+        self.counter += 1;
+        sealed
+    }
+
+    pub fn open(&mut self, ciphertext: &[u8]) -> Result<Vec<u8>, ChannelError> {
+        let plaintext = self.cipher.decrypt(&self.nonce(), ciphertext).map_err(|_| ChannelError::Decrypt)?;
+        self.counter += 1;
+        Ok(plaintext)
+    }
+}
+
+/// An established connection; `send` and `recv` carry whole message bodies
+pub struct SecureChannel<S> {
+    stream: S,
+    sender: CipherState,
+    receiver: CipherState,
+    peer: HybridPublicKey,
+}
+
+fn random_nonce() -> [u8; 32] {
+    let mut nonce = [0u8; 32];
+    rand::rngs::OsRng.fill_bytes(&mut nonce);
+    nonce
+}
+
+fn read_message<S: Read, T: Decode>(stream: &mut S) -> Result<T, ChannelError> {
+    let body = read_frame(stream)?.ok_or(ChannelError::Closed)?;
+    Ok(T::from_bytes(&body)?)
+}
+
+fn check_trusted(key: &HybridPublicKey, trusted: &[HybridPublicKey]) -> Result<(), ChannelError> {
+    if trusted.contains(key) {
+        Ok(())
+    } else {
+        Err(ChannelError::UntrustedPeer(key.address()))
+    }
+}
+
+fn check_signature(key: &HybridPublicKey, message: &[u8], signature: &HybridSignature) -> Result<(), ChannelError> {
+    match verify_hybrid_signature(key, message, signature) {
+        Ok(true) => Ok(()),
+        _ => Err(ChannelError::InvalidSignature),
+    }
+}
+
+fn server_transcript(hello: &ClientHello, public_key: &HybridPublicKey, nonce: &[u8; 32], ciphertext: &[u8]) -> Vec<u8> {
+    (SERVER_SIGNING_DOMAIN, hello, public_key, nonce, ciphertext).to_bytes()
+}
+
+fn client_transcript(hello: &ClientHello, server: &ServerHello, ciphertext: &[u8]) -> Vec<u8> {
+    (CLIENT_SIGNING_DOMAIN, hello, server, ciphertext).to_bytes()
+}
+
+/// Client-to-server and server-to-client keys from both shared secrets and the full transcript
+fn session_keys(to_client: &[u8], to_server: &[u8], transcript: &[u8]) -> ([u8; 32], [u8; 32]) {
+    let salt = Sha256::digest(transcript);
+    let hkdf = Hkdf::<Sha256>::new(Some(&salt), &[to_client, to_server].concat());
+    let mut client_to_server = [0u8; 32];
+    let mut server_to_client = [0u8; 32];
+    hkdf.expand(b"neonet/bridge client->server", &mut client_to_server).expect("32 bytes is a valid HKDF length");
+    hkdf.expand(b"neonet/bridge server->client", &mut server_to_client).expect("32 bytes is a valid HKDF length");
+    (client_to_server, server_to_client)
+}
+
+impl<S: Read + Write> SecureChannel<S> {
+    /// Run the client side of the handshake against a server that must hold `server_key`.
+    /// The node itself only accepts connections, so this side is built for the tests that
+    /// dial it the way its peers do.
+    #[cfg(test)]
+    pub fn connect(mut stream: S, keypair: &HybridKeyPair, server_key: &HybridPublicKey) -> Result<Self, ChannelError> {
+        let hello = ClientHello { version: CHANNEL_VERSION, public_key: keypair.public_key(), nonce: random_nonce() };
+        write_frame(&mut stream, &hello.to_bytes())?;
+
+        let server: ServerHello = read_message(&mut stream)?;
+        check_trusted(&server.public_key, std::slice::from_ref(server_key))?;
+        let transcript = server_transcript(&hello, &server.public_key, &server.nonce, &server.ciphertext);
+        check_signature(&server.public_key, &transcript, &server.signature)?;
+        let to_client = keypair.kyber_decapsulate(&server.ciphertext).map_err(|e| ChannelError::KeyExchange(e.to_string()))?;
+
+        let (to_server, ciphertext) = kyber_encapsulate_to(&server.public_key).map_err(|e| ChannelError::KeyExchange(e.to_string()))?;
+        let transcript = client_transcript(&hello, &server, &ciphertext);
+        let finish = ClientFinish { signature: keypair.sign(&transcript), ciphertext };
+        write_frame(&mut stream, &finish.to_bytes())?;
+
+        let (client_to_server, server_to_client) = session_keys(&to_client, &to_server, &transcript);
+        Ok(SecureChannel {
+            stream,
+            sender: CipherState::new(&client_to_server),
+            receiver: CipherState::new(&server_to_client),
+            peer: server.public_key,
+        })
+    }
+
+    /// Run the server side of the handshake, accepting only clients whose key is in `trusted`.
+    /// The handshake blocks on the client, so network streams should have read and write
+    /// timeouts set before they are passed in.
+    pub fn accept(mut stream: S, keypair: &HybridKeyPair, trusted: &[HybridPublicKey]) -> Result<Self, ChannelError> {
+        let hello: ClientHello = read_message(&mut stream)?;
+        if hello.version != CHANNEL_VERSION {
+            return Err(ChannelError::UnsupportedVersion(hello.version));
+        }
+        check_trusted(&hello.public_key, trusted)?;
+
+        let (to_client, ciphertext) = kyber_encapsulate_to(&hello.public_key).map_err(|e| ChannelError::KeyExchange(e.to_string()))?;
+        let public_key = keypair.public_key();
+        let nonce = random_nonce();
+        let signature = keypair.sign(&server_transcript(&hello, &public_key, &nonce, &ciphertext));
+        let server = ServerHello { public_key, nonce, ciphertext, signature };
+        write_frame(&mut stream, &server.to_bytes())?;
+
+        let finish: ClientFinish = read_message(&mut stream)?;
+        let transcript = client_transcript(&hello, &server, &finish.ciphertext);
+        check_signature(&hello.public_key, &transcript, &finish.signature)?;
+        let to_server = keypair.kyber_decapsulate(&finish.ciphertext).map_err(|e| ChannelError::KeyExchange(e.to_string()))?;
+
+        let (client_to_server, server_to_client) = session_keys(&to_client, &to_server, &transcript);
+        Ok(SecureChannel {
+            stream,
+            sender: CipherState::new(&server_to_client),
+            receiver: CipherState::new(&client_to_server),
+            peer: hello.public_key,
+        })
+    }
+
+    /// Key the peer proved it holds during the handshake
+    pub fn peer(&self) -> &HybridPublicKey {
+        &self.peer
+    }
+
+    pub fn send(&mut self, body: &[u8]) -> Result<(), ChannelError> {
+        let sealed = self.sender.seal(body);
+        Ok(write_frame(&mut self.stream, &sealed)?)
+    }
+
+    /// Next message body, or `None` once the peer has closed the connection
+    pub fn recv(&mut self) -> Result<Option<Vec<u8>>, ChannelError> {
+        match read_frame(&mut self.stream)? {
+            Some(sealed) => self.receiver.open(&sealed).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// The underlying stream, bypassing encryption
+    #[cfg(test)]
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.stream
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{TcpListener, TcpStream};
+    use std::thread;
+
+    #[test]
+    fn test_handshake_authenticates_both_peers_and_encrypts() {
+        let server_key = HybridKeyPair::generate();
+        let client_key = HybridKeyPair::generate();
+        let server_public = server_key.public_key();
+        let trusted = vec![client_key.public_key()];
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut channel = SecureChannel::accept(stream, &server_key, &trusted).unwrap();
+            let request = channel.recv().unwrap().unwrap();
+            channel.send(&[request.as_slice(), b" back"].concat()).unwrap();
+            let peer = channel.peer().clone();
+            // An untrusted client is turned away after its hello
+            let (stream, _) = listener.accept().unwrap();
+            let rejected = SecureChannel::accept(stream, &server_key, &[]).err();
+            (peer, rejected)
+        });
+
+        let mut channel = SecureChannel::connect(TcpStream::connect(addr).unwrap(), &client_key, &server_public).unwrap();
+        channel.send(b"ping").unwrap();
+        assert_eq!(channel.recv().unwrap().unwrap(), b"ping back");
+        assert_eq!(channel.peer(), &server_public);
+
+        let stranger = SecureChannel::connect(TcpStream::connect(addr).unwrap(), &HybridKeyPair::generate(), &server_public);
+        assert!(matches!(stranger, Err(ChannelError::Closed) | Err(ChannelError::Io(_))));
+        let (peer, rejected) = server.join().unwrap();
+        assert_eq!(peer, client_key.public_key());
+        assert!(matches!(rejected, Some(ChannelError::UntrustedPeer(_))));
+    }
+
+    #[test]
+    fn test_client_rejects_unexpected_server_key() {
+        let server_key = HybridKeyPair::generate();
+        let client_key = HybridKeyPair::generate();
+        let trusted = vec![client_key.public_key()];
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            SecureChannel::accept(stream, &server_key, &trusted).is_err()
+        });
+
+        let pinned = HybridKeyPair::generate().public_key();
+        let result = SecureChannel::connect(TcpStream::connect(addr).unwrap(), &client_key, &pinned);
+        assert!(matches!(result, Err(ChannelError::UntrustedPeer(_))));
+        assert!(server.join().unwrap());
+    }
+
+    #[test]
+    fn test_sealed_frames_reject_tampering_reordering_and_replay() {
+        let key = [9u8; 32];
+        let mut sender = CipherState::new(&key);
+        let mut receiver = CipherState::new(&key);
+        let first = sender.seal(b"first");
+        let second = sender.seal(b"second");
+
+        let mut tampered = first.clone();
+        tampered[0] ^= 1;
+        assert!(matches!(receiver.open(&tampered), Err(ChannelError::Decrypt)));
+        assert!(matches!(receiver.open(&second), Err(ChannelError::Decrypt)));
+        assert_eq!(receiver.open(&first).unwrap(), b"first");
+        assert!(matches!(receiver.open(&first), Err(ChannelError::Decrypt)));
+        assert_eq!(receiver.open(&second).unwrap(), b"second");
+        assert!(matches!(CipherState::new(&[1u8; 32]).open(&first), Err(ChannelError::Decrypt)));
+    }
+}
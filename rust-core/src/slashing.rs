@@ -0,0 +1,369 @@
+// Slashing for NeoNet - equivocation and downtime evidence, stake penalties and jailing
+//
+// Evidence is carried in blocks and checked against the hybrid keys of the validator set
+// that was active at the offense height. Penalties take effect at the next epoch boundary:
+// the offender loses a share of its stake and sits out of the set for `jail_blocks`.
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fmt;
+
+use crate::codec::{CodecError, Decode, Decoder, Encode};
+use crate::finality::{CommitCertificate, FinalityError, Vote};
+use crate::fork_choice::BlockTree;
+use crate::validator_set::StakingState;
+use crate::Block;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SlashingConfig {
+    /// Share of stake taken for signing two blocks or votes at one height, in basis points
+    pub double_sign_penalty_bps: u64,
+    /// Share of stake taken for downtime, in basis points
+    pub downtime_penalty_bps: u64,
+    /// Consecutive commit certificates a validator must be missing from to count as down
+    pub downtime_window: usize,
+    /// Blocks an offender stays out of the validator set, counted from the evidence block
+    pub jail_blocks: u64,
+}
+
+impl Default for SlashingConfig {
+    fn default() -> Self {
+        SlashingConfig {
+            double_sign_penalty_bps: 500,
+            downtime_penalty_bps: 100,
+            downtime_window: 50,
+            jail_blocks: 1_000,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum Evidence {
+    /// One proposer signed two different blocks at the same height. Only headers are kept;
+    /// the block hash commits to the transactions through `tx_root`.
+    DoubleProposal { first: Box<Block>, second: Box<Block> },
+    /// One validator signed two different blocks in the same vote step
+    DoubleVote { first: Box<Vote>, second: Box<Vote> },
+    /// The validator was in the set but signed none of these consecutive certificates.
+    /// Certificates only need a 2/3+ quorum, so the window should be long enough that an
+    /// online validator is unlikely to be left out of every one.
+    Downtime { validator: String, certificates: Vec<CommitCertificate> },
+}
+
+impl Encode for Evidence {
+    fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            Evidence::DoubleProposal { first, second } => (0u8, first, second).encode(out),
+            Evidence::DoubleVote { first, second } => (1u8, first, second).encode(out),
+            Evidence::Downtime { validator, certificates } => (2u8, validator, certificates).encode(out),
+        }
+    }
+}
+
+impl Decode for Evidence {
+    fn decode(input: &mut Decoder<'_>) -> Result<Self, CodecError> {
+        match input.tag()? {
+            0 => Ok(Evidence::DoubleProposal { first: Decode::decode(input)?, second: Decode::decode(input)? }),
+            1 => Ok(Evidence::DoubleVote { first: Decode::decode(input)?, second: Decode::decode(input)? }),
+            2 => Ok(Evidence::Downtime { validator: Decode::decode(input)?, certificates: Decode::decode(input)? }),
+            tag => Err(CodecError::InvalidTag { type_name: "Evidence", tag }),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OffenseKind {
+    DoubleSign,
+    Downtime,
+}
+
+/// A verified offense, punished at most once per `id`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Offense {
+    pub validator: String,
+    pub height: u64,
+    pub kind: OffenseKind,
+    pub id: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SlashingError {
+    /// The two signed items don't conflict: different heights, signers or steps, or the same block
+    NotConflicting,
+    HashMismatch { height: u64 },
+    UnknownValidator { validator: String, height: u64 },
+    /// No validator set is known for the offense height
+    UnknownHeight(u64),
+    InvalidSignature { validator: String, height: u64 },
+    InvalidVote(FinalityError),
+    InvalidCertificate { height: u64, reason: FinalityError },
+    WindowTooShort { have: usize, need: usize },
+    NonConsecutive { expected: u64, got: u64 },
+    /// The validator signed a certificate inside the claimed downtime window
+    NotDown { validator: String, height: u64 },
+    AlreadyPunished(String),
+    /// Neither block of a double proposal is in, or extends, the local block tree
+    UnknownBranch { height: u64 },
+}
+
+impl fmt::Display for SlashingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use SlashingError::*;
+        match self {
+            NotConflicting => write!(f, "evidence items do not conflict"),
+            HashMismatch { height } => write!(f, "block {} in evidence does not match its hash", height),
+            UnknownValidator { validator, height } => write!(f, "{} was not a validator at height {}", validator, height),
+            UnknownHeight(height) => write!(f, "no validator set known for height {}", height),
+            InvalidSignature { validator, height } => write!(f, "invalid signature by {} at height {}", validator, height),
+            InvalidVote(e) => write!(f, "invalid vote: {}", e),
+            InvalidCertificate { height, reason } => write!(f, "invalid certificate at height {}: {}", height, reason),
+            WindowTooShort { have, need } => write!(f, "downtime window covers {} certificates, need {}", have, need),
+            NonConsecutive { expected, got } => write!(f, "certificate at height {} does not follow {}", got, expected - 1),
+            NotDown { validator, height } => write!(f, "{} signed the certificate at height {}", validator, height),
+            AlreadyPunished(id) => write!(f, "offense {} already punished", id),
+            UnknownBranch { height } => write!(f, "neither block at height {} links into the block tree", height),
+        }
+    }
+}
+
+impl std::error::Error for SlashingError {}
+
+impl Evidence {
+    /// Evidence of two conflicting blocks, stripped to their headers
+    pub fn double_proposal(first: &Block, second: &Block) -> Self {
+        Evidence::DoubleProposal { first: Box::new(first.header()), second: Box::new(second.header()) }
+    }
+
+    pub fn id(&self) -> [u8; 32] {
+        Sha256::digest(self.to_bytes()).into()
+    }
+
+    /// A double proposal must name a block in `tree`, or one built on a block in it; two
+    /// signed blocks from some other network's history aren't evidence against this one.
+    /// Other kinds carry no blocks and always link.
+    pub fn links_into(&self, tree: &BlockTree) -> Result<(), SlashingError> {
+        match self {
+            Evidence::DoubleProposal { first, second } => {
+                if [first, second].iter().any(|b| tree.contains(&b.hash) || tree.contains(&b.prev_hash)) {
+                    Ok(())
+                } else {
+                    Err(SlashingError::UnknownBranch { height: first.index })
+                }
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Check the evidence against the validator set active at the offense height, accepting
+    /// only signatures made for this network
+    pub fn verify(&self, staking: &StakingState) -> Result<Offense, SlashingError> {
+        match self {
+            Evidence::DoubleProposal { first, second } => {
+                if first.index != second.index || first.proposer != second.proposer || first.hash == second.hash {
+                    return Err(SlashingError::NotConflicting);
+                }
+                let height = first.index;
+                let set = staking.set_for_height(height).ok_or(SlashingError::UnknownHeight(height))?;
+                let validator = set.get(&first.proposer)
+                    .ok_or_else(|| SlashingError::UnknownValidator { validator: first.proposer.clone(), height })?;
+                for block in [first, second] {
+                    if block.compute_hash() != block.hash {
+                        return Err(SlashingError::HashMismatch { height });
+                    }
+                    if !matches!(block.verify_signature(&validator.public_key, &staking.network), Ok(true)) {
+                        return Err(SlashingError::InvalidSignature { validator: validator.address.clone(), height });
+                    }
+                }
+                Ok(Offense::double_sign(&validator.address, height))
+            }
+            Evidence::DoubleVote { first, second } => {
+                if first.validator != second.validator
+                    || first.vote_type != second.vote_type
+                    || first.height != second.height
+                    || first.round != second.round
+                    || first.block_hash == second.block_hash
+                {
+                    return Err(SlashingError::NotConflicting);
+                }
+                let set = staking.set_for_height(first.height).ok_or(SlashingError::UnknownHeight(first.height))?;
+                first.verify(set, &staking.network).map_err(SlashingError::InvalidVote)?;
+                second.verify(set, &staking.network).map_err(SlashingError::InvalidVote)?;
+                Ok(Offense::double_sign(&first.validator, first.height))
+            }
+            Evidence::Downtime { validator, certificates } => {
+                let need = staking.slashing.downtime_window;
+                if certificates.is_empty() || certificates.len() < need {
+                    return Err(SlashingError::WindowTooShort { have: certificates.len(), need });
+                }
+                let mut expected = certificates[0].height;
+                for cert in certificates {
+                    if cert.height != expected {
+                        return Err(SlashingError::NonConsecutive { expected, got: cert.height });
+                    }
+                    expected += 1;
+                    let set = staking.set_for_height(cert.height).ok_or(SlashingError::UnknownHeight(cert.height))?;
+                    if !set.contains(validator) {
+                        return Err(SlashingError::UnknownValidator { validator: validator.clone(), height: cert.height });
+                    }
+                    cert.verify(set, &staking.network).map_err(|reason| SlashingError::InvalidCertificate { height: cert.height, reason })?;
+                    if cert.precommits.iter().any(|v| &v.validator == validator) {
+                        return Err(SlashingError::NotDown { validator: validator.clone(), height: cert.height });
+                    }
+                }
+                let height = expected - 1;
+                Ok(Offense {
+                    validator: validator.clone(),
+                    height,
+                    kind: OffenseKind::Downtime,
+                    // At most one downtime penalty per epoch, however the windows overlap
+                    id: format!("downtime/{}/{}", validator, staking.epoch_of(height)),
+                })
+            }
+        }
+    }
+}
+
+impl Offense {
+    fn double_sign(validator: &str, height: u64) -> Self {
+        Offense {
+            validator: validator.to_string(),
+            height,
+            kind: OffenseKind::DoubleSign,
+            id: format!("double-sign/{}/{}", validator, height),
+        }
+    }
+
+    /// Stake taken from `stake` for this offense
+    pub fn penalty(&self, stake: u64, config: &SlashingConfig) -> u64 {
+        let bps = match self.kind {
+            OffenseKind::DoubleSign => config.double_sign_penalty_bps,
+            OffenseKind::Downtime => config.downtime_penalty_bps,
+        };
+        (stake as u128 * bps.min(10_000) as u128 / 10_000) as u64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::finality::VoteType;
+    use crate::pqc::HybridKeyPair;
+    use crate::genesis::NetworkId;
+    use crate::tests::TestNet;
+    use crate::validation::ValidationError;
+
+    #[test]
+    fn test_double_proposal_evidence() {
+        let net = TestNet::new(2);
+        let chain = net.chain();
+        let genesis = chain.blocks[0].clone();
+        let a1 = net.child(&chain, &genesis, vec![]);
+        let mut b1 = a1.clone();
+        b1.nonce = 1;
+        b1.seal(net.proposer(1), chain.network());
+
+        let offense = Evidence::double_proposal(&a1, &b1).verify(&chain.state.staking).unwrap();
+        assert_eq!(offense.validator, net.proposer(1).public_key().address());
+        assert_eq!(offense.kind, OffenseKind::DoubleSign);
+
+        assert_eq!(Evidence::double_proposal(&a1, &a1).verify(&chain.state.staking), Err(SlashingError::NotConflicting));
+        let mut forged = b1.clone();
+        forged.signature = net.non_proposer(1).sign(forged.hash.as_bytes());
+        assert!(matches!(Evidence::double_proposal(&a1, &forged).verify(&chain.state.staking), Err(SlashingError::InvalidSignature { .. })));
+        let mut outsider = b1.clone();
+        outsider.seal(&HybridKeyPair::generate(), chain.network());
+        let mut outsider_a = a1.clone();
+        outsider_a.proposer = outsider.proposer.clone();
+        assert!(matches!(Evidence::double_proposal(&outsider_a, &outsider).verify(&chain.state.staking), Err(SlashingError::UnknownValidator { .. })));
+    }
+
+    #[test]
+    fn test_double_proposal_must_link_into_tree() {
+        let net = TestNet::new(2);
+        let mut chain = net.chain();
+        let genesis = chain.blocks[0].clone();
+        let a1 = net.child(&chain, &genesis, vec![]);
+        let mut b1 = a1.clone();
+        b1.nonce = 1;
+        b1.seal(net.proposer(1), chain.network());
+        assert_eq!(Evidence::double_proposal(&a1, &b1).links_into(&chain.tree), Ok(()));
+
+        // Validly signed blocks on a branch this node has never seen
+        let detached: Vec<Block> = [a1, b1].into_iter().map(|mut block| {
+            block.prev_hash = "ab".repeat(32);
+            block.seal(net.proposer(1), chain.network());
+            block
+        }).collect();
+        let evidence = Evidence::double_proposal(&detached[0], &detached[1]);
+        assert!(evidence.verify(&chain.state.staking).is_ok());
+        assert_eq!(evidence.links_into(&chain.tree), Err(SlashingError::UnknownBranch { height: 1 }));
+        assert_eq!(chain.submit_evidence(evidence.clone()), Err(SlashingError::UnknownBranch { height: 1 }));
+        assert!(chain.evidence_pool.is_empty());
+
+        // Nor is it accepted when a peer's block carries it
+        let mut carrier = net.child(&chain, &genesis, vec![]);
+        carrier.evidence = vec![evidence];
+        carrier.evidence_root = Block::compute_evidence_root(&carrier.evidence);
+        carrier.seal(net.proposer(1), chain.network());
+        let err = chain.import_block(carrier).unwrap_err();
+        assert_eq!(err.downcast_ref::<ValidationError>(), Some(&ValidationError::InvalidEvidence {
+            height: 1,
+            reason: SlashingError::UnknownBranch { height: 1 },
+        }));
+    }
+
+    #[test]
+    fn test_double_vote_evidence() {
+        let net = TestNet::new(2);
+        let chain = net.chain();
+        let key = &net.keys[0];
+        let first = Vote::new(key, chain.network(), VoteType::Precommit, 1, 0, "aa");
+        let second = Vote::new(key, chain.network(), VoteType::Precommit, 1, 0, "bb");
+        let evidence = Evidence::DoubleVote { first: Box::new(first.clone()), second: Box::new(second) };
+        assert_eq!(evidence.verify(&chain.state.staking).unwrap().id, format!("double-sign/{}/1", key.public_key().address()));
+
+        let next_round = Vote::new(key, chain.network(), VoteType::Precommit, 1, 1, "bb");
+        let evidence = Evidence::DoubleVote { first: Box::new(first.clone()), second: Box::new(next_round) };
+        assert_eq!(evidence.verify(&chain.state.staking), Err(SlashingError::NotConflicting));
+        // A vote signed for another network, even one sharing the chain id, is no evidence
+        // of equivocation on this one
+        let other_genesis = NetworkId { genesis_hash: "00".repeat(32), ..chain.network().clone() };
+        let elsewhere = Vote::new(key, &other_genesis, VoteType::Precommit, 1, 0, "bb");
+        let evidence = Evidence::DoubleVote { first: Box::new(first), second: Box::new(elsewhere) };
+        assert!(matches!(evidence.verify(&chain.state.staking), Err(SlashingError::InvalidVote(_))));
+    }
+
+    #[test]
+    fn test_downtime_evidence() {
+        let net = TestNet::new(4);
+        let mut chain = net.chain();
+        chain.state.staking.slashing.downtime_window = 2;
+        let blocks = [net.mine(&mut chain), net.mine(&mut chain)];
+        let absent = net.keys[3].public_key().address();
+        let certificates: Vec<CommitCertificate> = blocks.iter().map(|b| {
+            let mut cert = net.certificate(&chain, b);
+            cert.precommits.retain(|v| v.validator != absent);
+            cert
+        }).collect();
+
+        let evidence = Evidence::Downtime { validator: absent.clone(), certificates: certificates.clone() };
+        assert_eq!(evidence.verify(&chain.state.staking).unwrap().kind, OffenseKind::Downtime);
+        let offense = chain.submit_evidence(evidence).unwrap();
+        assert_eq!((offense.validator.as_str(), offense.height), (absent.as_str(), 2));
+        assert_eq!(chain.evidence_pool.len(), 1);
+
+        let short = Evidence::Downtime { validator: absent.clone(), certificates: certificates[..1].to_vec() };
+        assert_eq!(short.verify(&chain.state.staking), Err(SlashingError::WindowTooShort { have: 1, need: 2 }));
+
+        let present = net.keys[0].public_key().address();
+        let online = Evidence::Downtime { validator: present.clone(), certificates };
+        assert_eq!(online.verify(&chain.state.staking), Err(SlashingError::NotDown { validator: present, height: 1 }));
+    }
+
+    #[test]
+    fn test_offense_penalty() {
+        let config = SlashingConfig::default();
+        let offense = Offense::double_sign("neo1", 1);
+        assert_eq!(offense.penalty(1_000, &config), 50);
+        assert_eq!(offense.penalty(10, &config), 0);
+    }
+}
@@ -0,0 +1,304 @@
+// Block storage for NeoNet - sled-backed blocks, tx index, commit certificates and chain head
+use anyhow::{Result, anyhow};
+use serde::{Deserialize, Serialize};
+
+use crate::codec::{impl_codec_struct, Decode, Encode};
+use crate::eth_rpc::EthReceipt;
+use crate::finality::CommitCertificate;
+use crate::{Block, Tx};
+
+const BLOCK_PREFIX: &[u8] = b"block/";
+const HASH_PREFIX: &[u8] = b"hash/";
+const TX_PREFIX: &[u8] = b"tx/";
+const CERT_PREFIX: &[u8] = b"cert/";
+const RECEIPT_PREFIX: &[u8] = b"receipt/";
+const EVM_BLOCK_PREFIX: &[u8] = b"evmblock/";
+const EVM_STATE_KEY: &[u8] = b"evmstate";
+const HEAD_KEY: &[u8] = b"head";
+const FINALIZED_KEY: &[u8] = b"finalized";
+
+/// Where a transaction lives in the chain
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TxLocation {
+    pub height: u64,
+    pub index: u64,
+}
+
+impl_codec_struct!(TxLocation { height, index });
+
+/// Handles are cheap to clone and share one database
+#[derive(Clone)]
+pub struct BlockStore {
+    db: sled::Db,
+}
+
+fn key(prefix: &[u8], id: &[u8]) -> Vec<u8> {
+    [prefix, id].concat()
+}
+
+fn height_key(height: u64) -> Vec<u8> {
+    key(BLOCK_PREFIX, &height.to_be_bytes())
+}
+
+impl BlockStore {
+    pub fn open(path: &str) -> Result<Self> {
+        // Writes flush explicitly, so skip sled's background flusher; it can briefly hold the
+        // database lock after the store is dropped and make an immediate reopen fail
+        let db = sled::Config::new().path(path).flush_every_ms(None).open()
+            .map_err(|e| anyhow!("Failed to open block store at {}: {}", path, e))?;
+        Ok(BlockStore { db })
+    }
+
+    /// In-memory store that is discarded on drop
+    pub fn temporary() -> Result<Self> {
+        let db = sled::Config::new().temporary(true).open()?;
+        Ok(BlockStore { db })
+    }
+
+    /// Write a block, its hash and tx index entries and move the head to it in one atomic batch
+    pub fn put_block(&self, block: &Block) -> Result<()> {
+        self.put_branch(&[], std::slice::from_ref(block))
+    }
+
+    /// Switch the canonical chain in one atomic batch: drop the `reverted` blocks (highest
+    /// first or in any order) and their index entries, then write `applied` in height order.
+    /// The head moves to the last applied block, or below the lowest reverted one.
+    pub fn put_branch(&self, reverted: &[Block], applied: &[Block]) -> Result<()> {
+        let mut batch = sled::Batch::default();
+        for block in reverted {
+            batch.remove(height_key(block.index));
+            batch.remove(key(HASH_PREFIX, block.hash.as_bytes()));
+            for tx in &block.txs {
+                batch.remove(key(TX_PREFIX, tx.hash().as_bytes()));
+            }
+        }
+        for block in applied {
+            batch.insert(height_key(block.index), block.to_versioned_bytes());
+            batch.insert(key(HASH_PREFIX, block.hash.as_bytes()), &block.index.to_be_bytes());
+            for (index, tx) in block.txs.iter().enumerate() {
+                let location = TxLocation { height: block.index, index: index as u64 };
+                batch.insert(key(TX_PREFIX, tx.hash().as_bytes()), location.to_versioned_bytes());
+            }
+        }
+        let head = match (applied.last(), reverted.iter().map(|b| b.index).min()) {
+            (Some(block), _) => Some(block.index),
+            (None, Some(lowest)) => lowest.checked_sub(1),
+            (None, None) => None,
+        };
+        if let Some(head) = head {
+            batch.insert(HEAD_KEY, &head.to_be_bytes());
+        }
+
+        self.db.apply_batch(batch)?;
+        self.db.flush()?;
+        Ok(())
+    }
+
+    pub fn head_height(&self) -> Result<Option<u64>> {
+        self.db.get(HEAD_KEY)?.map(|v| decode_height(&v)).transpose()
+    }
+
+    pub fn get_block_by_height(&self, height: u64) -> Result<Option<Block>> {
+        match self.db.get(height_key(height))? {
+            Some(bytes) => Ok(Some(Block::from_versioned_bytes(&bytes)?)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn get_block_by_hash(&self, hash: &str) -> Result<Option<Block>> {
+        match self.db.get(key(HASH_PREFIX, hash.as_bytes()))? {
+            Some(height) => self.get_block_by_height(decode_height(&height)?),
+            None => Ok(None),
+        }
+    }
+
+    pub fn get_tx_location(&self, hash: &str) -> Result<Option<TxLocation>> {
+        match self.db.get(key(TX_PREFIX, hash.as_bytes()))? {
+            Some(bytes) => Ok(Some(TxLocation::from_versioned_bytes(&bytes)?)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn get_tx(&self, hash: &str) -> Result<Option<(Tx, TxLocation)>> {
+        let location = match self.get_tx_location(hash)? {
+            Some(location) => location,
+            None => return Ok(None),
+        };
+        let block = self.get_block_by_height(location.height)?
+            .ok_or_else(|| anyhow!("Tx index points at missing block {}", location.height))?;
+        let tx = block.txs.get(location.index as usize).cloned()
+            .ok_or_else(|| anyhow!("Tx index points past the end of block {}", location.height))?;
+        Ok(Some((tx, location)))
+    }
+
+    /// Store a commit certificate and advance the finalized height to it in one atomic batch
+    pub fn put_certificate(&self, cert: &CommitCertificate) -> Result<()> {
+        let mut batch = sled::Batch::default();
+        batch.insert(key(CERT_PREFIX, &cert.height.to_be_bytes()), cert.to_versioned_bytes());
+        batch.insert(FINALIZED_KEY, &cert.height.to_be_bytes());
+        self.db.apply_batch(batch)?;
+        self.db.flush()?;
+        Ok(())
+    }
+
+    pub fn get_certificate(&self, height: u64) -> Result<Option<CommitCertificate>> {
+        match self.db.get(key(CERT_PREFIX, &height.to_be_bytes()))? {
+            Some(bytes) => Ok(Some(CommitCertificate::from_versioned_bytes(&bytes)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// All stored certificates in height order
+    pub fn load_certificates(&self) -> Result<Vec<CommitCertificate>> {
+        self.db.scan_prefix(CERT_PREFIX)
+            .map(|entry| Ok(CommitCertificate::from_versioned_bytes(&entry?.1)?))
+            .collect()
+    }
+
+    pub fn finalized_height(&self) -> Result<Option<u64>> {
+        self.db.get(FINALIZED_KEY)?.map(|v| decode_height(&v)).transpose()
+    }
+
+    /// Store the receipt of an EVM transaction, indexed by hash and by the EVM block it sealed,
+    /// together with the EVM state that block left behind
+    pub fn put_receipt(&self, receipt: &EthReceipt, evm_state: &[u8]) -> Result<()> {
+        let mut batch = sled::Batch::default();
+        batch.insert(key(RECEIPT_PREFIX, &receipt.tx_hash), receipt.to_versioned_bytes());
+        batch.insert(key(EVM_BLOCK_PREFIX, &receipt.block_number.to_be_bytes()), &receipt.tx_hash);
+        batch.insert(EVM_STATE_KEY, evm_state);
+        self.db.apply_batch(batch)?;
+        self.db.flush()?;
+        Ok(())
+    }
+
+    pub fn get_receipt(&self, tx_hash: &[u8; 32]) -> Result<Option<EthReceipt>> {
+        match self.db.get(key(RECEIPT_PREFIX, tx_hash))? {
+            Some(bytes) => Ok(Some(EthReceipt::from_versioned_bytes(&bytes)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Receipts of the EVM blocks `from..=to`, in block order
+    pub fn receipts_in_range(&self, from: u64, to: u64) -> Result<Vec<EthReceipt>> {
+        let range = key(EVM_BLOCK_PREFIX, &from.to_be_bytes())..=key(EVM_BLOCK_PREFIX, &to.to_be_bytes());
+        self.db.range(range)
+            .map(|entry| {
+                let hash: [u8; 32] = entry?.1.as_ref().try_into()
+                    .map_err(|_| anyhow!("Corrupt EVM block entry"))?;
+                self.get_receipt(&hash)?
+                    .ok_or_else(|| anyhow!("EVM block index points at missing receipt {}", hex::encode(hash)))
+            })
+            .collect()
+    }
+
+    /// EVM state as of `last_evm_block`, in the adapter's `encode_state` form
+    pub fn evm_state(&self) -> Result<Option<Vec<u8>>> {
+        Ok(self.db.get(EVM_STATE_KEY)?.map(|v| v.to_vec()))
+    }
+
+    /// Highest EVM block with a stored receipt
+    pub fn last_evm_block(&self) -> Result<Option<u64>> {
+        match self.db.scan_prefix(EVM_BLOCK_PREFIX).next_back() {
+            Some(entry) => Ok(Some(decode_height(&entry?.0[EVM_BLOCK_PREFIX.len()..])?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Load blocks from genesis up to the head. Returns an empty vector for a fresh store.
+    pub fn load_chain(&self) -> Result<Vec<Block>> {
+        let head = match self.head_height()? {
+            Some(head) => head,
+            None => return Ok(vec![]),
+        };
+        (0..=head)
+            .map(|height| self.get_block_by_height(height)?
+                .ok_or_else(|| anyhow!("Block store is missing block {}", height)))
+            .collect()
+    }
+}
+
+fn decode_height(bytes: &[u8]) -> Result<u64> {
+    let arr: [u8; 8] = bytes.try_into().map_err(|_| anyhow!("Corrupt height entry"))?;
+    Ok(u64::from_be_bytes(arr))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Chain;
+    use crate::pqc::HybridKeyPair;
+    use crate::tests::{TestNet, TEST_CHAIN_ID};
+
+    #[test]
+    fn test_put_and_get_block() {
+        let store = BlockStore::temporary().unwrap();
+        let net = TestNet::new(1);
+        let alice = HybridKeyPair::generate();
+        let mut chain = net.funded_chain(&[&alice]);
+        chain.add_tx(Tx::signed(&alice, TEST_CHAIN_ID, "bob", "transfer 1 NEO", 0)).unwrap();
+        let block = net.mine(&mut chain);
+
+        assert_eq!(store.head_height().unwrap(), None);
+        store.put_block(&chain.blocks[0]).unwrap();
+        store.put_block(&block).unwrap();
+
+        assert_eq!(store.head_height().unwrap(), Some(1));
+        assert_eq!(store.get_block_by_hash(&block.hash).unwrap().unwrap().index, 1);
+        assert_eq!(store.get_block_by_height(1).unwrap().unwrap().hash, block.hash);
+        assert!(store.get_block_by_height(2).unwrap().is_none());
+
+        let (tx, location) = store.get_tx(&block.txs[0].hash()).unwrap().unwrap();
+        assert_eq!(tx.nonce, 0);
+        assert_eq!(location, TxLocation { height: 1, index: 0 });
+        assert_eq!(store.load_chain().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_put_branch_replaces_reverted_blocks() {
+        let store = BlockStore::temporary().unwrap();
+        let net = TestNet::new(1);
+        let alice = HybridKeyPair::generate();
+        let mut chain = net.funded_chain(&[&alice]);
+        chain.add_tx(Tx::signed(&alice, TEST_CHAIN_ID, "bob", "transfer 1 NEO", 0)).unwrap();
+        let old = net.mine(&mut chain);
+        store.put_block(&chain.blocks[0]).unwrap();
+        store.put_block(&old).unwrap();
+
+        let mut replacement = old.clone();
+        replacement.txs.clear();
+        replacement.tx_root = crate::Block::compute_tx_root(&[]);
+        replacement.hash = replacement.compute_hash();
+        store.put_branch(std::slice::from_ref(&old), std::slice::from_ref(&replacement)).unwrap();
+
+        assert_eq!(store.head_height().unwrap(), Some(1));
+        assert!(store.get_block_by_hash(&old.hash).unwrap().is_none());
+        assert_eq!(store.get_block_by_height(1).unwrap().unwrap().hash, replacement.hash);
+        assert!(store.get_tx(&old.txs[0].hash()).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_chain_recovers_from_store() {
+        let dir = std::env::temp_dir().join(format!("neonet_store_test_{}", std::process::id()));
+        let path = dir.to_str().unwrap();
+        // A run that panicked may have left a store with another genesis behind
+        let _ = std::fs::remove_dir_all(&dir);
+        let alice = HybridKeyPair::generate();
+        let net = TestNet::new(1);
+        let head_hash = {
+            let mut chain = Chain::open(path, &net.genesis(&[&alice])).unwrap();
+            chain.add_tx(Tx::signed(&alice, TEST_CHAIN_ID, "bob", "transfer 1 NEO", 0)).unwrap();
+            let block = net.mine(&mut chain);
+            chain.finalize(net.certificate(&chain, &block)).unwrap();
+            block.hash
+        };
+
+        let chain = Chain::open(path, &net.genesis(&[&alice])).unwrap();
+        assert_eq!(chain.blocks.len(), 2);
+        assert_eq!(chain.blocks[1].hash, head_hash);
+        assert_eq!(chain.finalized_height, 1);
+        assert_eq!(chain.certificates[&1].block_hash, head_hash);
+        assert_eq!(chain.state_nonce(&alice.public_key().address()), 1);
+        drop(chain);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -1,7 +1,26 @@
 use std::collections::HashMap;
 use std::sync::{Arc, RwLock};
 use sha2::{Sha256, Digest};
+use sha3::Keccak256;
+use crate::codec::{impl_codec_enum, impl_codec_struct, Encode};
+use crate::eth_tx::{self, encode_bytes, encode_list, encode_uint};
+use crate::pqc;
 
+/// Signing domain of the Dilithium signature over a unified transaction
+pub const UNIFIED_TX_DOMAIN: &str = "neonet/unified-tx";
+
+/// EIP-155 `v` for an ECDSA signature with `recovery_id` (0 or 1) made on `chain_id`
+pub fn eip155_v(chain_id: u64, recovery_id: u8) -> u64 {
+    chain_id * 2 + 35 + recovery_id as u64
+}
+
+/// Chain id committed to by an ECDSA `v`; `None` for pre-EIP-155 values (27/28), which
+/// are valid on every network
+pub fn eip155_chain_id(v: u64) -> Option<u64> {
+    if v >= 35 { Some((v - 35) / 2) } else { None }
+}
+
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Debug, Clone, PartialEq)]
 pub enum RuntimeType {
     EVM,
@@ -10,6 +29,8 @@ pub enum RuntimeType {
     AIOptimized,
 }
 
+impl_codec_enum!(RuntimeType { EVM = 0, WASM = 1, Hybrid = 2, AIOptimized = 3 });
+
 #[derive(Debug, Clone)]
 pub struct DualAddress {
     pub evm_address: [u8; 20],
@@ -17,10 +38,12 @@ pub struct DualAddress {
     pub account_id: [u8; 32],
 }
 
+impl_codec_struct!(DualAddress { evm_address, neo_address, account_id });
+
 impl DualAddress {
     pub fn from_evm(evm_addr: [u8; 20]) -> Self {
         let mut hasher = Sha256::new();
-        hasher.update(&evm_addr);
+        hasher.update(evm_addr);
         let hash = hasher.finalize();
         let neo_addr = format!("neo1{}", hex::encode(&hash[..19]));
         
@@ -33,39 +56,30 @@ impl DualAddress {
             account_id,
         }
     }
-    
-    pub fn from_neo(neo_addr: &str) -> Option<Self> {
-        if !neo_addr.starts_with("neo1") || neo_addr.len() < 42 {
-            return None;
-        }
-        
-        let hex_part = &neo_addr[4..];
-        let bytes = hex::decode(hex_part).ok()?;
-        
-        let mut evm_address = [0u8; 20];
-        evm_address.copy_from_slice(&bytes[..20.min(bytes.len())]);
-        
-        let mut hasher = Sha256::new();
-        hasher.update(&evm_address);
-        let hash = hasher.finalize();
-        let mut account_id = [0u8; 32];
-        account_id.copy_from_slice(&hash);
-        
-        Some(Self {
-            evm_address,
-            neo_address: neo_addr.to_string(),
-            account_id,
-        })
+
+    /// Whether the NEO address and account id are the ones derived from the EVM address
+    pub fn is_derived(&self) -> bool {
+        let derived = Self::from_evm(self.evm_address);
+        self.neo_address == derived.neo_address && self.account_id == derived.account_id
     }
 }
 
 #[derive(Debug, Clone)]
 pub struct DualSignature {
     pub ecdsa_sig: Option<Vec<u8>>,
+    /// ECDSA recovery value, EIP-155 encoded so the signature commits to the chain id;
+    /// `None` when the signature comes with an `eth_envelope`
+    pub ecdsa_v: Option<u64>,
     pub dilithium_sig: Option<Vec<u8>>,
     pub signature_mode: SignatureMode,
+    /// Raw typed (EIP-2930 or EIP-1559) Ethereum transaction the ECDSA signature was made
+    /// over. Its signing payload includes fields the unified form doesn't carry, so the
+    /// signature is checked against the envelope, which must describe this transaction.
+    pub eth_envelope: Option<Vec<u8>>,
 }
 
+impl_codec_struct!(DualSignature { ecdsa_sig, ecdsa_v, dilithium_sig, signature_mode, eth_envelope });
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum SignatureMode {
     EVMOnly,
@@ -73,9 +87,13 @@ pub enum SignatureMode {
     Hybrid,
 }
 
+impl_codec_enum!(SignatureMode { EVMOnly = 0, QuantumOnly = 1, Hybrid = 2 });
+
 #[derive(Debug, Clone)]
 pub struct UnifiedTransaction {
     pub tx_hash: [u8; 32],
+    /// Network the transaction is valid on; bound into both signatures
+    pub chain_id: u64,
     pub from: DualAddress,
     pub to: Option<DualAddress>,
     pub value: u128,
@@ -89,6 +107,171 @@ pub struct UnifiedTransaction {
     pub timestamp: u64,
 }
 
+impl_codec_struct!(UnifiedTransaction {
+    tx_hash,
+    chain_id,
+    from,
+    to,
+    value,
+    gas_limit,
+    gas_price,
+    nonce,
+    data,
+    signature,
+    runtime_hint,
+    cross_runtime_calls,
+    timestamp,
+});
+
+impl UnifiedTransaction {
+    /// Canonical encoding of every field except the hash and the signatures
+    pub fn body_bytes(&self) -> Vec<u8> {
+        (
+            &self.from,
+            &self.to,
+            self.value,
+            self.gas_limit,
+            self.gas_price,
+            self.nonce,
+            &self.data,
+            &self.runtime_hint,
+            &self.cross_runtime_calls,
+            self.timestamp,
+        ).to_bytes()
+    }
+
+    /// Hash identifying the transaction, over its chain id and body
+    pub fn compute_hash(&self) -> [u8; 32] {
+        Sha256::digest((self.chain_id, self.body_bytes()).to_bytes()).into()
+    }
+
+    /// Bytes the Dilithium signature covers: every field except the hash and the signatures,
+    /// behind the unified transaction domain and the chain id
+    pub fn pq_signing_message(&self) -> Vec<u8> {
+        pqc::domain_message(UNIFIED_TX_DOMAIN, self.chain_id, &self.body_bytes())
+    }
+
+    /// Bytes the ECDSA signature covers: the EIP-155 legacy payload
+    /// rlp([nonce, gasPrice, gas, to, value, data, chainId, 0, 0])
+    pub fn ecdsa_signing_payload(&self) -> Vec<u8> {
+        encode_list(&[
+            encode_uint(self.nonce as u128),
+            encode_uint(self.gas_price as u128),
+            encode_uint(self.gas_limit as u128),
+            encode_bytes(self.to.as_ref().map_or(&[][..], |to| &to.evm_address[..])),
+            encode_uint(self.value),
+            encode_bytes(&self.data),
+            encode_uint(self.chain_id as u128),
+            encode_uint(0),
+            encode_uint(0),
+        ])
+    }
+
+    /// Sign the EIP-155 payload with `key` the way an Ethereum wallet does
+    pub fn sign_ecdsa(&mut self, key: &k256::ecdsa::SigningKey) {
+        let hash = Keccak256::digest(self.ecdsa_signing_payload());
+        let (sig, recovery_id) = key.sign_prehash_recoverable(&hash).expect("signing a 32-byte prehash cannot fail");
+        self.signature.ecdsa_sig = Some(sig.to_bytes().to_vec());
+        self.signature.ecdsa_v = Some(eip155_v(self.chain_id, recovery_id.to_byte()));
+    }
+
+    /// Check the signatures `signature_mode` requires: the ECDSA one must recover `from`,
+    /// the Dilithium one must verify under `dilithium_key`, the sender's registered key.
+    /// The ECDSA payload leaves out cross-runtime calls, so only a Dilithium signature can
+    /// authorize them.
+    pub fn verify_signatures(&self, dilithium_key: Option<&[u8]>) -> Result<(), String> {
+        let sig = &self.signature;
+        let (need_ecdsa, need_dilithium) = match sig.signature_mode {
+            SignatureMode::EVMOnly => (true, !self.cross_runtime_calls.is_empty()),
+            SignatureMode::QuantumOnly => (false, true),
+            SignatureMode::Hybrid => (true, true),
+        };
+        if need_ecdsa {
+            let signer = match &sig.eth_envelope {
+                Some(raw) => self.envelope_signer(raw)?,
+                None => self.eip155_signer()?,
+            };
+            if signer != self.from.evm_address {
+                return Err("ECDSA signature is not from the sender".to_string());
+            }
+        }
+        if need_dilithium {
+            let sig = sig.dilithium_sig.as_ref().ok_or_else(|| "missing Dilithium signature".to_string())?;
+            let key = dilithium_key.ok_or_else(|| "sender has no registered Dilithium key".to_string())?;
+            if !pqc::verify_dilithium(key, &self.pq_signing_message(), sig) {
+                return Err("invalid Dilithium signature".to_string());
+            }
+        }
+        Ok(())
+    }
+
+    /// Signer of an ECDSA signature over the EIP-155 payload rebuilt from the transaction
+    fn eip155_signer(&self) -> Result<[u8; 20], String> {
+        let (rs, v) = match (&self.signature.ecdsa_sig, self.signature.ecdsa_v) {
+            (Some(rs), Some(v)) => (rs, v),
+            _ => return Err("missing ECDSA signature".to_string()),
+        };
+        let rs: &[u8; 64] = rs.as_slice().try_into().map_err(|_| "ECDSA signature must be 64 bytes".to_string())?;
+        let recovery_id = match eip155_chain_id(v) {
+            Some(id) if id == self.chain_id => ((v - 35) % 2) as u8,
+            _ => return Err("ECDSA signature is not EIP-155 signed for this chain".to_string()),
+        };
+        let hash: [u8; 32] = Keccak256::digest(self.ecdsa_signing_payload()).into();
+        let (r, s) = rs.split_at(32);
+        eth_tx::recover_sender(&hash, r.try_into().unwrap(), s.try_into().unwrap(), recovery_id)
+            .map_err(|e| format!("ECDSA {}", e))
+    }
+
+    /// Signer of the typed Ethereum transaction in `raw`, which must be for this chain and
+    /// agree with every field the two forms share, signature included
+    fn envelope_signer(&self, raw: &[u8]) -> Result<[u8; 20], String> {
+        let signed = eth_tx::decode_signed_tx(raw).map_err(|e| format!("Ethereum envelope: {}", e))?;
+        let matches = signed.tx_type != eth_tx::EthTxType::Legacy
+            && signed.chain_id == Some(self.chain_id)
+            && signed.nonce == self.nonce
+            && signed.gas_price == self.gas_price
+            && signed.gas_limit == self.gas_limit
+            && signed.to == self.to.as_ref().map(|to| to.evm_address)
+            && signed.value == self.value
+            && signed.data == self.data
+            && self.signature.ecdsa_sig.as_deref() == Some(&[signed.r, signed.s].concat()[..]);
+        if !matches {
+            return Err("Ethereum envelope does not match the transaction".to_string());
+        }
+        Ok(signed.from)
+    }
+
+    /// Reject sender or recipient addresses whose account id or NEO address was not derived
+    /// from their EVM address. The ECDSA signature only covers the EVM addresses, so otherwise
+    /// a signer could name another account to be debited or credited.
+    pub fn check_addresses(&self) -> Result<(), String> {
+        if !self.from.is_derived() {
+            return Err("sender account does not match its EVM address".to_string());
+        }
+        if self.to.as_ref().is_some_and(|to| !to.is_derived()) {
+            return Err("recipient account does not match its EVM address".to_string());
+        }
+        Ok(())
+    }
+
+    /// Reject transactions for another network. An ECDSA signature must carry an EIP-155
+    /// `v` for the same chain, or come in an envelope whose chain id is checked along with
+    /// the signature; unprotected legacy signatures could be replayed anywhere.
+    pub fn check_chain_id(&self, chain_id: u64) -> Result<(), String> {
+        if self.chain_id != chain_id {
+            return Err(format!("transaction is for chain {}, expected {}", self.chain_id, chain_id));
+        }
+        if self.signature.ecdsa_sig.is_some() && self.signature.eth_envelope.is_none() {
+            match self.signature.ecdsa_v.map(eip155_chain_id) {
+                Some(Some(id)) if id == chain_id => {}
+                Some(Some(id)) => return Err(format!("ECDSA signature is for chain {}, expected {}", id, chain_id)),
+                _ => return Err("ECDSA signature is not replay protected".to_string()),
+            }
+        }
+        Ok(())
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct CrossRuntimeCall {
     pub source_runtime: RuntimeType,
@@ -99,17 +282,12 @@ pub struct CrossRuntimeCall {
     pub gas_budget: u64,
 }
 
-#[derive(Debug, Clone)]
+impl_codec_struct!(CrossRuntimeCall { source_runtime, target_runtime, target_contract, method, params, gas_budget });
+
+#[derive(Debug, Clone, Default)]
 pub struct UnifiedAccount {
-    pub account_id: [u8; 32],
-    pub dual_address: DualAddress,
     pub balance: u128,
     pub nonce: u64,
-    pub evm_code_hash: Option<[u8; 32]>,
-    pub wasm_code_id: Option<u64>,
-    pub storage_root: [u8; 32],
-    pub ai_reputation_score: f64,
-    pub quantum_key_registered: bool,
 }
 
 #[derive(Debug, Clone)]
@@ -126,16 +304,9 @@ pub struct DualStateEngine {
     evm_storage: Arc<RwLock<HashMap<[u8; 32], Vec<u8>>>>,
     wasm_storage: Arc<RwLock<HashMap<[u8; 32], Vec<u8>>>>,
     shared_storage: Arc<RwLock<HashMap<String, Vec<u8>>>>,
+    /// Dilithium public keys accounts have registered, by account id
+    quantum_keys: Arc<RwLock<HashMap<[u8; 32], Vec<u8>>>>,
     state_root: Arc<RwLock<DualStateNode>>,
-    pending_changes: Arc<RwLock<Vec<StateChange>>>,
-}
-
-#[derive(Debug, Clone)]
-pub enum StateChange {
-    AccountUpdate(UnifiedAccount),
-    EVMStorageWrite { key: [u8; 32], value: Vec<u8> },
-    WASMStorageWrite { key: [u8; 32], value: Vec<u8> },
-    SharedStorageWrite { key: String, value: Vec<u8> },
 }
 
 impl DualStateEngine {
@@ -145,6 +316,7 @@ impl DualStateEngine {
             evm_storage: Arc::new(RwLock::new(HashMap::new())),
             wasm_storage: Arc::new(RwLock::new(HashMap::new())),
             shared_storage: Arc::new(RwLock::new(HashMap::new())),
+            quantum_keys: Arc::new(RwLock::new(HashMap::new())),
             state_root: Arc::new(RwLock::new(DualStateNode {
                 hash: [0u8; 32],
                 evm_state_root: [0u8; 32],
@@ -152,30 +324,12 @@ impl DualStateEngine {
                 shared_state_root: [0u8; 32],
                 height: 0,
             })),
-            pending_changes: Arc::new(RwLock::new(Vec::new())),
         }
     }
-    
-    pub fn get_account(&self, account_id: &[u8; 32]) -> Option<UnifiedAccount> {
-        self.accounts.read().unwrap().get(account_id).cloned()
-    }
-    
+
     pub fn create_account(&self, dual_address: DualAddress) -> UnifiedAccount {
-        let account = UnifiedAccount {
-            account_id: dual_address.account_id,
-            dual_address: dual_address.clone(),
-            balance: 0,
-            nonce: 0,
-            evm_code_hash: None,
-            wasm_code_id: None,
-            storage_root: [0u8; 32],
-            ai_reputation_score: 0.5,
-            quantum_key_registered: false,
-        };
-        
+        let account = UnifiedAccount::default();
         self.accounts.write().unwrap().insert(dual_address.account_id, account.clone());
-        self.pending_changes.write().unwrap().push(StateChange::AccountUpdate(account.clone()));
-        
         account
     }
     
@@ -183,48 +337,66 @@ impl DualStateEngine {
         let mut accounts = self.accounts.write().unwrap();
         if let Some(account) = accounts.get_mut(account_id) {
             account.balance = new_balance;
-            self.pending_changes.write().unwrap().push(StateChange::AccountUpdate(account.clone()));
             true
         } else {
             false
         }
     }
     
-    pub fn write_evm_storage(&self, key: [u8; 32], value: Vec<u8>) {
-        self.evm_storage.write().unwrap().insert(key, value.clone());
-        self.pending_changes.write().unwrap().push(StateChange::EVMStorageWrite { key, value });
+    pub fn balance(&self, account_id: &[u8; 32]) -> u128 {
+        self.accounts.read().unwrap().get(account_id).map_or(0, |account| account.balance)
     }
-    
-    pub fn write_wasm_storage(&self, key: [u8; 32], value: Vec<u8>) {
-        self.wasm_storage.write().unwrap().insert(key, value.clone());
-        self.pending_changes.write().unwrap().push(StateChange::WASMStorageWrite { key, value });
+
+    /// Nonce the account's next transaction must carry
+    pub fn nonce(&self, account_id: &[u8; 32]) -> u64 {
+        self.accounts.read().unwrap().get(account_id).map_or(0, |account| account.nonce)
     }
-    
-    pub fn write_shared_storage(&self, key: String, value: Vec<u8>) {
-        self.shared_storage.write().unwrap().insert(key.clone(), value.clone());
-        self.pending_changes.write().unwrap().push(StateChange::SharedStorageWrite { key, value });
+
+    /// Count a transaction from `address`, creating the account if it is new
+    pub fn increment_nonce(&self, address: &DualAddress) {
+        let mut accounts = self.accounts.write().unwrap();
+        accounts.entry(address.account_id).or_default().nonce += 1;
     }
-    
-    pub fn read_evm_storage(&self, key: &[u8; 32]) -> Option<Vec<u8>> {
-        self.evm_storage.read().unwrap().get(key).cloned()
+
+    /// Move `amount` from `from` to `to`, creating the recipient if it is new. Nothing changes
+    /// if the sender is unknown or can't cover the amount.
+    pub fn transfer(&self, from: &[u8; 32], to: &DualAddress, amount: u128) -> bool {
+        let mut accounts = self.accounts.write().unwrap();
+        let recipient_balance = accounts.get(&to.account_id).map_or(0, |account| account.balance);
+        match accounts.get(from) {
+            Some(sender) if sender.balance >= amount => {}
+            _ => return false,
+        }
+        if *from != to.account_id && recipient_balance.checked_add(amount).is_none() {
+            return false;
+        }
+        accounts.get_mut(from).unwrap().balance -= amount;
+        accounts.entry(to.account_id).or_default().balance += amount;
+        true
     }
-    
-    pub fn read_wasm_storage(&self, key: &[u8; 32]) -> Option<Vec<u8>> {
-        self.wasm_storage.read().unwrap().get(key).cloned()
+
+    /// Record the Dilithium public key the account's quantum signatures must verify under
+    pub fn register_quantum_key(&self, account_id: &[u8; 32], public_key: Vec<u8>) -> bool {
+        if !self.accounts.read().unwrap().contains_key(account_id) {
+            return false;
+        }
+        self.quantum_keys.write().unwrap().insert(*account_id, public_key);
+        true
     }
-    
-    pub fn read_shared_storage(&self, key: &str) -> Option<Vec<u8>> {
-        self.shared_storage.read().unwrap().get(key).cloned()
+
+    pub fn quantum_key(&self, account_id: &[u8; 32]) -> Option<Vec<u8>> {
+        self.quantum_keys.read().unwrap().get(account_id).cloned()
     }
-    
+
+
     pub fn compute_state_root(&self) -> [u8; 32] {
         let mut hasher = Sha256::new();
         
         let accounts = self.accounts.read().unwrap();
         for (id, account) in accounts.iter() {
             hasher.update(id);
-            hasher.update(&account.balance.to_le_bytes());
-            hasher.update(&account.nonce.to_le_bytes());
+            hasher.update(account.balance.to_le_bytes());
+            hasher.update(account.nonce.to_le_bytes());
         }
         
         let evm = self.evm_storage.read().unwrap();
@@ -257,15 +429,15 @@ impl DualStateEngine {
         };
         
         *self.state_root.write().unwrap() = node.clone();
-        self.pending_changes.write().unwrap().clear();
         
         node
     }
     
-    pub fn rollback(&self) {
-        self.pending_changes.write().unwrap().clear();
+    /// State root recorded by the last `commit`
+    pub fn last_commit(&self) -> DualStateNode {
+        self.state_root.read().unwrap().clone()
     }
-    
+
     fn compute_evm_root(&self) -> [u8; 32] {
         let mut hasher = Sha256::new();
         hasher.update(b"EVM_STATE");
@@ -310,9 +482,7 @@ impl DualStateEngine {
 }
 
 pub struct CrossVMCallManager {
-    state: Arc<DualStateEngine>,
     call_stack: Arc<RwLock<Vec<CrossRuntimeCall>>>,
-    results: Arc<RwLock<HashMap<[u8; 32], CrossVMResult>>>,
 }
 
 #[derive(Debug, Clone)]
@@ -320,24 +490,12 @@ pub struct CrossVMResult {
     pub success: bool,
     pub return_data: Vec<u8>,
     pub gas_used: u64,
-    pub source_runtime: RuntimeType,
-    pub target_runtime: RuntimeType,
-    pub logs: Vec<CrossVMLog>,
-}
-
-#[derive(Debug, Clone)]
-pub struct CrossVMLog {
-    pub topics: Vec<[u8; 32]>,
-    pub data: Vec<u8>,
-    pub runtime: RuntimeType,
 }
 
 impl CrossVMCallManager {
-    pub fn new(state: Arc<DualStateEngine>) -> Self {
+    pub fn new() -> Self {
         Self {
-            state,
             call_stack: Arc::new(RwLock::new(Vec::new())),
-            results: Arc::new(RwLock::new(HashMap::new())),
         }
     }
     
@@ -353,9 +511,6 @@ impl CrossVMCallManager {
                 success: false,
                 return_data: b"Unsupported cross-runtime call".to_vec(),
                 gas_used: 0,
-                source_runtime: call.source_runtime.clone(),
-                target_runtime: call.target_runtime.clone(),
-                logs: vec![],
             },
         };
         
@@ -379,9 +534,6 @@ impl CrossVMCallManager {
             success: wasm_result.success,
             return_data,
             gas_used: wasm_result.gas_used,
-            source_runtime: RuntimeType::EVM,
-            target_runtime: RuntimeType::WASM,
-            logs: wasm_result.logs,
         }
     }
     
@@ -401,9 +553,6 @@ impl CrossVMCallManager {
             success: evm_result.success,
             return_data,
             gas_used: evm_result.gas_used,
-            source_runtime: RuntimeType::WASM,
-            target_runtime: RuntimeType::EVM,
-            logs: evm_result.logs,
         }
     }
     
@@ -428,9 +577,6 @@ impl CrossVMCallManager {
             success: true,
             return_data: format!("EVM:{}", method).into_bytes(),
             gas_used: gas_budget / 10,
-            source_runtime: RuntimeType::EVM,
-            target_runtime: RuntimeType::EVM,
-            logs: vec![],
         }
     }
     
@@ -439,9 +585,6 @@ impl CrossVMCallManager {
             success: true,
             return_data: format!("WASM:{}", method).into_bytes(),
             gas_used: gas_budget / 10,
-            source_runtime: RuntimeType::WASM,
-            target_runtime: RuntimeType::WASM,
-            logs: vec![],
         }
     }
 }
@@ -451,13 +594,11 @@ pub struct AIRuntimePlanner {
     routing_policy: Arc<RwLock<RoutingPolicy>>,
 }
 
+/// Gas a transaction used on the runtime it was routed to
 #[derive(Debug, Clone)]
 pub struct RuntimeMetrics {
     pub runtime: RuntimeType,
     pub avg_gas_cost: f64,
-    pub avg_execution_time_ms: f64,
-    pub success_rate: f64,
-    pub timestamp: u64,
 }
 
 #[derive(Debug, Clone)]
@@ -465,8 +606,6 @@ pub struct RoutingPolicy {
     pub prefer_wasm_for_ai: bool,
     pub prefer_evm_for_defi: bool,
     pub gas_optimization_enabled: bool,
-    pub quantum_security_required: bool,
-    pub max_cross_runtime_depth: u8,
 }
 
 impl Default for RoutingPolicy {
@@ -475,8 +614,6 @@ impl Default for RoutingPolicy {
             prefer_wasm_for_ai: true,
             prefer_evm_for_defi: true,
             gas_optimization_enabled: true,
-            quantum_security_required: false,
-            max_cross_runtime_depth: 4,
         }
     }
 }
@@ -484,19 +621,6 @@ impl Default for RoutingPolicy {
 #[derive(Debug, Clone)]
 pub struct RoutingDecision {
     pub recommended_runtime: RuntimeType,
-    pub confidence: f64,
-    pub reasoning: String,
-    pub fallback_runtime: RuntimeType,
-    pub estimated_gas: u64,
-    pub security_level: SecurityLevel,
-}
-
-#[derive(Debug, Clone, PartialEq)]
-pub enum SecurityLevel {
-    Standard,
-    Enhanced,
-    QuantumSafe,
-    Maximum,
 }
 
 impl AIRuntimePlanner {
@@ -510,69 +634,37 @@ impl AIRuntimePlanner {
     pub fn plan_execution(&self, tx: &UnifiedTransaction) -> RoutingDecision {
         let policy = self.routing_policy.read().unwrap();
         
+        // The sender's runtime hint always wins
         if let Some(hint) = &tx.runtime_hint {
-            return RoutingDecision {
-                recommended_runtime: hint.clone(),
-                confidence: 1.0,
-                reasoning: "User specified runtime hint".to_string(),
-                fallback_runtime: RuntimeType::EVM,
-                estimated_gas: tx.gas_limit,
-                security_level: self.determine_security_level(&tx.signature),
-            };
+            return RoutingDecision { recommended_runtime: hint.clone() };
         }
         
         let tx_type = self.analyze_transaction_type(tx);
         let metrics = self.historical_metrics.read().unwrap();
         
-        let (runtime, confidence, reasoning) = match tx_type.as_str() {
+        let runtime = match tx_type.as_str() {
             "ai_computation" | "model_inference" | "ml_training" => {
-                if policy.prefer_wasm_for_ai {
-                    (RuntimeType::WASM, 0.92, "AI computation optimized for WASM runtime".to_string())
-                } else {
-                    (RuntimeType::EVM, 0.75, "Using EVM for AI (policy override)".to_string())
-                }
+                if policy.prefer_wasm_for_ai { RuntimeType::WASM } else { RuntimeType::EVM }
             },
             "token_transfer" | "defi_swap" | "liquidity" => {
-                if policy.prefer_evm_for_defi {
-                    (RuntimeType::EVM, 0.95, "DeFi operation optimized for EVM compatibility".to_string())
-                } else {
-                    (RuntimeType::WASM, 0.80, "Using WASM for DeFi (policy override)".to_string())
-                }
-            },
-            "cross_runtime" | "hybrid" => {
-                (RuntimeType::Hybrid, 0.88, "Cross-runtime operation requires hybrid execution".to_string())
-            },
-            "governance" | "voting" => {
-                (RuntimeType::AIOptimized, 0.90, "Governance optimized with AI verification".to_string())
+                if policy.prefer_evm_for_defi { RuntimeType::EVM } else { RuntimeType::WASM }
             },
+            "cross_runtime" | "hybrid" => RuntimeType::Hybrid,
+            "governance" | "voting" => RuntimeType::AIOptimized,
             _ => {
                 let avg_evm_gas = self.get_avg_metric(&metrics, RuntimeType::EVM);
                 let avg_wasm_gas = self.get_avg_metric(&metrics, RuntimeType::WASM);
                 
+                // Default to EVM unless WASM has been cheaper so far
                 if avg_wasm_gas < avg_evm_gas && policy.gas_optimization_enabled {
-                    (RuntimeType::WASM, 0.70, "WASM selected for gas optimization".to_string())
+                    RuntimeType::WASM
                 } else {
-                    (RuntimeType::EVM, 0.75, "EVM selected as default".to_string())
+                    RuntimeType::EVM
                 }
             }
         };
         
-        let security_level = if tx.signature.signature_mode == SignatureMode::Hybrid {
-            SecurityLevel::QuantumSafe
-        } else if tx.signature.dilithium_sig.is_some() {
-            SecurityLevel::Enhanced
-        } else {
-            SecurityLevel::Standard
-        };
-        
-        RoutingDecision {
-            recommended_runtime: runtime,
-            confidence,
-            reasoning,
-            fallback_runtime: RuntimeType::EVM,
-            estimated_gas: self.estimate_gas(tx, &metrics),
-            security_level,
-        }
+        RoutingDecision { recommended_runtime: runtime }
     }
     
     fn analyze_transaction_type(&self, tx: &UnifiedTransaction) -> String {
@@ -600,14 +692,6 @@ impl AIRuntimePlanner {
         }
     }
     
-    fn determine_security_level(&self, sig: &DualSignature) -> SecurityLevel {
-        match sig.signature_mode {
-            SignatureMode::Hybrid => SecurityLevel::Maximum,
-            SignatureMode::QuantumOnly => SecurityLevel::QuantumSafe,
-            SignatureMode::EVMOnly => SecurityLevel::Standard,
-        }
-    }
-    
     fn get_avg_metric(&self, metrics: &[RuntimeMetrics], runtime: RuntimeType) -> f64 {
         let filtered: Vec<_> = metrics.iter().filter(|m| m.runtime == runtime).collect();
         if filtered.is_empty() {
@@ -616,14 +700,6 @@ impl AIRuntimePlanner {
         filtered.iter().map(|m| m.avg_gas_cost).sum::<f64>() / filtered.len() as f64
     }
     
-    fn estimate_gas(&self, tx: &UnifiedTransaction, metrics: &[RuntimeMetrics]) -> u64 {
-        let base_gas = 21000u64;
-        let data_gas = tx.data.len() as u64 * 16;
-        let cross_call_gas = tx.cross_runtime_calls.len() as u64 * 50000;
-        
-        base_gas + data_gas + cross_call_gas
-    }
-    
     pub fn record_metrics(&self, metrics: RuntimeMetrics) {
         let mut hist = self.historical_metrics.write().unwrap();
         hist.push(metrics);
@@ -631,10 +707,6 @@ impl AIRuntimePlanner {
             hist.remove(0);
         }
     }
-    
-    pub fn update_policy(&self, policy: RoutingPolicy) {
-        *self.routing_policy.write().unwrap() = policy;
-    }
 }
 
 pub struct UnifiedGasModel {
@@ -671,13 +743,10 @@ impl UnifiedGasModel {
         
         total as u64
     }
-    
-    pub fn estimate_cost(&self, gas: u64, gas_price: u64) -> u128 {
-        gas as u128 * gas_price as u128
-    }
 }
 
 pub struct NeoNetUnifiedFabric {
+    pub chain_id: u64,
     pub state_engine: Arc<DualStateEngine>,
     pub cross_vm_manager: Arc<CrossVMCallManager>,
     pub ai_planner: Arc<AIRuntimePlanner>,
@@ -685,47 +754,63 @@ pub struct NeoNetUnifiedFabric {
 }
 
 impl NeoNetUnifiedFabric {
-    pub fn new() -> Self {
-        let state = Arc::new(DualStateEngine::new());
-        let cross_vm = Arc::new(CrossVMCallManager::new(state.clone()));
-        
+    pub fn new(chain_id: u64) -> Self {
         Self {
-            state_engine: state,
-            cross_vm_manager: cross_vm,
+            chain_id,
+            state_engine: Arc::new(DualStateEngine::new()),
+            cross_vm_manager: Arc::new(CrossVMCallManager::new()),
             ai_planner: Arc::new(AIRuntimePlanner::new()),
             gas_model: Arc::new(UnifiedGasModel::new()),
         }
     }
     
-    pub fn execute_transaction(&self, tx: UnifiedTransaction) -> ExecutionResult {
+    /// Verify and run `tx` in a block proposed by `proposer`. The transaction must carry the
+    /// sender's next nonce, and the sender must hold `gas_limit * gas_price + value` up front.
+    /// Once the transaction has run, whether or not it succeeded, the nonce is used up and
+    /// `gas_used * gas_price` is paid to the proposer.
+    pub fn execute_transaction(&self, tx: UnifiedTransaction, proposer: &DualAddress) -> ExecutionResult {
         let routing = self.ai_planner.plan_execution(&tx);
         let gas_estimate = self.gas_model.calculate_gas(&tx, &routing.recommended_runtime);
         
+        let dilithium_key = self.state_engine.quantum_key(&tx.from.account_id);
+        let checked = tx.check_chain_id(self.chain_id)
+            .and_then(|()| tx.check_addresses())
+            .and_then(|()| tx.verify_signatures(dilithium_key.as_deref()));
+        if let Err(e) = checked {
+            return ExecutionResult::failed(routing.recommended_runtime, e.into_bytes());
+        }
+        let expected_nonce = self.state_engine.nonce(&tx.from.account_id);
+        if tx.nonce != expected_nonce {
+            let reason = format!("nonce {} does not match the sender's next nonce {}", tx.nonce, expected_nonce);
+            return ExecutionResult::failed(routing.recommended_runtime, reason.into_bytes());
+        }
+        
         if gas_estimate > tx.gas_limit {
-            return ExecutionResult {
-                success: false,
-                gas_used: 0,
-                return_data: b"Insufficient gas".to_vec(),
-                logs: vec![],
-                state_changes: vec![],
-                runtime_used: routing.recommended_runtime,
-                cross_runtime_results: vec![],
-            };
+            return ExecutionResult::failed(routing.recommended_runtime, b"Insufficient gas".to_vec());
         }
+
+        let upfront = u128::from(tx.gas_limit).checked_mul(u128::from(tx.gas_price))
+            .and_then(|gas| gas.checked_add(tx.value));
+        let balance = self.state_engine.balance(&tx.from.account_id);
+        if upfront.is_none_or(|cost| balance < cost) {
+            return ExecutionResult::failed(routing.recommended_runtime, b"Insufficient funds for gas * price + value".to_vec());
+        }
+        self.state_engine.increment_nonce(&tx.from);
         
-        let result = match routing.recommended_runtime {
+        let mut result = match routing.recommended_runtime {
             RuntimeType::EVM => self.execute_evm(tx.clone()),
             RuntimeType::WASM => self.execute_wasm(tx.clone()),
             RuntimeType::Hybrid => self.execute_hybrid(tx.clone()),
             RuntimeType::AIOptimized => self.execute_ai_optimized(tx.clone()),
         };
+        // Covered by the up-front check, since gas used never exceeds the limit
+        result.gas_used = result.gas_used.min(tx.gas_limit);
+        let fee = u128::from(result.gas_used) * u128::from(tx.gas_price);
+        self.state_engine.transfer(&tx.from.account_id, proposer, fee);
         
         self.ai_planner.record_metrics(RuntimeMetrics {
             runtime: routing.recommended_runtime.clone(),
             avg_gas_cost: result.gas_used as f64,
-            avg_execution_time_ms: 10.0,
-            success_rate: if result.success { 1.0 } else { 0.0 },
-            timestamp: tx.timestamp,
         });
         
         result
@@ -735,17 +820,16 @@ impl NeoNetUnifiedFabric {
         let gas_used = self.gas_model.calculate_gas(&tx, &RuntimeType::EVM);
         
         if let Some(to) = &tx.to {
-            self.state_engine.update_balance(&tx.from.account_id, 0);
+            if !self.state_engine.transfer(&tx.from.account_id, to, tx.value) {
+                return ExecutionResult { gas_used, ..ExecutionResult::failed(RuntimeType::EVM, b"Value transfer failed".to_vec()) };
+            }
         }
         
         ExecutionResult {
             success: true,
             gas_used,
             return_data: vec![],
-            logs: vec![],
-            state_changes: vec![],
             runtime_used: RuntimeType::EVM,
-            cross_runtime_results: vec![],
         }
     }
     
@@ -756,31 +840,31 @@ impl NeoNetUnifiedFabric {
             success: true,
             gas_used,
             return_data: vec![],
-            logs: vec![],
-            state_changes: vec![],
             runtime_used: RuntimeType::WASM,
-            cross_runtime_results: vec![],
         }
     }
     
+    /// Run the cross-runtime calls in order, stopping at the first that fails. The result
+    /// carries the output of the last call that ran.
     fn execute_hybrid(&self, tx: UnifiedTransaction) -> ExecutionResult {
-        let mut cross_results = vec![];
-        
+        let mut success = true;
+        let mut return_data = vec![];
         for call in &tx.cross_runtime_calls {
             let result = self.cross_vm_manager.execute_cross_call(call.clone());
-            cross_results.push(result);
+            success = result.success;
+            return_data = result.return_data;
+            if !success {
+                break;
+            }
         }
         
         let gas_used = self.gas_model.calculate_gas(&tx, &RuntimeType::Hybrid);
         
         ExecutionResult {
-            success: true,
+            success,
             gas_used,
-            return_data: vec![],
-            logs: vec![],
-            state_changes: vec![],
+            return_data,
             runtime_used: RuntimeType::Hybrid,
-            cross_runtime_results: cross_results,
         }
     }
     
@@ -791,10 +875,7 @@ impl NeoNetUnifiedFabric {
             success: true,
             gas_used,
             return_data: b"AI_OPTIMIZED".to_vec(),
-            logs: vec![],
-            state_changes: vec![],
             runtime_used: RuntimeType::AIOptimized,
-            cross_runtime_results: vec![],
         }
     }
     
@@ -807,11 +888,20 @@ impl NeoNetUnifiedFabric {
 pub struct ExecutionResult {
     pub success: bool,
     pub gas_used: u64,
+    /// Output of the call, or why the transaction failed
     pub return_data: Vec<u8>,
-    pub logs: Vec<CrossVMLog>,
-    pub state_changes: Vec<StateChange>,
     pub runtime_used: RuntimeType,
-    pub cross_runtime_results: Vec<CrossVMResult>,
+}
+
+impl ExecutionResult {
+    fn failed(runtime: RuntimeType, reason: Vec<u8>) -> Self {
+        ExecutionResult {
+            success: false,
+            gas_used: 0,
+            return_data: reason,
+            runtime_used: runtime,
+        }
+    }
 }
 
 #[cfg(test)]
@@ -829,11 +919,13 @@ mod tests {
     
     #[test]
     fn test_unified_fabric_execution() {
-        let fabric = NeoNetUnifiedFabric::new();
+        let fabric = NeoNetUnifiedFabric::new(1);
+        let key = k256::ecdsa::SigningKey::from_slice(&[1; 32]).unwrap();
         
-        let tx = UnifiedTransaction {
+        let mut tx = UnifiedTransaction {
             tx_hash: [0u8; 32],
-            from: DualAddress::from_evm([1u8; 20]),
+            chain_id: 1,
+            from: DualAddress::from_evm(eth_tx::evm_address(key.verifying_key())),
             to: Some(DualAddress::from_evm([2u8; 20])),
             value: 1000,
             gas_limit: 100000,
@@ -842,15 +934,270 @@ mod tests {
             data: vec![],
             signature: DualSignature {
                 ecdsa_sig: Some(vec![1, 2, 3]),
+                ecdsa_v: Some(eip155_v(1, 0)),
                 dilithium_sig: None,
                 signature_mode: SignatureMode::EVMOnly,
+                eth_envelope: None,
             },
             runtime_hint: None,
             cross_runtime_calls: vec![],
             timestamp: 12345,
         };
+        let proposer = DualAddress::from_evm([9u8; 20]);
+        // The placeholder signature above is refused; a real one over the EIP-155 payload passes
+        assert!(!fabric.execute_transaction(tx.clone(), &proposer).success);
+        tx.sign_ecdsa(&key);
+
+        // The sender has to cover the whole gas limit and the value before anything runs
+        let result = fabric.execute_transaction(tx.clone(), &proposer);
+        assert_eq!(result.return_data, b"Insufficient funds for gas * price + value");
+        fabric.state_engine.create_account(tx.from.clone());
+        fabric.state_engine.update_balance(&tx.from.account_id, 101_000);
+        
+        let result = fabric.execute_transaction(tx.clone(), &proposer);
+        assert!(result.success, "{}", String::from_utf8_lossy(&result.return_data));
+        assert_eq!(result.gas_used, 21_000);
+        assert_eq!(fabric.state_engine.balance(&tx.from.account_id), 101_000 - 1000 - 21_000);
+        assert_eq!(fabric.state_engine.balance(&tx.to.as_ref().unwrap().account_id), 1000);
+        assert_eq!(fabric.state_engine.balance(&proposer.account_id), 21_000);
+        assert_eq!(fabric.state_engine.nonce(&tx.from.account_id), 1);
+        // What is left no longer covers the up-front cost of the next transaction
+        let mut next = tx.clone();
+        next.nonce = 1;
+        next.sign_ecdsa(&key);
+        let result = fabric.execute_transaction(next, &proposer);
+        assert_eq!(result.return_data, b"Insufficient funds for gas * price + value");
+        assert_eq!(fabric.state_engine.nonce(&tx.from.account_id), 1);
+        
+        let mainnet = NeoNetUnifiedFabric::new(2);
+        assert!(!mainnet.execute_transaction(tx.clone(), &proposer).success);
+
+        // Changing any signed field, or claiming another sender, breaks the signature
+        let mut tampered = tx.clone();
+        tampered.value += 1;
+        assert!(tampered.verify_signatures(None).is_err());
+        let mut impersonated = tx.clone();
+        impersonated.from = DualAddress::from_evm([1u8; 20]);
+        assert!(impersonated.verify_signatures(None).is_err());
+        // Cross-runtime calls are outside the ECDSA payload and need a Dilithium signature
+        let mut smuggled = tx.clone();
+        smuggled.cross_runtime_calls.push(CrossRuntimeCall {
+            source_runtime: RuntimeType::EVM,
+            target_runtime: RuntimeType::WASM,
+            target_contract: DualAddress::from_evm([3u8; 20]),
+            method: "drain".to_string(),
+            params: vec![],
+            gas_budget: 1,
+        });
+        assert!(smuggled.verify_signatures(None).is_err());
         
-        let result = fabric.execute_transaction(tx);
-        assert!(result.success);
+        let mut legacy = tx.clone();
+        legacy.signature.ecdsa_v = Some(27);
+        assert!(legacy.check_chain_id(1).is_err());
+        let mut relabeled = tx.clone();
+        relabeled.chain_id = 2;
+        assert!(relabeled.check_chain_id(2).is_err());
+        assert_ne!(relabeled.pq_signing_message(), tx.pq_signing_message());
+    }
+
+    #[test]
+    fn test_ecdsa_sender_is_bound_to_its_account_and_nonce() {
+        let fabric = NeoNetUnifiedFabric::new(1);
+        let victim = DualAddress::from_evm([6u8; 20]);
+        fabric.state_engine.create_account(victim.clone());
+        fabric.state_engine.update_balance(&victim.account_id, 1_000_000);
+        let key = k256::ecdsa::SigningKey::from_slice(&[2; 32]).unwrap();
+        let attacker = DualAddress::from_evm(eth_tx::evm_address(key.verifying_key()));
+        fabric.state_engine.create_account(attacker.clone());
+        fabric.state_engine.update_balance(&attacker.account_id, 1_000_000);
+        let proposer = DualAddress::from_evm([9u8; 20]);
+
+        let mut tx = UnifiedTransaction {
+            tx_hash: [0u8; 32],
+            chain_id: 1,
+            from: attacker.clone(),
+            to: Some(DualAddress::from_evm([7u8; 20])),
+            value: 500,
+            gas_limit: 100_000,
+            gas_price: 1,
+            nonce: 0,
+            data: vec![],
+            signature: DualSignature { ecdsa_sig: None, ecdsa_v: None, dilithium_sig: None, signature_mode: SignatureMode::EVMOnly, eth_envelope: None },
+            runtime_hint: Some(RuntimeType::EVM),
+            cross_runtime_calls: vec![],
+            timestamp: 1,
+        };
+
+        // The attacker's own signature, but the victim's account to debit
+        let mut stolen = tx.clone();
+        stolen.from.account_id = victim.account_id;
+        stolen.sign_ecdsa(&key);
+        let result = fabric.execute_transaction(stolen, &proposer);
+        assert_eq!(result.return_data, b"sender account does not match its EVM address");
+        assert_eq!(fabric.state_engine.balance(&victim.account_id), 1_000_000);
+
+        // The recipient's account id is not signed either
+        let mut redirected = tx.clone();
+        redirected.to.as_mut().unwrap().account_id = attacker.account_id;
+        redirected.sign_ecdsa(&key);
+        assert!(!fabric.execute_transaction(redirected, &proposer).success);
+
+        // A transaction runs once; replaying it or skipping ahead is refused
+        tx.sign_ecdsa(&key);
+        assert!(fabric.execute_transaction(tx.clone(), &proposer).success);
+        let replayed = fabric.execute_transaction(tx.clone(), &proposer);
+        assert_eq!(replayed.return_data, b"nonce 0 does not match the sender's next nonce 1");
+        tx.nonce = 2;
+        tx.sign_ecdsa(&key);
+        assert!(!fabric.execute_transaction(tx, &proposer).success);
+        assert_eq!(fabric.state_engine.balance(&DualAddress::from_evm([7u8; 20]).account_id), 500);
+        assert_eq!(fabric.state_engine.nonce(&attacker.account_id), 1);
+    }
+
+    #[test]
+    fn test_decoded_eth_txs_execute() {
+        use crate::eth_tx::{decode_unified_tx, encode_bytes, encode_list, encode_uint, sign_tx, EthTxType};
+
+        let fabric = NeoNetUnifiedFabric::new(5);
+        let key = k256::ecdsa::SigningKey::from_slice(&[4; 32]).unwrap();
+        let sender = DualAddress::from_evm(eth_tx::evm_address(key.verifying_key()));
+        fabric.state_engine.create_account(sender.clone());
+        fabric.state_engine.update_balance(&sender.account_id, 1_000_000);
+        let recipient = DualAddress::from_evm([7u8; 20]);
+        let proposer = DualAddress::from_evm([9u8; 20]);
+
+        // Legacy EIP-155: nonce, gas price, gas, to, value, data
+        let legacy = |nonce: u128, chain_id: u64| {
+            let fields = vec![encode_uint(nonce), encode_uint(1), encode_uint(100_000), encode_bytes(&[7u8; 20]), encode_uint(300), encode_bytes(&[])];
+            sign_tx(&key, EthTxType::Legacy, chain_id, fields)
+        };
+        let tx = decode_unified_tx(&legacy(0, 5), 1).unwrap();
+        assert_eq!(tx.from.account_id, sender.account_id);
+        assert_eq!(tx.signature.ecdsa_v.and_then(eip155_chain_id), Some(5));
+        let result = fabric.execute_transaction(tx, &proposer);
+        assert!(result.success, "{}", String::from_utf8_lossy(&result.return_data));
+
+        // EIP-1559: chain id, nonce, tip, max fee, gas, to, value, data, access list
+        let fields = vec![
+            encode_uint(5),
+            encode_uint(1),
+            encode_uint(1),
+            encode_uint(1),
+            encode_uint(100_000),
+            encode_bytes(&[7u8; 20]),
+            encode_uint(700),
+            encode_bytes(&[]),
+            encode_list(&[]),
+        ];
+        let tx = decode_unified_tx(&sign_tx(&key, EthTxType::DynamicFee, 5, fields), 1).unwrap();
+        assert!(tx.signature.eth_envelope.is_some());
+        // The envelope pins every field it shares with the unified form
+        let mut tampered = tx.clone();
+        tampered.value = 5000;
+        let rejected = fabric.execute_transaction(tampered, &proposer);
+        assert_eq!(rejected.return_data, b"Ethereum envelope does not match the transaction");
+        let result = fabric.execute_transaction(tx, &proposer);
+        assert!(result.success, "{}", String::from_utf8_lossy(&result.return_data));
+        assert_eq!(fabric.state_engine.balance(&recipient.account_id), 1000);
+        assert_eq!(fabric.state_engine.nonce(&sender.account_id), 2);
+
+        // Signed for another chain, or with a field changed after signing
+        assert!(!fabric.execute_transaction(decode_unified_tx(&legacy(2, 6), 1).unwrap(), &proposer).success);
+        let mut tampered = decode_unified_tx(&legacy(2, 5), 1).unwrap();
+        tampered.value = 5000;
+        assert!(!fabric.execute_transaction(tampered, &proposer).success);
+    }
+
+    #[test]
+    fn test_quantum_signature_uses_registered_key() {
+        let fabric = NeoNetUnifiedFabric::new(1);
+        let owner = pqc::HybridKeyPair::generate();
+        let from = DualAddress::from_evm([4u8; 20]);
+        let mut tx = UnifiedTransaction {
+            tx_hash: [0u8; 32],
+            chain_id: 1,
+            from: from.clone(),
+            to: Some(DualAddress::from_evm([5u8; 20])),
+            value: 1,
+            gas_limit: 100_000,
+            gas_price: 1,
+            nonce: 0,
+            data: vec![],
+            signature: DualSignature {
+                ecdsa_sig: None,
+                ecdsa_v: None,
+                dilithium_sig: None,
+                signature_mode: SignatureMode::QuantumOnly,
+                eth_envelope: None,
+            },
+            runtime_hint: Some(RuntimeType::WASM),
+            cross_runtime_calls: vec![],
+            timestamp: 1,
+        };
+        tx.signature.dilithium_sig = Some(owner.sign(&tx.pq_signing_message()).dilithium_sig);
+        let proposer = DualAddress::from_evm([9u8; 20]);
+        // Nobody has registered a key for the sender yet
+        assert!(!fabric.execute_transaction(tx.clone(), &proposer).success);
+
+        fabric.state_engine.create_account(from.clone());
+        fabric.state_engine.update_balance(&from.account_id, 1_000_000);
+        assert!(fabric.state_engine.register_quantum_key(&from.account_id, owner.public_key().dilithium_public));
+        assert!(fabric.execute_transaction(tx.clone(), &proposer).success);
+
+        // A signature over the message without the chain id binding does not verify
+        let mut unbound = tx.clone();
+        unbound.signature.dilithium_sig = Some(owner.sign(&unbound.body_bytes()).dilithium_sig);
+        assert!(!fabric.execute_transaction(unbound, &proposer).success);
+        let mut forged = tx.clone();
+        forged.signature.dilithium_sig = Some(pqc::HybridKeyPair::generate().sign(&tx.pq_signing_message()).dilithium_sig);
+        assert!(!fabric.execute_transaction(forged, &proposer).success);
+    }
+
+    #[test]
+    fn test_unified_tx_canonical_encoding() {
+        use crate::codec::{CodecError, Decode};
+
+        let tx = UnifiedTransaction {
+            tx_hash: [0u8; 32],
+            chain_id: 1,
+            from: DualAddress::from_evm([1u8; 20]),
+            to: None,
+            value: 5,
+            gas_limit: 21000,
+            gas_price: 1,
+            nonce: 3,
+            data: vec![0xde, 0xad],
+            signature: DualSignature {
+                ecdsa_sig: None,
+                ecdsa_v: None,
+                dilithium_sig: Some(vec![9; 4]),
+                signature_mode: SignatureMode::QuantumOnly,
+                eth_envelope: None,
+            },
+            runtime_hint: Some(RuntimeType::WASM),
+            cross_runtime_calls: vec![CrossRuntimeCall {
+                source_runtime: RuntimeType::WASM,
+                target_runtime: RuntimeType::EVM,
+                target_contract: DualAddress::from_evm([2u8; 20]),
+                method: "transfer".to_string(),
+                params: vec![1],
+                gas_budget: 50_000,
+            }],
+            timestamp: 12345,
+        };
+        let bytes = tx.to_versioned_bytes();
+        let decoded = UnifiedTransaction::from_versioned_bytes(&bytes).unwrap();
+        assert_eq!(decoded.to_versioned_bytes(), bytes);
+        assert_eq!(decoded.compute_hash(), tx.compute_hash());
+
+        // Signatures are not part of the hash, the body is
+        let mut resigned = tx.clone();
+        resigned.signature.dilithium_sig = Some(vec![7; 4]);
+        assert_eq!(resigned.compute_hash(), tx.compute_hash());
+        let mut retargeted = tx.clone();
+        retargeted.cross_runtime_calls[0].method = "burn".to_string();
+        assert_ne!(retargeted.compute_hash(), tx.compute_hash());
+
+        assert!(matches!(RuntimeType::from_bytes(&[4]), Err(CodecError::InvalidTag { type_name: "RuntimeType", tag: 4 })));
     }
 }
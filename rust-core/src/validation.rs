@@ -0,0 +1,334 @@
+// Block validation for NeoNet - header, proposer, transaction and state checks
+//
+// Each layer assumes the previous ones passed, and the first failure is returned as a
+// `ValidationError` describing exactly what was wrong.
+use std::collections::HashSet;
+use std::fmt;
+
+use crate::genesis::{GenesisSpec, NetworkId};
+use crate::rewards::RewardError;
+use crate::slashing::SlashingError;
+use crate::validator_set::{StakingOp, ValidatorSet};
+use crate::{Block, ChainState, TxRejection};
+
+/// Maximum serialized size of all transactions in a block
+pub const MAX_BLOCK_SIZE: usize = 4 * 1024 * 1024;
+/// How far a block timestamp may run ahead of the local clock
+pub const MAX_FUTURE_DRIFT_MILLIS: u128 = 15_000;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ValidationError {
+    // Header layer
+    UnknownParent { prev_hash: String },
+    HeightMismatch { expected: u64, got: u64 },
+    ParentHashMismatch { expected: String, got: String },
+    TimestampBeforeParent { parent: u128, got: u128 },
+    TimestampInFuture { max: u128, got: u128 },
+    TxRootMismatch { expected: String, got: String },
+    EvidenceRootMismatch { expected: String, got: String },
+    HashMismatch { expected: String, got: String },
+    // Proposer layer
+    NoValidators,
+    ValidatorSetMismatch { height: u64, expected: String, got: String },
+    UnexpectedProposer { height: u64, expected: String, got: String },
+    InvalidBlockSignature { height: u64 },
+    MalformedBlockSignature { height: u64, reason: String },
+    // Transaction layer
+    InvalidTx { index: usize, reason: TxRejection },
+    DuplicateTx { index: usize, hash: String },
+    GasLimitExceeded { limit: u64, used: u64 },
+    SizeLimitExceeded { limit: usize, size: usize },
+    // State transition layer
+    BadNonce { index: usize, sender: String, expected: u64, got: u64 },
+    InsufficientFunds { index: usize, sender: String, balance: u128, required: u128 },
+    InvalidEvidence { height: u64, reason: SlashingError },
+    StateRootMismatch { height: u64, expected: String, got: String },
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use ValidationError::*;
+        match self {
+            UnknownParent { prev_hash } => write!(f, "header: unknown parent {}", prev_hash),
+            HeightMismatch { expected, got } => write!(f, "header: height {} does not follow parent, expected {}", got, expected),
+            ParentHashMismatch { expected, got } => write!(f, "header: prev_hash {} does not match parent {}", got, expected),
+            TimestampBeforeParent { parent, got } => write!(f, "header: timestamp {} is before parent timestamp {}", got, parent),
+            TimestampInFuture { max, got } => write!(f, "header: timestamp {} is too far in the future (max {})", got, max),
+            TxRootMismatch { expected, got } => write!(f, "header: tx_root {} does not match transactions ({})", got, expected),
+            EvidenceRootMismatch { expected, got } => write!(f, "header: evidence_root {} does not match evidence ({})", got, expected),
+            HashMismatch { expected, got } => write!(f, "header: hash {} does not match contents ({})", got, expected),
+            NoValidators => write!(f, "proposer: validator set is empty"),
+            ValidatorSetMismatch { height, expected, got } => write!(f, "proposer: block {} commits to validator set {}, expected {}", height, got, expected),
+            UnexpectedProposer { height, expected, got } => write!(f, "proposer: block {} proposed by {}, expected {}", height, got, expected),
+            InvalidBlockSignature { height } => write!(f, "proposer: block {} signature does not match the scheduled proposer", height),
+            MalformedBlockSignature { height, reason } => write!(f, "proposer: block {} has a malformed signature: {}", height, reason),
+            InvalidTx { index, reason } => write!(f, "tx {}: {}", index, reason),
+            DuplicateTx { index, hash } => write!(f, "tx {}: duplicate of earlier tx {}", index, hash),
+            GasLimitExceeded { limit, used } => write!(f, "txs: gas {} exceeds block limit {}", used, limit),
+            SizeLimitExceeded { limit, size } => write!(f, "txs: size {} exceeds block limit {}", size, limit),
+            BadNonce { index, sender, expected, got } => write!(f, "state: tx {} from {} has nonce {}, expected {}", index, sender, got, expected),
+            InsufficientFunds { index, sender, balance, required } => write!(f, "state: tx {} from {} needs fee {}, balance {}", index, sender, required, balance),
+            InvalidEvidence { height, reason } => write!(f, "state: block {} carries invalid evidence: {}", height, reason),
+            StateRootMismatch { height, expected, got } => write!(f, "state: block {} state_root {} does not match execution ({})", height, got, expected),
+        }
+    }
+}
+
+impl std::error::Error for ValidationError {}
+
+/// Structural checks against the parent: linkage, height, timestamps and hashes
+pub fn validate_header(block: &Block, parent: &Block, now: u128) -> Result<(), ValidationError> {
+    if block.prev_hash != parent.hash {
+        return Err(ValidationError::ParentHashMismatch { expected: parent.hash.clone(), got: block.prev_hash.clone() });
+    }
+    if block.index != parent.index + 1 {
+        return Err(ValidationError::HeightMismatch { expected: parent.index + 1, got: block.index });
+    }
+    if block.timestamp < parent.timestamp {
+        return Err(ValidationError::TimestampBeforeParent { parent: parent.timestamp, got: block.timestamp });
+    }
+    if block.timestamp > now + MAX_FUTURE_DRIFT_MILLIS {
+        return Err(ValidationError::TimestampInFuture { max: now + MAX_FUTURE_DRIFT_MILLIS, got: block.timestamp });
+    }
+    let tx_root = Block::compute_tx_root(&block.txs);
+    if tx_root != block.tx_root {
+        return Err(ValidationError::TxRootMismatch { expected: tx_root, got: block.tx_root.clone() });
+    }
+    let evidence_root = Block::compute_evidence_root(&block.evidence);
+    if evidence_root != block.evidence_root {
+        return Err(ValidationError::EvidenceRootMismatch { expected: evidence_root, got: block.evidence_root.clone() });
+    }
+    let hash = block.compute_hash();
+    if hash != block.hash {
+        return Err(ValidationError::HashMismatch { expected: hash, got: block.hash.clone() });
+    }
+    Ok(())
+}
+
+/// The block must commit to its epoch's validator set and come from, and be signed by,
+/// the validator that set schedules for its height
+pub fn validate_proposer(block: &Block, validators: &ValidatorSet, network: &NetworkId) -> Result<(), ValidationError> {
+    if block.validator_set_hash != validators.hash() {
+        return Err(ValidationError::ValidatorSetMismatch {
+            height: block.index,
+            expected: validators.hash().to_string(),
+            got: block.validator_set_hash.clone(),
+        });
+    }
+    let expected = validators.proposer(block.index).ok_or(ValidationError::NoValidators)?;
+    if block.proposer != expected.address {
+        return Err(ValidationError::UnexpectedProposer {
+            height: block.index,
+            expected: expected.address.clone(),
+            got: block.proposer.clone(),
+        });
+    }
+    match block.verify_signature(&expected.public_key, network) {
+        Ok(true) => Ok(()),
+        Ok(false) => Err(ValidationError::InvalidBlockSignature { height: block.index }),
+        Err(e) => Err(ValidationError::MalformedBlockSignature { height: block.index, reason: e.to_string() }),
+    }
+}
+
+/// Every transaction is well-formed, signed for this chain and pays the minimum gas price,
+/// none repeats, and the block fits its limits
+pub fn validate_txs(block: &Block, genesis: &GenesisSpec) -> Result<(), ValidationError> {
+    let gas_config = &genesis.gas;
+    let mut seen = HashSet::new();
+    let mut gas = 0u64;
+    let mut size = 0usize;
+    for (index, tx) in block.txs.iter().enumerate() {
+        tx.verify(genesis.chain_id).map_err(|reason| ValidationError::InvalidTx { index, reason })?;
+        if tx.gas_price < gas_config.min_gas_price {
+            return Err(ValidationError::InvalidTx { index, reason: TxRejection::GasPriceTooLow { min: gas_config.min_gas_price } });
+        }
+        if let Some(Err(e)) = StakingOp::from_tx(tx) {
+            return Err(ValidationError::InvalidTx { index, reason: TxRejection::InvalidPayload(e) });
+        }
+        let hash = tx.hash();
+        if !seen.insert(hash.clone()) {
+            return Err(ValidationError::DuplicateTx { index, hash });
+        }
+        gas = gas.saturating_add(tx.gas_limit);
+        size = size.saturating_add(tx.encoded_len());
+    }
+    if gas > gas_config.block_gas_limit {
+        return Err(ValidationError::GasLimitExceeded { limit: gas_config.block_gas_limit, used: gas });
+    }
+    if size > MAX_BLOCK_SIZE {
+        return Err(ValidationError::SizeLimitExceeded { limit: MAX_BLOCK_SIZE, size });
+    }
+    Ok(())
+}
+
+/// Execute the block's transactions, evidence and reward against `state`, which must hold the
+/// state at the parent, and return the resulting state root. On failure `state` may be
+/// partially updated, so callers execute on a copy.
+pub fn execute_block(block: &Block, state: &mut ChainState) -> Result<String, ValidationError> {
+    let mut fees = 0u128;
+    for (index, tx) in block.txs.iter().enumerate() {
+        let expected = state.nonces.get(&tx.from).copied().unwrap_or(0);
+        if tx.nonce != expected {
+            return Err(ValidationError::BadNonce { index, sender: tx.from.clone(), expected, got: tx.nonce });
+        }
+        fees += state.ledger.charge_fee(tx).map_err(|e| match e {
+            RewardError::InsufficientFunds { balance, required } => {
+                ValidationError::InsufficientFunds { index, sender: tx.from.clone(), balance, required }
+            }
+            other => unreachable!("charging a fee can't fail with {}", other),
+        })?;
+        state.nonces.insert(tx.from.clone(), expected + 1);
+    }
+    state.staking.apply_block(block, &mut state.ledger).map_err(|reason| ValidationError::InvalidEvidence { height: block.index, reason })?;
+    // Genesis has no proposer to reward; its allocations are the initial supply
+    if block.index > 0 {
+        state.ledger.reward_proposer(&block.proposer, fees);
+    }
+    Ok(state.state_root())
+}
+
+/// Execute the block and check the result against the `state_root` it commits to
+pub fn apply_state(block: &Block, state: &mut ChainState) -> Result<(), ValidationError> {
+    let state_root = execute_block(block, state)?;
+    if state_root != block.state_root {
+        return Err(ValidationError::StateRootMismatch { height: block.index, expected: state_root, got: block.state_root.clone() });
+    }
+    Ok(())
+}
+
+/// Run the header, proposer and transaction layers for a block received from outside
+pub fn validate_block(
+    block: &Block,
+    parent: &Block,
+    validators: &ValidatorSet,
+    genesis: &GenesisSpec,
+    network: &NetworkId,
+    now: u128,
+) -> Result<(), ValidationError> {
+    validate_header(block, parent, now)?;
+    validate_proposer(block, validators, network)?;
+    validate_txs(block, genesis)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pqc::HybridKeyPair;
+    use crate::rewards::{self, Ledger};
+    use crate::tests::{TestNet, TEST_CHAIN_ID};
+    use crate::Tx;
+
+    #[test]
+    fn test_header_errors() {
+        let net = TestNet::new(2);
+        let chain = net.chain();
+        let genesis = chain.blocks[0].clone();
+        let now = genesis.timestamp + 10;
+        let good = net.child(&chain, &genesis, vec![]);
+        assert_eq!(validate_header(&good, &genesis, now), Ok(()));
+
+        let mut bad = good.clone();
+        bad.index = 2;
+        assert_eq!(validate_header(&bad, &genesis, now), Err(ValidationError::HeightMismatch { expected: 1, got: 2 }));
+
+        let mut bad = good.clone();
+        bad.timestamp = now + MAX_FUTURE_DRIFT_MILLIS + 1;
+        assert!(matches!(validate_header(&bad, &genesis, now), Err(ValidationError::TimestampInFuture { .. })));
+
+        let mut bad = good.clone();
+        bad.timestamp = genesis.timestamp - 1;
+        assert!(matches!(validate_header(&bad, &genesis, now), Err(ValidationError::TimestampBeforeParent { .. })));
+
+        let mut bad = good.clone();
+        bad.nonce = 7;
+        assert!(matches!(validate_header(&bad, &genesis, now), Err(ValidationError::HashMismatch { .. })));
+    }
+
+    #[test]
+    fn test_proposer_schedule_and_signature() {
+        let net = TestNet::new(2);
+        let validators = net.validators();
+        let chain = net.chain();
+        let genesis = chain.blocks[0].clone();
+        let b1 = net.child(&chain, &genesis, vec![]);
+        assert_eq!(validate_proposer(&b1, &validators, chain.network()), Ok(()));
+
+        let mut wrong = b1.clone();
+        wrong.seal(net.non_proposer(1), chain.network());
+        assert_eq!(validate_proposer(&wrong, &validators, chain.network()), Err(ValidationError::UnexpectedProposer {
+            height: 1,
+            expected: net.proposer(1).public_key().address(),
+            got: net.non_proposer(1).public_key().address(),
+        }));
+
+        let mut stale = b1.clone();
+        stale.validator_set_hash = "00".repeat(32);
+        stale.seal(net.proposer(1), chain.network());
+        assert!(matches!(validate_proposer(&stale, &validators, chain.network()), Err(ValidationError::ValidatorSetMismatch { height: 1, .. })));
+
+        let mut forged = b1.clone();
+        forged.signature = net.non_proposer(1).sign(forged.hash.as_bytes());
+        assert_eq!(validate_proposer(&forged, &validators, chain.network()), Err(ValidationError::InvalidBlockSignature { height: 1 }));
+        // The proposer's own signature counts only in the block domain of this network
+        let mut undomained = b1.clone();
+        undomained.signature = net.proposer(1).sign(undomained.hash.as_bytes());
+        assert_eq!(validate_proposer(&undomained, &validators, chain.network()), Err(ValidationError::InvalidBlockSignature { height: 1 }));
+        let other_chain = NetworkId { chain_id: TEST_CHAIN_ID + 1, ..chain.network().clone() };
+        assert_eq!(validate_proposer(&b1, &validators, &other_chain), Err(ValidationError::InvalidBlockSignature { height: 1 }));
+        let other_genesis = NetworkId { genesis_hash: "00".repeat(32), ..chain.network().clone() };
+        assert_eq!(validate_proposer(&b1, &validators, &other_genesis), Err(ValidationError::InvalidBlockSignature { height: 1 }));
+
+        let mut unsigned = b1.clone();
+        unsigned.signature = Default::default();
+        assert!(matches!(validate_proposer(&unsigned, &validators, chain.network()), Err(ValidationError::MalformedBlockSignature { .. })));
+        let empty = ValidatorSet::new(vec![]);
+        let mut orphaned = b1.clone();
+        orphaned.validator_set_hash = empty.hash().to_string();
+        assert_eq!(validate_proposer(&orphaned, &empty, chain.network()), Err(ValidationError::NoValidators));
+    }
+
+    #[test]
+    fn test_tx_and_state_errors() {
+        let net = TestNet::new(1);
+        let alice = HybridKeyPair::generate();
+        let mut chain = net.funded_chain(&[&alice]);
+        let genesis = chain.blocks[0].clone();
+        let tx = Tx::signed(&alice, TEST_CHAIN_ID, "bob", "transfer 1 NEO", 0);
+
+        let mut forged = tx.clone();
+        forged.payload = "transfer 9 NEO".into();
+        let block = net.child(&chain, &genesis, vec![forged]);
+        assert_eq!(validate_txs(&block, &chain.genesis), Err(ValidationError::InvalidTx { index: 0, reason: TxRejection::InvalidSignature }));
+
+        let block = net.child(&chain, &genesis, vec![tx.clone(), tx.clone()]);
+        assert_eq!(validate_txs(&block, &chain.genesis), Err(ValidationError::DuplicateTx { index: 1, hash: tx.hash() }));
+
+        let free = Tx::signed_with_gas(&alice, TEST_CHAIN_ID, "bob", "transfer 1 NEO", 0, 0, tx.gas_limit);
+        let block = net.child(&chain, &genesis, vec![free]);
+        assert_eq!(validate_txs(&block, &chain.genesis), Err(ValidationError::InvalidTx { index: 0, reason: TxRejection::GasPriceTooLow { min: 1 } }));
+
+        let skipped = Tx::signed(&alice, TEST_CHAIN_ID, "bob", "transfer 1 NEO", 1);
+        let block = net.child(&chain, &genesis, vec![skipped]);
+        assert_eq!(validate_txs(&block, &chain.genesis), Ok(()));
+        assert_eq!(apply_state(&block, &mut chain.state.clone()), Err(ValidationError::BadNonce {
+            index: 0,
+            sender: alice.public_key().address(),
+            expected: 0,
+            got: 1,
+        }));
+
+        let block = net.child(&chain, &genesis, vec![tx.clone()]);
+        assert_eq!(apply_state(&block, &mut chain.state.clone()), Ok(()));
+        let mut tampered = block.clone();
+        tampered.state_root = "00".repeat(32);
+        assert!(matches!(apply_state(&tampered, &mut chain.state.clone()), Err(ValidationError::StateRootMismatch { height: 1, .. })));
+
+        chain.state.ledger = Ledger::default();
+        assert_eq!(apply_state(&block, &mut chain.state), Err(ValidationError::InsufficientFunds {
+            index: 0,
+            sender: alice.public_key().address(),
+            balance: 0,
+            required: rewards::tx_fee(&tx),
+        }));
+    }
+}
@@ -0,0 +1,427 @@
+// Validator set for NeoNet - stake-weighted validators with epoch transitions
+//
+// Staking operations are ordinary signed transactions sent to `STAKING_ADDRESS`. They take
+// effect at the next epoch boundary, together with any slashing penalties, so every block in
+// an epoch is proposed and voted on by the same set, whose hash each block header records.
+// Stake is bonded out of the sender's ledger balance when it takes effect; an operation the
+// sender can't pay for is dropped, and leaving or lowering stake returns the difference.
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+
+use crate::codec::Encode;
+use crate::genesis::NetworkId;
+use crate::pqc::HybridPublicKey;
+use crate::rewards::Ledger;
+use crate::slashing::{Offense, SlashingConfig, SlashingError};
+use crate::{Block, Tx};
+
+/// Blocks per epoch unless configured otherwise
+pub const DEFAULT_EPOCH_LENGTH: u64 = 100;
+/// Transactions sent here carry a JSON `StakingOp` payload
+pub const STAKING_ADDRESS: &str = "staking";
+/// Largest stake a single validator may hold
+pub const MAX_STAKE: u64 = 1 << 60;
+
+/// A change to the sender's own validator entry
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum StakingOp {
+    Join { stake: u64 },
+    Leave,
+    SetStake { stake: u64 },
+}
+
+impl StakingOp {
+    /// Parse the staking operation carried by `tx`, if it is sent to the staking address
+    pub fn from_tx(tx: &Tx) -> Option<Result<Self, String>> {
+        if tx.to != STAKING_ADDRESS {
+            return None;
+        }
+        Some(serde_json::from_str(&tx.payload).map_err(|e| e.to_string()))
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Validator {
+    pub address: String,
+    pub public_key: HybridPublicKey,
+    pub stake: u64,
+}
+
+/// Immutable validator set for one epoch, ordered by address
+#[derive(Debug, Clone, PartialEq)]
+pub struct ValidatorSet {
+    validators: Vec<Validator>,
+    hash: String,
+}
+
+impl ValidatorSet {
+    /// Build a set from keys and stakes; zero-stake entries are dropped and a repeated key
+    /// keeps its last stake
+    pub fn new(members: Vec<(HybridPublicKey, u64)>) -> Self {
+        let mut validators: Vec<Validator> = vec![];
+        for (public_key, stake) in members {
+            let address = public_key.address();
+            validators.retain(|v| v.address != address);
+            if stake > 0 {
+                validators.push(Validator { address, public_key, stake });
+            }
+        }
+        validators.sort_by(|a, b| a.address.cmp(&b.address));
+        let hash = Self::compute_hash(&validators);
+        ValidatorSet { validators, hash }
+    }
+
+    pub fn with_equal_stake(keys: Vec<HybridPublicKey>, stake: u64) -> Self {
+        Self::new(keys.into_iter().map(|k| (k, stake)).collect())
+    }
+
+    fn compute_hash(validators: &[Validator]) -> String {
+        let entries: Vec<(&str, &[u8], &[u8], u64)> = validators.iter()
+            .map(|v| (v.address.as_str(), v.public_key.ed25519_public.as_slice(), v.public_key.dilithium_public.as_slice(), v.stake))
+            .collect();
+        hex::encode(Sha256::digest(entries.to_bytes()))
+    }
+
+    /// Commitment to the addresses, signing keys and stakes of the set
+    pub fn hash(&self) -> &str {
+        &self.hash
+    }
+
+    pub fn validators(&self) -> &[Validator] {
+        &self.validators
+    }
+
+    pub fn len(&self) -> usize {
+        self.validators.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.validators.is_empty()
+    }
+
+    pub fn get(&self, address: &str) -> Option<&Validator> {
+        self.validators.iter().find(|v| v.address == address)
+    }
+
+    pub fn contains(&self, address: &str) -> bool {
+        self.get(address).is_some()
+    }
+
+    pub fn stake_of(&self, address: &str) -> u64 {
+        self.get(address).map_or(0, |v| v.stake)
+    }
+
+    pub fn total_stake(&self) -> u128 {
+        self.validators.iter().map(|v| u128::from(v.stake)).sum()
+    }
+
+    /// Proposer for `height`, drawn with probability proportional to stake from a seed
+    /// derived from the set hash and height, so every node picks the same validator
+    pub fn proposer(&self, height: u64) -> Option<&Validator> {
+        let total = self.total_stake();
+        if total == 0 {
+            return None;
+        }
+        let mut hasher = Sha256::new();
+        hasher.update(self.hash.as_bytes());
+        hasher.update(height.to_be_bytes());
+        let seed = hasher.finalize();
+        let mut ticket = u128::from_be_bytes(seed[..16].try_into().unwrap()) % total;
+        for validator in &self.validators {
+            if ticket < u128::from(validator.stake) {
+                return Some(validator);
+            }
+            ticket -= u128::from(validator.stake);
+        }
+        None
+    }
+
+    /// Copy of the set with `validator` added or its entry replaced
+    pub fn with(&self, validator: Validator) -> ValidatorSet {
+        let mut members: Vec<(HybridPublicKey, u64)> = self.validators.iter().map(|v| (v.public_key.clone(), v.stake)).collect();
+        members.push((validator.public_key, validator.stake));
+        ValidatorSet::new(members)
+    }
+
+    /// Copy of the set without `address`
+    pub fn without(&self, address: &str) -> ValidatorSet {
+        ValidatorSet::new(self.validators.iter()
+            .filter(|v| v.address != address)
+            .map(|v| (v.public_key.clone(), v.stake))
+            .collect())
+    }
+
+    /// Apply one operation from `public_key`. Operations that don't make sense for the
+    /// current set (joining twice, leaving as a non-member, emptying the set, staking more
+    /// than `MAX_STAKE`) are ignored.
+    pub fn apply(&self, public_key: &HybridPublicKey, op: &StakingOp) -> Option<ValidatorSet> {
+        let address = public_key.address();
+        let mut members: Vec<(HybridPublicKey, u64)> = self.validators.iter().map(|v| (v.public_key.clone(), v.stake)).collect();
+        match op {
+            StakingOp::Join { stake } if (1..=MAX_STAKE).contains(stake) && !self.contains(&address) => {
+                members.push((public_key.clone(), *stake));
+            }
+            StakingOp::Leave if self.contains(&address) && self.len() > 1 => {
+                members.retain(|(k, _)| k.address() != address);
+            }
+            StakingOp::SetStake { stake } if (1..=MAX_STAKE).contains(stake) && self.contains(&address) => {
+                members.push((public_key.clone(), *stake));
+            }
+            _ => return None,
+        }
+        Some(ValidatorSet::new(members))
+    }
+}
+
+/// Validator sets per epoch plus the staking operations and penalties queued for the next one
+#[derive(Debug, Clone)]
+pub struct StakingState {
+    /// Network whose block and vote signatures evidence must carry; the genesis hash is
+    /// filled in once the genesis block is built
+    pub network: NetworkId,
+    pub epoch_length: u64,
+    pub slashing: SlashingConfig,
+    /// Set active in each epoch so far, indexed by epoch
+    sets: Vec<ValidatorSet>,
+    /// Operations included in the current epoch, applied when it ends
+    pending: Vec<(HybridPublicKey, StakingOp)>,
+    /// Offenses included in the current epoch with the height their jail time ends
+    penalties: Vec<(Offense, u64)>,
+    /// Offenders held out of the set, with the height they are released at
+    jailed: Vec<(Validator, u64)>,
+    /// Ids of every offense already punished
+    punished: HashSet<String>,
+}
+
+impl StakingState {
+    pub fn new(genesis: ValidatorSet, epoch_length: u64) -> Self {
+        StakingState {
+            network: NetworkId::default(),
+            epoch_length: epoch_length.max(1),
+            slashing: SlashingConfig::default(),
+            sets: vec![genesis],
+            pending: vec![],
+            penalties: vec![],
+            jailed: vec![],
+            punished: HashSet::new(),
+        }
+    }
+
+    /// Fresh state with only the genesis set, for replaying blocks from genesis
+    pub fn reset(&self) -> Self {
+        StakingState { network: self.network.clone(), slashing: self.slashing, ..Self::new(self.sets[0].clone(), self.epoch_length) }
+    }
+
+    /// Epoch of a block height; genesis and the first `epoch_length` blocks are epoch 0
+    pub fn epoch_of(&self, height: u64) -> u64 {
+        height.saturating_sub(1) / self.epoch_length
+    }
+
+    /// Set that proposes and votes on the block at `height`, once the previous epoch is
+    /// fully applied
+    pub fn set_for_height(&self, height: u64) -> Option<&ValidatorSet> {
+        self.sets.get(self.epoch_of(height) as usize)
+    }
+
+    /// Latest known set
+    pub fn current(&self) -> &ValidatorSet {
+        self.sets.last().unwrap()
+    }
+
+    pub fn pending(&self) -> &[(HybridPublicKey, StakingOp)] {
+        &self.pending
+    }
+
+    /// Jailed validators with their remaining stake and release height
+    pub fn jailed(&self) -> &[(Validator, u64)] {
+        &self.jailed
+    }
+
+    pub fn is_jailed(&self, address: &str) -> bool {
+        self.jailed.iter().any(|(v, _)| v.address == address)
+    }
+
+    pub fn is_punished(&self, offense_id: &str) -> bool {
+        self.punished.contains(offense_id)
+    }
+
+    /// Queue the block's evidence and staking operations and, if it closes an epoch, activate
+    /// the next set, bonding and slashing stake in `ledger`. All evidence is verified first,
+    /// so on error the state is unchanged.
+    pub fn apply_block(&mut self, block: &Block, ledger: &mut Ledger) -> Result<(), SlashingError> {
+        let mut offenses: Vec<Offense> = vec![];
+        for evidence in &block.evidence {
+            let offense = evidence.verify(self)?;
+            if self.punished.contains(&offense.id) || offenses.iter().any(|o| o.id == offense.id) {
+                return Err(SlashingError::AlreadyPunished(offense.id));
+            }
+            offenses.push(offense);
+        }
+        for offense in offenses {
+            self.punished.insert(offense.id.clone());
+            self.penalties.push((offense, block.index + self.slashing.jail_blocks));
+        }
+        for tx in &block.txs {
+            if let Some(Ok(op)) = StakingOp::from_tx(tx) {
+                self.pending.push((tx.public_key.clone(), op));
+            }
+        }
+        if block.index > 0 && block.index.is_multiple_of(self.epoch_length) {
+            let next = self.next_set(block.index, ledger);
+            self.sets.push(next);
+        }
+        Ok(())
+    }
+
+    /// Apply queued operations, then penalties, then releases for the epoch ending at `height`
+    fn next_set(&mut self, height: u64, ledger: &mut Ledger) -> ValidatorSet {
+        let mut next = self.current().clone();
+        for (public_key, op) in std::mem::take(&mut self.pending) {
+            let address = public_key.address();
+            // Jailed validators can't rejoin or restake their way out early
+            if self.is_jailed(&address) {
+                continue;
+            }
+            let Some(updated) = next.apply(&public_key, &op) else { continue };
+            let (before, after) = (u128::from(next.stake_of(&address)), u128::from(updated.stake_of(&address)));
+            if after > before {
+                if ledger.bond(&address, after - before).is_err() {
+                    continue;
+                }
+            } else {
+                ledger.unbond(&address, before - after);
+            }
+            next = updated;
+        }
+
+        for (offense, until) in std::mem::take(&mut self.penalties) {
+            if let Some((jailed, jailed_until)) = self.jailed.iter_mut().find(|(v, _)| v.address == offense.validator) {
+                let penalty = offense.penalty(jailed.stake, &self.slashing);
+                jailed.stake -= penalty;
+                ledger.slash(&jailed.address, u128::from(penalty));
+                *jailed_until = (*jailed_until).max(until);
+            } else if let Some(validator) = next.get(&offense.validator).cloned() {
+                let stake = validator.stake - offense.penalty(validator.stake, &self.slashing);
+                // Never jail the last validator; the chain would halt
+                let stake = if next.len() > 1 { stake } else { stake.max(1) };
+                ledger.slash(&validator.address, u128::from(validator.stake - stake));
+                if next.len() > 1 {
+                    next = next.without(&validator.address);
+                    self.jailed.push((Validator { stake, ..validator }, until));
+                } else {
+                    next = next.with(Validator { stake, ..validator });
+                }
+            }
+        }
+
+        let (released, still_jailed): (Vec<_>, Vec<_>) = std::mem::take(&mut self.jailed)
+            .into_iter()
+            .partition(|(_, until)| *until <= height);
+        self.jailed = still_jailed;
+        for (validator, _) in released {
+            if validator.stake > 0 && !next.contains(&validator.address) {
+                next = next.with(validator);
+            }
+        }
+        next
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pqc::HybridKeyPair;
+    use crate::tests::{TestNet, TEST_CHAIN_ID};
+
+    fn staking_tx(keypair: &HybridKeyPair, op: &StakingOp, nonce: u64) -> Tx {
+        Tx::signed(keypair, TEST_CHAIN_ID, STAKING_ADDRESS, &serde_json::to_string(op).unwrap(), nonce)
+    }
+
+    #[test]
+    fn test_proposer_selection_follows_stake() {
+        let keys: Vec<HybridKeyPair> = (0..2).map(|_| HybridKeyPair::generate()).collect();
+        let set = ValidatorSet::new(vec![(keys[0].public_key(), 1), (keys[1].public_key(), 9)]);
+        assert_eq!(set.total_stake(), 10);
+
+        let heavy = keys[1].public_key().address();
+        let picks = (1..=1000).filter(|h| set.proposer(*h).unwrap().address == heavy).count();
+        assert!((800..=980).contains(&picks), "heavy validator proposed {} of 1000", picks);
+        assert_eq!(set.proposer(7), set.clone().proposer(7));
+        assert!(ValidatorSet::new(vec![]).proposer(1).is_none());
+    }
+
+    #[test]
+    fn test_set_hash_tracks_membership_and_stake() {
+        let keys: Vec<HybridPublicKey> = (0..2).map(|_| HybridKeyPair::generate().public_key()).collect();
+        let set = ValidatorSet::with_equal_stake(keys.clone(), 5);
+        let reordered = ValidatorSet::with_equal_stake(keys.iter().rev().cloned().collect(), 5);
+        assert_eq!(set.hash(), reordered.hash());
+
+        let restaked = set.apply(&keys[0], &StakingOp::SetStake { stake: 6 }).unwrap();
+        assert_ne!(restaked.hash(), set.hash());
+        assert_eq!(restaked.stake_of(&keys[0].address()), 6);
+        assert!(set.apply(&keys[0], &StakingOp::Join { stake: 1 }).is_none());
+        assert!(ValidatorSet::with_equal_stake(vec![keys[0].clone()], 1).apply(&keys[0], &StakingOp::Leave).is_none());
+    }
+
+    #[test]
+    fn test_operations_apply_at_epoch_boundary() {
+        let net = TestNet::new(2);
+        let newcomer = HybridKeyPair::generate();
+        let mut spec = net.genesis(&[&newcomer, &net.keys[0]]);
+        spec.epoch_length = 2;
+        let mut chain = crate::Chain::new(&spec).unwrap();
+        let genesis_hash = chain.state.staking.current().hash().to_string();
+        let (joiner, leaver) = (newcomer.public_key().address(), net.keys[0].public_key().address());
+        assert_eq!(chain.state.ledger.bonded(&leaver), 1);
+
+        chain.add_tx(staking_tx(&newcomer, &StakingOp::Join { stake: 3 }, 0)).unwrap();
+        chain.add_tx(staking_tx(net.keys.first().unwrap(), &StakingOp::Leave, 0)).unwrap();
+        let b1 = net.mine(&mut chain);
+        assert_eq!(b1.txs.len(), 2);
+        assert_eq!(chain.state.staking.pending().len(), 2);
+        assert_eq!(chain.state.staking.set_for_height(2).unwrap().hash(), genesis_hash);
+        let balance = chain.balance(&joiner);
+
+        let b2 = net.mine(&mut chain);
+        assert_eq!(b2.validator_set_hash, genesis_hash);
+        let next = chain.state.staking.set_for_height(3).unwrap();
+        assert_ne!(next.hash(), genesis_hash);
+        assert_eq!(next.stake_of(&newcomer.public_key().address()), 3);
+        assert!(!next.contains(&net.keys[0].public_key().address()));
+        assert_eq!(next.len(), 2);
+        assert!(chain.state.staking.pending().is_empty());
+        // The new stake came out of the joiner's balance; the leaver's bond was returned
+        assert_eq!(chain.balance(&joiner), balance - 3);
+        assert_eq!(chain.state.ledger.bonded(&joiner), 3);
+        assert_eq!(chain.state.ledger.bonded(&leaver), 0);
+        assert_eq!(chain.validate(), Ok(()));
+    }
+
+    #[test]
+    fn test_stake_must_be_paid_for_and_capped() {
+        let net = TestNet::new(2);
+        let (poor, whale) = (HybridKeyPair::generate(), HybridKeyPair::generate());
+        let join = |keypair: &HybridKeyPair, stake| staking_tx(keypair, &StakingOp::Join { stake }, 0);
+        let mut spec = net.genesis(&[&whale]);
+        spec.epoch_length = 1;
+        spec.allocations.insert(poor.public_key().address(), crate::rewards::tx_fee(&join(&poor, 10)) + 5);
+        let mut chain = crate::Chain::new(&spec).unwrap();
+
+        chain.add_tx(join(&poor, 10)).unwrap();
+        chain.add_tx(join(&whale, u64::MAX)).unwrap();
+        net.mine(&mut chain);
+        let next = chain.state.staking.current();
+        assert_eq!(next.len(), 2);
+        assert_eq!(chain.balance(&poor.public_key().address()), 5);
+        assert_eq!(chain.state.ledger.bonded(&whale.public_key().address()), 0);
+
+        // Stake totals and proposer draws don't overflow even at the cap
+        let keys: Vec<HybridPublicKey> = (0..4).map(|_| HybridKeyPair::generate().public_key()).collect();
+        let capped = ValidatorSet::with_equal_stake(keys, MAX_STAKE);
+        assert_eq!(capped.total_stake(), 4 * u128::from(MAX_STAKE));
+        assert!(capped.proposer(1).is_some());
+        assert!(capped.apply(&whale.public_key(), &StakingOp::Join { stake: MAX_STAKE + 1 }).is_none());
+    }
+}
@@ -2,6 +2,31 @@
 use serde::{Deserialize, Serialize};
 use anyhow::{Result, anyhow};
 use std::collections::HashMap;
+use sha2::{Sha256, Digest};
+use wasmi::{Caller, Config, Engine, Extern, Instance, Linker, Module, Store, Value};
+use wasmi::core::{Trap, TrapCode, ValueType};
+
+use crate::cosmwasm::{self, BlockInfo, Coin, ContractInfo, ContractResult, Env, MessageInfo, Response, NATIVE_DENOM};
+
+/// Import namespace for NeoNet host functions
+const HOST_MODULE: &str = "env";
+const HOST_FUNCTIONS: [&str; 8] = [
+    "storage_get", "storage_set", "storage_remove", "balance",
+    "caller", "input_len", "input_read", "set_return",
+];
+
+/// Export invoked by `execute_wasm` with the raw input bytes
+const ENTRY_POINT: &str = "main";
+
+// Gas schedule: instructions are metered as fuel by the interpreter,
+// host functions charge on top of that.
+const GAS_DEPLOY_BASE: u64 = 21000;
+const GAS_PER_CODE_BYTE: u64 = 10;
+pub(crate) const GAS_HOST_CALL: u64 = 50;
+pub(crate) const GAS_STORAGE_READ: u64 = 200;
+pub(crate) const GAS_STORAGE_WRITE: u64 = 5000;
+pub(crate) const GAS_PER_DATA_BYTE: u64 = 3;
+const GAS_INSTANTIATE: u64 = 10000;
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct WasmContract {
@@ -9,24 +34,89 @@ pub struct WasmContract {
     pub code: Vec<u8>,
     pub storage: HashMap<String, String>,
     pub balance: u64,
+    /// Code ID for CosmWasm instances; `None` for contracts deployed directly
+    #[serde(default)]
+    pub code_id: Option<u64>,
+    /// Account allowed to migrate a CosmWasm instance
+    #[serde(default)]
+    pub admin: Option<String>,
+    #[serde(default)]
+    pub label: String,
+}
+
+/// Uploaded CosmWasm code, instantiable any number of times
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CodeInfo {
+    pub code_id: u64,
+    pub creator: String,
+    pub checksum: String,
+    pub code: Vec<u8>,
 }
 
 pub struct WasmVM {
+    engine: Engine,
+    modules: HashMap<String, Module>,
     contracts: HashMap<String, WasmContract>,
+    codes: HashMap<u64, CodeInfo>,
+    code_modules: HashMap<u64, Module>,
+    /// Native balances of accounts that are not contracts, debited when they send funds
+    accounts: HashMap<String, u64>,
+    next_code_id: u64,
+    instance_count: u64,
+    block: BlockInfo,
+    /// Gas each call may use
     gas_limit: u64,
+    /// Gas used by the current or most recent call
     gas_used: u64,
 }
 
+/// Per-invocation state visible to host functions
+pub(crate) struct HostState {
+    pub(crate) storage: HashMap<String, String>,
+    pub(crate) balance: u64,
+    pub(crate) caller: String,
+    pub(crate) input: Vec<u8>,
+    pub(crate) output: Option<Vec<u8>>,
+    /// Set for queries: storage writes trap and changes are never persisted
+    pub(crate) read_only: bool,
+    pub(crate) debug_log: Vec<String>,
+}
+
 impl WasmVM {
+    /// A VM whose deployments and calls may each use up to `gas_limit`
     pub fn new(gas_limit: u64) -> Self {
+        let mut config = Config::default();
+        config.consume_fuel(true);
         WasmVM {
+            engine: Engine::new(&config),
+            modules: HashMap::new(),
             contracts: HashMap::new(),
+            codes: HashMap::new(),
+            code_modules: HashMap::new(),
+            accounts: HashMap::new(),
+            next_code_id: 1,
+            instance_count: 0,
+            block: BlockInfo {
+                height: 0,
+                time: "0".to_string(),
+                chain_id: String::new(),
+            },
             gas_limit,
             gas_used: 0,
         }
     }
 
+    /// Update the block context passed to CosmWasm contracts in `Env`
+    pub fn set_block_info(&mut self, height: u64, time_nanos: u64, chain_id: &str) {
+        self.block = BlockInfo {
+            height,
+            time: time_nanos.to_string(),
+            chain_id: chain_id.to_string(),
+        };
+    }
+
     pub fn deploy_contract(&mut self, address: String, code: Vec<u8>) -> Result<()> {
+        self.begin_call();
         if self.contracts.contains_key(&address) {
             return Err(anyhow!("Contract already exists at address"));
         }
@@ -35,78 +125,223 @@ impl WasmVM {
             return Err(anyhow!("Invalid WASM magic number"));
         }
 
+        let module = self.compile(&code)?;
+        self.charge(GAS_DEPLOY_BASE + GAS_PER_CODE_BYTE * code.len() as u64)?;
+
         let contract = WasmContract {
             address: address.clone(),
             code,
             storage: HashMap::new(),
             balance: 0,
+            code_id: None,
+            admin: None,
+            label: String::new(),
         };
 
+        self.modules.insert(address.clone(), module);
         self.contracts.insert(address, contract);
-        self.gas_used += 21000;
         Ok(())
     }
 
     pub fn call_contract(&mut self, address: &str, method: &str, args: Vec<String>) -> Result<String> {
-        self.gas_used += 3000;
-        
-        if self.gas_used > self.gas_limit {
-            return Err(anyhow!("Out of gas"));
-        }
+        self.call_contract_as("", address, method, args)
+    }
 
-        let contract = self.contracts.get_mut(address)
+    /// Call an exported function on behalf of `caller`.
+    /// Arguments are parsed according to the export's parameter types and also
+    /// made available to the contract as a JSON array through `input_read`.
+    pub fn call_contract_as(&mut self, caller: &str, address: &str, method: &str, args: Vec<String>) -> Result<String> {
+        self.begin_call();
+        let module = self.module_for(address)
             .ok_or_else(|| anyhow!("Contract not found"))?;
 
-        match method {
-            "get_balance" => Ok(contract.balance.to_string()),
-            "get_storage" => {
-                if let Some(key) = args.get(0) {
-                    Ok(contract.storage.get(key).cloned().unwrap_or_default())
-                } else {
-                    Err(anyhow!("Missing storage key"))
-                }
-            },
-            "set_storage" => {
-                if args.len() >= 2 {
-                    let key = args[0].clone();
-                    let value = args[1].clone();
-                    contract.storage.insert(key.clone(), value.clone());
-                    self.gas_used += 5000;
-                    Ok(format!("Storage set: {} = {}", key, value))
-                } else {
-                    Err(anyhow!("Missing key or value"))
-                }
-            },
-            "transfer" => {
-                if let Some(amount_str) = args.get(0) {
-                    let amount: u64 = amount_str.parse().unwrap_or(0);
-                    if contract.balance >= amount {
-                        contract.balance -= amount;
-                        self.gas_used += 10000;
-                        Ok(format!("Transferred: {}", amount))
-                    } else {
-                        Err(anyhow!("Insufficient balance"))
-                    }
-                } else {
-                    Err(anyhow!("Missing amount"))
-                }
-            },
-            _ => {
-                self.gas_used += 1000;
-                Ok(format!("WASM method '{}' executed with {} args", method, args.len()))
-            }
+        if !exports_function(module, method) {
+            return self.call_builtin(address, method, args);
         }
+
+        let input = serde_json::to_vec(&args)?;
+        let output = self.invoke(address, caller, input, false, |store, instance| {
+            call_export(store, instance, method, &args)
+        })?;
+        Ok(String::from_utf8_lossy(&output).into_owned())
     }
 
     pub fn execute_wasm(&mut self, address: &str, input: &[u8]) -> Result<Vec<u8>> {
+        self.begin_call();
         if !self.contracts.contains_key(address) {
             return Err(anyhow!("Contract not found"));
         }
-        
-        self.gas_used += 10000;
-        Ok(format!("WASM executed for {} bytes input", input.len()).into_bytes())
+
+        self.invoke(address, "", input.to_vec(), false, |store, instance| {
+            call_export(store, instance, ENTRY_POINT, &[])
+        })
+    }
+
+}
+
+// CosmWasm contract lifecycle; the CosmWasm host imports and message types in `cosmwasm`
+// are reached through these
+impl WasmVM {
+    /// Upload CosmWasm code and return its code ID
+    pub fn store_code(&mut self, creator: &str, code: Vec<u8>) -> Result<u64> {
+        self.begin_call();
+        let module = self.compile(&code)?;
+        for export in cosmwasm::REQUIRED_EXPORTS {
+            if !module.exports().any(|e| e.name() == export) {
+                return Err(anyhow!("Not a CosmWasm contract: missing export '{}'", export));
+            }
+        }
+        self.charge(GAS_DEPLOY_BASE + GAS_PER_CODE_BYTE * code.len() as u64)?;
+
+        let code_id = self.next_code_id;
+        self.next_code_id += 1;
+        self.codes.insert(code_id, CodeInfo {
+            code_id,
+            creator: creator.to_string(),
+            checksum: hex::encode(Sha256::digest(&code)),
+            code,
+        });
+        self.code_modules.insert(code_id, module);
+        Ok(code_id)
+    }
+
+    pub fn get_code(&self, code_id: u64) -> Option<&CodeInfo> {
+        self.codes.get(&code_id)
+    }
+
+    /// Create a new contract instance from stored code and run its `instantiate` entry point.
+    /// Returns the new contract address along with the contract's `Response`.
+    pub fn instantiate(
+        &mut self,
+        code_id: u64,
+        sender: &str,
+        msg: &serde_json::Value,
+        funds: &[Coin],
+        admin: Option<String>,
+        label: &str,
+    ) -> Result<(String, Response)> {
+        self.begin_call();
+        if !self.codes.contains_key(&code_id) {
+            return Err(anyhow!("Code ID {} not found", code_id));
+        }
+        let deposit = native_amount(funds)?;
+        if self.balance_of(sender) < deposit {
+            return Err(anyhow!("Insufficient funds: {} has {}, needs {}", sender, self.balance_of(sender), deposit));
+        }
+        self.charge(GAS_INSTANTIATE)?;
+
+        self.instance_count += 1;
+        let mut hasher = Sha256::new();
+        hasher.update(code_id.to_be_bytes());
+        hasher.update(self.instance_count.to_be_bytes());
+        let address = format!("neo1{}", hex::encode(&hasher.finalize()[..19]));
+
+        self.contracts.insert(address.clone(), WasmContract {
+            address: address.clone(),
+            code: vec![],
+            storage: HashMap::new(),
+            balance: 0,
+            code_id: Some(code_id),
+            admin,
+            label: label.to_string(),
+        });
+        self.move_funds(sender, &address, deposit)?;
+
+        let info = MessageInfo { sender: sender.to_string(), funds: funds.to_vec() };
+        match self.call_cosmwasm(&address, "instantiate", Some(&info), msg, false)
+            .and_then(|response| parse_response(&response))
+        {
+            Ok(response) => Ok((address, response)),
+            Err(e) => {
+                self.move_funds(&address, sender, deposit)?;
+                self.contracts.remove(&address);
+                Err(e)
+            }
+        }
+    }
+
+    /// Run a contract's `execute` entry point with the given JSON message and funds
+    pub fn execute(
+        &mut self,
+        contract: &str,
+        sender: &str,
+        msg: &serde_json::Value,
+        funds: &[Coin],
+    ) -> Result<Response> {
+        self.begin_call();
+        self.cosmwasm_instance(contract)?;
+        let deposit = native_amount(funds)?;
+        let info = MessageInfo { sender: sender.to_string(), funds: funds.to_vec() };
+
+        self.move_funds(sender, contract, deposit)?;
+        match self.call_cosmwasm(contract, "execute", Some(&info), msg, false)
+            .and_then(|response| parse_response(&response))
+        {
+            Ok(response) => Ok(response),
+            Err(e) => {
+                self.move_funds(contract, sender, deposit)?;
+                Err(e)
+            }
+        }
+    }
+
+    /// Run a contract's `query` entry point. Queries are read-only: storage
+    /// writes trap and no state change is ever persisted.
+    pub fn query(&mut self, contract: &str, msg: &serde_json::Value) -> Result<Vec<u8>> {
+        self.begin_call();
+        self.cosmwasm_instance(contract)?;
+        let result = self.call_cosmwasm(contract, "query", None, msg, true)?;
+        let binary: String = serde_json::from_slice::<ContractResult<String>>(&result)
+            .map_err(|e| anyhow!("Invalid query result: {}", e))?
+            .into_result()?;
+        cosmwasm::decode_binary(&binary)
+    }
+
+    /// Switch a contract to `new_code_id` and run the new code's `migrate` entry point.
+    /// Only the contract admin may migrate.
+    pub fn migrate(
+        &mut self,
+        contract: &str,
+        sender: &str,
+        new_code_id: u64,
+        msg: &serde_json::Value,
+    ) -> Result<Response> {
+        self.begin_call();
+        let instance = self.cosmwasm_instance(contract)?;
+        match &instance.admin {
+            Some(admin) if admin == sender => {}
+            Some(_) => return Err(anyhow!("Unauthorized: only the contract admin can migrate")),
+            None => return Err(anyhow!("Contract has no admin and cannot be migrated")),
+        }
+        if !self.codes.contains_key(&new_code_id) {
+            return Err(anyhow!("Code ID {} not found", new_code_id));
+        }
+        let old_code_id = instance.code_id;
+
+        self.set_code_id(contract, Some(new_code_id));
+        match self.call_cosmwasm(contract, "migrate", None, msg, false) {
+            Ok(response) => parse_response(&response),
+            Err(e) => {
+                self.set_code_id(contract, old_code_id);
+                Err(e)
+            }
+        }
     }
 
+    /// Change or clear the admin of a CosmWasm instance. Only the current admin may do this.
+    pub fn update_admin(&mut self, contract: &str, sender: &str, new_admin: Option<String>) -> Result<()> {
+        let instance = self.contracts.get_mut(contract)
+            .filter(|c| c.code_id.is_some())
+            .ok_or_else(|| anyhow!("CosmWasm contract {} not found", contract))?;
+        if instance.admin.as_deref() != Some(sender) {
+            return Err(anyhow!("Unauthorized: only the contract admin can update the admin"));
+        }
+        instance.admin = new_admin;
+        Ok(())
+    }
+}
+
+impl WasmVM {
     pub fn get_gas_used(&self) -> u64 {
         self.gas_used
     }
@@ -115,24 +350,476 @@ impl WasmVM {
         self.contracts.get(address)
     }
 
+    /// Credit native tokens to a non-contract account, e.g. from genesis or a bridge
+    pub fn fund_account(&mut self, address: &str, amount: u64) -> Result<()> {
+        let balance = self.accounts.entry(address.to_string()).or_insert(0);
+        *balance = balance.checked_add(amount).ok_or_else(|| anyhow!("Balance overflow"))?;
+        Ok(())
+    }
+
+    /// Native balance of `address`, whether it is a contract or a plain account
+    pub fn balance_of(&self, address: &str) -> u64 {
+        match self.contracts.get(address) {
+            Some(contract) => contract.balance,
+            None => self.accounts.get(address).copied().unwrap_or(0),
+        }
+    }
+
+    /// Move `amount` from `from` to `to`, each of which may be a contract or a plain account
+    fn move_funds(&mut self, from: &str, to: &str, amount: u64) -> Result<()> {
+        if amount == 0 {
+            return Ok(());
+        }
+        if self.balance_of(from) < amount {
+            return Err(anyhow!("Insufficient funds: {} has {}, needs {}", from, self.balance_of(from), amount));
+        }
+        self.balance_of(to).checked_add(amount).ok_or_else(|| anyhow!("Balance overflow"))?;
+        *self.balance_mut(from) -= amount;
+        *self.balance_mut(to) += amount;
+        Ok(())
+    }
+
+    fn balance_mut(&mut self, address: &str) -> &mut u64 {
+        match self.contracts.get_mut(address) {
+            Some(contract) => &mut contract.balance,
+            None => self.accounts.entry(address.to_string()).or_insert(0),
+        }
+    }
+
     pub fn deposit(&mut self, address: &str, amount: u64) -> Result<()> {
         let contract = self.contracts.get_mut(address)
             .ok_or_else(|| anyhow!("Contract not found"))?;
         contract.balance += amount;
         Ok(())
     }
+
+    fn compile(&self, code: &[u8]) -> Result<Module> {
+        let module = Module::new(&self.engine, code)
+            .map_err(|e| anyhow!("Invalid WASM module: {}", e))?;
+
+        for import in module.imports() {
+            let known = HOST_FUNCTIONS.contains(&import.name()) || cosmwasm::IMPORTS.contains(&import.name());
+            if import.module() != HOST_MODULE || !known {
+                return Err(anyhow!("Unsupported import {}::{}", import.module(), import.name()));
+            }
+        }
+        Ok(module)
+    }
+
+    /// Start metering a new deployment or call against a fresh `gas_limit`
+    fn begin_call(&mut self) {
+        self.gas_used = 0;
+    }
+
+    fn charge(&mut self, gas: u64) -> Result<()> {
+        if self.gas_used + gas > self.gas_limit {
+            return Err(anyhow!("Out of gas"));
+        }
+        self.gas_used += gas;
+        Ok(())
+    }
+
+    fn module_for(&self, address: &str) -> Option<&Module> {
+        match self.contracts.get(address)?.code_id {
+            Some(code_id) => self.code_modules.get(&code_id),
+            None => self.modules.get(address),
+        }
+    }
+
+    fn cosmwasm_instance(&self, address: &str) -> Result<WasmContract> {
+        self.contracts.get(address)
+            .filter(|c| c.code_id.is_some())
+            .cloned()
+            .ok_or_else(|| anyhow!("CosmWasm contract {} not found", address))
+    }
+
+    fn set_code_id(&mut self, address: &str, code_id: Option<u64>) {
+        if let Some(contract) = self.contracts.get_mut(address) {
+            contract.code_id = code_id;
+        }
+    }
+
+    /// Call a CosmWasm entry point with `(env, [info], msg)` and return the raw JSON result.
+    /// A `{"error": ..}` result fails the call so its storage changes are discarded.
+    fn call_cosmwasm(
+        &mut self,
+        address: &str,
+        entry_point: &str,
+        info: Option<&MessageInfo>,
+        msg: &serde_json::Value,
+        read_only: bool,
+    ) -> Result<Vec<u8>> {
+        let env = Env {
+            block: self.block.clone(),
+            transaction: None,
+            contract: ContractInfo { address: address.to_string() },
+        };
+        let mut args = vec![serde_json::to_vec(&env)?];
+        if let Some(info) = info {
+            args.push(serde_json::to_vec(info)?);
+        }
+        args.push(serde_json::to_vec(msg)?);
+
+        let sender = info.map(|i| i.sender.clone()).unwrap_or_default();
+        self.invoke(address, &sender, vec![], read_only, |store, instance| {
+            let result = cosmwasm::call_entry_point(store, instance, entry_point, &args)
+                .map_err(map_wasm_error)?;
+            let value: serde_json::Value = serde_json::from_slice(&result)
+                .map_err(|e| anyhow!("Invalid {} result: {}", entry_point, e))?;
+            if let Some(error) = value.get("error") {
+                return Err(anyhow!("Contract error: {}", error.as_str().unwrap_or_default()));
+            }
+            Ok(result)
+        })
+    }
+
+    /// Instantiate the contract module with metered fuel and run `call` against it.
+    /// Storage changes are written back only if the call succeeds and is not read-only.
+    fn invoke<F>(&mut self, address: &str, caller: &str, input: Vec<u8>, read_only: bool, call: F) -> Result<Vec<u8>>
+    where
+        F: FnOnce(&mut Store<HostState>, &Instance) -> Result<Vec<u8>>,
+    {
+        let contract = self.contracts.get(address)
+            .ok_or_else(|| anyhow!("Contract not found"))?;
+        let module = self.module_for(address)
+            .ok_or_else(|| anyhow!("Contract code not found"))?;
+
+        let state = HostState {
+            storage: contract.storage.clone(),
+            balance: contract.balance,
+            caller: caller.to_string(),
+            input,
+            output: None,
+            read_only,
+            debug_log: vec![],
+        };
+        let mut store = Store::new(&self.engine, state);
+        let fuel = self.gas_limit.saturating_sub(self.gas_used);
+        store.add_fuel(fuel).map_err(|e| anyhow!("Fuel metering error: {}", e))?;
+
+        let result = instantiate_module(&self.engine, &mut store, module)
+            .and_then(|instance| call(&mut store, &instance));
+        self.gas_used += store.fuel_consumed().unwrap_or(0);
+
+        let output = match result {
+            Ok(output) => output,
+            Err(e) if is_out_of_fuel(&e) => {
+                self.gas_used = self.gas_limit;
+                return Err(anyhow!("Out of gas"));
+            }
+            Err(e) => return Err(e),
+        };
+
+        let state = store.into_data();
+        if !read_only {
+            if let Some(contract) = self.contracts.get_mut(address) {
+                contract.storage = state.storage;
+            }
+        }
+        Ok(output)
+    }
+
+    /// Read-only getters available on every contract that does not export its own version.
+    /// Anything that changes a contract's storage or balance goes through its own code.
+    fn call_builtin(&mut self, address: &str, method: &str, args: Vec<String>) -> Result<String> {
+        if !matches!(method, "get_balance" | "get_storage") {
+            return Err(anyhow!("Method '{}' is not exported by contract", method));
+        }
+        self.charge(GAS_STORAGE_READ)?;
+
+        let contract = self.contracts.get(address)
+            .ok_or_else(|| anyhow!("Contract not found"))?;
+
+        match method {
+            "get_balance" => Ok(contract.balance.to_string()),
+            _ => {
+                if let Some(key) = args.first() {
+                    Ok(contract.storage.get(key).cloned().unwrap_or_default())
+                } else {
+                    Err(anyhow!("Missing storage key"))
+                }
+            },
+        }
+    }
+}
+
+fn exports_function(module: &Module, name: &str) -> bool {
+    module.exports().any(|export| export.name() == name && export.ty().func().is_some())
+}
+
+fn native_amount(funds: &[Coin]) -> Result<u64> {
+    funds.iter().try_fold(0u64, |total, coin| {
+        if coin.denom != NATIVE_DENOM {
+            return Err(anyhow!("Unsupported denom '{}'", coin.denom));
+        }
+        let amount: u64 = coin.amount.parse()
+            .map_err(|_| anyhow!("Invalid coin amount '{}'", coin.amount))?;
+        total.checked_add(amount).ok_or_else(|| anyhow!("Funds overflow"))
+    })
+}
+
+fn parse_response(result: &[u8]) -> Result<Response> {
+    serde_json::from_slice::<ContractResult<Response>>(result)
+        .map_err(|e| anyhow!("Invalid contract response: {}", e))?
+        .into_result()
+}
+
+fn instantiate_module(engine: &Engine, store: &mut Store<HostState>, module: &Module) -> Result<Instance> {
+    let linker = host_linker(engine)?;
+    linker.instantiate(&mut *store, module)
+        .map_err(|e| anyhow!("Instantiation failed: {}", e))?
+        .start(&mut *store)
+        .map_err(map_wasm_error)
+}
+
+/// Run an exported function, parsing `args` according to its parameter types.
+/// Returns the bytes passed to `set_return`, or the function's first result rendered as text.
+fn call_export(store: &mut Store<HostState>, instance: &Instance, export: &str, args: &[String]) -> Result<Vec<u8>> {
+    let func = instance.get_func(&*store, export)
+        .ok_or_else(|| anyhow!("Method '{}' is not exported by contract", export))?;
+    let ty = func.ty(&*store);
+
+    if ty.params().len() != args.len() {
+        return Err(anyhow!("Method '{}' expects {} args, got {}", export, ty.params().len(), args.len()));
+    }
+    let params = ty.params().iter().zip(args)
+        .map(|(param, arg)| parse_value(*param, arg))
+        .collect::<Result<Vec<_>>>()?;
+    let mut results: Vec<Value> = ty.results().iter().map(|r| Value::default(*r)).collect();
+
+    func.call(&mut *store, &params, &mut results).map_err(map_wasm_error)?;
+
+    Ok(store.data_mut().output.take().unwrap_or_else(|| {
+        results.first().map(value_to_string).unwrap_or_default().into_bytes()
+    }))
+}
+
+fn map_wasm_error(e: wasmi::Error) -> anyhow::Error {
+    match &e {
+        wasmi::Error::Trap(trap) if matches!(trap.trap_code(), Some(TrapCode::OutOfFuel)) => anyhow!(OutOfFuel),
+        _ => anyhow!("WASM execution trapped: {}", e),
+    }
+}
+
+#[derive(Debug)]
+struct OutOfFuel;
+
+impl std::fmt::Display for OutOfFuel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "out of fuel")
+    }
+}
+
+impl std::error::Error for OutOfFuel {}
+
+fn is_out_of_fuel(e: &anyhow::Error) -> bool {
+    e.downcast_ref::<OutOfFuel>().is_some()
+}
+
+fn parse_value(ty: ValueType, arg: &str) -> Result<Value> {
+    match ty {
+        ValueType::I32 => arg.parse::<i32>().map(Value::I32)
+            .map_err(|_| anyhow!("Invalid i32 argument '{}'", arg)),
+        ValueType::I64 => arg.parse::<i64>().map(Value::I64)
+            .map_err(|_| anyhow!("Invalid i64 argument '{}'", arg)),
+        other => Err(anyhow!("Unsupported parameter type {:?}", other)),
+    }
+}
+
+fn value_to_string(value: &Value) -> String {
+    match value {
+        Value::I32(v) => v.to_string(),
+        Value::I64(v) => v.to_string(),
+        other => format!("{:?}", other),
+    }
+}
+
+pub(crate) fn consume(caller: &mut Caller<'_, HostState>, gas: u64) -> Result<(), Trap> {
+    caller.consume_fuel(gas)
+        .map(|_| ())
+        .map_err(|_| Trap::from(TrapCode::OutOfFuel))
+}
+
+fn read_memory(caller: &Caller<'_, HostState>, ptr: i32, len: i32) -> Result<Vec<u8>, Trap> {
+    let memory = caller.get_export("memory")
+        .and_then(Extern::into_memory)
+        .ok_or_else(|| Trap::new("contract does not export memory"))?;
+    if ptr < 0 || len < 0 {
+        return Err(Trap::from(TrapCode::MemoryOutOfBounds));
+    }
+    let mut buf = vec![0u8; len as usize];
+    memory.read(caller, ptr as usize, &mut buf)
+        .map_err(|_| Trap::from(TrapCode::MemoryOutOfBounds))?;
+    Ok(buf)
+}
+
+fn write_memory(caller: &mut Caller<'_, HostState>, ptr: i32, data: &[u8]) -> Result<(), Trap> {
+    let memory = caller.get_export("memory")
+        .and_then(Extern::into_memory)
+        .ok_or_else(|| Trap::new("contract does not export memory"))?;
+    if ptr < 0 {
+        return Err(Trap::from(TrapCode::MemoryOutOfBounds));
+    }
+    memory.write(caller, ptr as usize, data)
+        .map_err(|_| Trap::from(TrapCode::MemoryOutOfBounds))
+}
+
+fn read_string(caller: &Caller<'_, HostState>, ptr: i32, len: i32) -> Result<String, Trap> {
+    String::from_utf8(read_memory(caller, ptr, len)?)
+        .map_err(|_| Trap::new("storage keys and values must be UTF-8"))
+}
+
+/// Host functions exposed to contracts under the `env` namespace:
+///
+/// - `storage_get(key_ptr, key_len, out_ptr, out_cap) -> i32`: value length, or -1 if unset
+/// - `storage_set(key_ptr, key_len, value_ptr, value_len)`
+/// - `storage_remove(key_ptr, key_len)`
+/// - `balance() -> i64`
+/// - `caller(out_ptr, out_cap) -> i32`: caller address length
+/// - `input_len() -> i32` / `input_read(out_ptr)`: call input bytes
+/// - `set_return(ptr, len)`: bytes returned to the caller
+///
+/// CosmWasm contracts additionally get the imports from `cosmwasm::register_imports`.
+fn host_linker(engine: &Engine) -> Result<Linker<HostState>> {
+    let mut linker = Linker::new(engine);
+    cosmwasm::register_imports(&mut linker)?;
+
+    linker.func_wrap(HOST_MODULE, "storage_get",
+        |mut caller: Caller<'_, HostState>, key_ptr: i32, key_len: i32, out_ptr: i32, out_cap: i32| -> Result<i32, Trap> {
+            consume(&mut caller, GAS_STORAGE_READ + GAS_PER_DATA_BYTE * key_len.max(0) as u64)?;
+            let key = read_string(&caller, key_ptr, key_len)?;
+            let value = match caller.data().storage.get(&key) {
+                Some(value) => value.clone().into_bytes(),
+                None => return Ok(-1),
+            };
+            let n = value.len().min(out_cap.max(0) as usize);
+            consume(&mut caller, GAS_PER_DATA_BYTE * n as u64)?;
+            write_memory(&mut caller, out_ptr, &value[..n])?;
+            Ok(value.len() as i32)
+        })
+        .map_err(|e| anyhow!("{}", e))?;
+
+    linker.func_wrap(HOST_MODULE, "storage_set",
+        |mut caller: Caller<'_, HostState>, key_ptr: i32, key_len: i32, value_ptr: i32, value_len: i32| -> Result<(), Trap> {
+            let bytes = key_len.max(0) as u64 + value_len.max(0) as u64;
+            if caller.data().read_only {
+                return Err(Trap::new("storage_set is not allowed in a read-only call"));
+            }
+            consume(&mut caller, GAS_STORAGE_WRITE + GAS_PER_DATA_BYTE * bytes)?;
+            let key = read_string(&caller, key_ptr, key_len)?;
+            let value = read_string(&caller, value_ptr, value_len)?;
+            caller.data_mut().storage.insert(key, value);
+            Ok(())
+        })
+        .map_err(|e| anyhow!("{}", e))?;
+
+    linker.func_wrap(HOST_MODULE, "storage_remove",
+        |mut caller: Caller<'_, HostState>, key_ptr: i32, key_len: i32| -> Result<(), Trap> {
+            if caller.data().read_only {
+                return Err(Trap::new("storage_remove is not allowed in a read-only call"));
+            }
+            consume(&mut caller, GAS_STORAGE_WRITE)?;
+            let key = read_string(&caller, key_ptr, key_len)?;
+            caller.data_mut().storage.remove(&key);
+            Ok(())
+        })
+        .map_err(|e| anyhow!("{}", e))?;
+
+    linker.func_wrap(HOST_MODULE, "balance",
+        |mut caller: Caller<'_, HostState>| -> Result<i64, Trap> {
+            consume(&mut caller, GAS_HOST_CALL)?;
+            Ok(caller.data().balance as i64)
+        })
+        .map_err(|e| anyhow!("{}", e))?;
+
+    linker.func_wrap(HOST_MODULE, "caller",
+        |mut caller: Caller<'_, HostState>, out_ptr: i32, out_cap: i32| -> Result<i32, Trap> {
+            consume(&mut caller, GAS_HOST_CALL)?;
+            let address = caller.data().caller.clone().into_bytes();
+            let n = address.len().min(out_cap.max(0) as usize);
+            write_memory(&mut caller, out_ptr, &address[..n])?;
+            Ok(address.len() as i32)
+        })
+        .map_err(|e| anyhow!("{}", e))?;
+
+    linker.func_wrap(HOST_MODULE, "input_len",
+        |mut caller: Caller<'_, HostState>| -> Result<i32, Trap> {
+            consume(&mut caller, GAS_HOST_CALL)?;
+            Ok(caller.data().input.len() as i32)
+        })
+        .map_err(|e| anyhow!("{}", e))?;
+
+    linker.func_wrap(HOST_MODULE, "input_read",
+        |mut caller: Caller<'_, HostState>, out_ptr: i32| -> Result<(), Trap> {
+            let input = caller.data().input.clone();
+            consume(&mut caller, GAS_HOST_CALL + GAS_PER_DATA_BYTE * input.len() as u64)?;
+            write_memory(&mut caller, out_ptr, &input)
+        })
+        .map_err(|e| anyhow!("{}", e))?;
+
+    linker.func_wrap(HOST_MODULE, "set_return",
+        |mut caller: Caller<'_, HostState>, ptr: i32, len: i32| -> Result<(), Trap> {
+            consume(&mut caller, GAS_HOST_CALL + GAS_PER_DATA_BYTE * len.max(0) as u64)?;
+            let data = read_memory(&caller, ptr, len)?;
+            caller.data_mut().output = Some(data);
+            Ok(())
+        })
+        .map_err(|e| anyhow!("{}", e))?;
+
+    Ok(linker)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    // Counter contract: `increment` bumps storage["count"] (single ASCII digit) and returns it,
+    // `who` returns the caller address, `main` echoes its input.
+    const COUNTER_WAT: &str = r#"
+        (module
+          (import "env" "storage_get" (func $get (param i32 i32 i32 i32) (result i32)))
+          (import "env" "storage_set" (func $set (param i32 i32 i32 i32)))
+          (import "env" "balance" (func $balance (result i64)))
+          (import "env" "caller" (func $caller (param i32 i32) (result i32)))
+          (import "env" "input_len" (func $input_len (result i32)))
+          (import "env" "input_read" (func $input_read (param i32)))
+          (import "env" "set_return" (func $set_return (param i32 i32)))
+          (memory (export "memory") 1)
+          (data (i32.const 0) "count")
+          (func (export "increment") (result i32)
+            (local $n i32)
+            (if (i32.eq (call $get (i32.const 0) (i32.const 5) (i32.const 16) (i32.const 1)) (i32.const 1))
+              (then (local.set $n (i32.sub (i32.load8_u (i32.const 16)) (i32.const 48)))))
+            (local.set $n (i32.add (local.get $n) (i32.const 1)))
+            (i32.store8 (i32.const 16) (i32.add (local.get $n) (i32.const 48)))
+            (call $set (i32.const 0) (i32.const 5) (i32.const 16) (i32.const 1))
+            (local.get $n))
+          (func (export "add") (param i32 i64) (result i64)
+            (i64.add (i64.extend_i32_s (local.get 0)) (local.get 1)))
+          (func (export "my_balance") (result i64)
+            (call $balance))
+          (func (export "who")
+            (call $set_return (i32.const 32) (call $caller (i32.const 32) (i32.const 64))))
+          (func (export "main")
+            (call $input_read (i32.const 128))
+            (call $set_return (i32.const 128) (call $input_len)))
+          (func (export "spin")
+            (loop $l (br $l))))
+    "#;
+
+    fn counter_vm(gas_limit: u64) -> WasmVM {
+        let mut vm = WasmVM::new(gas_limit);
+        let code = wat::parse_str(COUNTER_WAT).unwrap();
+        vm.deploy_contract("counter".to_string(), code).unwrap();
+        vm
+    }
+
     #[test]
     fn test_deploy_and_call_contract() {
         let mut vm = WasmVM::new(1000000);
         let code = vec![0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00];
         assert!(vm.deploy_contract("contract1".to_string(), code).is_ok());
-        
+
         let result = vm.call_contract("contract1", "get_balance", vec![]);
         assert!(result.is_ok());
         assert_eq!(result.unwrap(), "0");
@@ -143,19 +830,252 @@ mod tests {
         let mut vm = WasmVM::new(1000000);
         let code = vec![0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00];
         vm.deploy_contract("contract1".to_string(), code).unwrap();
-        
+        vm.deposit("contract1", 50).unwrap();
+
+        // Only the contract's own code may write its storage or move its balance
         let set_result = vm.call_contract(
             "contract1",
             "set_storage",
             vec!["key1".to_string(), "value1".to_string()]
         );
-        assert!(set_result.is_ok());
-        
+        assert!(set_result.is_err());
+        assert!(vm.call_contract("contract1", "transfer", vec!["50".to_string()]).is_err());
+
         let get_result = vm.call_contract(
             "contract1",
             "get_storage",
             vec!["key1".to_string()]
         );
-        assert_eq!(get_result.unwrap(), "value1");
+        assert_eq!(get_result.unwrap(), "");
+        assert_eq!(vm.call_contract("contract1", "get_balance", vec![]).unwrap(), "50");
+    }
+
+    #[test]
+    fn test_rejects_invalid_module() {
+        let mut vm = WasmVM::new(1000000);
+        assert!(vm.deploy_contract("bad".to_string(), b"\0asm".to_vec()).is_err());
+
+        let unknown_import = wat::parse_str(r#"(module (import "env" "exec" (func)))"#).unwrap();
+        assert!(vm.deploy_contract("bad".to_string(), unknown_import).is_err());
+    }
+
+    #[test]
+    fn test_exported_function_uses_host_storage() {
+        let mut vm = counter_vm(1_000_000);
+
+        assert_eq!(vm.call_contract("counter", "increment", vec![]).unwrap(), "1");
+        assert_eq!(vm.call_contract("counter", "increment", vec![]).unwrap(), "2");
+        assert_eq!(vm.get_contract("counter").unwrap().storage.get("count").unwrap(), "2");
+        assert_eq!(vm.call_contract("counter", "get_storage", vec!["count".to_string()]).unwrap(), "2");
+    }
+
+    #[test]
+    fn test_typed_arguments_and_host_context() {
+        let mut vm = counter_vm(1_000_000);
+        vm.deposit("counter", 77).unwrap();
+
+        assert_eq!(vm.call_contract("counter", "add", vec!["-2".to_string(), "10".to_string()]).unwrap(), "8");
+        assert!(vm.call_contract("counter", "add", vec!["x".to_string(), "1".to_string()]).is_err());
+        assert_eq!(vm.call_contract("counter", "my_balance", vec![]).unwrap(), "77");
+        assert_eq!(vm.call_contract_as("neo1alice", "counter", "who", vec![]).unwrap(), "neo1alice");
+        assert_eq!(vm.execute_wasm("counter", b"ping").unwrap(), b"ping");
+        assert!(vm.call_contract("counter", "missing", vec![]).is_err());
+    }
+
+    #[test]
+    fn test_gas_metered_per_instruction() {
+        let mut vm = counter_vm(10_000_000);
+        vm.call_contract("counter", "add", vec!["1".to_string(), "2".to_string()]).unwrap();
+        let cheap = vm.get_gas_used();
+
+        vm.call_contract("counter", "increment", vec![]).unwrap();
+        let storage = vm.get_gas_used();
+
+        assert!(cheap > 0 && cheap < 100);
+        assert!(storage > GAS_STORAGE_WRITE);
+    }
+
+    #[test]
+    fn test_out_of_gas_discards_storage() {
+        let mut vm = counter_vm(1_000_000);
+        assert!(vm.call_contract("counter", "spin", vec![]).unwrap_err().to_string().contains("Out of gas"));
+        assert_eq!(vm.get_gas_used(), 1_000_000);
+        assert!(vm.get_contract("counter").unwrap().storage.is_empty());
+
+        // Gas is metered per call, so a call that ran out doesn't lock the VM
+        assert_eq!(vm.call_contract("counter", "increment", vec![]).unwrap(), "1");
+        assert!(vm.get_gas_used() < 1_000_000);
+    }
+
+    // Minimal CosmWasm contract: `instantiate`/`migrate` store msg under "config", `execute` copies
+    // "config" to "copy" and fails when msg is "f..", `query` returns "hello" or writes on "w..".
+    const COSMWASM_WAT: &str = r#"
+        (module
+          (import "env" "db_read" (func $db_read (param i32) (result i32)))
+          (import "env" "db_write" (func $db_write (param i32 i32)))
+          (memory (export "memory") 1)
+          (global $heap (mut i32) (i32.const 4096))
+          (data (i32.const 0) "config")
+          (data (i32.const 16) "copy")
+          (data (i32.const 32) "{\"ok\":{\"messages\":[],\"attributes\":[{\"key\":\"action\",\"value\":\"done\"}],\"events\":[],\"data\":null}}")
+          (data (i32.const 256) "{\"error\":\"boom\"}")
+          (data (i32.const 288) "{\"ok\":\"aGVsbG8=\"}")
+          (func $alloc_header (result i32)
+            (local $ptr i32)
+            (local.set $ptr (global.get $heap))
+            (global.set $heap (i32.add (global.get $heap) (i32.const 12)))
+            (local.get $ptr))
+          (func $region (param $offset i32) (param $len i32) (result i32)
+            (local $ptr i32)
+            (local.set $ptr (call $alloc_header))
+            (i32.store (local.get $ptr) (local.get $offset))
+            (i32.store offset=4 (local.get $ptr) (local.get $len))
+            (i32.store offset=8 (local.get $ptr) (local.get $len))
+            (local.get $ptr))
+          (func $second_byte (param $msg i32) (result i32)
+            (i32.load8_u offset=1 (i32.load (local.get $msg))))
+          (func (export "interface_version_8"))
+          (func (export "allocate") (param $size i32) (result i32)
+            (local $ptr i32)
+            (local.set $ptr (call $alloc_header))
+            (i32.store (local.get $ptr) (global.get $heap))
+            (i32.store offset=4 (local.get $ptr) (local.get $size))
+            (i32.store offset=8 (local.get $ptr) (i32.const 0))
+            (global.set $heap (i32.add (global.get $heap) (local.get $size)))
+            (local.get $ptr))
+          (func (export "deallocate") (param i32))
+          (func (export "instantiate") (param $env i32) (param $info i32) (param $msg i32) (result i32)
+            (call $db_write (call $region (i32.const 0) (i32.const 6)) (local.get $msg))
+            (call $region (i32.const 32) (i32.const 93)))
+          (func (export "execute") (param $env i32) (param $info i32) (param $msg i32) (result i32)
+            (call $db_write (call $region (i32.const 16) (i32.const 4))
+              (call $db_read (call $region (i32.const 0) (i32.const 6))))
+            (if (result i32) (i32.eq (call $second_byte (local.get $msg)) (i32.const 102))
+              (then (call $region (i32.const 256) (i32.const 16)))
+              (else (call $region (i32.const 32) (i32.const 93)))))
+          (func (export "query") (param $env i32) (param $msg i32) (result i32)
+            (if (i32.eq (call $second_byte (local.get $msg)) (i32.const 119))
+              (then (call $db_write (call $region (i32.const 0) (i32.const 6)) (local.get $msg))))
+            (call $region (i32.const 288) (i32.const 17)))
+          (func (export "migrate") (param $env i32) (param $msg i32) (result i32)
+            (call $db_write (call $region (i32.const 0) (i32.const 6)) (local.get $msg))
+            (call $region (i32.const 32) (i32.const 93))))
+    "#;
+
+    fn stored_value(vm: &WasmVM, contract: &str, key: &str) -> Option<serde_json::Value> {
+        let value = vm.get_contract(contract)?.storage.get(&hex::encode(key))?;
+        serde_json::from_slice(&hex::decode(value).unwrap()).ok()
+    }
+
+    fn instantiated_vm() -> (WasmVM, u64, String) {
+        let mut vm = WasmVM::new(10_000_000);
+        vm.fund_account("alice", 1000).unwrap();
+        vm.fund_account("bob", 1000).unwrap();
+        let code_id = vm.store_code("creator", wat::parse_str(COSMWASM_WAT).unwrap()).unwrap();
+        let (address, _) = vm.instantiate(
+            code_id, "alice", &serde_json::json!("init"), &[Coin::new(250, NATIVE_DENOM)],
+            Some("alice".to_string()), "test",
+        ).unwrap();
+        (vm, code_id, address)
+    }
+
+    #[test]
+    fn test_cosmwasm_store_code_requires_entry_points() {
+        let mut vm = WasmVM::new(10_000_000);
+        let plain = vec![0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00];
+        assert!(vm.store_code("creator", plain).is_err());
+
+        let code_id = vm.store_code("creator", wat::parse_str(COSMWASM_WAT).unwrap()).unwrap();
+        assert_eq!(code_id, 1);
+        assert_eq!(vm.get_code(code_id).unwrap().checksum.len(), 64);
+    }
+
+    #[test]
+    fn test_cosmwasm_instantiate_and_execute() {
+        let (mut vm, code_id, address) = instantiated_vm();
+        let contract = vm.get_contract(&address).unwrap();
+        assert_eq!(contract.code_id, Some(code_id));
+        assert_eq!(contract.balance, 250);
+        assert_eq!(stored_value(&vm, &address, "config"), Some(serde_json::json!("init")));
+
+        let response = vm.execute(&address, "bob", &serde_json::json!("go"), &[Coin::new(50, NATIVE_DENOM)]).unwrap();
+        assert_eq!(response.attribute("action"), Some("done"));
+        assert_eq!(stored_value(&vm, &address, "copy"), Some(serde_json::json!("init")));
+        assert_eq!(vm.get_contract(&address).unwrap().balance, 300);
+        assert_eq!((vm.balance_of("alice"), vm.balance_of("bob")), (750, 950));
+
+        assert!(vm.execute(&address, "bob", &serde_json::json!("go"), &[Coin::new(1, "uatom")]).is_err());
+        // Funds come out of the sender; nobody can send more than they hold
+        assert!(vm.execute(&address, "bob", &serde_json::json!("go"), &[Coin::new(951, NATIVE_DENOM)]).is_err());
+        assert!(vm.execute(&address, "mallory", &serde_json::json!("go"), &[Coin::new(1, NATIVE_DENOM)]).is_err());
+        let err = vm.instantiate(code_id, "mallory", &serde_json::json!("init"), &[Coin::new(1, NATIVE_DENOM)], None, "x");
+        assert!(err.is_err());
+        assert_eq!(vm.get_contract(&address).unwrap().balance, 300);
+        assert_eq!(vm.balance_of("bob"), 950);
+    }
+
+    #[test]
+    fn test_cosmwasm_error_discards_state() {
+        let mut vm = WasmVM::new(10_000_000);
+        vm.fund_account("bob", 10).unwrap();
+        let code_id = vm.store_code("creator", wat::parse_str(COSMWASM_WAT).unwrap()).unwrap();
+        let (address, _) = vm.instantiate(code_id, "alice", &serde_json::json!("init"), &[], None, "test").unwrap();
+
+        let err = vm.execute(&address, "bob", &serde_json::json!("fail"), &[Coin::new(10, NATIVE_DENOM)]).unwrap_err();
+        assert!(err.to_string().contains("boom"));
+        assert_eq!(stored_value(&vm, &address, "copy"), None);
+        assert_eq!(vm.get_contract(&address).unwrap().balance, 0);
+        assert_eq!(vm.balance_of("bob"), 10);
+    }
+
+    #[test]
+    fn test_cosmwasm_forged_result_region_is_refused() {
+        // `instantiate` returns a region claiming 4 GiB of data in a one-page memory
+        const FORGED_WAT: &str = r#"
+            (module
+              (memory (export "memory") 1)
+              (func (export "interface_version_8"))
+              (func (export "allocate") (param $size i32) (result i32)
+                (i32.store (i32.const 1024) (i32.const 2048))
+                (i32.store (i32.const 1028) (local.get $size))
+                (i32.store (i32.const 1032) (i32.const 0))
+                (i32.const 1024))
+              (func (export "deallocate") (param i32))
+              (func (export "instantiate") (param i32 i32 i32) (result i32)
+                (i32.store (i32.const 16) (i32.const 0))
+                (i32.store (i32.const 20) (i32.const -1))
+                (i32.store (i32.const 24) (i32.const -1))
+                (i32.const 16)))
+        "#;
+        let mut vm = WasmVM::new(10_000_000);
+        let code_id = vm.store_code("creator", wat::parse_str(FORGED_WAT).unwrap()).unwrap();
+        let err = vm.instantiate(code_id, "alice", &serde_json::json!("init"), &[], None, "test").unwrap_err();
+        assert!(err.to_string().contains("region data"), "{}", err);
+    }
+
+    #[test]
+    fn test_cosmwasm_query_is_read_only() {
+        let (mut vm, _, address) = instantiated_vm();
+        assert_eq!(vm.query(&address, &serde_json::json!("get")).unwrap(), b"hello");
+
+        assert!(vm.query(&address, &serde_json::json!("write")).is_err());
+        assert_eq!(stored_value(&vm, &address, "config"), Some(serde_json::json!("init")));
+    }
+
+    #[test]
+    fn test_cosmwasm_migrate_requires_admin() {
+        let (mut vm, _, address) = instantiated_vm();
+        let new_code_id = vm.store_code("creator", wat::parse_str(COSMWASM_WAT).unwrap()).unwrap();
+
+        assert!(vm.migrate(&address, "mallory", new_code_id, &serde_json::json!("v2")).is_err());
+        assert!(vm.migrate(&address, "alice", 99, &serde_json::json!("v2")).is_err());
+
+        vm.migrate(&address, "alice", new_code_id, &serde_json::json!("v2")).unwrap();
+        assert_eq!(vm.get_contract(&address).unwrap().code_id, Some(new_code_id));
+        assert_eq!(stored_value(&vm, &address, "config"), Some(serde_json::json!("v2")));
+
+        assert!(vm.update_admin(&address, "mallory", None).is_err());
+        vm.update_admin(&address, "alice", None).unwrap();
+        assert!(vm.migrate(&address, "alice", new_code_id, &serde_json::json!("v3")).is_err());
     }
 }